@@ -0,0 +1,80 @@
+//! Evaluation of the formulas of a large sheet walking their trees against running them compiled
+//! to bytecode, alone and as part of recalculation, which also works out the order of the cells.
+//! Run with `cargo bench --bench bytecode -- ROWS`, which defaults to 2000 rows.
+
+use std::time::{Duration, Instant};
+
+use gridkid::bytecode::Program;
+use gridkid::environment::Environment;
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Evaluatable, Primitive};
+
+const SAMPLES: usize = 31;
+
+/// Sheet whose rows each hold an input and three formulas over it and the row above
+fn workbook(rows: i32) -> Environment {
+    let mut environment = Environment::init();
+    environment.begin("Generate");
+    for row in 0..rows {
+        let n = row + 1;
+        let above = if row == 0 { String::from("0") } else { format!("B{row}") };
+        let formulas = [
+            format!("{}", n % 97),
+            format!("(A{n} * 3 + {above} % 7) * 2 - A{n} * (A{n} - 1) + 4 * (A{n} + 2) * (A{n} - 3)"),
+            format!("IntToFloat(B{n}) * 1.5 + IntToFloat(A{n} * A{n} + 1) * 0.25 - (2.5 - IntToFloat(A{n})) * 3.0"),
+            format!("(B{n} > 100 && C{n} < 1000.0 || !(A{n} == 0)) == (A{n} & 1 == 1)"),
+        ];
+        for (column, formula) in formulas.iter().enumerate() {
+            environment.set_cell(&CellAddress(column as i32, row), parse_formula(formula).unwrap()).unwrap();
+        }
+    }
+    environment.commit().unwrap();
+    environment
+}
+
+/// Median time taken by a task
+fn median(mut task: impl FnMut()) -> Duration {
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            task();
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    samples[SAMPLES / 2]
+}
+
+fn report(task: &str, tree: Duration, compiled: Duration) {
+    println!("{task}: tree {tree:?}, bytecode {compiled:?} ({:.2}x)", tree.as_secs_f64() / compiled.as_secs_f64());
+}
+
+fn main() {
+    let rows = std::env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(2000);
+    let mut environment = workbook(rows);
+
+    let formulas: Vec<&dyn Evaluatable> = environment.cells().map(|(_, val)| val).collect();
+    let programs: Vec<Program> = formulas.iter().map(|val| Program::compile(*val)).collect();
+    let mut stack = Vec::new();
+    let tree = median(|| {
+        for val in &formulas {
+            std::hint::black_box(val.evaluate(&environment)).ok();
+        }
+    });
+    let compiled = median(|| {
+        for program in &programs {
+            std::hint::black_box(program.evaluate_with(&environment, &mut stack)).ok();
+        }
+    });
+    report(&format!("evaluate {} cells", formulas.len()), tree, compiled);
+
+    let mut round = 0;
+    let mut change = |environment: &mut Environment| {
+        round += 1;
+        environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(round % 97))).unwrap();
+    };
+    let tree = median(|| change(&mut environment));
+    environment.set_compiled(true);
+    let compiled = median(|| change(&mut environment));
+    report(&format!("recalculate {} cells", rows * 4), tree, compiled);
+}
@@ -0,0 +1,166 @@
+//! Timings of the formula models on synthetic workbooks: deeply nested operations, functions over
+//! wide ranges, long chains of cell references and setting many cells at once. Run with
+//! `cargo bench --bench models -- [SCENARIO] [SIZE]` to run the scenarios whose names contain
+//! SCENARIO, scaled by SIZE, which defaults to 1000.
+
+use std::time::{Duration, Instant};
+
+use gridkid::address::CellRange;
+use gridkid::environment::Environment;
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Evaluatable, Primitive, Statistics};
+
+const SAMPLES: usize = 21;
+
+/// Name of a scenario and what runs it at a given size
+type Scenario = (&'static str, fn(usize));
+
+const SCENARIOS: [Scenario; 4] = [("nesting", nesting), ("statistics", statistics), ("chain", chain), ("import", import)];
+
+/// Median time taken by a task
+fn median(mut task: impl FnMut()) -> Duration {
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            task();
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    samples[SAMPLES / 2]
+}
+
+fn report(scenario: &str, task: &str, time: Duration) {
+    println!("{scenario:<10} {task:<48} {time:>12.3?}");
+}
+
+/// Formula `depth` operations deep over A1, alternating the operators so that none simplify away
+fn nested(depth: usize) -> String {
+    let mut text = String::from("A1");
+    for level in 0..depth {
+        text = match level % 3 {
+            0 => format!("({text} + {level})"),
+            1 => format!("({text} * 2 - A1)"),
+            _ => format!("({text} % 1000 + 1)"),
+        };
+    }
+    text
+}
+
+/// Parses and evaluates one formula of `size` nested operations, at most 2000
+fn nesting(size: usize) {
+    let depth = size.min(2000);
+    let mut environment = Environment::init();
+    environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(3))).unwrap();
+
+    let text = nested(depth);
+    report("nesting", &format!("parse {depth} levels"), median(|| {
+        std::hint::black_box(parse_formula(&text).unwrap());
+    }));
+    let val = parse_formula(&text).unwrap();
+    report("nesting", &format!("evaluate {depth} levels"), median(|| {
+        std::hint::black_box(val.evaluate(&environment)).ok();
+    }));
+    report("nesting", &format!("set a cell to {depth} levels"), median(|| {
+        environment.set_cell(&CellAddress(1, 0), parse_formula(&text).unwrap()).unwrap();
+    }));
+}
+
+/// Functions over a column of `size * 100` numbers, some of them formulas
+fn statistics(size: usize) {
+    let rows = (size * 100) as i32;
+    let mut environment = Environment::init();
+    environment.begin("Generate");
+    for row in 0..rows {
+        let val = match row % 10 {
+            0 if row > 0 => parse_formula(&format!("A{row} * 2")).unwrap(),
+            1..=4 => Box::new(Primitive::Float(row as f32 / 8.0)) as Box<dyn Evaluatable>,
+            _ => Box::new(Primitive::Integer(row % 1000)),
+        };
+        environment.set_cell(&CellAddress(0, row), val).unwrap();
+    }
+    environment.commit().unwrap();
+
+    let full = CellRange::new(CellAddress(0, 0), CellAddress(0, rows - 1));
+    for statistics in [
+        Statistics::Sum(full.clone()),
+        Statistics::Mean(full.clone()),
+        Statistics::Max(full.clone()),
+        Statistics::Min(full),
+        Statistics::Sum(CellRange::whole_columns(0, 3)),
+    ] {
+        report("statistics", &format!("{statistics} over {rows} rows"), median(|| {
+            std::hint::black_box(statistics.evaluate(&environment)).ok();
+        }));
+    }
+}
+
+/// Recalculates a chain of `size * 10` cells, each reading the one above
+fn chain(size: usize) {
+    let length = (size * 10) as i32;
+    let mut environment = Environment::init();
+    environment.begin("Generate");
+    environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(0))).unwrap();
+    for row in 1..length {
+        environment.set_cell(&CellAddress(0, row), parse_formula(&format!("A{row} + 1")).unwrap()).unwrap();
+    }
+    environment.commit().unwrap();
+
+    let mut round = 0;
+    report("chain", &format!("change the head of {length} cells"), median(|| {
+        round += 1;
+        environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(round))).unwrap();
+    }));
+    report("chain", &format!("change the tail of {length} cells"), median(|| {
+        round += 1;
+        environment.set_cell(&CellAddress(1, 0), Box::new(Primitive::Integer(round))).unwrap();
+    }));
+}
+
+/// Sets `size` rows of ten cells, as an import does
+fn import(size: usize) {
+    let rows = size as i32;
+    let cells = |row: i32| (0..10).map(move |column| {
+        let val: Box<dyn Evaluatable> = match column {
+            0..=5 => Box::new(Primitive::Integer(row * column)),
+            6 | 7 => Box::new(Primitive::Float(row as f32 * 0.5)),
+            8 => Box::new(Primitive::String(format!("row {row}"))),
+            _ => parse_formula(&format!("A{n} + B{n} * 2", n = row + 1)).unwrap(),
+        };
+        (CellAddress(column, row), val)
+    });
+
+    report("import", &format!("set {} cells in a transaction", rows * 10), median(|| {
+        let mut environment = Environment::init();
+        environment.begin("Import");
+        for row in 0..rows {
+            for (adr, val) in cells(row) {
+                environment.set_cell(&adr, val).unwrap();
+            }
+        }
+        environment.commit().unwrap();
+    }));
+    // Outside a transaction every cell set recalculates, so fewer rows keep this bearable
+    let rows = rows.min(50);
+    report("import", &format!("set {} cells one by one", rows * 10), median(|| {
+        let mut environment = Environment::init();
+        for row in 0..rows {
+            for (adr, val) in cells(row) {
+                environment.set_cell(&adr, val).unwrap();
+            }
+        }
+    }));
+}
+
+fn main() {
+    // `cargo bench` passes `--bench` to every benchmark
+    let args: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let size = args.iter().find_map(|arg| arg.parse().ok()).unwrap_or(1000);
+    let filter = args.iter().find(|arg| arg.parse::<usize>().is_err());
+
+    for (name, scenario) in SCENARIOS {
+        if filter.is_none_or(|filter| name.contains(filter.as_str())) {
+            scenario(size);
+        }
+    }
+}
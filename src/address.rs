@@ -0,0 +1,533 @@
+//! Cell coordinates and their spreadsheet notations.
+//!
+//! `CellAddress(column, row)` is zero based, so `CellAddress(0, 0)` is `A1` in A1 notation and
+//! `R1C1` in R1C1 notation, and `CellAddress(1, 2)` is `B3` / `R3C2`. Negative coordinates are
+//! valid addresses in a grid but have no spreadsheet notation.
+//!
+//! References and ranges may name the sheet they point into, as in `Inputs!B2` or
+//! `'Tax rates'!A1:A5`, and a range whose ends name different sheets, like `Q1:Q4!B2:B9`, is a
+//! 3-D range spanning every sheet between the two.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Largest column that can be written in A1 notation (`FXSHRXW`), so that the 1-based column
+/// number used by R1C1 notation fits an `i32`
+pub const MAX_COLUMN: i32 = i32::MAX - 1;
+/// Largest row that can be written in A1 notation, so that the 1-based row number fits an `i32`
+pub const MAX_ROW: i32 = i32::MAX - 1;
+
+/// Address of given cell
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub struct CellAddress(pub i32, pub i32);
+
+impl CellAddress {
+    pub fn column(&self) -> i32 {
+        self.0
+    }
+
+    pub fn row(&self) -> i32 {
+        self.1
+    }
+
+    /// Checks that the address can be written in A1 and R1C1 notation
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0..=MAX_COLUMN).contains(&self.0) {
+            Err(format!("Column {} of cell ({}, {}) is out of range", self.0, self.0, self.1))
+        } else if !(0..=MAX_ROW).contains(&self.1) {
+            Err(format!("Row {} of cell ({}, {}) is out of range", self.1, self.0, self.1))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn to_a1(&self) -> Result<String, String> {
+        self.validate()?;
+        Ok(format!("{}{}", column_name(self.0)?, self.1 + 1))
+    }
+
+    pub fn to_r1c1(&self) -> Result<String, String> {
+        self.validate()?;
+        Ok(format!("R{}C{}", self.1 + 1, self.0 + 1))
+    }
+
+    /// Parses an absolute R1C1 address such as `R3C2`
+    pub fn from_r1c1(text: &str) -> Result<CellAddress, String> {
+        let reference = CellReference::from_r1c1(text, &CellAddress(0, 0))?;
+        if !reference.absolute_column || !reference.absolute_row {
+            return Err(format!("Relative R1C1 reference {text} needs a base cell"));
+        }
+        Ok(reference.address)
+    }
+}
+
+/// A1 notation, or the raw coordinates for addresses that have none
+impl fmt::Display for CellAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_a1() {
+            Ok(a1) => write!(f, "{a1}"),
+            Err(_) => write!(f, "({}, {})", self.0, self.1),
+        }
+    }
+}
+
+/// Parses A1 notation, ignoring any `$` markers
+impl FromStr for CellAddress {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<CellAddress, String> {
+        Ok(parse_cell(text)?.address)
+    }
+}
+
+/// Letters naming a zero based column, e.g. `0` is `A` and `26` is `AA`
+pub fn column_name(column: i32) -> Result<String, String> {
+    if !(0..=MAX_COLUMN).contains(&column) {
+        return Err(format!("Column {column} is out of range"));
+    }
+
+    let mut name = Vec::new();
+    let mut remaining = column as i64 + 1;
+    while remaining > 0 {
+        remaining -= 1;
+        name.push(b'A' + (remaining % 26) as u8);
+        remaining /= 26;
+    }
+
+    name.reverse();
+    Ok(String::from_utf8(name).unwrap())
+}
+
+/// Zero based column named by letters, case insensitive
+pub fn column_index(name: &str) -> Result<i32, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("Invalid column name {name}"));
+    }
+
+    let mut column: i64 = 0;
+    for c in name.chars() {
+        column = column * 26 + (c.to_ascii_uppercase() as u8 - b'A') as i64 + 1;
+        if column - 1 > MAX_COLUMN as i64 {
+            return Err(format!("Column {name} is out of range"));
+        }
+    }
+
+    Ok((column - 1) as i32)
+}
+
+fn row_index(number: &str) -> Result<i32, String> {
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid row number {number}"));
+    }
+
+    match number.parse::<i64>() {
+        Ok(row) if row >= 1 && row - 1 <= MAX_ROW as i64 => Ok((row - 1) as i32),
+        _ => Err(format!("Row {number} is out of range")),
+    }
+}
+
+/// A cell address as written in a formula, where each axis may be anchored with `$`. A reference
+/// without a sheet points into the sheet holding the formula.
+#[derive(Eq, Hash, PartialEq, Clone, Debug)]
+pub struct CellReference {
+    pub sheet: Option<String>,
+    pub address: CellAddress,
+    pub absolute_column: bool,
+    pub absolute_row: bool,
+}
+
+impl CellReference {
+    /// Reference with both axes relative, like `B3`
+    pub fn relative(address: CellAddress) -> CellReference {
+        CellReference { sheet: None, address, absolute_column: false, absolute_row: false }
+    }
+
+    /// Reference with both axes absolute, like `$B$3`
+    pub fn absolute(address: CellAddress) -> CellReference {
+        CellReference { sheet: None, address, absolute_column: true, absolute_row: true }
+    }
+
+    /// The same reference pointing into the named sheet, like `Inputs!B3`
+    pub fn on_sheet(self, sheet: &str) -> CellReference {
+        CellReference { sheet: Some(sheet.to_string()), ..self }
+    }
+
+    /// The reference as it reads once its formula has been moved by the given number of columns
+    /// and rows: relative axes move along with the formula while absolute axes stay put
+    pub fn offset(&self, columns: i64, rows: i64) -> Result<CellReference, String> {
+        let shift = |index: i32, absolute: bool, delta: i64| {
+            if absolute {
+                Ok(index)
+            } else {
+                i32::try_from(index as i64 + delta).map_err(|_| format!("Reference {} moved out of the grid", self))
+            }
+        };
+
+        let address = CellAddress(shift(self.address.0, self.absolute_column, columns)?, shift(self.address.1, self.absolute_row, rows)?);
+        address.validate().map_err(|_| format!("Reference {} moved out of the grid", self))?;
+
+        Ok(CellReference { address, ..self.clone() })
+    }
+
+    /// R1C1 notation with relative axes written as offsets from `base`, e.g. `R[1]C2`
+    pub fn to_r1c1(&self, base: &CellAddress) -> Result<String, String> {
+        let (row, column) = self.r1c1_axes(base)?;
+        Ok(format!("{}{}{}", sheet_prefix(self.sheet.as_deref()), row, column))
+    }
+
+    fn r1c1_axes(&self, base: &CellAddress) -> Result<(String, String), String> {
+        self.address.validate()?;
+
+        let axis = |prefix: char, absolute: bool, index: i32, base: i32| {
+            if absolute {
+                format!("{}{}", prefix, index as i64 + 1)
+            } else if index == base {
+                String::from(prefix)
+            } else {
+                format!("{}[{}]", prefix, index as i64 - base as i64)
+            }
+        };
+
+        Ok((axis('R', self.absolute_row, self.address.1, base.1),
+            axis('C', self.absolute_column, self.address.0, base.0)))
+    }
+
+    /// Parses R1C1 notation, resolving relative offsets such as `R[-1]C` against `base`
+    pub fn from_r1c1(text: &str, base: &CellAddress) -> Result<CellReference, String> {
+        let (sheet, text) = split_sheet(text)?;
+        let sheet = sheet.map(parse_sheet_name).transpose()?;
+
+        let upper = text.to_ascii_uppercase();
+        let rest = upper.strip_prefix('R').ok_or(format!("Invalid R1C1 reference {text}"))?;
+        let split = rest.find('C').ok_or(format!("Invalid R1C1 reference {text}"))?;
+        let (row, column) = (&rest[..split], &rest[split + 1..]);
+
+        let (row, absolute_row) = r1c1_axis(row, base.1, text)?;
+        let (column, absolute_column) = r1c1_axis(column, base.0, text)?;
+
+        let address = CellAddress(column, row);
+        address.validate()?;
+        Ok(CellReference { sheet, address, absolute_column, absolute_row })
+    }
+
+    /// A1 notation without the sheet
+    fn cell_text(&self) -> String {
+        match (column_name(self.address.0), self.address.validate()) {
+            (Ok(column), Ok(())) => format!("{}{}{}{}",
+                if self.absolute_column { "$" } else { "" },
+                column,
+                if self.absolute_row { "$" } else { "" },
+                self.address.1 + 1),
+            _ => self.address.to_string(),
+        }
+    }
+}
+
+/// One axis of an R1C1 reference: empty (same as base), `[offset]` or a 1-based index
+fn r1c1_axis(text: &str, base: i32, reference: &str) -> Result<(i32, bool), String> {
+    if text.is_empty() {
+        return Ok((base, false));
+    }
+
+    if let Some(offset) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let offset = offset.parse::<i64>().map_err(|_| format!("Invalid R1C1 reference {reference}"))?;
+        let index = i32::try_from(base as i64 + offset).map_err(|_| format!("R1C1 reference {reference} is out of range"))?;
+        return Ok((index, false));
+    }
+
+    let index = row_index(text).map_err(|_| format!("Invalid R1C1 reference {reference}"))?;
+    Ok((index, true))
+}
+
+impl fmt::Display for CellReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", sheet_prefix(self.sheet.as_deref()), self.cell_text())
+    }
+}
+
+/// Parses A1 notation such as `B3`, `$B3`, `$B$3` or `Inputs!B3`
+impl FromStr for CellReference {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<CellReference, String> {
+        let (sheet, cell) = split_sheet(text)?;
+        let sheet = sheet.map(parse_sheet_name).transpose()?;
+        Ok(CellReference { sheet, ..parse_cell(cell)? })
+    }
+}
+
+/// Unqualified A1 reference
+fn parse_cell(text: &str) -> Result<CellReference, String> {
+    let (absolute_column, rest) = strip_anchor(text);
+    let split = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+    let (letters, rest) = rest.split_at(split);
+    let (absolute_row, digits) = strip_anchor(rest);
+
+    if letters.is_empty() || digits.is_empty() {
+        return Err(format!("Invalid cell reference {text}"));
+    }
+
+    let column = column_index(letters).map_err(|e| format!("Invalid cell reference {text}: {e}"))?;
+    let row = row_index(digits).map_err(|e| format!("Invalid cell reference {text}: {e}"))?;
+    Ok(CellReference { sheet: None, address: CellAddress(column, row), absolute_column, absolute_row })
+}
+
+fn strip_anchor(text: &str) -> (bool, &str) {
+    match text.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    }
+}
+
+/// Rectangle of cells between two corner references, in the order they were written.
+/// Whole columns (`B:B`) and whole rows (`3:3`) are ranges spanning every row or column.
+#[derive(Eq, Hash, PartialEq, Clone, Debug)]
+pub struct CellRange {
+    pub start: CellReference,
+    pub end: CellReference,
+}
+
+impl CellRange {
+    /// Range with relative corners
+    pub fn new(start: CellAddress, end: CellAddress) -> CellRange {
+        CellRange { start: CellReference::relative(start), end: CellReference::relative(end) }
+    }
+
+    /// Every row of the given columns, e.g. `B:D`
+    pub fn whole_columns(first: i32, last: i32) -> CellRange {
+        CellRange::new(CellAddress(first, 0), CellAddress(last, MAX_ROW))
+    }
+
+    /// Every column of the given rows, e.g. `3:5`
+    pub fn whole_rows(first: i32, last: i32) -> CellRange {
+        CellRange::new(CellAddress(0, first), CellAddress(MAX_COLUMN, last))
+    }
+
+    pub fn columns(&self) -> RangeInclusive<i32> {
+        let (start, end) = (self.start.address.0, self.end.address.0);
+        start.min(end)..=start.max(end)
+    }
+
+    pub fn rows(&self) -> RangeInclusive<i32> {
+        let (start, end) = (self.start.address.1, self.end.address.1);
+        start.min(end)..=start.max(end)
+    }
+
+    /// The same range pointing into the named sheet, like `Inputs!A1:B5`
+    pub fn on_sheet(self, sheet: &str) -> CellRange {
+        CellRange { start: self.start.on_sheet(sheet), end: self.end.on_sheet(sheet) }
+    }
+
+    /// Whether the range spans several sheets, like `Q1:Q4!B2:B9`
+    pub fn is_3d(&self) -> bool {
+        self.start.sheet != self.end.sheet
+    }
+
+    pub fn contains(&self, adr: &CellAddress) -> bool {
+        self.columns().contains(&adr.0) && self.rows().contains(&adr.1)
+    }
+
+    pub fn is_whole_column(&self) -> bool {
+        self.rows() == (0..=MAX_ROW)
+    }
+
+    pub fn is_whole_row(&self) -> bool {
+        self.columns() == (0..=MAX_COLUMN)
+    }
+
+    /// Corners in the order `Statistics` takes them: the first column with the last row, then
+    /// the last column with the first row
+    pub fn statistics_corners(&self) -> (CellAddress, CellAddress) {
+        (CellAddress(*self.columns().start(), *self.rows().end()),
+         CellAddress(*self.columns().end(), *self.rows().start()))
+    }
+
+    /// R1C1 notation with relative axes written as offsets from `base`, e.g. `R1C1:R[4]C3`.
+    /// Whole columns and rows are written as `C2:C4` and `R3:R5`.
+    pub fn to_r1c1(&self, base: &CellAddress) -> Result<String, String> {
+        let (start_row, start_column) = self.start.r1c1_axes(base)?;
+        let (end_row, end_column) = self.end.r1c1_axes(base)?;
+        let prefix = self.sheet_prefix();
+
+        if self.is_whole_column() && !self.is_whole_row() {
+            Ok(format!("{prefix}{start_column}:{end_column}"))
+        } else if self.is_whole_row() && !self.is_whole_column() {
+            Ok(format!("{prefix}{start_row}:{end_row}"))
+        } else {
+            Ok(format!("{prefix}{start_row}{start_column}:{end_row}{end_column}"))
+        }
+    }
+
+    /// `Sheet!` or `First:Last!` for a 3-D range
+    fn sheet_prefix(&self) -> String {
+        match (&self.start.sheet, &self.end.sheet) {
+            (Some(first), Some(last)) if first != last => format!("{}:{}!", quote_sheet_name(first), quote_sheet_name(last)),
+            (first, last) => sheet_prefix(first.as_deref().or(last.as_deref())),
+        }
+    }
+}
+
+impl fmt::Display for CellRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let anchor = |absolute: bool| if absolute { "$" } else { "" };
+        let column = |reference: &CellReference| match column_name(reference.address.0) {
+            Ok(name) => format!("{}{}", anchor(reference.absolute_column), name),
+            Err(_) => reference.address.0.to_string(),
+        };
+        let row = |reference: &CellReference| format!("{}{}", anchor(reference.absolute_row), reference.address.1 as i64 + 1);
+
+        write!(f, "{}", self.sheet_prefix())?;
+        if self.is_whole_column() && !self.is_whole_row() {
+            write!(f, "{}:{}", column(&self.start), column(&self.end))
+        } else if self.is_whole_row() && !self.is_whole_column() {
+            write!(f, "{}:{}", row(&self.start), row(&self.end))
+        } else {
+            write!(f, "{}:{}", self.start.cell_text(), self.end.cell_text())
+        }
+    }
+}
+
+/// Parses `A1:C5`, whole columns `B:B`, whole rows `3:3`, or a single cell `A1`, optionally
+/// preceded by a sheet (`Inputs!A1:C5`) or by the first and last sheets of a 3-D range
+/// (`Q1:Q4!A1:C5`)
+impl FromStr for CellRange {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<CellRange, String> {
+        let (sheets, cells) = split_sheet(text)?;
+        let (first, last) = match sheets {
+            Some(sheets) => match split_unquoted(sheets, ':') {
+                Some((first, last)) => (Some(parse_sheet_name(first)?), Some(parse_sheet_name(last)?)),
+                None => (Some(parse_sheet_name(sheets)?), Some(parse_sheet_name(sheets)?)),
+            },
+            None => (None, None),
+        };
+
+        let range = parse_range(cells).map_err(|_| format!("Invalid cell range {text}"))?;
+        Ok(CellRange {
+            start: CellReference { sheet: first, ..range.start },
+            end: CellReference { sheet: last, ..range.end },
+        })
+    }
+}
+
+/// Unqualified range
+fn parse_range(text: &str) -> Result<CellRange, String> {
+    let (start, end) = match text.split_once(':') {
+        Some(corners) => corners,
+        None => {
+            let cell = parse_cell(text)?;
+            return Ok(CellRange { start: cell.clone(), end: cell });
+        }
+    };
+
+    if let (Ok(start), Ok(end)) = (parse_cell(start), parse_cell(end)) {
+        return Ok(CellRange { start, end });
+    }
+
+    let (start_anchor, start) = strip_anchor(start);
+    let (end_anchor, end) = strip_anchor(end);
+    let reference = |address, absolute_column, absolute_row| CellReference { sheet: None, address, absolute_column, absolute_row };
+
+    if let (Ok(first), Ok(last)) = (column_index(start), column_index(end)) {
+        Ok(CellRange {
+            start: reference(CellAddress(first, 0), start_anchor, true),
+            end: reference(CellAddress(last, MAX_ROW), end_anchor, true),
+        })
+    } else if let (Ok(first), Ok(last)) = (row_index(start), row_index(end)) {
+        Ok(CellRange {
+            start: reference(CellAddress(0, first), true, start_anchor),
+            end: reference(CellAddress(MAX_COLUMN, last), true, end_anchor),
+        })
+    } else {
+        Err(format!("Invalid cell range {text}"))
+    }
+}
+
+/// Checks that a sheet name can be written in a reference
+pub fn validate_sheet_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        Err(String::from("Sheet name cannot be empty"))
+    } else if let Some(c) = name.chars().find(|c| matches!(c, '[' | ']' | '*' | '?' | ':' | '/' | '\\')) {
+        Err(format!("Sheet name {name} cannot contain '{c}'"))
+    } else if name.starts_with('\'') || name.ends_with('\'') {
+        Err(format!("Sheet name {name} cannot start or end with an apostrophe"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that a name for a cell, range or constant can be told apart from cell notation, so
+/// that `TaxRate` is accepted while `TAX2023`, `R1C1` and `TRUE` are not
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let identifier = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+
+    if !identifier {
+        Err(format!("Name {name} must start with a letter or underscore and contain only letters, digits, underscores and periods"))
+    } else if parse_cell(name).is_ok() {
+        Err(format!("Name {name} cannot be used as it is a cell reference"))
+    } else if CellReference::from_r1c1(name, &CellAddress(0, 0)).is_ok() || name.eq_ignore_ascii_case("R") || name.eq_ignore_ascii_case("C") {
+        Err(format!("Name {name} cannot be used as it is an R1C1 reference"))
+    } else if name.eq_ignore_ascii_case("TRUE") || name.eq_ignore_ascii_case("FALSE") {
+        Err(format!("Name {name} cannot be used as it is a boolean"))
+    } else if name.eq_ignore_ascii_case("NAN") || name.eq_ignore_ascii_case("INF") {
+        Err(format!("Name {name} cannot be used as it is a float"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Sheet name as written before the `!` of a reference, quoted unless it is a plain identifier,
+/// e.g. `Inputs` or `'Tax rates'`
+pub fn quote_sheet_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+
+    if plain {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "''"))
+    }
+}
+
+fn sheet_prefix(sheet: Option<&str>) -> String {
+    match sheet {
+        Some(sheet) => format!("{}!", quote_sheet_name(sheet)),
+        None => String::new(),
+    }
+}
+
+fn parse_sheet_name(text: &str) -> Result<String, String> {
+    let name = match text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+        Some(quoted) => quoted.replace("''", "'"),
+        None => text.to_string(),
+    };
+    validate_sheet_name(&name)?;
+    Ok(name)
+}
+
+/// Splits `Sheet!A1` at its first unquoted `!`, which must exist if the text has any `!`
+fn split_sheet(text: &str) -> Result<(Option<&str>, &str), String> {
+    match split_unquoted(text, '!') {
+        Some((sheet, rest)) => Ok((Some(sheet), rest)),
+        None if text.contains('!') => Err(format!("Invalid reference {text}")),
+        None => Ok((None, text)),
+    }
+}
+
+/// Splits at the first occurrence of `separator` outside a quoted sheet name
+fn split_unquoted(text: &str, separator: char) -> Option<(&str, &str)> {
+    let mut quoted = false;
+    for (index, c) in text.char_indices() {
+        if c == '\'' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            return Some((&text[..index], &text[index + 1..]));
+        }
+    }
+    None
+}
@@ -0,0 +1,363 @@
+//! `gridkid-server`: hosts a workbook so several programs can read and change it over HTTP. It
+//! only listens on localhost, speaks JSON and needs nothing beyond the standard library.
+//!
+//! ```text
+//! gridkid-server [FILE] [--port PORT]
+//!
+//! GET    /sheets              names of the sheets
+//! GET    /cells/CELL          formula and value of a cell, e.g. /cells/Inputs!B2
+//! PUT    /cells/CELL          set a cell to the formula in the request body
+//! DELETE /cells/CELL          empty a cell
+//! POST   /eval                value of the formula in the request body
+//! GET    /precedents/CELL     cells a cell reads
+//! GET    /dependents/CELL     cells that read a cell
+//! GET    /events              server-sent events for every change to the workbook
+//! ```
+//!
+//! Cells without a sheet are on the first sheet. Every request is served on its own thread, and
+//! the workbook belongs to a single thread that runs requests against it one at a time, so each
+//! request sees the workbook as the previous one left it.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::process::ExitCode;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use gridkid::address::CellReference;
+use gridkid::environment::Environment;
+use gridkid::events::Event;
+use gridkid::format::formula::{parse_formula, write_formula_on};
+use gridkid::format::{json, read_workbook};
+use gridkid::model::CellAddress;
+
+const DEFAULT_PORT: u16 = 7878;
+const MAX_BODY: usize = 1 << 20;
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Comments sent on an idle event stream, so that clients that went away are noticed
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+type Job = Box<dyn FnOnce(&mut Environment) + Send>;
+
+/// Handle to the thread that owns the workbook
+#[derive(Clone)]
+struct Workbook {
+    jobs: Sender<Job>,
+}
+
+impl Workbook {
+    /// Starts the thread owning the workbook, loading it from a file if one is given
+    fn spawn(path: Option<String>) -> Result<Workbook, String> {
+        let (jobs, queue) = channel::<Job>();
+        let (ready, loaded) = channel();
+
+        thread::spawn(move || {
+            let mut environment = match path {
+                Some(path) => match read_workbook(path) {
+                    Ok(environment) => environment,
+                    Err(e) => {
+                        let _ = ready.send(Err(e));
+                        return;
+                    }
+                },
+                None => Environment::init(),
+            };
+            let _ = ready.send(Ok(()));
+
+            // A job that panics fails its own request, and the next job sees the workbook as it left it
+            for job in queue {
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&mut environment)));
+            }
+        });
+
+        loaded.recv().map_err(|_| String::from("The workbook thread stopped"))??;
+        Ok(Workbook { jobs })
+    }
+
+    /// Runs a function against the workbook and waits for its result, or fails if the function
+    /// panicked
+    fn with<T: Send + 'static>(&self, job: impl FnOnce(&mut Environment) -> T + Send + 'static) -> Result<T, String> {
+        let (sender, receiver) = channel();
+        self.jobs
+            .send(Box::new(move |environment| {
+                let _ = sender.send(job(environment));
+            }))
+            .map_err(|_| String::from("The workbook thread stopped"))?;
+        // The sender is dropped without a result when the job panics
+        receiver.recv().map_err(|_| String::from("The request failed in the workbook"))
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+/// Body of a response or why the request is wrong, unless it failed in the workbook
+type Outcome = Result<Result<String, String>, String>;
+
+enum Response {
+    Json(u16, String),
+    Events(Receiver<Event>),
+}
+
+impl Response {
+    fn error(status: u16, message: &str) -> Response {
+        Response::Json(status, format!("{{\"error\": {}}}", json::string(message)))
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        return Err(String::from("Expected a request line"));
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Err(String::from("Connection closed in the headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, val)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = val.trim().parse().map_err(|_| format!("Invalid content length {}", val.trim()))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(format!("Bodies are limited to {MAX_BODY} bytes"));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    let body = String::from_utf8(body).map_err(|_| String::from("The body is not UTF-8"))?;
+
+    // Queries are not used, and a cell such as 'Tax rates'!A1 arrives percent-encoded
+    let path = target.split('?').next().unwrap_or_default();
+    Ok(Request { method, path: percent_decode(path)?, body })
+}
+
+fn percent_decode(text: &str) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' && tail.len() >= 2 {
+            let hex = std::str::from_utf8(&tail[..2]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            bytes.push(hex.ok_or(format!("Invalid escape in {text}"))?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("Invalid escape in {text}"))
+}
+
+fn route(workbook: &Workbook, request: Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').splitn(2, '/').collect();
+
+    let result = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["sheets"]) => workbook.with(|environment| {
+            let names: Vec<String> = environment.sheet_names().map(json::string).collect();
+            Ok(format!("{{\"sheets\": [{}]}}", names.join(", ")))
+        }),
+        ("GET", ["cells", cell]) => cell_request(workbook, cell, |_, _| Ok(())),
+        ("PUT", ["cells", cell]) => {
+            let formula = request.body;
+            cell_request(workbook, cell, move |environment, reference| {
+                let val = parse_formula(formula.trim())?;
+                environment.set_cell_on(&sheet_of(environment, reference), &reference.address, val)
+            })
+        }
+        ("DELETE", ["cells", cell]) => cell_request(workbook, cell, |environment, reference| {
+            environment.clear_cell_on(&sheet_of(environment, reference), &reference.address)
+        }),
+        ("POST", ["eval"]) => {
+            let formula = request.body;
+            workbook.with(move |environment| {
+                let val = parse_formula(formula.trim())?;
+                Ok(format!("{{\"value\": {}}}", json::value(&Some(val.evaluate(environment)))))
+            })
+        }
+        ("GET", ["precedents", cell]) => cell_list(workbook, cell, "precedents", Environment::precedents),
+        ("GET", ["dependents", cell]) => cell_list(workbook, cell, "dependents", Environment::dependents),
+        ("GET", ["events"]) => {
+            return match workbook.with(|environment| environment.subscribe_channel()) {
+                Ok(events) => Response::Events(events),
+                Err(e) => Response::error(500, &e),
+            };
+        }
+        (_, ["sheets"] | ["cells", _] | ["eval"] | ["precedents", _] | ["dependents", _] | ["events"]) => {
+            return Response::error(405, &format!("{} is not supported on {}", request.method, request.path));
+        }
+        _ => return Response::error(404, &format!("Nothing at {}", request.path)),
+    };
+
+    match result {
+        Ok(Ok(body)) => Response::Json(200, body),
+        Ok(Err(e)) => Response::error(400, &e),
+        Err(e) => Response::error(500, &e),
+    }
+}
+
+fn sheet_of(environment: &Environment, reference: &CellReference) -> String {
+    reference.sheet.clone().unwrap_or(environment.active_sheet().to_string())
+}
+
+/// Changes a cell, then describes it by its formula and value
+fn cell_request(
+    workbook: &Workbook,
+    cell: &str,
+    change: impl FnOnce(&mut Environment, &CellReference) -> Result<(), String> + Send + 'static,
+) -> Outcome {
+    let cell = cell.to_string();
+    workbook.with(move |environment| {
+        let reference: CellReference = cell.parse()?;
+        let sheet = sheet_of(environment, &reference);
+        if !environment.sheet_names().any(|name| name == sheet) {
+            return Err(format!("Sheet {sheet} not found"));
+        }
+        change(environment, &reference)?;
+        let formula = match environment.get_cell_on(&sheet, &reference.address) {
+            Some(val) => json::string(&write_formula_on(val, &sheet)),
+            None => String::from("null"),
+        };
+        let val = environment.value_on(&sheet, &reference.address);
+        Ok(format!("{{\"cell\": {}, \"formula\": {formula}, \"value\": {}}}", json::string(&cell_name(&sheet, &reference.address)), json::value(&val)))
+    })
+}
+
+type Audit = fn(&Environment, &str, &CellAddress) -> Result<Vec<CellReference>, String>;
+
+fn cell_list(workbook: &Workbook, cell: &str, member: &'static str, audit: Audit) -> Outcome {
+    let cell = cell.to_string();
+    workbook.with(move |environment| {
+        let reference: CellReference = cell.parse()?;
+        let sheet = sheet_of(environment, &reference);
+        let cells: Vec<String> = audit(environment, &sheet, &reference.address)?.iter().map(|cell| json::string(&cell.to_string())).collect();
+        Ok(format!("{{\"cell\": {}, \"{member}\": [{}]}}", json::string(&cell_name(&sheet, &reference.address)), cells.join(", ")))
+    })
+}
+
+fn cell_name(sheet: &str, adr: &CellAddress) -> String {
+    CellReference { sheet: Some(sheet.to_string()), ..CellReference::relative(*adr) }.to_string()
+}
+
+/// Name and data of a server-sent event
+fn event_text(event: &Event) -> String {
+    let (name, data) = match event {
+        Event::CellSet { sheet, adr } => ("cell-set", format!("{{\"cell\": {}}}", json::string(&cell_name(sheet, adr)))),
+        Event::CellCleared { sheet, adr } => ("cell-cleared", format!("{{\"cell\": {}}}", json::string(&cell_name(sheet, adr)))),
+        Event::ValueChanged { sheet, adr, old, new } => (
+            "value-changed",
+            format!("{{\"cell\": {}, \"old\": {}, \"new\": {}}}", json::string(&cell_name(sheet, adr)), json::value(old), json::value(new)),
+        ),
+        Event::StructureChanged => ("structure-changed", String::from("{}")),
+    };
+    format!("event: {name}\ndata: {data}\n\n")
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "Error",
+    }
+}
+
+fn serve(workbook: Workbook, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+
+    let response = match read_request(&mut reader) {
+        Ok(request) => route(&workbook, request),
+        Err(e) => Response::error(400, &e),
+    };
+
+    match response {
+        Response::Json(status, body) => write!(
+            stream,
+            "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            status_text(status),
+            body.len()
+        ),
+        Response::Events(events) => {
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+            stream.flush()?;
+            // Ends when the client goes away and a write fails, which drops the subscription
+            loop {
+                match events.recv_timeout(KEEP_ALIVE) {
+                    Ok(event) => stream.write_all(event_text(&event).as_bytes())?,
+                    Err(RecvTimeoutError::Timeout) => stream.write_all(b": keep-alive\n\n")?,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                stream.flush()?;
+            }
+        }
+    }
+}
+
+fn arguments() -> Result<(Option<String>, u16), String> {
+    let mut path = None;
+    let mut port = DEFAULT_PORT;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                let text = args.next().ok_or("Expected a port after --port")?;
+                port = text.parse().map_err(|_| format!("Invalid port {text}"))?;
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {flag}")),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Expected a single workbook, got {arg} as well")),
+        }
+    }
+    Ok((path, port))
+}
+
+fn main() -> ExitCode {
+    let started = arguments().and_then(|(path, port)| {
+        let workbook = Workbook::spawn(path)?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).map_err(|e| format!("Cannot listen on port {port}: {e}"))?;
+        Ok((workbook, listener))
+    });
+    let (workbook, listener) = match started {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("error: {e}\nusage: gridkid-server [FILE] [--port PORT]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Port 0 picks a free port, so say which one
+    if let Ok(address) = listener.local_addr() {
+        println!("listening on http://{address}");
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let workbook = workbook.clone();
+                thread::spawn(move || serve(workbook, stream));
+            }
+            Err(e) => eprintln!("error: {e}"),
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,634 @@
+//! Terminal spreadsheet editor without the terminal: decoding of keys, the effect of each key
+//! on the workbook and the selection, and drawing of the screen as ANSI escape sequences. `main`
+//! feeds it keys from a terminal in raw mode and prints what it draws.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use gridkid::address::{column_name, CellReference};
+use gridkid::environment::Environment;
+use gridkid::format::formula::{parse_formula, write_formula_on};
+use gridkid::format::write_workbook;
+use gridkid::model::{CellAddress, Primitive};
+
+const DEFAULT_WIDTH: usize = 10;
+const MIN_WIDTH: usize = 3;
+const MAX_WIDTH: usize = 60;
+
+/// Key pressed, as decoded from what a terminal in raw mode sends
+#[derive(Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    CtrlLeft,
+    CtrlRight,
+    PageUp,
+    PageDown,
+    Home,
+    Enter,
+    Tab,
+    Escape,
+    Backspace,
+    Delete,
+    Ctrl(char),
+}
+
+/// Reads keys from the bytes a terminal in raw mode sends, such as standard input
+pub struct Keys<R: Read> {
+    input: R,
+}
+
+impl<R: Read> Keys<R> {
+    pub fn new(input: R) -> Keys<R> {
+        Keys { input }
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let mut buffer = [0];
+        match self.input.read(&mut buffer) {
+            Ok(1) => Some(buffer[0]),
+            _ => None,
+        }
+    }
+
+    /// Next key, or `None` if none was pressed for a moment
+    pub fn next_key(&mut self) -> Option<Key> {
+        let byte = self.byte()?;
+        Some(match byte {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x1b => self.escape(),
+            0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+            _ if byte < 0x80 => Key::Char(byte as char),
+            _ => self.utf8(byte),
+        })
+    }
+
+    fn escape(&mut self) -> Key {
+        let sequence = match self.byte() {
+            Some(b'[') | Some(b'O') => {
+                let mut sequence = Vec::new();
+                while let Some(byte) = self.byte() {
+                    sequence.push(byte);
+                    if byte.is_ascii_alphabetic() || byte == b'~' {
+                        break;
+                    }
+                }
+                sequence
+            }
+            _ => return Key::Escape,
+        };
+
+        match sequence.as_slice() {
+            b"A" => Key::Up,
+            b"B" => Key::Down,
+            b"C" => Key::Right,
+            b"D" => Key::Left,
+            b"1;5C" => Key::CtrlRight,
+            b"1;5D" => Key::CtrlLeft,
+            b"H" | b"1~" => Key::Home,
+            b"3~" => Key::Delete,
+            b"5~" => Key::PageUp,
+            b"6~" => Key::PageDown,
+            _ => Key::Escape,
+        }
+    }
+
+    fn utf8(&mut self, first: u8) -> Key {
+        let length = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        let mut bytes = vec![first];
+        for _ in 1..length {
+            bytes.extend(self.byte());
+        }
+        String::from_utf8(bytes).ok()
+            .and_then(|text| text.chars().next())
+            .map_or(Key::Escape, Key::Char)
+    }
+}
+
+/// What the screen shows: a window onto the active sheet with one cell selected, whose formula
+/// may be being edited
+pub struct Editor {
+    environment: Environment,
+    path: Option<String>,
+    cursor: CellAddress,
+    /// Top left cell on screen
+    origin: CellAddress,
+    widths: HashMap<(String, i32), usize>,
+    /// Formula being typed into the selected cell
+    editing: Option<String>,
+    message: String,
+    rows: usize,
+    columns: usize,
+}
+
+impl Editor {
+    /// Editor of a workbook on a screen of the given size, saving to `path` if there is one
+    pub fn new(environment: Environment, path: Option<String>, rows: usize, columns: usize) -> Editor {
+        Editor {
+            environment,
+            path,
+            cursor: CellAddress(0, 0),
+            origin: CellAddress(0, 0),
+            widths: HashMap::new(),
+            editing: None,
+            message: String::from("Ctrl-Q quits"),
+            rows,
+            columns,
+        }
+    }
+
+    /// Rows and columns of the screen
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.columns)
+    }
+
+    /// Fits the editor to a resized screen, keeping the selected cell on it
+    pub fn set_size(&mut self, rows: usize, columns: usize) {
+        (self.rows, self.columns) = (rows, columns);
+        self.scroll();
+    }
+
+    fn width(&self, column: i32) -> usize {
+        let key = (self.environment.active_sheet().to_string(), column);
+        self.widths.get(&key).copied().unwrap_or(DEFAULT_WIDTH)
+    }
+
+    fn resize(&mut self, change: isize) {
+        let width = self.width(self.cursor.0).saturating_add_signed(change).clamp(MIN_WIDTH, MAX_WIDTH);
+        self.widths.insert((self.environment.active_sheet().to_string(), self.cursor.0), width);
+    }
+
+    /// Number of grid rows on screen, below the formula bar and column headers and above the
+    /// status line
+    fn visible_rows(&self) -> i32 {
+        self.rows.saturating_sub(3).max(1) as i32
+    }
+
+    fn row_header_width(&self) -> usize {
+        (self.origin.1 + self.visible_rows()).to_string().len().max(3) + 1
+    }
+
+    /// Columns that fit on screen from the leftmost shown, which is always included
+    fn visible_columns(&self) -> Vec<i32> {
+        let mut columns = vec![self.origin.0];
+        let mut used = self.row_header_width() + self.width(self.origin.0) + 1;
+        let mut column = self.origin.0 + 1;
+        while used + self.width(column) < self.columns && CellAddress(column, 0).validate().is_ok() {
+            columns.push(column);
+            used += self.width(column) + 1;
+            column += 1;
+        }
+        columns
+    }
+
+    fn move_by(&mut self, columns: i32, rows: i32) {
+        let moved = CellAddress(self.cursor.0.saturating_add(columns).max(0), self.cursor.1.saturating_add(rows).max(0));
+        if moved.validate().is_ok() {
+            self.cursor = moved;
+        }
+        self.scroll();
+    }
+
+    /// Scrolls so the selected cell is on screen
+    fn scroll(&mut self) {
+        self.origin.1 = self.origin.1.min(self.cursor.1).max(self.cursor.1 - self.visible_rows() + 1);
+        if self.cursor.0 < self.origin.0 {
+            self.origin.0 = self.cursor.0;
+        }
+        while !self.visible_columns().contains(&self.cursor.0) {
+            self.origin.0 += 1;
+        }
+    }
+
+    fn switch_sheet(&mut self, step: isize) {
+        let names: Vec<String> = self.environment.sheet_names().map(String::from).collect();
+        let index = names.iter().position(|name| name == self.environment.active_sheet()).unwrap_or(0);
+        let next = (index as isize + step).rem_euclid(names.len() as isize) as usize;
+        let _ = self.environment.set_active_sheet(&names[next]);
+        self.message = format!("Sheet {}", names[next]);
+    }
+
+    fn formula(&self) -> String {
+        self.environment.get_cell(&self.cursor)
+            .map(|val| write_formula_on(val, self.environment.active_sheet()))
+            .unwrap_or_default()
+    }
+
+    fn store(&mut self, formula: &str) -> bool {
+        let result = if formula.trim().is_empty() {
+            self.environment.clear_cell(&self.cursor);
+            Ok(())
+        } else {
+            parse_formula(formula).and_then(|val| self.environment.set_cell(&self.cursor, val))
+        };
+
+        match result {
+            Ok(()) => {
+                let sheet = self.environment.active_sheet();
+                let checked = self.environment.get_cell(&self.cursor).map(|val| self.environment.check_formula(sheet, val));
+                self.message = match (checked, self.environment.value(&self.cursor)) {
+                    (Some(Err(e)), _) => format!("{}: type error: {e}", self.cursor),
+                    (_, Some(Err(e))) => format!("{}: {e}", self.cursor),
+                    _ => String::new(),
+                };
+                true
+            }
+            Err(e) => {
+                self.message = e;
+                false
+            }
+        }
+    }
+
+    /// Handles a key, returning false to quit
+    pub fn handle(&mut self, key: Key) -> bool {
+        if let Some(mut formula) = self.editing.take() {
+            match key {
+                Key::Enter | Key::Tab => {
+                    if self.store(&formula) {
+                        if key == Key::Tab { self.move_by(1, 0) } else { self.move_by(0, 1) }
+                    } else {
+                        self.editing = Some(formula);
+                    }
+                }
+                Key::Escape => self.message.clear(),
+                Key::Backspace => {
+                    formula.pop();
+                    self.editing = Some(formula);
+                }
+                Key::Char(c) => {
+                    formula.push(c);
+                    self.editing = Some(formula);
+                }
+                _ => self.editing = Some(formula),
+            }
+            return true;
+        }
+
+        match key {
+            Key::Up => self.move_by(0, -1),
+            Key::Down => self.move_by(0, 1),
+            Key::Left => self.move_by(-1, 0),
+            Key::Right | Key::Tab => self.move_by(1, 0),
+            Key::PageUp => self.move_by(0, -self.visible_rows()),
+            Key::PageDown => self.move_by(0, self.visible_rows()),
+            Key::Home => {
+                self.cursor = CellAddress(0, 0);
+                self.scroll();
+            }
+            Key::CtrlLeft => self.resize(-1),
+            Key::CtrlRight => self.resize(1),
+            Key::Enter => self.editing = Some(self.formula()),
+            Key::Char(c) => self.editing = Some(c.to_string()),
+            Key::Delete | Key::Backspace => {
+                self.environment.clear_cell(&self.cursor);
+            }
+            Key::Ctrl('n') => self.switch_sheet(1),
+            Key::Ctrl('p') => self.switch_sheet(-1),
+            Key::Ctrl('z') => {
+                let label = self.environment.undo_label().map(String::from);
+                self.message = match label {
+                    Some(label) if self.environment.undo() => format!("Undid {label}"),
+                    _ => String::from("Nothing to undo"),
+                };
+            }
+            Key::Ctrl('y') => {
+                let label = self.environment.redo_label().map(String::from);
+                self.message = match label {
+                    Some(label) if self.environment.redo() => format!("Redid {label}"),
+                    _ => String::from("Nothing to redo"),
+                };
+            }
+            Key::Ctrl('s') => {
+                self.message = match &self.path {
+                    Some(path) => match write_workbook(&self.environment, path) {
+                        Ok(()) => format!("Saved {path}"),
+                        Err(e) => e,
+                    },
+                    None => String::from("No file to save to; give one on the command line"),
+                };
+            }
+            Key::Ctrl('q') | Key::Ctrl('c') => return false,
+            _ => {}
+        }
+        true
+    }
+
+    /// Escape sequences drawing the whole screen over what was there
+    pub fn render(&self) -> String {
+        let mut out = String::from("\x1b[H");
+        let cell = CellReference::relative(self.cursor);
+
+        // Formula bar
+        let bar = match &self.editing {
+            Some(formula) => format!("{cell} > {formula}\x1b[7m \x1b[0m"),
+            None => format!("{cell} = {}", self.formula()),
+        };
+        out.push_str(&line(&bar, self.columns + if self.editing.is_some() { 8 } else { 0 }));
+
+        // Column headers
+        let columns = self.visible_columns();
+        let header_width = self.row_header_width();
+        let mut header = format!("\x1b[1m{}", " ".repeat(header_width));
+        for column in &columns {
+            let name = column_name(*column).unwrap_or_default();
+            let style = if *column == self.cursor.0 { "\x1b[7m" } else { "" };
+            header.push_str(&format!("{style}{}\x1b[0m\x1b[1m ", center(&name, self.width(*column))));
+        }
+        out.push_str(&header);
+        out.push_str("\x1b[0m\x1b[K\r\n");
+
+        for row in self.origin.1..self.origin.1 + self.visible_rows() {
+            let style = if row == self.cursor.1 { "\x1b[7m" } else { "\x1b[1m" };
+            out.push_str(&format!("{style}{:>width$}\x1b[0m ", row + 1, width = header_width - 1));
+
+            for column in &columns {
+                let adr = CellAddress(*column, row);
+                let width = self.width(*column);
+                let (text, right, error) = match self.environment.value(&adr) {
+                    None => (String::new(), false, false),
+                    Some(Ok(val @ (Primitive::Integer(_) | Primitive::Float(_)))) => (val.to_string(), true, false),
+                    Some(Ok(Primitive::String(text))) => (text, false, false),
+                    Some(Ok(val)) => (val.to_string(), false, false),
+                    Some(Err(e)) => (format!("#ERR {e}"), false, true),
+                };
+
+                let text = fit(&text, width, right);
+                let selected = adr == self.cursor;
+                let style = match (selected, error) {
+                    (true, _) => "\x1b[7m",
+                    (false, true) => "\x1b[41;97m",
+                    (false, false) => "",
+                };
+                out.push_str(&format!("{style}{text}\x1b[0m "));
+            }
+            out.push_str("\x1b[K\r\n");
+        }
+
+        // Status line
+        let status = format!("\x1b[7m {} \x1b[0m {}", self.environment.active_sheet(), self.message);
+        out.push_str(&line(&status, self.columns + 8));
+        out
+    }
+}
+
+/// Text clipped to the screen width and cleared to the end of the line
+fn line(text: &str, width: usize) -> String {
+    format!("{}\x1b[0m\x1b[K\r\n", text.chars().take(width).collect::<String>())
+}
+
+/// Text padded or truncated to exactly `width` characters
+fn fit(text: &str, width: usize, right: bool) -> String {
+    let length = text.chars().count();
+    if length > width {
+        // Numbers that do not fit are hidden rather than shown misleadingly cut short
+        if right {
+            return "#".repeat(width);
+        }
+        let mut clipped: String = text.chars().take(width - 1).collect();
+        clipped.push('…');
+        clipped
+    } else if right {
+        format!("{}{text}", " ".repeat(width - length))
+    } else {
+        format!("{text}{}", " ".repeat(width - length))
+    }
+}
+
+fn center(text: &str, width: usize) -> String {
+    let length = text.chars().count().min(width);
+    let left = (width - length) / 2;
+    format!("{}{}{}", " ".repeat(left), &text[..length], " ".repeat(width - length - left))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Editor of an empty workbook on a screen of 6 rows, which leaves 3 for the grid
+    fn editor() -> Editor {
+        Editor::new(Environment::init(), None, 6, 30)
+    }
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            editor.handle(Key::Char(c));
+        }
+    }
+
+    /// Lines of the screen without escape sequences
+    fn screen(editor: &Editor) -> Vec<String> {
+        let render = editor.render();
+        let mut text = String::new();
+        let mut chars = render.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        text.split_terminator("\r\n").map(String::from).collect()
+    }
+
+    #[test]
+    fn decodes_keys() {
+        let bytes = "a\r\n\t\x7f\x08\x1b[A\x1b[B\x1b[C\x1b[D\x1bOA\x1b[1;5C\x1b[1;5D\x1b[H\x1b[1~\x1b[3~\x1b[5~\x1b[6~\x1b[2~\x11\x1aé€😀\x1b";
+        let mut keys = Keys::new(bytes.as_bytes());
+        let mut decoded = Vec::new();
+        while let Some(key) = keys.next_key() {
+            decoded.push(key);
+        }
+        assert_eq!(decoded, [
+            Key::Char('a'), Key::Enter, Key::Enter, Key::Tab, Key::Backspace, Key::Backspace,
+            Key::Up, Key::Down, Key::Right, Key::Left, Key::Up, Key::CtrlRight, Key::CtrlLeft,
+            Key::Home, Key::Home, Key::Delete, Key::PageUp, Key::PageDown, Key::Escape,
+            Key::Ctrl('q'), Key::Ctrl('z'), Key::Char('é'), Key::Char('€'), Key::Char('😀'), Key::Escape,
+        ]);
+
+        // Bytes that are not UTF-8 are no character
+        let mut keys = Keys::new(&[0xc3, 0x28][..]);
+        assert_eq!(keys.next_key(), Some(Key::Escape));
+    }
+
+    #[test]
+    fn enters_formulas() {
+        let mut editor = editor();
+        type_text(&mut editor, "12");
+        assert_eq!(editor.editing.as_deref(), Some("12"));
+        assert_eq!(editor.environment.value(&CellAddress(0, 0)), None);
+        editor.handle(Key::Enter);
+        assert_eq!(editor.editing.as_deref(), None);
+        assert_eq!(editor.cursor, CellAddress(0, 1));
+
+        type_text(&mut editor, "A1 * 3x");
+        editor.handle(Key::Backspace);
+        editor.handle(Key::Tab);
+        assert_eq!(editor.cursor, CellAddress(1, 1));
+        assert_eq!(editor.environment.value(&CellAddress(0, 1)), Some(Ok(Primitive::Integer(36))));
+
+        // Enter edits the formula already there, and Esc abandons the edit
+        editor.handle(Key::Left);
+        editor.handle(Key::Enter);
+        assert_eq!(editor.editing.as_deref(), Some("A1 * 3"));
+        type_text(&mut editor, " + 1");
+        editor.handle(Key::Escape);
+        assert_eq!(editor.editing.as_deref(), None);
+        assert_eq!(editor.environment.value(&CellAddress(0, 1)), Some(Ok(Primitive::Integer(36))));
+
+        // Storing nothing clears the cell
+        editor.handle(Key::Enter);
+        for _ in 0.."A1 * 3".len() {
+            editor.handle(Key::Backspace);
+        }
+        editor.handle(Key::Enter);
+        assert_eq!(editor.environment.value(&CellAddress(0, 1)), None);
+    }
+
+    #[test]
+    fn keeps_editing_a_formula_that_does_not_parse() {
+        let mut editor = editor();
+        type_text(&mut editor, "1 +");
+        editor.handle(Key::Enter);
+        assert_eq!(editor.editing.as_deref(), Some("1 +"));
+        assert_eq!(editor.cursor, CellAddress(0, 0));
+        assert!(!editor.message.is_empty());
+
+        // Keys other than typing leave the edit alone
+        editor.handle(Key::Down);
+        editor.handle(Key::Ctrl('q'));
+        assert_eq!(editor.editing.as_deref(), Some("1 +"));
+        type_text(&mut editor, " 2");
+        editor.handle(Key::Enter);
+        assert_eq!(editor.environment.value(&CellAddress(0, 0)), Some(Ok(Primitive::Integer(3))));
+        assert_eq!(editor.message, "");
+    }
+
+    #[test]
+    fn reports_errors_of_stored_formulas() {
+        let mut editor = editor();
+        type_text(&mut editor, "1 / 0");
+        editor.handle(Key::Enter);
+        assert_eq!(editor.message, "A1: Division by 0");
+        type_text(&mut editor, "\"x\" + 1");
+        editor.handle(Key::Enter);
+        assert!(editor.message.starts_with("A2: type error: "));
+    }
+
+    #[test]
+    fn moves_within_the_grid() {
+        let mut editor = editor();
+        editor.handle(Key::Up);
+        editor.handle(Key::Left);
+        assert_eq!(editor.cursor, CellAddress(0, 0));
+        editor.handle(Key::PageDown);
+        editor.handle(Key::Right);
+        assert_eq!(editor.cursor, CellAddress(1, 3));
+        editor.handle(Key::PageUp);
+        editor.handle(Key::PageUp);
+        assert_eq!(editor.cursor, CellAddress(1, 0));
+        editor.handle(Key::Down);
+        editor.handle(Key::Home);
+        assert_eq!(editor.cursor, CellAddress(0, 0));
+    }
+
+    #[test]
+    fn clears_cells_and_undoes() {
+        let mut editor = editor();
+        type_text(&mut editor, "5");
+        editor.handle(Key::Enter);
+        editor.handle(Key::Up);
+        editor.handle(Key::Delete);
+        assert_eq!(editor.environment.value(&CellAddress(0, 0)), None);
+
+        editor.handle(Key::Ctrl('z'));
+        assert_eq!(editor.message, "Undid Clear A1");
+        assert_eq!(editor.environment.value(&CellAddress(0, 0)), Some(Ok(Primitive::Integer(5))));
+        editor.handle(Key::Ctrl('y'));
+        assert_eq!(editor.message, "Redid Clear A1");
+        editor.handle(Key::Ctrl('y'));
+        assert_eq!(editor.message, "Nothing to redo");
+        editor.handle(Key::Ctrl('z'));
+        editor.handle(Key::Ctrl('z'));
+        editor.handle(Key::Ctrl('z'));
+        assert_eq!(editor.message, "Nothing to undo");
+    }
+
+    #[test]
+    fn switches_sheets_and_quits() {
+        let mut environment = Environment::init();
+        environment.add_sheet("Data").unwrap();
+        let mut editor = Editor::new(environment, None, 6, 30);
+        editor.handle(Key::Ctrl('n'));
+        assert_eq!((editor.environment.active_sheet(), editor.message.as_str()), ("Data", "Sheet Data"));
+        editor.handle(Key::Ctrl('n'));
+        assert_eq!(editor.environment.active_sheet(), "Sheet1");
+        editor.handle(Key::Ctrl('p'));
+        assert_eq!(editor.environment.active_sheet(), "Data");
+
+        editor.handle(Key::Ctrl('s'));
+        assert_eq!(editor.message, "No file to save to; give one on the command line");
+        assert!(editor.handle(Key::Ctrl('x')));
+        assert!(!editor.handle(Key::Ctrl('q')));
+        assert!(!editor.handle(Key::Ctrl('c')));
+    }
+
+    #[test]
+    fn draws_the_visible_region() {
+        let mut editor = editor();
+        for text in ["12", "\"long text here\"", "1 / 0", "-2000000000"] {
+            type_text(&mut editor, text);
+            editor.handle(Key::Tab);
+        }
+        editor.handle(Key::Home);
+        assert_eq!(screen(&editor), [
+            "A1 = 12",
+            "        A          B      ",
+            "  1         12 long text… ",
+            "  2                       ",
+            "  3                       ",
+            " Sheet1  ",
+        ]);
+
+        // The selection scrolls into view
+        editor.handle(Key::Right);
+        editor.handle(Key::Right);
+        editor.handle(Key::Right);
+        assert_eq!(screen(&editor)[1..3], ["        C          D      ", "  1 #ERR Divi… ########## "]);
+        editor.handle(Key::CtrlRight);
+        editor.handle(Key::CtrlRight);
+        assert_eq!(screen(&editor)[2], "  1 #ERR Divi…  -2000000000 ");
+
+        editor.handle(Key::Home);
+        for _ in 0..4 {
+            editor.handle(Key::Down);
+        }
+        type_text(&mut editor, "x");
+        assert_eq!(screen(&editor)[..3], ["A5 > x ", "        A          B      ", "  3                       "]);
+    }
+
+    #[test]
+    fn fits_a_resized_screen() {
+        let mut editor = editor();
+        for _ in 0..10 {
+            editor.handle(Key::Down);
+        }
+        assert_eq!(screen(&editor)[2..5], ["  9                       ", " 10                       ", " 11                       "]);
+        editor.set_size(4, 20);
+        assert_eq!(editor.size(), (4, 20));
+        assert_eq!(screen(&editor), ["A11 = ", "        A      ", " 11            ", " Sheet1  Ctrl-Q quit"]);
+    }
+}
@@ -0,0 +1,116 @@
+//! `gridkid-tui`: a full-screen spreadsheet editor for the terminal. It drives the terminal with
+//! ANSI escape sequences and `stty`, so it runs on Unix-like systems and needs nothing beyond the
+//! standard library.
+//!
+//! Arrow keys, Page Up/Down and Home move around; typing starts editing the selected cell and
+//! Enter edits its formula. While editing, Enter or Tab stores the formula and Esc abandons it.
+//! Delete clears a cell, Ctrl-Left/Right narrow and widen a column, Ctrl-N/Ctrl-P switch sheets,
+//! Ctrl-Z/Ctrl-Y undo and redo, Ctrl-S saves to the file given on the command line and Ctrl-Q
+//! quits.
+
+mod editor;
+
+use std::io::{self, Write};
+use std::process::{Command, ExitCode, Stdio};
+
+use editor::{Editor, Keys};
+use gridkid::environment::Environment;
+use gridkid::format::read_workbook;
+
+/// Puts the terminal into raw mode on the alternate screen, restoring it when dropped
+struct Terminal {
+    settings: String,
+}
+
+impl Terminal {
+    fn open() -> Result<Terminal, String> {
+        let settings = stty(&["-g"])?;
+        // Reads give up after a tenth of a second, which tells a lone Esc from an escape sequence
+        stty(&["raw", "-echo", "min", "0", "time", "1"])?;
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = io::stdout().flush();
+        Ok(Terminal { settings: settings.trim().to_string() })
+    }
+
+    /// Rows and columns of the terminal
+    fn size(&self) -> (usize, usize) {
+        stty(&["size"]).ok()
+            .and_then(|size| {
+                let (rows, columns) = size.trim().split_once(' ')?;
+                Some((rows.parse().ok()?, columns.parse().ok()?))
+            })
+            .unwrap_or((24, 80))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[self.settings.as_str()]);
+    }
+}
+
+fn stty(arguments: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(arguments)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Cannot run stty: {e}"))?;
+    if !output.status.success() {
+        return Err(String::from("stty failed; is this a terminal?"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn main() -> ExitCode {
+    let path = std::env::args().nth(1);
+    let environment = match &path {
+        Some(path) if std::path::Path::new(path).exists() => match read_workbook(path) {
+            Ok(environment) => environment,
+            Err(e) => {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => Environment::init(),
+    };
+
+    let terminal = match Terminal::open() {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (rows, columns) = terminal.size();
+    let mut editor = Editor::new(environment, path, rows, columns);
+    let mut keys = Keys::new(io::stdin());
+    let mut idle = 0;
+
+    print!("\x1b[2J{}", editor.render());
+    let _ = io::stdout().flush();
+    loop {
+        let Some(key) = keys.next_key() else {
+            // Notice the terminal being resized about once a second
+            idle += 1;
+            if idle % 10 == 0 && terminal.size() != editor.size() {
+                let (rows, columns) = terminal.size();
+                editor.set_size(rows, columns);
+                print!("\x1b[2J{}", editor.render());
+                let _ = io::stdout().flush();
+            }
+            continue;
+        };
+
+        if !editor.handle(key) {
+            break;
+        }
+        print!("{}", editor.render());
+        let _ = io::stdout().flush();
+    }
+
+    drop(terminal);
+    ExitCode::SUCCESS
+}
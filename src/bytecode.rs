@@ -0,0 +1,176 @@
+//! Formulas lowered to a flat sequence of instructions for a small stack machine, which is
+//! quicker to run than walking the formula tree. A program gives the same values and the same
+//! errors as `Evaluatable::evaluate` on the formula it was compiled from.
+//!
+//! Every operator fails with the first error among its operands, so the result of a formula is
+//! the first error met in evaluation order, if any. The machine stops there rather than carrying
+//! errors up the stack.
+
+use std::fmt;
+
+use crate::environment::Environment;
+use crate::simplify::simplify;
+use crate::model::{apply_values, CellValue, Evaluatable, Expression, NamedValue, Operator, Primitive, Statistics, Tolerance};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Instruction {
+    /// Pushes a constant
+    Push(Primitive),
+    /// Pushes the value of a cell
+    Cell(CellValue),
+    /// Pushes the result of a function over a range
+    Statistics(Statistics),
+    /// Pushes the value of a name
+    Name(NamedValue),
+    /// Pushes the error of a reference to a deleted cell
+    Invalid,
+    /// Pops the operands of an operator, the last one on top, and pushes its result
+    Apply(Operator),
+    /// Pops the first operand of a binary operator whose second is a constant
+    ApplyConstant(Operator, Primitive),
+}
+
+/// Compiled formula, its operands ahead of the operators that use them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    code: Vec<Instruction>,
+    /// Most results on the stack at once
+    depth: usize,
+}
+
+impl Program {
+    /// Program for a formula, with its operations on constants worked out ahead of time
+    pub fn compile(expression: &dyn Evaluatable) -> Program {
+        let mut program = Program { code: Vec::new(), depth: 0 };
+        program.emit(simplify(expression).as_ref(), 0);
+        program
+    }
+
+    /// Appends the instructions for an expression whose result lands `height` results up the stack
+    fn emit(&mut self, expression: &dyn Evaluatable, height: usize) {
+        self.depth = self.depth.max(height + 1);
+        let instruction = match expression.expression() {
+            Expression::Primitive(val) => Instruction::Push(val.clone()),
+            Expression::CellValue(cell) => Instruction::Cell(cell.clone()),
+            Expression::Statistics(statistics) => Instruction::Statistics(statistics.clone()),
+            Expression::NamedValue(value) => Instruction::Name(value.clone()),
+            Expression::InvalidReference(_) => Instruction::Invalid,
+            Expression::Operation(operation) => {
+                let (val1, val2) = operation.operands();
+                self.emit(val1, height);
+                match val2.map(|val2| (val2, val2.expression())) {
+                    Some((_, Expression::Primitive(val2))) => Instruction::ApplyConstant(operation.operator(), val2.clone()),
+                    Some((val2, _)) => {
+                        self.emit(val2, height + 1);
+                        Instruction::Apply(operation.operator())
+                    }
+                    None => Instruction::Apply(operation.operator()),
+                }
+            }
+        };
+        self.code.push(instruction);
+    }
+
+    /// Number of instructions
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    pub fn evaluate(&self, environment: &Environment) -> Result<Primitive, String> {
+        self.evaluate_with(environment, &mut Vec::with_capacity(self.depth))
+    }
+
+    /// Evaluates using the given stack, which saves allocating one for each of many programs.
+    /// The stack is left empty.
+    pub fn evaluate_with(&self, environment: &Environment, stack: &mut Vec<Primitive>) -> Result<Primitive, String> {
+        let result = self.run(environment, stack);
+        stack.clear();
+        result
+    }
+
+    fn run(&self, environment: &Environment, stack: &mut Vec<Primitive>) -> Result<Primitive, String> {
+        for instruction in &self.code {
+            let val = match instruction {
+                Instruction::Push(val) => val.clone(),
+                Instruction::Cell(cell) => cell.evaluate(environment)?,
+                Instruction::Statistics(statistics) => statistics.evaluate(environment)?,
+                Instruction::Name(value) => value.evaluate(environment)?,
+                Instruction::Invalid => return Err(String::from("#REF!")),
+                Instruction::Apply(operator) => {
+                    let val2 = if operator.is_unary() { None } else { stack.pop() };
+                    let val1 = stack.pop().expect("operands are pushed before their operator");
+                    match val2 {
+                        Some(val2) => binary(*operator, val1, &val2, environment.tolerance())?,
+                        None => apply_values(*operator, val1, None, environment.tolerance())?,
+                    }
+                }
+                Instruction::ApplyConstant(operator, val2) => {
+                    let val1 = stack.pop().expect("operands are pushed before their operator");
+                    binary(*operator, val1, val2, environment.tolerance())?
+                }
+            };
+            stack.push(val);
+        }
+        Ok(stack.pop().expect("a program leaves its result on the stack"))
+    }
+}
+
+/// Result of a binary operator. Sums, differences and products of numbers are worked out in
+/// place, the same way `Operation::evaluate` works them out; everything else, integer overflow
+/// included, goes through it.
+fn binary(operator: Operator, val1: Primitive, val2: &Primitive, tolerance: Tolerance) -> Result<Primitive, String> {
+    let result = match (operator, &val1, val2) {
+        (Operator::Add | Operator::Subtract | Operator::Multiply, Primitive::Integer(v1), Primitive::Integer(v2)) => {
+            let result = match operator {
+                Operator::Add => v1.checked_add(*v2),
+                Operator::Subtract => v1.checked_sub(*v2),
+                _ => v1.checked_mul(*v2),
+            };
+            match result {
+                Some(result) => Primitive::Integer(result),
+                None => return apply_values(operator, val1, Some(val2.clone()), tolerance),
+            }
+        }
+        (Operator::Add | Operator::Subtract | Operator::Multiply, _, _) => match (float(&val1), float(val2)) {
+            (Some(v1), Some(v2)) => Primitive::Float(match operator {
+                Operator::Add => v1 + v2,
+                Operator::Subtract => v1 - v2,
+                _ => v1 * v2,
+            }),
+            _ => return apply_values(operator, val1, Some(val2.clone()), tolerance),
+        },
+        _ => return apply_values(operator, val1, Some(val2.clone()), tolerance),
+    };
+    Ok(result)
+}
+
+/// Value of a number as a float, as arithmetic on an integer and a float takes it
+fn float(val: &Primitive) -> Option<f32> {
+    match val {
+        Primitive::Integer(val) => Some(*val as f32),
+        Primitive::Float(val) => Some(*val),
+        _ => None,
+    }
+}
+
+/// One instruction per line, such as `cell A1`, `push 2` and `apply Multiply`
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for instruction in &self.code {
+            match instruction {
+                Instruction::Push(val) => writeln!(f, "push {}", val)?,
+                Instruction::Cell(cell) => writeln!(f, "cell {}", cell)?,
+                Instruction::Statistics(statistics) => writeln!(f, "call {}", statistics)?,
+                Instruction::Name(value) => writeln!(f, "name {}", value)?,
+                Instruction::Invalid => writeln!(f, "invalid")?,
+                Instruction::Apply(operator) => writeln!(f, "apply {:?}", operator)?,
+                Instruction::ApplyConstant(operator, val) => writeln!(f, "apply {:?} {}", operator, val)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Numbers typed into the cells of a sheet, kept column by column in dense arrays. `Sum`, `Mean`,
+//! `Max` and `Min` add up the slices of a range at once instead of reading its numbers cell by
+//! cell; only the other cells, such as formulas, are evaluated one at a time.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::address::CellRange;
+use crate::grid::Grid;
+use crate::model::{CellAddress, Evaluatable, Expression, Primitive};
+
+#[derive(Default)]
+pub(crate) struct Columns {
+    columns: BTreeMap<i32, Column>,
+    /// Populated cells holding anything but a number, such as formulas and text
+    others: Grid<()>,
+}
+
+#[derive(Default)]
+struct Column {
+    integers: Run<i64>,
+    floats: Run<f64>,
+}
+
+/// Numbers of one type in a column, ordered by row
+struct Run<T> {
+    rows: Vec<i32>,
+    values: Vec<T>,
+}
+
+impl<T> Default for Run<T> {
+    fn default() -> Run<T> {
+        Run { rows: Vec::new(), values: Vec::new() }
+    }
+}
+
+impl<T> Run<T> {
+    fn insert(&mut self, row: i32, val: T) {
+        match self.rows.binary_search(&row) {
+            Ok(index) => self.values[index] = val,
+            Err(index) => {
+                self.rows.insert(index, row);
+                self.values.insert(index, val);
+            }
+        }
+    }
+
+    fn remove(&mut self, row: i32) {
+        if let Ok(index) = self.rows.binary_search(&row) {
+            self.rows.remove(index);
+            self.values.remove(index);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Rows and numbers within the given rows
+    fn within(&self, rows: &RangeInclusive<i32>) -> (&[i32], &[T]) {
+        let start = self.rows.partition_point(|row| row < rows.start());
+        let end = self.rows.partition_point(|row| row <= rows.end());
+        (&self.rows[start..end], &self.values[start..end])
+    }
+}
+
+impl Columns {
+    pub fn new() -> Columns {
+        Columns::default()
+    }
+
+    /// Columns of the given cells
+    pub fn of<'c>(cells: impl Iterator<Item = (&'c CellAddress, &'c dyn Evaluatable)>) -> Columns {
+        let mut columns = Columns::new();
+        for (adr, val) in cells {
+            columns.set(*adr, Some(val));
+        }
+        columns
+    }
+
+    /// Records what a cell now holds, or that it was cleared
+    pub fn set(&mut self, adr: CellAddress, val: Option<&dyn Evaluatable>) {
+        let CellAddress(column, row) = adr;
+        self.others.remove(&adr);
+        if let Some(numbers) = self.columns.get_mut(&column) {
+            numbers.integers.remove(row);
+            numbers.floats.remove(row);
+            if numbers.integers.is_empty() && numbers.floats.is_empty() {
+                self.columns.remove(&column);
+            }
+        }
+
+        let Some(val) = val else { return };
+        match val.expression() {
+            Expression::Primitive(Primitive::Integer(number)) => self.columns.entry(column).or_default().integers.insert(row, i64::from(*number)),
+            Expression::Primitive(Primitive::Float(number)) => self.columns.entry(column).or_default().floats.insert(row, f64::from(*number)),
+            _ => {
+                self.others.insert(adr, ());
+            }
+        }
+    }
+
+    /// Cells of a range that hold something other than a number, row by row
+    pub fn others_in(&self, range: &CellRange) -> impl Iterator<Item = &CellAddress> {
+        self.others.range(range.columns(), range.rows()).map(|(adr, _)| adr)
+    }
+
+    /// Adds the numbers of a range to a tally
+    pub fn tally(&self, range: &CellRange, tally: &mut Tally) {
+        let rows = range.rows();
+        for numbers in self.columns.range(range.columns()).map(|(_, numbers)| numbers) {
+            let (_, integers) = numbers.integers.within(&rows);
+            tally.count += integers.len();
+            tally.integers += integers.iter().sum::<i64>();
+            for number in [integers.iter().max(), integers.iter().min()].into_iter().flatten() {
+                tally.extremes(*number as f64);
+            }
+
+            let (_, floats) = numbers.floats.within(&rows);
+            tally.count += floats.len();
+            tally.floats += floats.iter().sum::<f64>();
+            for number in [floats.iter().copied().reduce(f64::max), floats.iter().copied().reduce(f64::min)].into_iter().flatten() {
+                tally.extremes(number);
+            }
+        }
+    }
+
+    /// First number of a range, row by row, for which `pick` holds, with its address
+    pub fn first(&self, range: &CellRange, pick: impl Fn(f64) -> bool) -> Option<(CellAddress, Primitive)> {
+        let rows = range.rows();
+        let mut first: Option<(CellAddress, Primitive)> = None;
+        for (column, numbers) in self.columns.range(range.columns()) {
+            let (at, integers) = numbers.integers.within(&rows);
+            let integer = integers.iter().position(|number| pick(*number as f64))
+                .map(|index| (CellAddress(*column, at[index]), Primitive::Integer(integers[index] as i32)));
+            let (at, floats) = numbers.floats.within(&rows);
+            let float = floats.iter().position(|number| pick(*number))
+                .map(|index| (CellAddress(*column, at[index]), Primitive::Float(floats[index] as f32)));
+
+            for found in [integer, float].into_iter().flatten() {
+                if first.as_ref().is_none_or(|(adr, _)| (found.0.1, found.0.0) < (adr.1, adr.0)) {
+                    first = Some(found);
+                }
+            }
+        }
+        first
+    }
+}
+
+/// Running count, total and extremes of the numbers of a range
+#[derive(Default)]
+pub(crate) struct Tally {
+    count: usize,
+    integers: i64,
+    floats: f64,
+    max: Option<f64>,
+    min: Option<f64>,
+}
+
+impl Tally {
+    /// Counts a number found in a cell
+    pub fn add(&mut self, val: &Primitive) {
+        let number = match val {
+            Primitive::Integer(number) => {
+                self.integers += i64::from(*number);
+                f64::from(*number)
+            }
+            Primitive::Float(number) => {
+                self.floats += f64::from(*number);
+                f64::from(*number)
+            }
+            _ => return,
+        };
+        self.count += 1;
+        self.extremes(number);
+    }
+
+    fn extremes(&mut self, number: f64) {
+        // `f64::max` and `f64::min` pass over NaN
+        self.max = Some(self.max.map_or(number, |max| max.max(number)));
+        self.min = Some(self.min.map_or(number, |min| min.min(number)));
+    }
+
+    pub fn sum(&self) -> f32 {
+        (self.integers as f64 + self.floats) as f32
+    }
+
+    /// Mean of the numbers, or `None` if there were none
+    pub fn mean(&self) -> Option<f32> {
+        (self.count > 0).then(|| ((self.integers as f64 + self.floats) / self.count as f64) as f32)
+    }
+
+    /// Largest number, or `None` if there were none
+    pub fn max(&self) -> Option<f64> {
+        self.max
+    }
+
+    /// Smallest number, or `None` if there were none
+    pub fn min(&self) -> Option<f64> {
+        self.min
+    }
+}
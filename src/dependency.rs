@@ -0,0 +1,257 @@
+//! Which cells a formula reads, and the order to calculate a workbook in so that every cell comes
+//! after the cells it reads. Cells are identified by references naming their sheet.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::address::CellReference;
+use crate::environment::{Definition, Environment};
+use crate::model::{CellAddress, Evaluatable, Expression};
+
+/// Cells of a workbook in calculation order, along with the cells that cannot be calculated
+pub(crate) struct CalculationOrder {
+    pub cells: Vec<CellReference>,
+    /// For each cell, one more than the highest level of the cells it reads, or 0 if it reads
+    /// none. Cells on the same level do not read each other.
+    pub levels: Vec<usize>,
+    /// Cells on a circular reference or using a name defined in terms of itself, with the reason
+    pub errors: Vec<(CellReference, String)>,
+}
+
+/// Populated cells a formula on the given sheet reads, directly or through names. Fails when a
+/// name is defined in terms of itself, since such a formula cannot be evaluated.
+pub(crate) fn precedents(environment: &Environment, sheet: &str, val: &dyn Evaluatable) -> Result<Vec<CellReference>, String> {
+    let mut cells = Vec::new();
+    collect(environment, sheet, val, &mut Vec::new(), &mut cells)?;
+    Ok(cells)
+}
+
+fn collect(environment: &Environment, sheet: &str, val: &dyn Evaluatable, names: &mut Vec<(String, String)>,
+           cells: &mut Vec<CellReference>) -> Result<(), String> {
+    match val.expression() {
+        Expression::Primitive(_) | Expression::InvalidReference(_) => {}
+        Expression::CellValue(cell) => {
+            let name = cell.0.sheet.as_deref().unwrap_or(sheet);
+            if environment.get_cell_on(name, &cell.0.address).is_some() {
+                cells.push(CellReference::relative(cell.0.address).on_sheet(name));
+            }
+        }
+        Expression::Statistics(statistics) => {
+            let range = statistics.range();
+            for name in environment.spanned_sheets(range).unwrap_or_default() {
+                cells.extend(environment.cells_in(name, range)
+                    .map(|(adr, _)| CellReference::relative(*adr).on_sheet(name)));
+            }
+        }
+        Expression::NamedValue(value) => {
+            let scope = value.sheet.as_deref().unwrap_or(sheet).to_string();
+            let key = (value.name.clone(), scope);
+            if names.contains(&key) {
+                return Err(format!("Name {} is defined in terms of itself", value.name));
+            }
+
+            // Undefined names and names of ranges fail when evaluated and read nothing
+            match environment.lookup_name(&value.name, Some(&key.1)) {
+                Ok(Definition::Cell(reference)) => {
+                    let name = reference.sheet.as_deref().unwrap_or(&key.1);
+                    if environment.get_cell_on(name, &reference.address).is_some() {
+                        cells.push(CellReference::relative(reference.address).on_sheet(name));
+                    }
+                }
+                Ok(Definition::Constant(constant)) => {
+                    names.push(key);
+                    let result = collect(environment, sheet, constant.as_ref(), names, cells);
+                    names.pop();
+                    result?;
+                }
+                Ok(Definition::Range(_)) | Err(_) => {}
+            }
+        }
+        Expression::Operation(operation) => {
+            let (val1, val2) = operation.operands();
+            collect(environment, sheet, val1, names, cells)?;
+            if let Some(val2) = val2 {
+                collect(environment, sheet, val2, names, cells)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cells that read each populated cell of a workbook, directly or through names, listed sheet by
+/// sheet and row by row. Cells whose names are defined in terms of themselves read nothing.
+pub(crate) fn dependents_map(environment: &Environment) -> HashMap<CellReference, Vec<CellReference>> {
+    let mut dependents: HashMap<CellReference, Vec<CellReference>> = HashMap::new();
+    for sheet in environment.sheet_names() {
+        for (adr, val) in sorted_cells(environment, sheet) {
+            let cell = CellReference::relative(adr).on_sheet(sheet);
+            for precedent in precedents(environment, sheet, val).unwrap_or_default() {
+                let readers = dependents.entry(precedent).or_default();
+                if readers.last() != Some(&cell) {
+                    readers.push(cell.clone());
+                }
+            }
+        }
+    }
+    dependents
+}
+
+/// Empty cells a formula on the given sheet refers to, directly or through names. Ranges are left
+/// out, since they may well have gaps.
+pub(crate) fn empty_references(environment: &Environment, sheet: &str, val: &dyn Evaluatable) -> Vec<CellReference> {
+    let mut cells = Vec::new();
+    collect_empty(environment, sheet, val, &mut Vec::new(), &mut cells);
+    cells
+}
+
+fn collect_empty(environment: &Environment, sheet: &str, val: &dyn Evaluatable, names: &mut Vec<(String, String)>,
+                 cells: &mut Vec<CellReference>) {
+    let mut push = |reference: &CellReference, scope: &str| {
+        let name = reference.sheet.as_deref().unwrap_or(scope);
+        let cell = CellReference::relative(reference.address).on_sheet(name);
+        // A missing sheet is an error of its own rather than an empty cell
+        if environment.sheet_names().any(|sheet| sheet == name) && environment.get_cell_on(name, &reference.address).is_none() && !cells.contains(&cell) {
+            cells.push(cell);
+        }
+    };
+
+    match val.expression() {
+        Expression::Primitive(_) | Expression::InvalidReference(_) | Expression::Statistics(_) => {}
+        Expression::CellValue(cell) => push(&cell.0, sheet),
+        Expression::NamedValue(value) => {
+            let key = (value.name.clone(), value.sheet.as_deref().unwrap_or(sheet).to_string());
+            if names.contains(&key) {
+                return;
+            }
+            match environment.lookup_name(&value.name, Some(&key.1)) {
+                Ok(Definition::Cell(reference)) => push(reference, &key.1),
+                Ok(Definition::Constant(constant)) => {
+                    names.push(key);
+                    collect_empty(environment, sheet, constant.as_ref(), names, cells);
+                    names.pop();
+                }
+                Ok(Definition::Range(_)) | Err(_) => {}
+            }
+        }
+        Expression::Operation(operation) => {
+            let (val1, val2) = operation.operands();
+            collect_empty(environment, sheet, val1, names, cells);
+            if let Some(val2) = val2 {
+                collect_empty(environment, sheet, val2, names, cells);
+            }
+        }
+    }
+}
+
+/// Cells reachable from a cell by following `next`, breadth first so that the nearest come first.
+/// The cell itself is left out, even when it can be reached again through a circular reference.
+pub(crate) fn reachable(start: CellReference, mut next: impl FnMut(&CellReference) -> Result<Vec<CellReference>, String>)
+    -> Result<Vec<CellReference>, String> {
+    let mut seen = HashSet::from([start.clone()]);
+    let mut queue = VecDeque::from([start]);
+    let mut cells = Vec::new();
+    while let Some(cell) = queue.pop_front() {
+        for neighbour in next(&cell)? {
+            if seen.insert(neighbour.clone()) {
+                cells.push(neighbour.clone());
+                queue.push_back(neighbour);
+            }
+        }
+    }
+    Ok(cells)
+}
+
+/// Populated cells of a sheet, row by row
+pub(crate) fn sorted_cells<'e>(environment: &'e Environment, sheet: &str) -> Vec<(CellAddress, &'e dyn Evaluatable)> {
+    environment.cells_on(sheet).map(|(adr, val)| (*adr, val)).collect()
+}
+
+/// Orders every populated cell after the cells it reads. Cells on a circular reference are
+/// reported as errors, and are still listed so that cells reading them can be ordered.
+pub(crate) fn calculation_order(environment: &Environment) -> CalculationOrder {
+    let mut roots = Vec::new();
+    for sheet in environment.sheet_names() {
+        // Sorted so that the same workbook always reports the same cycle
+        roots.extend(sorted_cells(environment, sheet).into_iter().map(|(adr, _)| CellReference::relative(adr).on_sheet(sheet)));
+    }
+    order(environment, roots, None)
+}
+
+/// Orders the populated cells among `cells` as `calculation_order` does, taking the cells they
+/// read outside of them as already calculated. Cells come in the order they would in the whole
+/// workbook, so they are given the same errors.
+pub(crate) fn partial_calculation_order(environment: &Environment, cells: &HashSet<CellReference>) -> CalculationOrder {
+    let sheets: Vec<&str> = environment.sheet_names().collect();
+    let mut roots: Vec<CellReference> = cells.iter()
+        .filter(|cell| environment.get_cell_on(cell.sheet.as_deref().unwrap_or_default(), &cell.address).is_some())
+        .cloned()
+        .collect();
+    roots.sort_by_key(|cell| (sheets.iter().position(|sheet| Some(*sheet) == cell.sheet.as_deref()), cell.address.1, cell.address.0));
+    order(environment, roots, Some(cells))
+}
+
+fn order(environment: &Environment, roots: Vec<CellReference>, within: Option<&HashSet<CellReference>>) -> CalculationOrder {
+    let mut order = CalculationOrder { cells: Vec::new(), levels: Vec::new(), errors: Vec::new() };
+    // Cells being visited map to `None`, finished ones to their level
+    let mut finished: HashMap<CellReference, Option<usize>> = HashMap::new();
+
+    let precedents_of = |cell: &CellReference, errors: &mut Vec<(CellReference, String)>| {
+        let sheet = cell.sheet.as_deref().unwrap_or_default();
+        let val = environment.get_cell_on(sheet, &cell.address).expect("ordered cells are populated");
+        let mut cells = precedents(environment, sheet, val).unwrap_or_else(|e| {
+            errors.push((cell.clone(), e));
+            Vec::new()
+        });
+        if let Some(within) = within {
+            cells.retain(|cell| within.contains(cell));
+        }
+        cells
+    };
+
+    for root in roots {
+        if finished.contains_key(&root) {
+            continue;
+        }
+
+        finished.insert(root.clone(), None);
+        let precedents = precedents_of(&root, &mut order.errors);
+        let mut stack = vec![(root, precedents, 0)];
+
+        while let Some((cell, precedents, next)) = stack.last_mut() {
+            let Some(precedent) = precedents.get(*next).cloned() else {
+                // Cells on a circular reference are still being visited, and have no level
+                let level = precedents.iter()
+                    .filter_map(|precedent| finished.get(precedent).copied().flatten())
+                    .map(|level| level + 1)
+                    .max()
+                    .unwrap_or(0);
+                finished.insert(cell.clone(), Some(level));
+                order.cells.push(cell.clone());
+                order.levels.push(level);
+                stack.pop();
+                continue;
+            };
+            *next += 1;
+
+            match finished.get(&precedent) {
+                Some(Some(_)) => {}
+                Some(None) => {
+                    let start = stack.iter().position(|(cell, _, _)| *cell == precedent).expect("visiting cells are stacked");
+                    let cycle: Vec<String> = stack[start..].iter()
+                        .map(|(cell, _, _)| cell.to_string())
+                        .chain(std::iter::once(precedent.to_string()))
+                        .collect();
+                    let error = format!("Circular reference: {}", cycle.join(" -> "));
+                    for (cell, _, _) in &stack[start..] {
+                        order.errors.push((cell.clone(), error.clone()));
+                    }
+                }
+                None => {
+                    finished.insert(precedent.clone(), None);
+                    let precedents = precedents_of(&precedent, &mut order.errors);
+                    stack.push((precedent, precedents, 0));
+                }
+            }
+        }
+    }
+    order
+}
@@ -1,20 +1,1451 @@
-use std::collections::HashMap;
-use crate::model::{CellAddress, Evaluatable};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
+use crate::address::{validate_name, validate_sheet_name, CellRange, CellReference, MAX_COLUMN, MAX_ROW};
+use crate::bytecode::Program;
+use crate::columns::Columns;
+use crate::dependency::{calculation_order, dependents_map, empty_references, partial_calculation_order, precedents, reachable, sorted_cells, CalculationOrder};
+use crate::grid::Grid;
+use crate::events::{Event, SubscriptionId, Subscribers};
+use crate::history::{Group, History, DEFAULT_HISTORY_LIMIT};
+use crate::model::{map_references, rewrite_references, CellAddress, Evaluatable, Expression, InvalidReference, NamedValue, Primitive, ReferenceRewrite, Tolerance};
+use crate::types::{infer_type, AnyScope, Type, TypeScope, Types};
 
+/// Fewest independent cells worth handing to each thread during recalculation
+const PARALLEL_LEVEL: usize = 256;
+
+thread_local! {
+    /// Cells whose formulas this thread is working out outside a recalculation, so that a
+    /// circular reference made in a transaction gives an error rather than endless recursion
+    static EVALUATING: RefCell<Vec<CellReference>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Named grid of cells, along with the names defined only for it
+struct Sheet {
+    name: String,
+    map: Grid<Box<dyn Evaluatable>>,
+    /// Numbers typed into the cells, column by column, kept in step with `map`
+    columns: Columns,
+    names: HashMap<String, Definition>,
+    /// Values of the cells as of the last recalculation
+    values: HashMap<CellAddress, Result<Primitive, String>>,
+    /// Compiled formulas of the cells, made as they are first calculated
+    programs: HashMap<CellAddress, Program>,
+}
+
+impl Sheet {
+    fn new(name: &str) -> Sheet {
+        Sheet::with_cells(name, Grid::new(), HashMap::new())
+    }
+
+    fn with_cells(name: &str, map: Grid<Box<dyn Evaluatable>>, names: HashMap<String, Definition>) -> Sheet {
+        let columns = Columns::of(map.iter().map(|(adr, val)| (adr, val.as_ref())));
+        Sheet { name: name.to_string(), map, columns, names, values: HashMap::new(), programs: HashMap::new() }
+    }
+}
+
+/// Where a name can be used
+#[derive(Clone, Debug, PartialEq)]
+pub enum NameScope {
+    Workbook,
+    Sheet(String),
+}
+
+/// What setting a cell does with a formula that can never evaluate without a type error
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TypeCheck {
+    /// Store it anyway, leaving `check_formula` to tell users about it
+    #[default]
+    Allow,
+    /// Refuse to store it, returning the type error
+    Reject,
+}
+
+/// What a name stands for. References are kept up to date as rows, columns and sheets move, and
+/// a name whose cells are deleted becomes `#REF!`.
+pub enum Definition {
+    Cell(CellReference),
+    Range(CellRange),
+    Constant(Box<dyn Evaluatable>),
+}
+
+impl Definition {
+    /// The cells the name covers, if it names any
+    pub fn range(&self) -> Option<CellRange> {
+        match self {
+            Definition::Cell(reference) => Some(CellRange { start: reference.clone(), end: reference.clone() }),
+            Definition::Range(range) => Some(range.clone()),
+            Definition::Constant(_) => None,
+        }
+    }
+
+    fn bind(&self, sheet: &str) -> Definition {
+        self.rewrite(&mut BindSheet(sheet)).expect("binding references to a sheet cannot fail")
+    }
+
+    fn rewrite(&self, rewrite: &mut dyn ReferenceRewrite) -> Result<Definition, String> {
+        Ok(match self {
+            Definition::Cell(reference) => match rewrite.cell(reference)? {
+                Some(reference) => Definition::Cell(reference),
+                None => Definition::Constant(Box::new(InvalidReference)),
+            },
+            Definition::Range(range) => match rewrite.range(range)? {
+                Some(range) => Definition::Range(range),
+                None => Definition::Constant(Box::new(InvalidReference)),
+            },
+            Definition::Constant(val) => Definition::Constant(rewrite_references(val.as_ref(), rewrite)?),
+        })
+    }
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Definition::Cell(reference) => write!(f, "{}", reference),
+            Definition::Range(range) => write!(f, "{}", range),
+            Definition::Constant(val) => write!(f, "{}", val.to_string()),
+        }
+    }
+}
+
+/// Workbook of named sheets, one of which is active. Methods taking a bare `CellAddress` work on
+/// the active sheet. References that do not name a sheet are bound to the sheet of the cell
+/// holding them when the cell is set, so formulas keep pointing at the same cells whichever sheet
+/// is active later.
+///
+/// Every change to cells, sheets and names can be undone. Changes made between `begin_group` and
+/// `end_group` are undone as one step; choosing the active sheet is not a change.
+///
+/// Cell values are recalculated after every change, or once at `commit` for changes made in a
+/// transaction. Cells on a circular reference evaluate to an error. Subscribers are told which
+/// cells were changed and which values differ afterwards.
 pub struct Environment {
-    map: HashMap<CellAddress, Box<dyn Evaluatable>>,
+    sheets: Vec<Sheet>,
+    active: usize,
+    names: HashMap<String, Definition>,
+    history: History<Change>,
+    /// Number of open transactions, and how many changes the history group held when the
+    /// outermost one began
+    transactions: usize,
+    transaction_start: usize,
+    /// Whether the cached values reflect every change
+    calculated: bool,
+    /// Cells set or cleared since the last recalculation, along with the cells that read the
+    /// cleared ones; these and the cells reading them are all that need working out again. `None`
+    /// once anything else changed, which calls for recalculating every cell.
+    stale: Option<HashSet<CellReference>>,
+    /// Cells that could not be calculated at the last recalculation, so that a commit fails only
+    /// on circular references it introduced
+    uncalculable: HashSet<CellReference>,
+    subscribers: Subscribers,
+    /// Events not yet delivered, held back until recalculation
+    events: Vec<Event>,
+    type_check: TypeCheck,
+    /// Whether formulas are compiled to bytecode for recalculation
+    compiled: bool,
+    /// Most threads recalculation uses
+    threads: usize,
+    tolerance: Tolerance,
 }
 
 impl Environment {
+    /// Workbook with a single empty sheet named `Sheet1`
     pub fn init() -> Environment {
-        Environment { map: HashMap::new(), }
+        Environment {
+            sheets: vec![Sheet::new("Sheet1")],
+            active: 0,
+            names: HashMap::new(),
+            history: History::new(DEFAULT_HISTORY_LIMIT),
+            transactions: 0,
+            transaction_start: 0,
+            calculated: true,
+            stale: Some(HashSet::new()),
+            uncalculable: HashSet::new(),
+            subscribers: Subscribers::default(),
+            events: Vec::new(),
+            type_check: TypeCheck::default(),
+            compiled: false,
+            threads: std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            tolerance: Tolerance::default(),
+        }
+    }
+
+    /// Calls `callback` with every event from now on, e.g. to repaint the cells a change affected
+    pub fn subscribe(&mut self, callback: impl FnMut(&Event) + Send + 'static) -> SubscriptionId {
+        self.subscribers.subscribe(callback)
+    }
+
+    /// Sends every event from now on through a channel, until the receiver is dropped
+    pub fn subscribe_channel(&mut self) -> std::sync::mpsc::Receiver<Event> {
+        self.subscribers.channel()
+    }
+
+    /// Cancels a subscription, returning whether it existed
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.subscribers.unsubscribe(id)
+    }
+
+    /// Starts a transaction: changes until the matching `commit` are not recalculated one by
+    /// one, and are undone as a single step. Transactions may nest, in which case only the
+    /// outermost one counts.
+    pub fn begin(&mut self, label: &str) {
+        if self.transactions == 0 {
+            self.transaction_start = self.history.open_len();
+        }
+        self.transactions += 1;
+        self.history.begin(label);
+    }
+
+    /// Ends a transaction, recalculating once the outermost one ends. If the transaction made a
+    /// circular reference or a name defined in terms of itself, every change of the transaction
+    /// is reverted and the reason returned. Circular references that were already there do not
+    /// stop a commit.
+    pub fn commit(&mut self) -> Result<(), String> {
+        self.end_transaction(true)
+    }
+
+    /// Ends a transaction like `commit`, but keeps its changes even if some cells cannot be
+    /// calculated, as when loading a workbook; those cells evaluate to the error instead
+    pub fn commit_unchecked(&mut self) -> Result<(), String> {
+        self.end_transaction(false)
+    }
+
+    fn end_transaction(&mut self, checked: bool) -> Result<(), String> {
+        match self.transactions {
+            0 => return Err(String::from("No transaction to commit")),
+            1 => {}
+            _ => {
+                self.transactions -= 1;
+                self.history.end();
+                return Ok(());
+            }
+        }
+
+        if let Err(e) = self.recalculate() {
+            if checked {
+                self.rollback();
+                return Err(e);
+            }
+        }
+        self.transactions = 0;
+        self.history.end();
+        self.deliver();
+        Ok(())
+    }
+
+    /// Reverts every change made since the outermost open transaction began, and ends it
+    pub fn rollback(&mut self) {
+        if self.transactions == 0 {
+            return;
+        }
+
+        let changes = self.history.take_since(self.transaction_start, Change::size);
+        for change in changes.into_iter().rev() {
+            self.apply(change);
+        }
+        for _ in 0..self.transactions {
+            self.history.end();
+        }
+        self.transactions = 0;
+        // The values are those from before the transaction again, so nothing changed as far as
+        // subscribers are concerned, although a failed commit cached values they never saw
+        let _ = self.recalculate();
+        self.events.clear();
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transactions > 0
+    }
+
+    /// Starts a step of the undo history; changes until the matching `end_group` are undone
+    /// together. Groups may nest, in which case only the outermost one counts.
+    pub fn begin_group(&mut self, label: &str) {
+        self.history.begin(label);
+    }
+
+    pub fn end_group(&mut self) {
+        self.history.end();
+    }
+
+    /// Reverts the most recent step, returning whether there was one. Nothing is undone while a
+    /// transaction is open.
+    pub fn undo(&mut self) -> bool {
+        if self.in_transaction() {
+            return false;
+        }
+        match self.history.take_undo() {
+            Some(group) => {
+                let group = self.apply_group(group);
+                self.history.push_redo(group);
+                self.settle();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Repeats the most recently undone step, returning whether there was one
+    pub fn redo(&mut self) -> bool {
+        if self.in_transaction() {
+            return false;
+        }
+        match self.history.take_redo() {
+            Some(group) => {
+                let group = self.apply_group(group);
+                self.history.push_undo(group);
+                self.settle();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Description of the step `undo` would revert, like `Set B3`
+    pub fn undo_label(&self) -> Option<&str> {
+        self.history.undo_label()
+    }
+
+    pub fn redo_label(&self) -> Option<&str> {
+        self.history.redo_label()
+    }
+
+    /// Limits the approximate memory used by the undo history, forgetting the oldest steps first
+    pub fn set_history_limit(&mut self, bytes: usize) {
+        self.history.set_limit(bytes);
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Sheet names in workbook order
+    pub fn sheet_names(&self) -> impl Iterator<Item = &str> {
+        self.sheets.iter().map(|sheet| sheet.name.as_str())
+    }
+
+    pub fn active_sheet(&self) -> &str {
+        &self.sheets[self.active].name
+    }
+
+    pub fn set_active_sheet(&mut self, name: &str) -> Result<(), String> {
+        self.active = self.sheet_index(name)?;
+        Ok(())
+    }
+
+    /// Appends an empty sheet
+    pub fn add_sheet(&mut self, name: &str) -> Result<(), String> {
+        self.check_new_sheet_name(name)?;
+        self.change(&format!("Add sheet {name}"), Change::InsertSheet { index: self.sheets.len(), sheet: Box::new(Sheet::new(name)) });
+        Ok(())
+    }
+
+    /// Renames a sheet along with every reference to it
+    pub fn rename_sheet(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        let index = self.sheet_index(name)?;
+        if name == new_name {
+            return Ok(());
+        }
+        self.check_new_sheet_name(new_name)?;
+
+        let (mut sheets, names) = self.rewrite_all(&mut SheetEdit { order: self.sheet_order(), name: name.to_string(), new_name: Some(new_name.to_string()) })?;
+        sheets[index].name = new_name.to_string();
+        self.change(&format!("Rename sheet {name}"), Change::Workbook { sheets, names, active: self.active });
+        Ok(())
+    }
+
+    /// Moves a sheet to the given position, which changes the sheets spanned by 3-D ranges
+    pub fn move_sheet(&mut self, name: &str, position: usize) -> Result<(), String> {
+        let index = self.sheet_index(name)?;
+        if position >= self.sheets.len() {
+            return Err(format!("Sheet position {position} is out of range"));
+        }
+
+        self.change(&format!("Move sheet {name}"), Change::MoveSheet { from: index, to: position });
+        Ok(())
+    }
+
+    /// Deletes a sheet, its cells and its names. References into it become `#REF!`, and 3-D ranges starting
+    /// or ending on it shrink to the remaining sheets.
+    pub fn delete_sheet(&mut self, name: &str) -> Result<(), String> {
+        let index = self.sheet_index(name)?;
+        if self.sheets.len() == 1 {
+            return Err(String::from("Cannot delete the only sheet"));
+        }
+
+        let (mut sheets, names) = self.rewrite_all(&mut SheetEdit { order: self.sheet_order(), name: name.to_string(), new_name: None })?;
+        sheets.remove(index);
+        let active = if self.active > index || self.active == sheets.len() { self.active - 1 } else { self.active };
+        self.change(&format!("Delete sheet {name}"), Change::Workbook { sheets, names, active });
+        Ok(())
     }
-    
-    pub fn set_cell(&mut self, adr: &CellAddress, val: Box<dyn Evaluatable>) {
-       self.map.insert(*adr, val);
+
+    /// Sets a cell of the active sheet, which fails only if type errors are rejected
+    pub fn set_cell(&mut self, adr: &CellAddress, val: Box<dyn Evaluatable>) -> Result<(), String> {
+        if self.type_check == TypeCheck::Reject {
+            self.check_formula(self.active_sheet(), val.as_ref())?;
+        }
+        self.put(self.active, adr, Some(val));
+        Ok(())
+    }
+
+    /// Sets a cell of the named sheet
+    pub fn set_cell_on(&mut self, sheet: &str, adr: &CellAddress, val: Box<dyn Evaluatable>) -> Result<(), String> {
+        let index = self.sheet_index(sheet)?;
+        if self.type_check == TypeCheck::Reject {
+            self.check_formula(sheet, val.as_ref())?;
+        }
+        self.put(index, adr, Some(val));
+        Ok(())
+    }
+
+    pub fn set_type_check(&mut self, check: TypeCheck) {
+        self.type_check = check;
+    }
+
+    /// Most threads recalculation may use, which defaults to the number of processors. Cells are
+    /// only spread over threads when enough of them can be calculated independently; values are
+    /// the same however many threads there are.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Whether recalculation runs formulas compiled to bytecode rather than walking their trees.
+    /// Either way gives the same values.
+    pub fn set_compiled(&mut self, compiled: bool) {
+        self.compiled = compiled;
+        if !compiled {
+            for sheet in &mut self.sheets {
+                sheet.programs.clear();
+            }
+        }
+    }
+
+    /// How far apart floats may be and still compare equal, which defaults to
+    /// `Tolerance::Absolute(f32::EPSILON)`. Cells are recalculated with the new tolerance.
+    pub fn set_tolerance(&mut self, tolerance: Tolerance) {
+        self.tolerance = tolerance;
+        self.calculated = false;
+        self.stale = None;
+        self.settle();
+    }
+
+    pub fn tolerance(&self) -> Tolerance {
+        self.tolerance
+    }
+
+    /// Types a formula for a cell of the named sheet may evaluate to, or the type error it
+    /// cannot avoid given what the cells and names it refers to hold. A referenced formula may
+    /// give any type its own operators allow, and an empty cell or undefined name any type at
+    /// all, since they may be filled in later.
+    pub fn check_formula(&self, sheet: &str, val: &dyn Evaluatable) -> Result<Types, String> {
+        infer_type(val, &mut Contents { environment: self, sheet })
+    }
+
+    pub fn get_cell(&self, adr: &CellAddress) -> Option<&dyn Evaluatable> {
+        self.sheets[self.active].map.get(adr).map(|val| val.as_ref())
+    }
+
+    /// Cell of the named sheet, or `None` if either does not exist
+    pub fn get_cell_on(&self, sheet: &str, adr: &CellAddress) -> Option<&dyn Evaluatable> {
+        let sheet = self.sheets.iter().find(|s| s.name == sheet)?;
+        sheet.map.get(adr).map(|val| val.as_ref())
+    }
+
+    /// Value of a cell of the active sheet, or `None` if it is empty
+    pub fn value(&self, adr: &CellAddress) -> Option<Result<Primitive, String>> {
+        self.value_in(self.active, adr)
+    }
+
+    /// Value of a cell of the named sheet, or `None` if either does not exist
+    pub fn value_on(&self, sheet: &str, adr: &CellAddress) -> Option<Result<Primitive, String>> {
+        self.value_in(self.sheet_index(sheet).ok()?, adr)
+    }
+
+    /// Value of the cell a reference points at, looking in the active sheet when it names none,
+    /// or `None` if the cell is empty
+    pub fn value_at(&self, reference: &CellReference) -> Option<Result<Primitive, String>> {
+        match &reference.sheet {
+            Some(name) => match self.sheet_index(name) {
+                Ok(index) => self.value_in(index, &reference.address),
+                Err(e) => Some(Err(e)),
+            },
+            None => self.value_in(self.active, &reference.address),
+        }
+    }
+
+    /// Cell a reference points at, looking in the active sheet when it names none
+    pub fn resolve(&self, reference: &CellReference) -> Result<Option<&dyn Evaluatable>, String> {
+        let sheet = match &reference.sheet {
+            Some(name) => &self.sheets[self.sheet_index(name)?],
+            None => &self.sheets[self.active],
+        };
+        Ok(sheet.map.get(&reference.address).map(|val| val.as_ref()))
+    }
+
+    /// Names of the sheets a range covers in workbook order: one sheet for a plain range, or
+    /// every sheet between the two ends of a 3-D range
+    pub fn spanned_sheets(&self, range: &CellRange) -> Result<Vec<&str>, String> {
+        let index = |sheet: &Option<String>| match sheet {
+            Some(name) => self.sheet_index(name),
+            None => Ok(self.active),
+        };
+        let (first, last) = (index(&range.start.sheet)?, index(&range.end.sheet)?);
+
+        Ok(self.sheets[first.min(last)..=first.max(last)].iter().map(|sheet| sheet.name.as_str()).collect())
+    }
+
+    pub fn clear_cell(&mut self, adr: &CellAddress) {
+        self.put(self.active, adr, None);
+    }
+
+    /// Empties a cell of the named sheet
+    pub fn clear_cell_on(&mut self, sheet: &str, adr: &CellAddress) -> Result<(), String> {
+        self.put(self.sheet_index(sheet)?, adr, None);
+        Ok(())
+    }
+
+    /// All populated cells of the active sheet, row by row
+    pub fn cells(&self) -> impl Iterator<Item = (&CellAddress, &dyn Evaluatable)> {
+        self.sheets[self.active].map.iter().map(|(adr, val)| (adr, val.as_ref()))
+    }
+
+    /// All populated cells of the named sheet, row by row
+    pub fn cells_on(&self, sheet: &str) -> impl Iterator<Item = (&CellAddress, &dyn Evaluatable)> {
+        self.sheets.iter()
+            .find(|s| s.name == sheet)
+            .into_iter()
+            .flat_map(|s| s.map.iter())
+            .map(|(adr, val)| (adr, val.as_ref()))
+    }
+
+    /// Populated cells of the named sheet within the columns and rows of a range, row by row.
+    /// Empty cells are skipped without being visited, so a whole column costs no more than the
+    /// cells it holds.
+    pub fn cells_in(&self, sheet: &str, range: &CellRange) -> impl Iterator<Item = (&CellAddress, &dyn Evaluatable)> {
+        self.cells_within(sheet, range.columns(), range.rows())
+    }
+
+    /// Populated cells of a row of the named sheet, left to right
+    pub fn cells_in_row(&self, sheet: &str, row: i32) -> impl Iterator<Item = (&CellAddress, &dyn Evaluatable)> {
+        self.cells_within(sheet, 0..=MAX_COLUMN, row..=row)
+    }
+
+    /// Populated cells of a column of the named sheet, top to bottom
+    pub fn cells_in_column(&self, sheet: &str, column: i32) -> impl Iterator<Item = (&CellAddress, &dyn Evaluatable)> {
+        self.cells_within(sheet, column..=column, 0..=MAX_ROW)
+    }
+
+    /// Numbers typed into the named sheet, or `None` if there is no such sheet
+    pub(crate) fn columns_on(&self, sheet: &str) -> Option<&Columns> {
+        Some(&self.sheets.iter().find(|s| s.name == sheet)?.columns)
+    }
+
+    /// Smallest range holding every populated cell of the named sheet, or `None` if it has none
+    pub fn used_range(&self, sheet: &str) -> Option<CellRange> {
+        let (top_left, bottom_right) = self.sheets.iter().find(|s| s.name == sheet)?.map.bounds()?;
+        Some(CellRange::new(top_left, bottom_right))
+    }
+
+    fn cells_within(&self, sheet: &str, columns: RangeInclusive<i32>, rows: RangeInclusive<i32>) -> impl Iterator<Item = (&CellAddress, &dyn Evaluatable)> {
+        self.sheets.iter()
+            .find(|s| s.name == sheet)
+            .into_iter()
+            .flat_map(move |s| s.map.range(columns.clone(), rows.clone()))
+            .map(|(adr, val)| (adr, val.as_ref()))
+    }
+
+    /// Populated cells the formula in a cell reads, directly or through names
+    pub fn precedents(&self, sheet: &str, adr: &CellAddress) -> Result<Vec<CellReference>, String> {
+        match self.get_cell_on(sheet, adr) {
+            Some(val) => precedents(self, sheet, val),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Cells whose formulas read the given cell, directly or through names
+    pub fn dependents(&self, sheet: &str, adr: &CellAddress) -> Result<Vec<CellReference>, String> {
+        self.sheet_index(sheet)?;
+        let cell = CellReference::relative(*adr).on_sheet(sheet);
+
+        let mut dependents = Vec::new();
+        for host in &self.sheets {
+            dependents.extend(host.map.iter()
+                .filter(|(_, val)| precedents(self, &host.name, val.as_ref()).is_ok_and(|cells| cells.contains(&cell)))
+                .map(|(address, _)| CellReference::relative(*address).on_sheet(&host.name)));
+        }
+        Ok(dependents)
+    }
+
+    /// Populated cells a cell reads directly or through other cells, nearest first
+    pub fn transitive_precedents(&self, sheet: &str, adr: &CellAddress) -> Result<Vec<CellReference>, String> {
+        self.sheet_index(sheet)?;
+        let start = CellReference::relative(*adr).on_sheet(sheet);
+        reachable(start, |cell| {
+            let sheet = cell.sheet.as_deref().unwrap_or_default();
+            self.precedents(sheet, &cell.address)
+        })
+    }
+
+    /// Cells whose values depend on a cell directly or through other cells, nearest first
+    pub fn transitive_dependents(&self, sheet: &str, adr: &CellAddress) -> Result<Vec<CellReference>, String> {
+        self.sheet_index(sheet)?;
+        let dependents = dependents_map(self);
+        let start = CellReference::relative(*adr).on_sheet(sheet);
+        reachable(start, |cell| Ok(dependents.get(cell).cloned().unwrap_or_default()))
+    }
+
+    /// Longest chain of cells each reading the one before, from a cell that reads none to the
+    /// cell furthest from it. Circular references are not followed.
+    pub fn longest_chain(&self) -> Vec<CellReference> {
+        let order = calculation_order(self);
+        let levels: HashMap<&CellReference, usize> = order.cells.iter().zip(order.levels.iter().copied()).collect();
+        let Some((mut cell, mut level)) = order.cells.iter().zip(order.levels.iter().copied()).max_by_key(|(_, level)| *level) else {
+            return Vec::new();
+        };
+
+        let mut chain = vec![cell.clone()];
+        while level > 0 {
+            let sheet = cell.sheet.as_deref().unwrap_or_default();
+            let val = self.get_cell_on(sheet, &cell.address).expect("ordered cells are populated");
+            let precedents = precedents(self, sheet, val).unwrap_or_default();
+            let previous = precedents.iter()
+                .find_map(|precedent| levels.get_key_value(precedent).filter(|(_, l)| **l + 1 == level))
+                .expect("a cell above the first level reads a cell on the level below");
+            (cell, level) = (*previous.0, *previous.1);
+            chain.push(cell.clone());
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Cells holding plain values that no formula reads, sheet by sheet and row by row
+    pub fn orphaned_inputs(&self) -> Vec<CellReference> {
+        let dependents = dependents_map(self);
+        let mut orphans = Vec::new();
+        for sheet in self.sheet_names() {
+            orphans.extend(sorted_cells(self, sheet).into_iter()
+                .filter(|(_, val)| matches!(val.expression(), Expression::Primitive(_)))
+                .map(|(adr, _)| CellReference::relative(adr).on_sheet(sheet))
+                .filter(|cell| !dependents.contains_key(cell)));
+        }
+        orphans
+    }
+
+    /// Cells whose formulas refer to empty cells, directly or through names, along with the empty
+    /// cells. Ranges are left out, since they may well have gaps.
+    pub fn empty_references(&self) -> Vec<(CellReference, Vec<CellReference>)> {
+        let mut found = Vec::new();
+        for sheet in self.sheet_names() {
+            for (adr, val) in sorted_cells(self, sheet) {
+                let empty = empty_references(self, sheet, val);
+                if !empty.is_empty() {
+                    found.push((CellReference::relative(adr).on_sheet(sheet), empty));
+                }
+            }
+        }
+        found
+    }
+
+    /// Defines a name for a cell, a range or a constant. References that name no sheet are bound
+    /// to the scope's sheet, or to the active sheet for workbook names.
+    pub fn define_name(&mut self, name: &str, scope: &NameScope, definition: Definition) -> Result<(), String> {
+        validate_name(name)?;
+        let sheet = match scope {
+            NameScope::Workbook => self.active_sheet().to_string(),
+            NameScope::Sheet(sheet) => sheet.clone(),
+        };
+        let definition = definition.bind(&sheet);
+
+        let sheet = self.scope_index(scope)?;
+        if self.names_in(sheet).contains_key(name) {
+            return Err(format!("Name {name} is already defined"));
+        }
+        self.change(&format!("Define {name}"), Change::Name { sheet, name: name.to_string(), definition: Some(definition) });
+        Ok(())
+    }
+
+    pub fn remove_name(&mut self, name: &str, scope: &NameScope) -> Result<(), String> {
+        let sheet = self.scope_index(scope)?;
+        if !self.names_in(sheet).contains_key(name) {
+            return Err(format!("Name {name} is not defined"));
+        }
+        self.change(&format!("Remove {name}"), Change::Name { sheet, name: name.to_string(), definition: None });
+        Ok(())
+    }
+
+    /// Names defined in the given scope, in no particular order
+    pub fn names(&self, scope: &NameScope) -> impl Iterator<Item = (&str, &Definition)> {
+        let names = match scope {
+            NameScope::Workbook => Some(&self.names),
+            NameScope::Sheet(sheet) => self.sheets.iter().find(|s| s.name == *sheet).map(|s| &s.names),
+        };
+        names.into_iter().flatten().map(|(name, definition)| (name.as_str(), definition))
+    }
+
+    /// Definition a name has when used on the given sheet, or on the active sheet when none is
+    /// given: the sheet's own names first, then the workbook's
+    pub fn lookup_name(&self, name: &str, sheet: Option<&str>) -> Result<&Definition, String> {
+        let sheet = match sheet {
+            Some(sheet) => self.sheets.iter().find(|s| s.name == sheet),
+            None => Some(&self.sheets[self.active]),
+        };
+
+        sheet.and_then(|sheet| sheet.names.get(name))
+            .or_else(|| self.names.get(name))
+            .ok_or_else(|| format!("Name {name} is not defined"))
+    }
+
+    /// Cells covered by a name as seen from the active sheet, for use wherever a range is taken,
+    /// e.g. `Statistics::Sum(environment.name_range("Revenue")?)`
+    pub fn name_range(&self, name: &str) -> Result<CellRange, String> {
+        self.lookup_name(name, None)?.range().ok_or(format!("Name {name} does not refer to cells"))
+    }
+
+    /// Index of the sheet a scope is, or `None` for the workbook
+    fn scope_index(&self, scope: &NameScope) -> Result<Option<usize>, String> {
+        match scope {
+            NameScope::Workbook => Ok(None),
+            NameScope::Sheet(sheet) => self.sheet_index(sheet).map(Some),
+        }
+    }
+
+    fn names_in(&self, sheet: Option<usize>) -> &HashMap<String, Definition> {
+        match sheet {
+            Some(index) => &self.sheets[index].names,
+            None => &self.names,
+        }
+    }
+
+    /// Pastes the contents of `source` into `destination`, shifting relative references by the
+    /// distance between the two cells so that `=A1*2` copied one row down becomes `=A2*2`.
+    /// Copying an empty cell clears the destination.
+    pub fn copy_cell(&mut self, source: &CellAddress, destination: &CellAddress) -> Result<(), String> {
+        let copy = self.shifted_copy(source, destination)?;
+        self.put(self.active, destination, copy);
+        Ok(())
+    }
+
+    /// Pastes the contents of `source` into every cell of `range` other than `source` itself,
+    /// shifting relative references as `copy_cell` does. Nothing changes if any copy fails.
+    pub fn fill_range(&mut self, source: &CellAddress, range: &CellRange) -> Result<(), String> {
+        if range.is_whole_column() || range.is_whole_row() {
+            return Err(format!("Cannot fill whole rows or columns: {}", range));
+        }
+
+        let mut copies = Vec::new();
+        for y in range.rows() {
+            for x in range.columns() {
+                let destination = CellAddress(x, y);
+                if destination != *source {
+                    copies.push((destination, self.shifted_copy(source, &destination)?));
+                }
+            }
+        }
+
+        self.begin(&format!("Fill {range}"));
+        for (destination, copy) in copies {
+            self.put(self.active, &destination, copy);
+        }
+        self.commit()
+    }
+
+    /// Inserts `count` empty rows before row `at`, moving the rows below down and adjusting every
+    /// reference to them. Ranges spanning the insertion point grow to include the new rows.
+    pub fn insert_rows(&mut self, at: i32, count: i32) -> Result<(), String> {
+        self.restructure(Restructure { axis: Axis::Row, at, count, delete: false, sheet: self.active_sheet().to_string() })
+    }
+
+    /// Deletes `count` rows starting at row `at`, moving the rows below up. References to deleted
+    /// cells become `#REF!` and ranges shrink, becoming `#REF!` once nothing is left of them.
+    pub fn delete_rows(&mut self, at: i32, count: i32) -> Result<(), String> {
+        self.restructure(Restructure { axis: Axis::Row, at, count, delete: true, sheet: self.active_sheet().to_string() })
+    }
+
+    /// Inserts `count` empty columns before column `at`, as `insert_rows` does for rows
+    pub fn insert_columns(&mut self, at: i32, count: i32) -> Result<(), String> {
+        self.restructure(Restructure { axis: Axis::Column, at, count, delete: false, sheet: self.active_sheet().to_string() })
+    }
+
+    /// Deletes `count` columns starting at column `at`, as `delete_rows` does for rows
+    pub fn delete_columns(&mut self, at: i32, count: i32) -> Result<(), String> {
+        self.restructure(Restructure { axis: Axis::Column, at, count, delete: true, sheet: self.active_sheet().to_string() })
+    }
+
+    fn restructure(&mut self, mut edit: Restructure) -> Result<(), String> {
+        if edit.count < 0 {
+            return Err(format!("Cannot insert or delete a negative number of {}s", edit.axis.name()));
+        }
+        if !(0..=edit.axis.max()).contains(&edit.at) {
+            return Err(format!("{} {} is out of range", edit.axis.title(), edit.at));
+        }
+
+        if !edit.delete {
+            if let Some(adr) = self.sheets[self.active].map.keys().find(|adr| edit.index(edit.axis.index(adr)).is_none()) {
+                return Err(format!("Inserting would push cell {} off the grid", adr));
+            }
+        }
+
+        // Formulas and names anywhere may point into the edited sheet
+        let (mut sheets, names) = self.rewrite_all(&mut edit)?;
+
+        let active = &mut sheets[self.active];
+        let map = std::mem::take(&mut active.map).into_cells()
+            .filter_map(|(adr, val)| edit.index(edit.axis.index(&adr)).map(|moved| (edit.axis.with_index(&adr, moved), val)))
+            .collect();
+        *active = Sheet::with_cells(&active.name, map, std::mem::take(&mut active.names));
+
+        let label = format!("{} {}s", if edit.delete { "Delete" } else { "Insert" }, edit.axis.name());
+        self.change(&label, Change::Workbook { sheets, names, active: self.active });
+        Ok(())
+    }
+
+    /// Copy of every sheet and workbook name with their references rewritten
+    fn rewrite_all(&self, rewrite: &mut dyn ReferenceRewrite) -> Result<(Vec<Sheet>, HashMap<String, Definition>), String> {
+        let sheets = self.sheets.iter()
+            .map(|sheet| Ok(Sheet::with_cells(&sheet.name, rewrite_map(&sheet.map, rewrite)?, rewrite_names(&sheet.names, rewrite)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok((sheets, rewrite_names(&self.names, rewrite)?))
+    }
+
+    /// Sets or, given `None`, clears a cell
+    fn put(&mut self, sheet: usize, adr: &CellAddress, val: Option<Box<dyn Evaluatable>>) {
+        let label = match &val {
+            Some(_) => format!("Set {}", adr),
+            None => format!("Clear {}", adr),
+        };
+        let val = val.map(|val| bind(val.as_ref(), &self.sheets[sheet].name));
+        self.change(&label, Change::Cell { sheet, adr: *adr, val });
+    }
+
+    /// Makes a change, recording its inverse in the undo history
+    fn change(&mut self, label: &str, change: Change) {
+        let inverse = self.apply(change);
+        let size = inverse.size();
+        self.history.record(label, inverse, size);
+        self.settle();
+    }
+
+    /// Recalculates and delivers events unless a transaction is open. Outside a transaction a
+    /// circular reference is not an error of the change; the cells on it evaluate to the error
+    /// instead. Setting and clearing cells recalculates only the cells depending on them.
+    fn settle(&mut self) {
+        if !self.in_transaction() {
+            let _ = match self.stale.take() {
+                Some(cells) => self.recalculate_stale(cells),
+                None => self.recalculate(),
+            };
+            self.deliver();
+        }
+    }
+
+    fn deliver(&mut self) {
+        let events = std::mem::take(&mut self.events);
+        self.subscribers.notify(&events);
+    }
+
+    /// Works out the value of every cell afresh, returning the first reason a cell could not be
+    /// calculated that was not a reason last time
+    fn recalculate(&mut self) -> Result<(), String> {
+        let order = calculation_order(self);
+        let old = self.sheets.iter_mut().map(|sheet| std::mem::take(&mut sheet.values)).collect();
+        self.calculate(order, old, None)
+    }
+
+    /// Works out again the values of the given cells and of every cell depending on them,
+    /// keeping the values of the others
+    fn recalculate_stale(&mut self, stale: HashSet<CellReference>) -> Result<(), String> {
+        let dependents = dependents_map(self);
+        let mut cells = stale.clone();
+        for cell in stale {
+            cells.extend(reachable(cell, |cell| Ok(dependents.get(cell).cloned().unwrap_or_default())).unwrap_or_default());
+        }
+
+        let mut old: Vec<HashMap<CellAddress, Result<Primitive, String>>> = self.sheets.iter().map(|_| HashMap::new()).collect();
+        for cell in &cells {
+            let index = self.sheet_index(cell.sheet.as_deref().unwrap_or_default()).expect("stale cells are on existing sheets");
+            old[index].extend(self.sheets[index].values.remove_entry(&cell.address));
+        }
+        let order = partial_calculation_order(self, &cells);
+        self.calculate(order, old, Some(&cells))
+    }
+
+    /// Works out the values of ordered cells, whose values from before are given by sheet, and
+    /// tells subscribers which differ. `within` holds the cells being recalculated unless it is
+    /// every cell.
+    fn calculate(&mut self, order: CalculationOrder, mut old: Vec<HashMap<CellAddress, Result<Primitive, String>>>,
+                 within: Option<&HashSet<CellReference>>) -> Result<(), String> {
+        // Values are cached as they are worked out, which is safe since each cell comes after
+        // the cells it reads
+        self.calculated = true;
+        self.stale = Some(HashSet::new());
+
+        let failure = order.errors.iter().find(|(cell, _)| !self.uncalculable.contains(cell)).map(|(_, e)| e.clone());
+        match within {
+            Some(cells) => self.uncalculable.retain(|cell| !cells.contains(cell)),
+            None => self.uncalculable.clear(),
+        }
+        self.uncalculable.extend(order.errors.iter().map(|(cell, _)| cell.clone()));
+        // Cells given a value, once each
+        let mut calculated = Vec::new();
+        for (cell, error) in order.errors {
+            let index = self.sheet_index(cell.sheet.as_deref().unwrap_or_default()).expect("ordered cells are on existing sheets");
+            if self.sheets[index].values.insert(cell.address, Err(error)).is_none() {
+                calculated.push((index, cell.address));
+            }
+        }
+        // Cells are calculated a level at a time, the cells of a level being independent
+        let mut cells = Vec::new();
+        for (cell, level) in order.cells.iter().zip(order.levels) {
+            let index = self.sheet_index(cell.sheet.as_deref().unwrap_or_default()).expect("ordered cells are on existing sheets");
+            if !self.sheets[index].values.contains_key(&cell.address) {
+                cells.push((level, index, cell.address));
+            }
+        }
+        cells.sort_by_key(|(level, _, _)| *level);
+        for level in cells.chunk_by(|(a, _, _), (b, _, _)| a == b) {
+            if self.compiled {
+                for (_, index, adr) in level {
+                    self.compile(*index, adr);
+                }
+            }
+            for ((_, index, adr), result) in level.iter().zip(self.evaluate_level(level)) {
+                if let Some(result) = result {
+                    self.sheets[*index].values.insert(*adr, result);
+                    calculated.push((*index, *adr));
+                }
+            }
+        }
+
+        // After a structural change cells may have moved, so their values cannot be compared
+        if !self.subscribers.is_empty() && !self.events.contains(&Event::StructureChanged) {
+            for (index, adr) in calculated {
+                let sheet = &self.sheets[index];
+                let (old, new) = (old[index].remove(&adr), sheet.values.get(&adr));
+                if old.as_ref() != new {
+                    self.events.push(Event::ValueChanged { sheet: sheet.name.clone(), adr, old, new: new.cloned() });
+                }
+            }
+            for (sheet, old) in self.sheets.iter().zip(old) {
+                for (adr, old) in old {
+                    self.events.push(Event::ValueChanged { sheet: sheet.name.clone(), adr, old: Some(old), new: None });
+                }
+            }
+        }
+
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Values of cells that do not read each other, in order. Large levels are split between
+    /// threads; the values are the same whichever thread works them out.
+    fn evaluate_level(&self, cells: &[(usize, usize, CellAddress)]) -> Vec<Option<Result<Primitive, String>>> {
+        let threads = self.threads.min(cells.len() / PARALLEL_LEVEL).max(1);
+        let evaluate = |cells: &[(usize, usize, CellAddress)]| {
+            let mut stack = Vec::new();
+            cells.iter().map(|(_, index, adr)| self.evaluate_cell(*index, adr, &mut stack)).collect::<Vec<_>>()
+        };
+        if threads == 1 {
+            return evaluate(cells);
+        }
+
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = cells.chunks(cells.len().div_ceil(threads))
+                .map(|chunk| scope.spawn(move || evaluate(chunk)))
+                .collect();
+            workers.into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect()
+        })
+    }
+
+    /// Value of a cell from its compiled formula if it has one, or else from its tree
+    fn evaluate_cell(&self, sheet: usize, adr: &CellAddress, stack: &mut Vec<Primitive>) -> Option<Result<Primitive, String>> {
+        let sheet = &self.sheets[sheet];
+        match sheet.programs.get(adr) {
+            Some(program) => Some(program.evaluate_with(self, stack)),
+            None => sheet.map.get(adr).map(|val| val.evaluate(self)),
+        }
+    }
+
+    /// Compiles the formula of a cell unless it is already compiled. Constants are left alone,
+    /// as there is nothing to gain.
+    fn compile(&mut self, sheet: usize, adr: &CellAddress) {
+        let Sheet { map, programs, .. } = &mut self.sheets[sheet];
+        if let Some(val) = map.get(adr) {
+            if matches!(val.expression(), Expression::Operation(_)) {
+                programs.entry(*adr).or_insert_with(|| Program::compile(val.as_ref()));
+            }
+        }
+    }
+
+    /// Value of a cell, worked out afresh when changes since the last recalculation could
+    /// affect it. A cell met again while working out its own value is a circular reference.
+    fn value_in(&self, sheet: usize, adr: &CellAddress) -> Option<Result<Primitive, String>> {
+        let sheet = &self.sheets[sheet];
+        if self.calculated {
+            if let Some(value) = sheet.values.get(adr) {
+                return Some(value.clone());
+            }
+        }
+        let val = sheet.map.get(adr)?;
+
+        let cell = CellReference::relative(*adr).on_sheet(&sheet.name);
+        let cycle = EVALUATING.with_borrow(|cells| {
+            let start = cells.iter().position(|evaluating| *evaluating == cell)?;
+            Some(cells[start..].iter().chain([&cell]).map(|cell| cell.to_string()).collect::<Vec<_>>())
+        });
+        if let Some(cycle) = cycle {
+            return Some(Err(format!("Circular reference: {}", cycle.join(" -> "))));
+        }
+
+        EVALUATING.with_borrow_mut(|cells| cells.push(cell));
+        let value = val.evaluate(self);
+        EVALUATING.with_borrow_mut(|cells| cells.pop());
+        Some(value)
+    }
+
+    /// Notes a cell about to be set or cleared for the next recalculation. Nothing reads a cell
+    /// once it is cleared, so the cells reading it are noted beforehand.
+    fn mark_stale(&mut self, sheet: usize, adr: &CellAddress, clearing: bool) {
+        if self.stale.is_none() {
+            return;
+        }
+
+        let name = &self.sheets[sheet].name;
+        let mut cells = vec![CellReference::relative(*adr).on_sheet(name)];
+        if clearing && self.sheets[sheet].map.get(adr).is_some() {
+            cells.extend(self.transitive_dependents(name, adr).unwrap_or_default());
+        }
+        self.stale.get_or_insert_default().extend(cells);
+    }
+
+    /// Applies the changes of a history step in reverse, returning the step that reverts it
+    fn apply_group(&mut self, group: Group<Change>) -> Group<Change> {
+        let changes: Vec<Change> = group.changes.into_iter().rev().map(|change| self.apply(change)).collect();
+        let size = changes.iter().map(Change::size).sum();
+        Group { label: group.label, changes, size }
+    }
+
+    /// Makes a change, returning the change that reverts it
+    fn apply(&mut self, change: Change) -> Change {
+        self.calculated = false;
+        match &change {
+            Change::Cell { sheet, adr, val } if !self.in_transaction() => self.mark_stale(*sheet, adr, val.is_none()),
+            _ => self.stale = None,
+        }
+        if !self.subscribers.is_empty() {
+            let event = match &change {
+                Change::Cell { sheet, adr, val: Some(_) } => Some(Event::CellSet { sheet: self.sheets[*sheet].name.clone(), adr: *adr }),
+                Change::Cell { sheet, adr, val: None } => Some(Event::CellCleared { sheet: self.sheets[*sheet].name.clone(), adr: *adr }),
+                Change::Name { .. } => None,
+                _ => Some(Event::StructureChanged).filter(|event| !self.events.contains(event)),
+            };
+            self.events.extend(event);
+        }
+
+        match change {
+            Change::Cell { sheet, adr, val } => {
+                self.sheets[sheet].programs.remove(&adr);
+                self.sheets[sheet].columns.set(adr, val.as_deref());
+                let map = &mut self.sheets[sheet].map;
+                let previous = match val {
+                    Some(val) => map.insert(adr, val),
+                    None => map.remove(&adr),
+                };
+                Change::Cell { sheet, adr, val: previous }
+            }
+            Change::Name { sheet, name, definition } => {
+                let names = match sheet {
+                    Some(index) => &mut self.sheets[index].names,
+                    None => &mut self.names,
+                };
+                let previous = match definition {
+                    Some(definition) => names.insert(name.clone(), definition),
+                    None => names.remove(&name),
+                };
+                Change::Name { sheet, name, definition: previous }
+            }
+            Change::InsertSheet { index, sheet } => {
+                self.sheets.insert(index, *sheet);
+                if self.active >= index && self.sheets.len() > 1 {
+                    self.active += 1;
+                }
+                Change::RemoveSheet { index }
+            }
+            Change::RemoveSheet { index } => {
+                let mut sheet = self.sheets.remove(index);
+                sheet.values.clear();
+                if self.active > index || self.active == self.sheets.len() {
+                    self.active -= 1;
+                }
+                Change::InsertSheet { index, sheet: Box::new(sheet) }
+            }
+            Change::MoveSheet { from, to } => {
+                let active = self.active_sheet().to_string();
+                let sheet = self.sheets.remove(from);
+                self.sheets.insert(to, sheet);
+                self.active = self.sheets.iter().position(|sheet| sheet.name == active).unwrap_or(0);
+                Change::MoveSheet { from: to, to: from }
+            }
+            Change::Workbook { sheets, names, active } => {
+                let mut sheets = std::mem::replace(&mut self.sheets, sheets);
+                for sheet in &mut sheets {
+                    sheet.values.clear();
+                }
+                let names = std::mem::replace(&mut self.names, names);
+                let active = std::mem::replace(&mut self.active, active);
+                Change::Workbook { sheets, names, active }
+            }
+        }
+    }
+
+    fn sheet_index(&self, name: &str) -> Result<usize, String> {
+        self.sheets.iter().position(|sheet| sheet.name == name).ok_or_else(|| format!("Sheet {name} not found"))
+    }
+
+    fn sheet_order(&self) -> Vec<String> {
+        self.sheet_names().map(str::to_string).collect()
+    }
+
+    fn check_new_sheet_name(&self, name: &str) -> Result<(), String> {
+        validate_sheet_name(name)?;
+        if self.sheets.iter().any(|sheet| sheet.name == name) {
+            return Err(format!("Sheet {name} already exists"));
+        }
+        Ok(())
+    }
+
+    fn shifted_copy(&self, source: &CellAddress, destination: &CellAddress) -> Result<Option<Box<dyn Evaluatable>>, String> {
+        let columns = destination.0 as i64 - source.0 as i64;
+        let rows = destination.1 as i64 - source.1 as i64;
+
+        match self.get_cell(source) {
+            Some(val) => Ok(Some(map_references(val, &mut |reference| reference.offset(columns, rows))?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reversible change to an environment. Sheets are identified by position, which is stable
+/// because changes are always undone in the reverse order they were made.
+enum Change {
+    /// Sets a cell, or clears it given `None`
+    Cell { sheet: usize, adr: CellAddress, val: Option<Box<dyn Evaluatable>> },
+    /// Defines a name of a sheet, or of the workbook given no sheet, or removes it given `None`
+    Name { sheet: Option<usize>, name: String, definition: Option<Definition> },
+    InsertSheet { index: usize, sheet: Box<Sheet> },
+    RemoveSheet { index: usize },
+    MoveSheet { from: usize, to: usize },
+    /// Replaces every sheet and workbook name, for edits that rewrite references everywhere
+    Workbook { sheets: Vec<Sheet>, names: HashMap<String, Definition>, active: usize },
+}
+
+impl Change {
+    /// Approximate memory held by the change
+    fn size(&self) -> usize {
+        let names_size = |names: &HashMap<String, Definition>| -> usize {
+            names.iter().map(|(name, definition)| name.len() + definition_size(definition)).sum()
+        };
+        let sheet_size = |sheet: &Sheet| -> usize {
+            sheet.name.len()
+                + sheet.map.values().map(|val| ENTRY_SIZE + expression_size(val.as_ref())).sum::<usize>()
+                + names_size(&sheet.names)
+        };
+
+        ENTRY_SIZE + match self {
+            Change::Cell { val, .. } => val.as_ref().map_or(0, |val| expression_size(val.as_ref())),
+            Change::Name { name, definition, .. } => name.len() + definition.as_ref().map_or(0, definition_size),
+            Change::InsertSheet { sheet, .. } => sheet_size(sheet),
+            Change::RemoveSheet { .. } | Change::MoveSheet { .. } => 0,
+            Change::Workbook { sheets, names, .. } => sheets.iter().map(sheet_size).sum::<usize>() + names_size(names),
+        }
+    }
+}
+
+/// Rough heap footprint of a stored cell or formula node, used to budget the undo history
+const ENTRY_SIZE: usize = 64;
+
+fn expression_size(val: &dyn Evaluatable) -> usize {
+    let sheet = |sheet: &Option<String>| sheet.as_ref().map_or(0, String::len);
+
+    ENTRY_SIZE + match val.expression() {
+        Expression::Primitive(Primitive::String(text)) => text.len(),
+        Expression::Primitive(_) | Expression::InvalidReference(_) => 0,
+        Expression::CellValue(cell) => sheet(&cell.0.sheet),
+        Expression::Statistics(statistics) => sheet(&statistics.range().start.sheet) + sheet(&statistics.range().end.sheet),
+        Expression::NamedValue(value) => value.name.len() + sheet(&value.sheet),
+        Expression::Operation(operation) => {
+            let (val1, val2) = operation.operands();
+            expression_size(val1) + val2.map_or(0, expression_size)
+        }
+    }
+}
+
+fn definition_size(definition: &Definition) -> usize {
+    match definition {
+        Definition::Constant(val) => expression_size(val.as_ref()),
+        _ => ENTRY_SIZE,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Axis {
+    Column,
+    Row,
+}
+
+impl Axis {
+    fn index(&self, adr: &CellAddress) -> i32 {
+        match self {
+            Axis::Column => adr.0,
+            Axis::Row => adr.1,
+        }
+    }
+
+    fn with_index(&self, adr: &CellAddress, index: i32) -> CellAddress {
+        match self {
+            Axis::Column => CellAddress(index, adr.1),
+            Axis::Row => CellAddress(adr.0, index),
+        }
+    }
+
+    fn max(&self) -> i32 {
+        match self {
+            Axis::Column => MAX_COLUMN,
+            Axis::Row => MAX_ROW,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Axis::Column => "column",
+            Axis::Row => "row",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Axis::Column => "Column",
+            Axis::Row => "Row",
+        }
+    }
+}
+
+/// Insertion or deletion of `count` rows or columns starting at `at` on the named sheet
+struct Restructure {
+    axis: Axis,
+    at: i32,
+    count: i32,
+    delete: bool,
+    sheet: String,
+}
+
+impl Restructure {
+    /// Where a row or column ends up, if it survives
+    fn index(&self, index: i32) -> Option<i32> {
+        let (index, at, count) = (index as i64, self.at as i64, self.count as i64);
+        let moved = if index < at {
+            index
+        } else if !self.delete {
+            index + count
+        } else if index < at + count {
+            return None;
+        } else {
+            index - count
+        };
+
+        if moved > self.axis.max() as i64 { None } else { Some(moved as i32) }
+    }
+
+    fn reference(&self, reference: &CellReference, index: i32) -> CellReference {
+        CellReference { address: self.axis.with_index(&reference.address, index), ..reference.clone() }
+    }
+
+    fn is_edited(&self, reference: &CellReference) -> bool {
+        reference.sheet.as_ref().is_none_or(|sheet| *sheet == self.sheet)
+    }
+}
+
+impl ReferenceRewrite for Restructure {
+    fn cell(&mut self, reference: &CellReference) -> Result<Option<CellReference>, String> {
+        if !self.is_edited(reference) {
+            return Ok(Some(reference.clone()));
+        }
+        Ok(self.index(self.axis.index(&reference.address)).map(|index| self.reference(reference, index)))
+    }
+
+    fn range(&mut self, range: &CellRange) -> Result<Option<CellRange>, String> {
+        let (start, end) = (self.axis.index(&range.start.address), self.axis.index(&range.end.address));
+        let (first, last) = (start.min(end), start.max(end));
+
+        // Ranges into other sheets, 3-D ranges and whole rows or columns are left alone
+        if range.is_3d() || !self.is_edited(&range.start) || (first == 0 && last == self.axis.max()) {
+            return Ok(Some(range.clone()));
+        }
+
+        let (first, last) = if self.delete {
+            // Ends that were deleted move inwards to the nearest surviving row or column
+            let first = self.index(first).unwrap_or(self.at);
+            let last = self.index(last).unwrap_or(self.at - 1);
+            if first > last {
+                return Ok(None);
+            }
+            (first, last)
+        } else {
+            // The end of a range that reaches the edge of the grid stays on the edge
+            (self.index(first).unwrap_or(self.axis.max()), self.index(last).unwrap_or(self.axis.max()))
+        };
+
+        let (new_start, new_end) = if start <= end { (first, last) } else { (last, first) };
+        Ok(Some(CellRange { start: self.reference(&range.start, new_start), end: self.reference(&range.end, new_end) }))
+    }
+}
+
+/// Renaming or deletion of a sheet, given the sheet order beforehand
+struct SheetEdit {
+    order: Vec<String>,
+    name: String,
+    new_name: Option<String>,
+}
+
+impl SheetEdit {
+    /// The sheet an end of a 3-D range moves to when the sheet it is on is deleted: the next
+    /// sheet towards the other end
+    fn inwards(&self, sheet: &str, other: &str) -> Option<String> {
+        let index = self.order.iter().position(|name| name == sheet)?;
+        let other = self.order.iter().position(|name| name == other)?;
+        match index.cmp(&other) {
+            std::cmp::Ordering::Less => Some(self.order[index + 1].clone()),
+            std::cmp::Ordering::Greater => Some(self.order[index - 1].clone()),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+impl ReferenceRewrite for SheetEdit {
+    fn cell(&mut self, reference: &CellReference) -> Result<Option<CellReference>, String> {
+        if reference.sheet.as_ref() != Some(&self.name) {
+            return Ok(Some(reference.clone()));
+        }
+        Ok(self.new_name.as_ref().map(|name| CellReference { sheet: Some(name.clone()), ..reference.clone() }))
+    }
+
+    fn range(&mut self, range: &CellRange) -> Result<Option<CellRange>, String> {
+        let (first, last) = match (&range.start.sheet, &range.end.sheet) {
+            (Some(first), Some(last)) if *first == self.name || *last == self.name => (first, last),
+            _ => return Ok(Some(range.clone())),
+        };
+
+        let (first, last) = match &self.new_name {
+            Some(new_name) => {
+                let rename = |sheet: &String| if *sheet == self.name { new_name.clone() } else { sheet.clone() };
+                (rename(first), rename(last))
+            }
+            None if first == last => return Ok(None),
+            None if *first == self.name => match self.inwards(first, last) {
+                Some(first) => (first, last.clone()),
+                None => return Ok(None),
+            },
+            None => match self.inwards(last, first) {
+                Some(last) => (first.clone(), last),
+                None => return Ok(None),
+            },
+        };
+
+        Ok(Some(CellRange {
+            start: CellReference { sheet: Some(first), ..range.start.clone() },
+            end: CellReference { sheet: Some(last), ..range.end.clone() },
+        }))
+    }
+
+    fn name(&mut self, value: &NamedValue) -> Result<NamedValue, String> {
+        match (&value.sheet, &self.new_name) {
+            (Some(sheet), Some(new_name)) if *sheet == self.name => Ok(NamedValue { sheet: Some(new_name.clone()), ..value.clone() }),
+            _ => Ok(value.clone()),
+        }
+    }
+}
+
+/// Binds references that name no sheet to the given one
+struct BindSheet<'a>(&'a str);
+
+impl ReferenceRewrite for BindSheet<'_> {
+    fn cell(&mut self, reference: &CellReference) -> Result<Option<CellReference>, String> {
+        Ok(Some(self.bind(reference)))
+    }
+
+    fn range(&mut self, range: &CellRange) -> Result<Option<CellRange>, String> {
+        Ok(Some(CellRange { start: self.bind(&range.start), end: self.bind(&range.end) }))
+    }
+
+    fn name(&mut self, value: &NamedValue) -> Result<NamedValue, String> {
+        Ok(NamedValue { sheet: Some(value.sheet.clone().unwrap_or(self.0.to_string())), ..value.clone() })
+    }
+}
+
+impl BindSheet<'_> {
+    fn bind(&self, reference: &CellReference) -> CellReference {
+        match reference.sheet {
+            Some(_) => reference.clone(),
+            None => reference.clone().on_sheet(self.0),
+        }
+    }
+}
+
+fn bind(val: &dyn Evaluatable, sheet: &str) -> Box<dyn Evaluatable> {
+    rewrite_references(val, &mut BindSheet(sheet)).expect("binding references to a sheet cannot fail")
+}
+
+fn rewrite_map(
+    map: &Grid<Box<dyn Evaluatable>>,
+    rewrite: &mut dyn ReferenceRewrite,
+) -> Result<Grid<Box<dyn Evaluatable>>, String> {
+    map.iter()
+        .map(|(adr, val)| Ok((*adr, rewrite_references(val.as_ref(), rewrite)?)))
+        .collect()
+}
+
+fn rewrite_names(
+    names: &HashMap<String, Definition>,
+    rewrite: &mut dyn ReferenceRewrite,
+) -> Result<HashMap<String, Definition>, String> {
+    names.iter()
+        .map(|(name, definition)| Ok((name.clone(), definition.rewrite(rewrite)?)))
+        .collect()
+}
+
+/// Types of what the cells and names of a workbook hold, for checking a formula of a sheet
+struct Contents<'e> {
+    environment: &'e Environment,
+    sheet: &'e str,
+}
+
+impl Contents<'_> {
+    /// Types of a stored formula, without looking further into the cells it refers to
+    fn stored(val: &dyn Evaluatable) -> Result<Types, String> {
+        match val.expression() {
+            Expression::Primitive(val) => Ok(Type::of(val).into()),
+            _ => infer_type(val, &mut AnyScope),
+        }
+    }
+}
+
+impl TypeScope for Contents<'_> {
+    fn cell(&mut self, reference: &CellReference) -> Result<Types, String> {
+        let sheet = reference.sheet.as_deref().unwrap_or(self.sheet);
+        match self.environment.get_cell_on(sheet, &reference.address) {
+            Some(val) => Contents::stored(val),
+            None => Ok(Types::ANY),
+        }
     }
 
-    pub fn get_cell(&self, adr: &CellAddress) -> Option<&Box<dyn Evaluatable>> {
-        self.map.get(&adr)
+    fn name(&mut self, value: &NamedValue) -> Result<Types, String> {
+        match self.environment.lookup_name(&value.name, Some(value.sheet.as_deref().unwrap_or(self.sheet))) {
+            Ok(Definition::Cell(reference)) => self.cell(reference),
+            Ok(Definition::Range(range)) => Err(format!("Name {} refers to the range {} rather than a value", value.name, range)),
+            Ok(Definition::Constant(val)) => Contents::stored(val.as_ref()),
+            Err(_) => Ok(Types::ANY),
+        }
     }
 }
@@ -0,0 +1,86 @@
+//! Notifications of changes to an environment, for keeping a view of the grid up to date without
+//! redrawing every cell after each edit
+
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+
+use crate::model::{CellAddress, Primitive};
+
+/// Something that changed in an environment. Events of a transaction are delivered once it is
+/// committed, and not at all when it is rolled back.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    CellSet { sheet: String, adr: CellAddress },
+    CellCleared { sheet: String, adr: CellAddress },
+    /// The value of a cell differs after recalculation; `None` stands for an empty cell
+    ValueChanged {
+        sheet: String,
+        adr: CellAddress,
+        old: Option<Result<Primitive, String>>,
+        new: Option<Result<Primitive, String>>,
+    },
+    /// Sheets were added, removed, renamed or moved, or rows or columns inserted or deleted.
+    /// Cells may have moved, so values are not compared and every cell should be redrawn.
+    StructureChanged,
+}
+
+/// Identifies a subscription so it can be cancelled
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SubscriptionId(usize);
+
+/// Callbacks are removed once they return false
+type Callback = Box<dyn FnMut(&Event) -> bool + Send>;
+
+/// Callbacks of an environment. They are only called through `&mut self`, and are kept behind a
+/// mutex just so that the environment can be shared with the threads recalculating it.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    callbacks: Mutex<Vec<(SubscriptionId, Callback)>>,
+    next: usize,
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self, mut callback: impl FnMut(&Event) + Send + 'static) -> SubscriptionId {
+        self.add(Box::new(move |event| {
+            callback(event);
+            true
+        }))
+    }
+
+    /// Delivers events through a channel, until the receiver is dropped
+    pub fn channel(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.add(Box::new(move |event: &Event| sender.send(event.clone()).is_ok()));
+        receiver
+    }
+
+    /// Cancels a subscription, returning whether it existed
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let callbacks = self.callbacks();
+        let count = callbacks.len();
+        callbacks.retain(|(subscription, _)| *subscription != id);
+        callbacks.len() != count
+    }
+
+    pub fn is_empty(&mut self) -> bool {
+        self.callbacks().is_empty()
+    }
+
+    pub fn notify(&mut self, events: &[Event]) {
+        let callbacks = self.callbacks();
+        for event in events {
+            callbacks.retain_mut(|(_, callback)| callback(event));
+        }
+    }
+
+    fn callbacks(&mut self) -> &mut Vec<(SubscriptionId, Callback)> {
+        self.callbacks.get_mut().expect("callbacks are never locked, so never poisoned")
+    }
+
+    fn add(&mut self, callback: Callback) -> SubscriptionId {
+        let id = SubscriptionId(self.next);
+        self.next += 1;
+        self.callbacks().push((id, callback));
+        id
+    }
+}
@@ -0,0 +1,155 @@
+//! Comma-separated values, read into and written from a single sheet starting at `A1`. Fields
+//! holding numbers written in digits or `true`/`false` become those values, fields starting with
+//! `=` are formulas, and anything else is text. Quoted fields are always text, so text that would
+//! read as anything else is written quoted.
+
+use std::fs;
+use std::path::Path;
+
+use crate::environment::Environment;
+use crate::format::formula::{parse_formula, write_formula_on};
+use crate::model::{CellAddress, Evaluatable, Expression, Primitive};
+
+pub fn read_csv<P: AsRef<Path>>(path: P) -> Result<Environment, String> {
+    let text = fs::read_to_string(path.as_ref()).map_err(|e| format!("Cannot read {}: {e}", path.as_ref().display()))?;
+    from_text(&text)
+}
+
+/// Writes the active sheet
+pub fn write_csv<P: AsRef<Path>>(environment: &Environment, path: P) -> Result<(), String> {
+    fs::write(path.as_ref(), to_text(environment)).map_err(|e| format!("Cannot write {}: {e}", path.as_ref().display()))
+}
+
+pub fn from_text(text: &str) -> Result<Environment, String> {
+    let mut environment = Environment::init();
+    environment.begin("Load");
+
+    for (row, record) in records(text)?.into_iter().enumerate() {
+        for (column, field) in record.into_iter().enumerate() {
+            let adr = CellAddress(column as i32, row as i32);
+            let val = match field.quoted {
+                true => Some(Box::new(Primitive::String(field.text)) as Box<dyn Evaluatable>),
+                false => read_field(&field.text).map_err(|e| format!("Cell {adr}: {e}"))?,
+            };
+            if let Some(val) = val {
+                environment.set_cell(&adr, val)?;
+            }
+        }
+    }
+
+    environment.commit_unchecked()?;
+    // Loading is not something to undo
+    environment.clear_history();
+    Ok(environment)
+}
+
+fn read_field(field: &str) -> Result<Option<Box<dyn Evaluatable>>, String> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if let Some(formula) = field.strip_prefix('=') {
+        return parse_formula(formula).map(Some);
+    }
+
+    let primitive = if let Ok(val) = field.parse::<i32>() {
+        Primitive::Integer(val)
+    } else if let Some(val) = parse_float(field) {
+        Primitive::Float(val)
+    } else if field.eq_ignore_ascii_case("true") || field.eq_ignore_ascii_case("false") {
+        Primitive::Boolean(field.eq_ignore_ascii_case("true"))
+    } else {
+        Primitive::String(field.to_string())
+    };
+    Ok(Some(Box::new(primitive)))
+}
+
+/// Float written in digits, with an optional sign, point and exponent. Words such as `nan` and
+/// `inf`, which Rust would also take for floats, are left as text.
+fn parse_float(field: &str) -> Option<f32> {
+    let digits = field.bytes().any(|b| b.is_ascii_digit());
+    let numeric = field.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+    if digits && numeric { field.parse().ok() } else { None }
+}
+
+/// Field of a record, and whether it was quoted
+#[derive(Default)]
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+/// Splits text into records of fields, following RFC 4180 quoting
+fn records(text: &str) -> Result<Vec<Vec<Field>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = Field::default();
+    let mut chars = text.chars().peekable();
+    let mut quoting = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoting && chars.peek() == Some(&'"') => {
+                field.text.push('"');
+                chars.next();
+            }
+            '"' if quoting => quoting = false,
+            '"' if field.text.is_empty() && !field.quoted => {
+                quoting = true;
+                field.quoted = true;
+            }
+            ',' if !quoting => record.push(std::mem::take(&mut field)),
+            '\r' if !quoting && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoting => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.text.push(c),
+        }
+    }
+
+    if quoting {
+        return Err(String::from("Unterminated quoted field"));
+    }
+    if !field.text.is_empty() || field.quoted || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Writes the rectangle from `A1` to the last populated row and column of the active sheet
+pub fn to_text(environment: &Environment) -> String {
+    let sheet = environment.active_sheet();
+    let (columns, rows) = environment.cells()
+        .fold((0, 0), |(columns, rows), (adr, _)| (columns.max(adr.0 + 1), rows.max(adr.1 + 1)));
+
+    let mut out = String::new();
+    for row in 0..rows {
+        let fields: Vec<String> = (0..columns)
+            .map(|column| match environment.get_cell(&CellAddress(column, row)) {
+                Some(val) => field_text(val, sheet),
+                None => String::new(),
+            })
+            .collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Field holding a cell, which reads back as the same cell
+fn field_text(val: &dyn Evaluatable, sheet: &str) -> String {
+    match val.expression() {
+        Expression::Primitive(Primitive::String(text)) if reads_as_text(text) && !text.contains([',', '"', '\n', '\r']) => text.clone(),
+        Expression::Primitive(Primitive::String(text)) => format!("\"{}\"", text.replace('"', "\"\"")),
+        // Floats keep their point so that they read back as floats
+        Expression::Primitive(Primitive::Float(val)) if val.is_finite() => format!("{val:?}"),
+        Expression::Primitive(primitive @ (Primitive::Integer(_) | Primitive::Boolean(_))) => primitive.to_string(),
+        // Floats that are not numbers have no digits, so are written as formulas like any other
+        _ => format!("={}", write_formula_on(val, sheet)),
+    }
+}
+
+fn reads_as_text(field: &str) -> bool {
+    matches!(read_field(field), Ok(Some(val)) if matches!(val.expression(), Expression::Primitive(Primitive::String(_))))
+}
@@ -0,0 +1,390 @@
+//! Formulas as users type them, such as `A1 * 2`, `Inputs!B2 ** 2 >= 10` or `Sum(A1:A5)`.
+//! Operators are those of `Operation`, with unary operators binding tightest, then `**` (right
+//! associative), `* / %`, `+ -`, `<< >>`, `&`, `^`, `|`, comparisons, `&&` and finally `||`.
+//! Cells are written in A1 notation, optionally preceded by their sheet, and text is quoted with
+//! `"` using `\` to escape `"` and `\`. Floats that are not numbers are written `NaN`, `Inf` and
+//! `-Inf`. Written formulas only have the parentheses that
+//! precedence and associativity call for, and read back as the same expression.
+
+use crate::address::{quote_sheet_name, CellRange, CellReference};
+use crate::format::lexer::{describe, number, tokenize, Syntax, Token, Tokens};
+use crate::model::{CellValue, Evaluatable, Expression, InvalidReference, NamedValue, Operation, Primitive, Statistics};
+
+/// Parses a formula, leaving references without a sheet to be bound to the sheet of the cell
+/// that stores it
+pub fn parse_formula(text: &str) -> Result<Box<dyn Evaluatable>, String> {
+    let tokens = tokenize(text, &SYNTAX)?;
+    if tokens.contains(&Token::Operator("=")) {
+        return Err(format!("Use == to compare values in formula {text}"));
+    }
+
+    let mut parser = FormulaParser { tokens: Tokens::new(tokens) };
+    let expression = parser.or()?;
+    parser.tokens.finish(text)?;
+    Ok(expression)
+}
+
+/// Formula text that parses back to the same expression, naming the sheet of every reference
+/// that has one
+pub fn write_formula(expression: &dyn Evaluatable) -> String {
+    formula_text(expression, None)
+}
+
+/// Formula text for a cell of the given sheet, where references to that sheet need not name it
+pub fn write_formula_on(expression: &dyn Evaluatable, sheet: &str) -> String {
+    formula_text(expression, Some(sheet))
+}
+
+fn formula_text(expression: &dyn Evaluatable, host: Option<&str>) -> String {
+    match expression.expression() {
+        Expression::Primitive(primitive) => primitive_text(primitive),
+        Expression::CellValue(cell) => reference_text(&cell.0, host),
+        Expression::Statistics(statistics) => {
+            let name = match statistics {
+                Statistics::Max(_) => "Max",
+                Statistics::Min(_) => "Min",
+                Statistics::Mean(_) => "Mean",
+                Statistics::Sum(_) => "Sum",
+            };
+            format!("{name}({})", range_text(statistics.range(), host))
+        }
+        Expression::InvalidReference(_) => String::from("#REF!"),
+        Expression::NamedValue(value) => value.name.clone(),
+        Expression::Operation(operation) => {
+            if let Some(val) = negation(operation) {
+                return format!("-{}", operand_text(val, PREFIX, host));
+            }
+
+            let (val1, val2) = operation.operands();
+            match (symbol(operation), val2) {
+                (Symbol::Function(name), _) => format!("{name}({})", formula_text(val1, host)),
+                (Symbol::Prefix(symbol), _) => format!("{symbol}{}", operand_text(val1, PREFIX, host)),
+                // Operands on the side an operator does not group from need parentheses when
+                // they bind as tightly as the operator
+                (Symbol::Infix(symbol), Some(val2)) => {
+                    let level = infix_precedence(symbol);
+                    let (left, right) = if symbol == "**" { (level + 1, level) } else { (level, level + 1) };
+                    format!("{} {symbol} {}", operand_text(val1, left, host), operand_text(val2, right, host))
+                }
+                (Symbol::Infix(_), None) => unreachable!("infix operations have two operands"),
+            }
+        }
+    }
+}
+
+/// Text of an operand, parenthesized when it binds less tightly than `level`
+fn operand_text(expression: &dyn Evaluatable, level: u8, host: Option<&str>) -> String {
+    let text = formula_text(expression, host);
+    if precedence(expression) < level {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+const PREFIX: u8 = 11;
+const ATOM: u8 = 12;
+
+/// How tightly an expression binds, matching the levels of `FormulaParser`
+fn precedence(expression: &dyn Evaluatable) -> u8 {
+    match expression.expression() {
+        Expression::Operation(operation) if negation(operation).is_some() => PREFIX,
+        Expression::Operation(operation) => match symbol(operation) {
+            Symbol::Infix(symbol) => infix_precedence(symbol),
+            Symbol::Prefix(_) => PREFIX,
+            Symbol::Function(_) => ATOM,
+        },
+        _ => ATOM,
+    }
+}
+
+fn infix_precedence(symbol: &str) -> u8 {
+    match symbol {
+        "||" => 1,
+        "&&" => 2,
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => 3,
+        "|" => 4,
+        "^" => 5,
+        "&" => 6,
+        "<<" | ">>" => 7,
+        "+" | "-" => 8,
+        "*" | "/" | "%" => 9,
+        "**" => 10,
+        _ => unreachable!("{symbol} is not an infix operator"),
+    }
+}
+
+/// Operand of a subtraction from 0, which is how `-A1` is read. Subtracting a number written
+/// without a sign stays a subtraction, since `-5` reads as a negative number instead.
+fn negation(operation: &Operation) -> Option<&dyn Evaluatable> {
+    let Operation::Subtract(val1, val2) = operation else {
+        return None;
+    };
+    let unsigned = match val2.expression() {
+        Expression::Primitive(Primitive::Integer(val)) => *val >= 0,
+        Expression::Primitive(Primitive::Float(val)) => val.is_nan() || val.is_sign_positive(),
+        _ => false,
+    };
+    match val1.expression() {
+        Expression::Primitive(Primitive::Integer(0)) if !unsigned => Some(val2.as_ref()),
+        _ => None,
+    }
+}
+
+fn primitive_text(primitive: &Primitive) -> String {
+    match primitive {
+        Primitive::Integer(val) => val.to_string(),
+        // Floats always have a point or an exponent so that they read back as floats
+        Primitive::Float(val) if val.is_nan() => String::from("NaN"),
+        Primitive::Float(val) if val.is_infinite() => String::from(if *val < 0.0 { "-Inf" } else { "Inf" }),
+        Primitive::Float(val) => format!("{val:?}"),
+        Primitive::Boolean(val) => val.to_string(),
+        Primitive::String(val) => format!("\"{}\"", val.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+fn reference_text(reference: &CellReference, host: Option<&str>) -> String {
+    if reference.sheet.is_some() && reference.sheet.as_deref() == host {
+        CellReference { sheet: None, ..reference.clone() }.to_string()
+    } else {
+        reference.to_string()
+    }
+}
+
+fn range_text(range: &CellRange, host: Option<&str>) -> String {
+    if !range.is_3d() && range.start.sheet.is_some() && range.start.sheet.as_deref() == host {
+        CellRange { start: CellReference { sheet: None, ..range.start.clone() }, end: CellReference { sheet: None, ..range.end.clone() } }.to_string()
+    } else {
+        range.to_string()
+    }
+}
+
+enum Symbol {
+    Infix(&'static str),
+    Prefix(&'static str),
+    Function(&'static str),
+}
+
+fn symbol(operation: &Operation) -> Symbol {
+    match operation {
+        Operation::Add(..) => Symbol::Infix("+"),
+        Operation::Subtract(..) => Symbol::Infix("-"),
+        Operation::Multiply(..) => Symbol::Infix("*"),
+        Operation::Divide(..) => Symbol::Infix("/"),
+        Operation::Modulus(..) => Symbol::Infix("%"),
+        Operation::Power(..) => Symbol::Infix("**"),
+        Operation::LogicalAnd(..) => Symbol::Infix("&&"),
+        Operation::LogicalOr(..) => Symbol::Infix("||"),
+        Operation::LogicalNot(..) => Symbol::Prefix("!"),
+        Operation::BitwiseAnd(..) => Symbol::Infix("&"),
+        Operation::BitwiseOr(..) => Symbol::Infix("|"),
+        Operation::BitwiseXor(..) => Symbol::Infix("^"),
+        Operation::BitwiseNot(..) => Symbol::Prefix("~"),
+        Operation::LeftShift(..) => Symbol::Infix("<<"),
+        Operation::RightShift(..) => Symbol::Infix(">>"),
+        Operation::Equals(..) => Symbol::Infix("=="),
+        Operation::NotEquals(..) => Symbol::Infix("!="),
+        Operation::LessThan(..) => Symbol::Infix("<"),
+        Operation::LessThanOrEqual(..) => Symbol::Infix("<="),
+        Operation::GreaterThan(..) => Symbol::Infix(">"),
+        Operation::GreaterThanOrEqual(..) => Symbol::Infix(">="),
+        Operation::FloatToInt(..) => Symbol::Function("FloatToInt"),
+        Operation::IntToFloat(..) => Symbol::Function("IntToFloat"),
+    }
+}
+
+const OPERATORS: [&str; 22] = [
+    "**", "&&", "||", "<<", ">>", "==", "!=", "<=", ">=",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "=",
+];
+
+const SYNTAX: Syntax = Syntax { operators: &OPERATORS, backslash_escapes: true, bracketed_references: false };
+
+struct FormulaParser {
+    tokens: Tokens,
+}
+
+type Binary = fn(Box<dyn Evaluatable>, Box<dyn Evaluatable>) -> Operation;
+
+impl FormulaParser {
+    /// Left associative operators of one precedence level
+    fn binary(&mut self, operators: &[(&str, Binary)], operand: fn(&mut Self) -> Result<Box<dyn Evaluatable>, String>) -> Result<Box<dyn Evaluatable>, String> {
+        let symbols: Vec<&str> = operators.iter().map(|(symbol, _)| *symbol).collect();
+        let mut left = operand(self)?;
+
+        while let Some(symbol) = self.tokens.operator(&symbols) {
+            let right = operand(self)?;
+            let (_, operation) = operators.iter().find(|(candidate, _)| *candidate == symbol).expect("operator was matched");
+            left = Box::new(operation(left, right));
+        }
+
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("||", Operation::LogicalOr)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("&&", Operation::LogicalAnd)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[
+            ("==", Operation::Equals),
+            ("!=", Operation::NotEquals),
+            ("<", Operation::LessThan),
+            ("<=", Operation::LessThanOrEqual),
+            (">", Operation::GreaterThan),
+            (">=", Operation::GreaterThanOrEqual),
+        ], Self::bitwise_or)
+    }
+
+    fn bitwise_or(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("|", Operation::BitwiseOr)], Self::bitwise_xor)
+    }
+
+    fn bitwise_xor(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("^", Operation::BitwiseXor)], Self::bitwise_and)
+    }
+
+    fn bitwise_and(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("&", Operation::BitwiseAnd)], Self::shift)
+    }
+
+    fn shift(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("<<", Operation::LeftShift), (">>", Operation::RightShift)], Self::additive)
+    }
+
+    fn additive(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("+", Operation::Add), ("-", Operation::Subtract)], Self::multiplicative)
+    }
+
+    fn multiplicative(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        self.binary(&[("*", Operation::Multiply), ("/", Operation::Divide), ("%", Operation::Modulus)], Self::power)
+    }
+
+    fn power(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        let left = self.prefix()?;
+
+        if self.tokens.operator(&["**"]).is_some() {
+            return Ok(Box::new(Operation::Power(left, self.power()?)));
+        }
+
+        Ok(left)
+    }
+
+    fn prefix(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        match self.tokens.operator(&["-", "!", "~"]) {
+            Some("-") => {
+                // A negated number is a negative literal
+                let literal = match self.tokens.peek() {
+                    Some(Token::Number(text)) => Some(number(&format!("-{text}"))?),
+                    Some(Token::Word(word)) if word.eq_ignore_ascii_case("inf") => Some(Primitive::Float(f32::NEG_INFINITY)),
+                    _ => None,
+                };
+                if let Some(literal) = literal {
+                    self.tokens.skip();
+                    return Ok(Box::new(literal));
+                }
+                Ok(Box::new(Operation::Subtract(Box::new(Primitive::Integer(0)), self.prefix()?)))
+            }
+            Some("!") => Ok(Box::new(Operation::LogicalNot(self.prefix()?))),
+            Some(_) => Ok(Box::new(Operation::BitwiseNot(self.prefix()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        match self.tokens.next()? {
+            Token::Number(text) => Ok(Box::new(number(&text)?)),
+            Token::Text(text) => Ok(Box::new(Primitive::String(text))),
+            Token::Invalid => Ok(Box::new(InvalidReference)),
+            Token::Sheet(sheet) => match self.tokens.next()? {
+                Token::Word(cell) => {
+                    let reference: CellReference = cell.parse()?;
+                    self.single_cell(&reference.on_sheet(&sheet))
+                }
+                token => Err(format!("Expected a cell after sheet {} but found {}", quote_sheet_name(&sheet), describe(&token))),
+            },
+            Token::Open => {
+                let expression = self.or()?;
+                self.tokens.expect(Token::Close)?;
+                Ok(expression)
+            }
+            Token::Word(word) if self.tokens.peek() == Some(&Token::Open) => {
+                self.tokens.skip();
+                self.function(&word)
+            }
+            Token::Word(word) => {
+                if word.eq_ignore_ascii_case("true") || word.eq_ignore_ascii_case("false") {
+                    return Ok(Box::new(Primitive::Boolean(word.eq_ignore_ascii_case("true"))));
+                }
+                if word.eq_ignore_ascii_case("nan") {
+                    return Ok(Box::new(Primitive::Float(f32::NAN)));
+                }
+                if word.eq_ignore_ascii_case("inf") {
+                    return Ok(Box::new(Primitive::Float(f32::INFINITY)));
+                }
+                match word.parse::<CellReference>() {
+                    Ok(reference) => self.single_cell(&reference),
+                    Err(_) => Ok(Box::new(NamedValue::new(&word))),
+                }
+            }
+            token => Err(format!("Unexpected {} in formula", describe(&token))),
+        }
+    }
+
+    fn single_cell(&self, reference: &CellReference) -> Result<Box<dyn Evaluatable>, String> {
+        if self.tokens.peek() == Some(&Token::Colon) {
+            return Err(format!("Range starting at {reference} can only be used as a function argument"));
+        }
+        Ok(Box::new(CellValue(reference.clone())))
+    }
+
+    fn function(&mut self, name: &str) -> Result<Box<dyn Evaluatable>, String> {
+        let function = name.to_lowercase();
+        let statistics: Option<fn(CellRange) -> Statistics> = match function.as_str() {
+            "sum" => Some(Statistics::Sum),
+            "max" => Some(Statistics::Max),
+            "min" => Some(Statistics::Min),
+            "mean" => Some(Statistics::Mean),
+            _ => None,
+        };
+
+        if let Some(statistics) = statistics {
+            let range = self.range()?;
+            self.tokens.expect(Token::Close)?;
+            return Ok(Box::new(statistics(range)));
+        }
+
+        let argument = self.or()?;
+        self.tokens.expect(Token::Close)?;
+        match function.as_str() {
+            "floattoint" => Ok(Box::new(Operation::FloatToInt(argument))),
+            "inttofloat" => Ok(Box::new(Operation::IntToFloat(argument))),
+            _ => Err(format!("Unknown function {name}")),
+        }
+    }
+
+    /// Range argument such as `A1:B5`, `B:B`, `Inputs!A1:A3`, `Q1:Q4!C2` or `'Tax rates':Q4!C2`,
+    /// read back into text for `CellRange` to parse
+    fn range(&mut self) -> Result<CellRange, String> {
+        let mut text = String::new();
+        while let Some(token) = self.tokens.peek() {
+            match token {
+                Token::Word(word) | Token::Number(word) => text.push_str(word),
+                Token::Sheet(sheet) => text.push_str(&format!("{}!", quote_sheet_name(sheet))),
+                Token::FirstSheet(sheet) => text.push_str(&quote_sheet_name(sheet)),
+                Token::Colon => text.push(':'),
+                _ => break,
+            }
+            self.tokens.skip();
+        }
+
+        if text.is_empty() {
+            return Err(String::from("Expected a range of cells"));
+        }
+        text.parse()
+    }
+}
@@ -0,0 +1,153 @@
+//! The `.grid` text format: a workbook written as the formulas of its cells, one per line, so
+//! models can be read, diffed and edited by hand.
+//!
+//! ```text
+//! # Comments start with a hash
+//! sheet Inputs
+//! A1 = 5
+//! B1 = A1 * 2
+//! name Rate = 0.25
+//! sheet 'Tax rates'
+//! A1 = Sum(Inputs!A1:B1) * Rate
+//! workbook
+//! name Total = 'Tax rates'!A1
+//! ```
+//!
+//! Cells and names follow the `sheet` line of the sheet they belong to, and names of the whole
+//! workbook follow a `workbook` line. A name refers to a cell or a range when its definition is
+//! one, and is a constant formula otherwise.
+
+use std::fs;
+use std::path::Path;
+
+use crate::address::{quote_sheet_name, CellRange, CellReference};
+use crate::environment::{Definition, Environment, NameScope};
+use crate::format::formula::{parse_formula, write_formula, write_formula_on};
+
+pub fn read_grid<P: AsRef<Path>>(path: P) -> Result<Environment, String> {
+    let text = fs::read_to_string(path.as_ref()).map_err(|e| format!("Cannot read {}: {e}", path.as_ref().display()))?;
+    from_text(&text)
+}
+
+pub fn write_grid<P: AsRef<Path>>(environment: &Environment, path: P) -> Result<(), String> {
+    fs::write(path.as_ref(), to_text(environment)).map_err(|e| format!("Cannot write {}: {e}", path.as_ref().display()))
+}
+
+/// Reads a workbook, in a single transaction so it is only calculated once everything is read
+pub fn from_text(text: &str) -> Result<Environment, String> {
+    let mut environment = Environment::init();
+    environment.begin("Load");
+
+    // The first sheet line names the sheet every workbook starts with
+    let mut scope = NameScope::Sheet(environment.active_sheet().to_string());
+    let mut sheets = 0;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        read_line(line, &mut environment, &mut scope, &mut sheets).map_err(|e| format!("Line {}: {e}", number + 1))?;
+    }
+
+    environment.commit_unchecked()?;
+    let first = environment.sheet_names().next().expect("a workbook has a sheet").to_string();
+    environment.set_active_sheet(&first)?;
+    // Loading is not something to undo
+    environment.clear_history();
+    Ok(environment)
+}
+
+fn read_line(line: &str, environment: &mut Environment, scope: &mut NameScope, sheets: &mut usize) -> Result<(), String> {
+    if line == "workbook" {
+        *scope = NameScope::Workbook;
+    } else if let Some(name) = line.strip_prefix("sheet ") {
+        let name = sheet_name(name.trim());
+        if *sheets == 0 {
+            let first = environment.active_sheet().to_string();
+            environment.rename_sheet(&first, &name)?;
+        } else {
+            environment.add_sheet(&name)?;
+        }
+        environment.set_active_sheet(&name)?;
+        *sheets += 1;
+        *scope = NameScope::Sheet(name);
+    } else if let Some(definition) = line.strip_prefix("name ") {
+        let (name, text) = definition.split_once('=').ok_or(String::from("Expected name NAME = DEFINITION"))?;
+        environment.define_name(name.trim(), scope, parse_definition(text.trim())?)?;
+    } else {
+        let NameScope::Sheet(sheet) = scope else {
+            return Err(String::from("Cells must follow the sheet line of their sheet"));
+        };
+        let (cell, formula) = line.split_once('=').ok_or(String::from("Expected CELL = FORMULA"))?;
+        let reference: CellReference = cell.trim().parse()?;
+        if reference.sheet.is_some() {
+            return Err(format!("Cell {} must not name a sheet", cell.trim()));
+        }
+        environment.set_cell_on(sheet, &reference.address, parse_formula(formula.trim())?)?;
+    }
+    Ok(())
+}
+
+fn sheet_name(text: &str) -> String {
+    match text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+        Some(quoted) => quoted.replace("''", "'"),
+        None => text.to_string(),
+    }
+}
+
+fn parse_definition(text: &str) -> Result<Definition, String> {
+    if let Ok(reference) = text.parse::<CellReference>() {
+        return Ok(Definition::Cell(reference));
+    }
+    if text.contains(':') {
+        if let Ok(range) = text.parse::<CellRange>() {
+            return Ok(Definition::Range(range));
+        }
+    }
+    Ok(Definition::Constant(parse_formula(text)?))
+}
+
+/// Writes every sheet in workbook order, then the workbook's names
+pub fn to_text(environment: &Environment) -> String {
+    let mut out = String::new();
+
+    for sheet in environment.sheet_names() {
+        out.push_str(&format!("sheet {}\n", quote_sheet_name(sheet)));
+
+        let mut cells: Vec<_> = environment.cells_on(sheet).collect();
+        cells.sort_by_key(|(adr, _)| (adr.1, adr.0));
+        for (adr, val) in cells {
+            out.push_str(&format!("{} = {}\n", CellReference::relative(*adr), write_formula_on(val, sheet)));
+        }
+        write_names(environment, &NameScope::Sheet(sheet.to_string()), Some(sheet), &mut out);
+    }
+
+    if environment.names(&NameScope::Workbook).next().is_some() {
+        out.push_str("workbook\n");
+        write_names(environment, &NameScope::Workbook, None, &mut out);
+    }
+    out
+}
+
+fn write_names(environment: &Environment, scope: &NameScope, host: Option<&str>, out: &mut String) {
+    let mut names: Vec<_> = environment.names(scope).collect();
+    names.sort_by_key(|(name, _)| *name);
+
+    for (name, definition) in names {
+        let text = match definition {
+            Definition::Cell(reference) if reference.sheet.as_deref() == host => CellReference { sheet: None, ..reference.clone() }.to_string(),
+            Definition::Cell(reference) => reference.to_string(),
+            Definition::Range(range) if !range.is_3d() && range.start.sheet.as_deref() == host => {
+                CellRange { start: CellReference { sheet: None, ..range.start.clone() }, end: CellReference { sheet: None, ..range.end.clone() } }.to_string()
+            }
+            Definition::Range(range) => range.to_string(),
+            Definition::Constant(val) => match host {
+                Some(sheet) => write_formula_on(val.as_ref(), sheet),
+                None => write_formula(val.as_ref()),
+            },
+        };
+        out.push_str(&format!("name {name} = {text}\n"));
+    }
+}
@@ -0,0 +1,41 @@
+//! JSON text for values, for the command line tools that report to other programs
+
+use crate::model::Primitive;
+
+/// JSON has no infinities or NaN, so those are written as null
+pub fn primitive(val: &Primitive) -> String {
+    match val {
+        Primitive::Integer(val) => val.to_string(),
+        Primitive::Float(val) if val.is_finite() => format!("{val:?}"),
+        Primitive::Float(_) => String::from("null"),
+        Primitive::Boolean(val) => val.to_string(),
+        Primitive::String(text) => string(text),
+    }
+}
+
+/// The value of a cell: null when it is empty, and an object with an `error` member when it
+/// evaluates to an error
+pub fn value(val: &Option<Result<Primitive, String>>) -> String {
+    match val {
+        Some(Ok(val)) => primitive(val),
+        Some(Err(e)) => format!("{{\"error\": {}}}", string(e)),
+        None => String::from("null"),
+    }
+}
+
+pub fn string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,241 @@
+//! Tokens of formula text and the reading of them, shared by the native syntax of `formula` and
+//! the OpenFormula syntax of `ods`. The two differ in their operators, in how text is escaped and
+//! in how references are written; their grammars are left to each.
+
+use crate::address::quote_sheet_name;
+use crate::model::Primitive;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Token {
+    Number(String),
+    Text(String),
+    /// Word such as a cell, a name or a function
+    Word(String),
+    /// Sheet name followed by `!`
+    Sheet(String),
+    /// Quoted sheet name followed by `:`, starting a 3-D range such as `'Tax rates':Q4!B2:B9`
+    FirstSheet(String),
+    /// Contents of a bracketed reference such as `[$Inputs.B2]`
+    Reference(String),
+    Invalid,
+    Operator(&'static str),
+    Open,
+    Close,
+    Colon,
+    Separator,
+}
+
+/// What sets a syntax apart as far as its tokens go
+pub(super) struct Syntax {
+    /// Operators, each listed before any it starts with
+    pub operators: &'static [&'static str],
+    /// Whether `"` and `\` in text are escaped with `\`, rather than `"` being doubled
+    pub backslash_escapes: bool,
+    /// Whether references are bracketed and arguments separated by `;`, rather than references
+    /// being words such as `$A1` or `'Tax rates'!B2` and `#REF!`, and ranges using `:`
+    pub bracketed_references: bool,
+}
+
+pub(super) fn tokenize(text: &str, syntax: &Syntax) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+
+    let bare = !syntax.bracketed_references;
+    // A sheet name is followed by `!`, which is otherwise the start of `!=` or a negation
+    let sheet_follows = |pos: usize| chars.get(pos) == Some(&'!') && chars.get(pos + 1) != Some(&'=');
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(pos + 1).is_some_and(|c| c.is_ascii_digit())) {
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.') {
+                pos += 1;
+            }
+            if pos < chars.len() && (chars[pos] == 'e' || chars[pos] == 'E') {
+                pos += 1;
+                if pos < chars.len() && (chars[pos] == '+' || chars[pos] == '-') {
+                    pos += 1;
+                }
+                while pos < chars.len() && chars[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+            }
+            tokens.push(Token::Number(chars[start..pos].iter().collect()));
+        } else if c == '"' {
+            let value = quoted(&chars, &mut pos, syntax.backslash_escapes).ok_or(format!("Unterminated text in formula {text}"))?;
+            tokens.push(Token::Text(value));
+        } else if c == '\'' && bare {
+            let name = quoted(&chars, &mut pos, false).ok_or(format!("Unterminated sheet name in formula {text}"))?;
+            if chars.get(pos) == Some(&':') {
+                tokens.push(Token::FirstSheet(name));
+            } else if sheet_follows(pos) {
+                pos += 1;
+                tokens.push(Token::Sheet(name));
+            } else {
+                return Err(format!("Expected '!' or ':' after sheet name '{name}' in formula {text}"));
+            }
+        } else if c == '[' && !bare {
+            let mut quoted = false;
+            pos += 1;
+            while pos < chars.len() && (quoted || chars[pos] != ']') {
+                if chars[pos] == '\'' {
+                    quoted = !quoted;
+                }
+                pos += 1;
+            }
+            if pos == chars.len() {
+                return Err(format!("Unterminated reference in formula {text}"));
+            }
+            tokens.push(Token::Reference(chars[start + 1..pos].iter().collect()));
+            pos += 1;
+        } else if c.is_alphabetic() || c == '_' || (c == '$' && bare) {
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_' || chars[pos] == '.' || (chars[pos] == '$' && bare)) {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            if bare && sheet_follows(pos) {
+                pos += 1;
+                tokens.push(Token::Sheet(word));
+            } else {
+                tokens.push(Token::Word(word));
+            }
+        } else if bare && chars[pos..].starts_with(&['#', 'R', 'E', 'F', '!']) {
+            tokens.push(Token::Invalid);
+            pos += 5;
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            pos += 1;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            pos += 1;
+        } else if c == ':' && bare {
+            tokens.push(Token::Colon);
+            pos += 1;
+        } else if c == ';' && !bare {
+            tokens.push(Token::Separator);
+            pos += 1;
+        } else {
+            let rest: String = chars[pos..].iter().take(2).collect();
+            let operator = syntax.operators.iter()
+                .find(|operator| rest.starts_with(*operator))
+                .ok_or(format!("Unexpected character '{c}' in formula {text}"))?;
+            tokens.push(Token::Operator(operator));
+            pos += operator.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Text between the quote at `pos` and the matching one, moving `pos` past it. Inside, the quote
+/// is written twice or, with backslash escapes, any character is escaped with `\`. `None` if the
+/// text is not terminated.
+fn quoted(chars: &[char], pos: &mut usize, backslash_escapes: bool) -> Option<String> {
+    let quote = chars[*pos];
+    let mut value = String::new();
+    *pos += 1;
+    loop {
+        match *chars.get(*pos)? {
+            '\\' if backslash_escapes => {
+                value.push(*chars.get(*pos + 1)?);
+                *pos += 2;
+            }
+            c if c == quote && !backslash_escapes && chars.get(*pos + 1) == Some(&quote) => {
+                value.push(quote);
+                *pos += 2;
+            }
+            c if c == quote => {
+                *pos += 1;
+                return Some(value);
+            }
+            c => {
+                value.push(c);
+                *pos += 1;
+            }
+        }
+    }
+}
+
+/// Tokens of a formula, read front to back by a grammar
+pub(super) struct Tokens {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Tokens {
+    pub fn new(tokens: Vec<Token>) -> Tokens {
+        Tokens { tokens, pos: 0 }
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    pub fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("Unexpected end of formula")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Moves past the token `peek` returned
+    pub fn skip(&mut self) {
+        self.pos += 1;
+    }
+
+    pub fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("Expected {} but found {}", describe(&expected), describe(&token))),
+        }
+    }
+
+    /// Moves past the next token if it is one of the given operators, returning it
+    pub fn operator(&mut self, operators: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                let operator = *operator;
+                self.pos += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    /// Fails unless every token of the formula has been read
+    pub fn finish(&self, text: &str) -> Result<(), String> {
+        if self.pos < self.tokens.len() {
+            return Err(format!("Unexpected trailing input in formula {text}"));
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(text) | Token::Word(text) => text.clone(),
+        Token::Text(text) => format!("\"{text}\""),
+        Token::Sheet(sheet) => format!("{}!", quote_sheet_name(sheet)),
+        Token::FirstSheet(sheet) => quote_sheet_name(sheet),
+        Token::Reference(reference) => format!("[{reference}]"),
+        Token::Invalid => String::from("#REF!"),
+        Token::Operator(operator) => operator.to_string(),
+        Token::Open => String::from("'('"),
+        Token::Close => String::from("')'"),
+        Token::Colon => String::from("':'"),
+        Token::Separator => String::from("';'"),
+    }
+}
+
+/// Number as the tokenizer reads it: a float if it has a point or an exponent, and an integer
+/// otherwise
+pub(super) fn number(text: &str) -> Result<Primitive, String> {
+    if text.contains(['.', 'e', 'E']) {
+        text.parse::<f32>().map(Primitive::Float).map_err(|_| format!("Invalid number {text}"))
+    } else {
+        text.parse::<i32>().map(Primitive::Integer).map_err(|_| format!("Number {text} is too large"))
+    }
+}
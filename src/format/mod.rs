@@ -0,0 +1,44 @@
+//! Reading, writing and rendering grids in external formats
+
+use std::path::Path;
+
+use crate::environment::Environment;
+
+pub mod csv;
+pub mod formula;
+pub mod grid;
+pub mod json;
+pub mod ods;
+pub mod table;
+
+mod lexer;
+mod xml;
+mod zip;
+
+/// Reads a `.grid`, `.csv` or `.ods` file, telling the format from its extension
+pub fn read_workbook<P: AsRef<Path>>(path: P) -> Result<Environment, String> {
+    match extension(path.as_ref()).as_deref() {
+        Some("grid") => grid::read_grid(path),
+        Some("csv") => csv::read_csv(path),
+        Some("ods") => ods::read_ods(path),
+        _ => Err(unknown_format(path.as_ref())),
+    }
+}
+
+/// Writes a `.grid`, `.csv` or `.ods` file, telling the format from its extension
+pub fn write_workbook<P: AsRef<Path>>(environment: &Environment, path: P) -> Result<(), String> {
+    match extension(path.as_ref()).as_deref() {
+        Some("grid") => grid::write_grid(environment, path),
+        Some("csv") => csv::write_csv(environment, path),
+        Some("ods") => ods::write_ods(environment, path),
+        _ => Err(unknown_format(path.as_ref())),
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+fn unknown_format(path: &Path) -> String {
+    format!("Cannot tell the format of {}; expected .grid, .csv or .ods", path.display())
+}
@@ -0,0 +1,828 @@
+//! OpenDocument spreadsheet (.ods) import and export.
+//!
+//! Every table of a document becomes a sheet. Cell values map onto `Primitive` as follows:
+//! `float`, `percentage` and `currency` become `Integer` when the stored value is written without a
+//! fraction or exponent and fits, `Float` otherwise; `boolean` becomes `Boolean`; `string`, `date`
+//! and `time` become `String`. Floats are always exported with a fraction so they read back as
+//! floats.
+//!
+//! Formulas use the OpenFormula syntax (`of:=[.A1]+[.B2]`). Operators without an OpenFormula
+//! counterpart are written as `MOD`, `AND`, `BITLSHIFT`, `TRUNC`, ... and the two that have none
+//! at all (`BitwiseNot` and `IntToFloat`) as the extension functions `ORG.GRIDKID.BITNOT` and
+//! `ORG.GRIDKID.FLOAT`. Note that `MOD` keeps gridkid's truncating remainder on import.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::address::{quote_sheet_name, CellRange, CellReference};
+use crate::environment::{Definition, Environment, NameScope};
+use crate::format::lexer::{self, describe, tokenize, Syntax, Token, Tokens};
+use crate::format::xml::{self, Element, Node};
+use crate::format::zip;
+use crate::model::{CellAddress, CellValue, Evaluatable, Expression, InvalidReference, NamedValue, Operation, Primitive, Statistics};
+
+const MIME_TYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+const MANIFEST: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+    r#"<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">"#,
+    r#"<manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>"#,
+    r#"<manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>"#,
+    r#"</manifest:manifest>"#,
+);
+
+/// Reads every sheet of an .ods file into a new environment
+pub fn read_ods<P: AsRef<Path>>(path: P) -> Result<Environment, String> {
+    let bytes = fs::read(&path).map_err(|e| format!("Cannot read {}: {}", path.as_ref().display(), e))?;
+    from_bytes(&bytes)
+}
+
+/// Writes every sheet of the environment to an .ods file
+pub fn write_ods<P: AsRef<Path>>(environment: &Environment, path: P) -> Result<(), String> {
+    let bytes = to_bytes(environment)?;
+    fs::write(&path, bytes).map_err(|e| format!("Cannot write {}: {}", path.as_ref().display(), e))
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<Environment, String> {
+    let content = zip::read_entries(bytes)?
+        .into_iter()
+        .find(|(name, _)| name == "content.xml")
+        .map(|(_, content)| content)
+        .ok_or("Not an OpenDocument file: content.xml is missing")?;
+    let content = String::from_utf8(content).map_err(|_| String::from("content.xml is not valid UTF-8"))?;
+    let document = xml::parse(&content)?;
+
+    let spreadsheet = document.elements()
+        .filter(|element| element.name == "office:body")
+        .flat_map(|body| body.elements())
+        .find(|element| element.name == "office:spreadsheet")
+        .ok_or("Document does not contain a spreadsheet")?;
+    let tables: Vec<&Element> = spreadsheet.elements()
+        .filter(|element| element.name == "table:table")
+        .collect();
+    if tables.is_empty() {
+        return Err(String::from("Document does not contain a spreadsheet table"));
+    }
+
+    // Sheets are all created up front so that formulas can refer to later ones
+    let mut environment = Environment::init();
+    environment.begin("Load");
+    let mut names = Vec::new();
+    for (index, table) in tables.iter().enumerate() {
+        let name = table.attribute("table:name").map(String::from).unwrap_or(format!("Sheet{}", index + 1));
+        if index == 0 {
+            environment.rename_sheet(environment.active_sheet().to_string().as_str(), &name)?;
+        } else {
+            environment.add_sheet(&name)?;
+        }
+        names.push(name);
+    }
+
+    for (table, name) in tables.iter().zip(&names) {
+        read_table(table, name, &mut environment)?;
+        read_names(table, &NameScope::Sheet(name.clone()), &mut environment)?;
+    }
+    read_names(spreadsheet, &NameScope::Workbook, &mut environment)?;
+    environment.commit_unchecked()?;
+
+    // Loading is not something to undo
+    environment.clear_history();
+    Ok(environment)
+}
+
+pub fn to_bytes(environment: &Environment) -> Result<Vec<u8>, String> {
+    let mut content = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    content_element(environment)?.write(&mut content);
+
+    zip::write_entries(&[
+        ("mimetype", MIME_TYPE.as_bytes()),
+        ("META-INF/manifest.xml", MANIFEST.as_bytes()),
+        ("content.xml", content.as_bytes()),
+    ])
+}
+
+/// Converts a gridkid expression into an OpenFormula expression, including the `of:=` prefix.
+/// References naming a sheet are written with it, as in `[$Inputs.B2]`.
+pub fn write_formula(expression: &dyn Evaluatable) -> Result<String, String> {
+    Ok(format!("of:={}", formula_text(expression, None)?))
+}
+
+/// Converts an OpenFormula expression such as `of:=[.A1]+[$Inputs.B2]` into a gridkid
+/// expression
+pub fn parse_formula(formula: &str) -> Result<Box<dyn Evaluatable>, String> {
+    let text = formula.strip_prefix("of:").or_else(|| formula.strip_prefix("oooc:")).unwrap_or(formula);
+    if text.starts_with("msoxl:") {
+        return Err(format!("Unsupported formula namespace in {formula}"));
+    }
+    let text = text.strip_prefix('=').ok_or(format!("Formula must start with '=': {formula}"))?;
+
+    let mut parser = FormulaParser { tokens: Tokens::new(tokenize(text, &SYNTAX)?) };
+    let expression = parser.comparison()?;
+    parser.tokens.finish(formula)?;
+    Ok(expression)
+}
+
+/// Most cells a table may fill, counting each repetition of a populated cell, so that a small
+/// document cannot expand into an enormous workbook
+const MAX_POPULATED_CELLS: u64 = 1 << 22;
+
+fn read_table(table: &Element, sheet: &str, environment: &mut Environment) -> Result<(), String> {
+    let mut rows = Vec::new();
+    collect_rows(table, &mut rows);
+
+    let too_many = || format!("Table {sheet} fills more than {MAX_POPULATED_CELLS} cells");
+    let mut populated = 0u64;
+    let mut row = 0;
+    for row_element in rows {
+        let row_repeat = repeat_count(row_element, "table:number-rows-repeated")?;
+
+        let mut cells = Vec::new();
+        let mut column = 0;
+        for cell_element in row_element.elements() {
+            if cell_element.name != "table:table-cell" && cell_element.name != "table:covered-table-cell" {
+                continue;
+            }
+
+            let column_repeat = repeat_count(cell_element, "table:number-columns-repeated")?;
+            if is_populated(cell_element) {
+                if cells.len() as u64 + column_repeat as u64 > MAX_POPULATED_CELLS {
+                    return Err(too_many());
+                }
+                for offset in 0..column_repeat {
+                    cells.push((coordinate(column, offset)?, cell_element));
+                }
+            }
+            column += column_repeat as i64;
+        }
+
+        if !cells.is_empty() {
+            populated += cells.len() as u64 * row_repeat as u64;
+            if populated > MAX_POPULATED_CELLS {
+                return Err(too_many());
+            }
+            for offset in 0..row_repeat {
+                let y = coordinate(row, offset)?;
+                for (x, cell_element) in &cells {
+                    environment.set_cell_on(sheet, &CellAddress(*x, y), read_cell(cell_element)?)?;
+                }
+            }
+        }
+        row += row_repeat as i64;
+    }
+
+    Ok(())
+}
+
+/// Named ranges and expressions defined in a `table:named-expressions` child of `element`
+fn read_names(element: &Element, scope: &NameScope, environment: &mut Environment) -> Result<(), String> {
+    let named = element.elements()
+        .filter(|child| child.name == "table:named-expressions")
+        .flat_map(|expressions| expressions.elements());
+
+    for named in named {
+        let name = named.attribute("table:name").ok_or("Named expression is missing table:name")?;
+        let definition = match named.name.as_str() {
+            "table:named-range" => {
+                let address = named.attribute("table:cell-range-address").ok_or(format!("Named range {name} is missing its address"))?;
+                match parse_range(address)? {
+                    (start, end) if start == end => Definition::Cell(start),
+                    (start, end) => Definition::Range(CellRange { start, end }),
+                }
+            }
+            "table:named-expression" => {
+                let expression = named.attribute("table:expression").ok_or(format!("Named expression {name} is missing its expression"))?;
+                Definition::Constant(parse_formula(expression)?)
+            }
+            _ => continue,
+        };
+        environment.define_name(name, scope, definition)?;
+    }
+
+    Ok(())
+}
+
+fn collect_rows<'a>(element: &'a Element, rows: &mut Vec<&'a Element>) {
+    for child in element.elements() {
+        match child.name.as_str() {
+            "table:table-row" => rows.push(child),
+            "table:table-header-rows" | "table:table-row-group" | "table:table-rows" => collect_rows(child, rows),
+            _ => {}
+        }
+    }
+}
+
+fn repeat_count(element: &Element, attribute: &str) -> Result<u32, String> {
+    match element.attribute(attribute) {
+        Some(count) => count.parse().map_err(|_| format!("Invalid {attribute}: {count}")),
+        None => Ok(1),
+    }
+}
+
+fn coordinate(start: i64, offset: u32) -> Result<i32, String> {
+    i32::try_from(start + offset as i64).map_err(|_| String::from("Sheet is too large"))
+}
+
+fn is_populated(cell: &Element) -> bool {
+    cell.attribute("table:formula").is_some()
+        || cell.attribute("office:value-type").is_some()
+        || !cell_text(cell).is_empty()
+}
+
+fn read_cell(cell: &Element) -> Result<Box<dyn Evaluatable>, String> {
+    if let Some(formula) = cell.attribute("table:formula") {
+        return parse_formula(formula);
+    }
+
+    let value = |attribute: &str| cell.attribute(attribute).ok_or(format!("Cell is missing {attribute}"));
+
+    Ok(Box::new(match cell.attribute("office:value-type") {
+        Some("float") | Some("percentage") | Some("currency") => number(value("office:value")?)?,
+        Some("boolean") => Primitive::Boolean(value("office:boolean-value")? == "true"),
+        Some("date") => Primitive::String(String::from(value("office:date-value")?)),
+        Some("time") => Primitive::String(String::from(value("office:time-value")?)),
+        Some("string") | None => match cell.attribute("office:string-value") {
+            Some(text) => Primitive::String(String::from(text)),
+            None => Primitive::String(cell_text(cell)),
+        },
+        Some(other) => return Err(format!("Unsupported cell value type {other}")),
+    }))
+}
+
+/// Text of the cell's paragraphs, one line per paragraph
+fn cell_text(cell: &Element) -> String {
+    let paragraphs: Vec<String> = cell.elements()
+        .filter(|element| element.name == "text:p")
+        .map(|paragraph| {
+            let mut text = String::new();
+            paragraph_text(paragraph, &mut text);
+            text
+        })
+        .collect();
+
+    paragraphs.join("\n")
+}
+
+fn paragraph_text(element: &Element, text: &mut String) {
+    for child in &element.children {
+        match child {
+            Node::Text(content) => text.push_str(content),
+            Node::Element(child) => match child.name.as_str() {
+                "text:s" => {
+                    let count = child.attribute("text:c").and_then(|c| c.parse().ok()).unwrap_or(1);
+                    text.extend(std::iter::repeat_n(' ', count));
+                }
+                "text:tab" => text.push('\t'),
+                "text:line-break" => text.push('\n'),
+                _ => paragraph_text(child, text),
+            },
+        }
+    }
+}
+
+/// Number as the lexer reads it, except that integers too large for `Integer` become floats, as
+/// every OpenDocument number is one
+fn number(text: &str) -> Result<Primitive, String> {
+    lexer::number(text).or_else(|e| text.parse::<f32>().map(Primitive::Float).map_err(|_| e))
+}
+
+fn float_literal(val: f32) -> Result<String, String> {
+    if !val.is_finite() {
+        return Err(format!("Cannot represent {val} in OpenDocument"));
+    }
+
+    let text = format!("{val}");
+    Ok(if text.contains(['.', 'e', 'E']) { text } else { format!("{text}.0") })
+}
+
+fn content_element(environment: &Environment) -> Result<Element, String> {
+    let mut spreadsheet = Element::new("office:spreadsheet");
+    for sheet in environment.sheet_names() {
+        spreadsheet = spreadsheet.with_child(table_element(environment, sheet)?);
+    }
+    if let Some(names) = names_element(environment, &NameScope::Workbook)? {
+        spreadsheet = spreadsheet.with_child(names);
+    }
+
+    Ok(Element::new("office:document-content")
+        .with_attribute("xmlns:office", "urn:oasis:names:tc:opendocument:xmlns:office:1.0")
+        .with_attribute("xmlns:table", "urn:oasis:names:tc:opendocument:xmlns:table:1.0")
+        .with_attribute("xmlns:text", "urn:oasis:names:tc:opendocument:xmlns:text:1.0")
+        .with_attribute("xmlns:of", "urn:oasis:names:tc:opendocument:xmlns:of:1.2")
+        .with_attribute("office:version", "1.2")
+        .with_child(Element::new("office:body").with_child(spreadsheet)))
+}
+
+fn table_element(environment: &Environment, sheet: &str) -> Result<Element, String> {
+    let mut rows: BTreeMap<i32, BTreeMap<i32, &dyn Evaluatable>> = BTreeMap::new();
+    let mut max_column = 0;
+
+    for (adr, val) in environment.cells_on(sheet) {
+        cell_name(adr)?;
+        max_column = max_column.max(adr.0);
+        rows.entry(adr.1).or_default().insert(adr.0, val);
+    }
+
+    let mut table = Element::new("table:table")
+        .with_attribute("table:name", sheet)
+        .with_child(Element::new("table:table-column")
+            .with_attribute("table:number-columns-repeated", &(max_column + 1).to_string()));
+
+    let mut next_row = 0;
+    for (row, cells) in &rows {
+        if *row > next_row {
+            table = table.with_child(Element::new("table:table-row")
+                .with_attribute("table:number-rows-repeated", &(row - next_row).to_string())
+                .with_child(Element::new("table:table-cell")));
+        }
+
+        let mut row_element = Element::new("table:table-row");
+        let mut next_column = 0;
+        for (column, val) in cells {
+            if *column > next_column {
+                row_element = row_element.with_child(Element::new("table:table-cell")
+                    .with_attribute("table:number-columns-repeated", &(column - next_column).to_string()));
+            }
+            row_element = row_element.with_child(cell_element(*val, sheet, environment)?);
+            next_column = column + 1;
+        }
+
+        table = table.with_child(row_element);
+        next_row = row + 1;
+    }
+
+    match names_element(environment, &NameScope::Sheet(sheet.to_string()))? {
+        Some(names) => Ok(table.with_child(names)),
+        None => Ok(table),
+    }
+}
+
+/// `table:named-expressions` holding the names of a scope, sorted by name, if there are any
+fn names_element(environment: &Environment, scope: &NameScope) -> Result<Option<Element>, String> {
+    let mut names: Vec<(&str, &Definition)> = environment.names(scope).collect();
+    if names.is_empty() {
+        return Ok(None);
+    }
+    names.sort_by_key(|(name, _)| *name);
+
+    // References in names are never relative to where they are used, so they are written
+    // relative to A1 of the first sheet
+    let base = format!("${}.$A$1", quote_sheet_name(environment.sheet_names().next().unwrap_or_default()));
+    let mut element = Element::new("table:named-expressions");
+    for (name, definition) in names {
+        element = element.with_child(match definition {
+            Definition::Cell(reference) => Element::new("table:named-range")
+                .with_attribute("table:name", name)
+                .with_attribute("table:base-cell-address", &base)
+                .with_attribute("table:cell-range-address", &reference_name(reference, None)?),
+            Definition::Range(range) => Element::new("table:named-range")
+                .with_attribute("table:name", name)
+                .with_attribute("table:base-cell-address", &base)
+                .with_attribute("table:cell-range-address", &format!("{}:{}", reference_name(&range.start, None)?, reference_name(&range.end, None)?)),
+            Definition::Constant(val) => Element::new("table:named-expression")
+                .with_attribute("table:name", name)
+                .with_attribute("table:base-cell-address", &base)
+                .with_attribute("table:expression", &write_formula(val.as_ref())?),
+        });
+    }
+
+    Ok(Some(element))
+}
+
+fn cell_element(val: &dyn Evaluatable, sheet: &str, environment: &Environment) -> Result<Element, String> {
+    let mut cell = Element::new("table:table-cell");
+
+    let result = match val.expression() {
+        Expression::Primitive(primitive) => Ok(primitive.clone()),
+        _ => {
+            cell = cell.with_attribute("table:formula", &format!("of:={}", formula_text(val, Some(sheet))?));
+            val.evaluate(environment)
+        }
+    };
+
+    Ok(match result {
+        Ok(Primitive::Integer(v)) => cell
+            .with_attribute("office:value-type", "float")
+            .with_attribute("office:value", &v.to_string())
+            .with_child(paragraph(&v.to_string())),
+        Ok(Primitive::Float(v)) => cell
+            .with_attribute("office:value-type", "float")
+            .with_attribute("office:value", &float_literal(v)?)
+            .with_child(paragraph(&v.to_string())),
+        Ok(Primitive::Boolean(v)) => cell
+            .with_attribute("office:value-type", "boolean")
+            .with_attribute("office:boolean-value", &v.to_string())
+            .with_child(paragraph(if v { "TRUE" } else { "FALSE" })),
+        Ok(Primitive::String(v)) => {
+            let mut cell = cell
+                .with_attribute("office:value-type", "string")
+                .with_attribute("office:string-value", &v);
+            for line in v.split('\n') {
+                cell = cell.with_child(paragraph(line));
+            }
+            cell
+        }
+        Err(e) => cell.with_child(paragraph(&e)),
+    })
+}
+
+/// `text:p` element, with runs of spaces preserved through `text:s`
+fn paragraph(text: &str) -> Element {
+    let mut element = Element::new("text:p");
+    let mut run = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == ' ' && (run.is_empty() || run.ends_with(' ') || chars.peek() == Some(&' ')) {
+            let mut count = 1;
+            while chars.peek() == Some(&' ') {
+                chars.next();
+                count += 1;
+            }
+
+            if !run.is_empty() {
+                element = element.with_text(&run);
+                run.clear();
+            }
+            element = element.with_child(Element::new("text:s").with_attribute("text:c", &count.to_string()));
+        } else {
+            run.push(c);
+        }
+    }
+
+    if !run.is_empty() {
+        element = element.with_text(&run);
+    }
+    element
+}
+
+/// A1 name of a cell, which OpenDocument requires every exported cell to have
+fn cell_name(adr: &CellAddress) -> Result<String, String> {
+    adr.to_a1().map_err(|e| format!("Cell cannot be represented in OpenDocument: {e}"))
+}
+
+/// Reference as written between brackets, keeping its `$` markers and naming its sheet unless it
+/// is the host sheet, e.g. `.A1` or `$Inputs.$B$2`
+fn reference_name(reference: &CellReference, host: Option<&str>) -> Result<String, String> {
+    cell_name(&reference.address)?;
+    let cell = CellReference { sheet: None, ..reference.clone() };
+
+    Ok(match reference.sheet.as_deref() {
+        Some(sheet) if Some(sheet) != host => format!("${}.{}", quote_sheet_name(sheet), cell),
+        _ => format!(".{}", cell),
+    })
+}
+
+/// Formula text for a cell on the `host` sheet
+fn formula_text(expression: &dyn Evaluatable, host: Option<&str>) -> Result<String, String> {
+    match expression.expression() {
+        Expression::Primitive(primitive) => match primitive {
+            Primitive::Integer(val) => Ok(val.to_string()),
+            Primitive::Float(val) => float_literal(*val),
+            Primitive::Boolean(val) => Ok(String::from(if *val { "TRUE()" } else { "FALSE()" })),
+            Primitive::String(val) => Ok(format!("\"{}\"", val.replace('"', "\"\""))),
+        },
+        Expression::CellValue(cell) => Ok(format!("[{}]", reference_name(&cell.0, host)?)),
+        Expression::InvalidReference(_) => Ok(String::from("[.#REF!]")),
+        Expression::NamedValue(value) => Ok(value.name.clone()),
+        Expression::Statistics(statistics) => {
+            let range = statistics.range();
+            let range = format!("[{}:{}]", reference_name(&range.start, host)?, reference_name(&range.end, host)?);
+
+            Ok(match statistics {
+                Statistics::Max(_) => format!("MAX({range})"),
+                Statistics::Min(_) => format!("MIN({range})"),
+                Statistics::Mean(_) => format!("AVERAGE({range})"),
+                Statistics::Sum(_) => format!("SUM({range})"),
+            })
+        }
+        Expression::Operation(operation) => {
+            let (val1, val2) = operation.operands();
+
+            if let Some(symbol) = infix_symbol(operation) {
+                let val2 = val2.unwrap();
+                return Ok(format!("{}{}{}", operand_text(val1, host)?, symbol, operand_text(val2, host)?));
+            }
+
+            let name = match operation {
+                Operation::Modulus(_, _) => "MOD",
+                Operation::LogicalAnd(_, _) => "AND",
+                Operation::LogicalOr(_, _) => "OR",
+                Operation::LogicalNot(_) => "NOT",
+                Operation::BitwiseAnd(_, _) => "BITAND",
+                Operation::BitwiseOr(_, _) => "BITOR",
+                Operation::BitwiseXor(_, _) => "BITXOR",
+                Operation::BitwiseNot(_) => "ORG.GRIDKID.BITNOT",
+                Operation::LeftShift(_, _) => "BITLSHIFT",
+                Operation::RightShift(_, _) => "BITRSHIFT",
+                Operation::FloatToInt(_) => "TRUNC",
+                Operation::IntToFloat(_) => "ORG.GRIDKID.FLOAT",
+                _ => unreachable!("infix operations are handled above"),
+            };
+
+            match val2 {
+                Some(val2) => Ok(format!("{}({};{})", name, formula_text(val1, host)?, formula_text(val2, host)?)),
+                None => Ok(format!("{}({})", name, formula_text(val1, host)?)),
+            }
+        }
+    }
+}
+
+fn infix_symbol(operation: &Operation) -> Option<&'static str> {
+    match operation {
+        Operation::Add(_, _) => Some("+"),
+        Operation::Subtract(_, _) => Some("-"),
+        Operation::Multiply(_, _) => Some("*"),
+        Operation::Divide(_, _) => Some("/"),
+        Operation::Power(_, _) => Some("^"),
+        Operation::Equals(_, _) => Some("="),
+        Operation::NotEquals(_, _) => Some("<>"),
+        Operation::LessThan(_, _) => Some("<"),
+        Operation::LessThanOrEqual(_, _) => Some("<="),
+        Operation::GreaterThan(_, _) => Some(">"),
+        Operation::GreaterThanOrEqual(_, _) => Some(">="),
+        _ => None,
+    }
+}
+
+/// Operand of an infix operator, parenthesised when it is itself an infix operation
+fn operand_text(operand: &dyn Evaluatable, host: Option<&str>) -> Result<String, String> {
+    let text = formula_text(operand, host)?;
+
+    match operand.expression() {
+        Expression::Operation(operation) if infix_symbol(operation).is_some() => Ok(format!("({text})")),
+        _ => Ok(text),
+    }
+}
+
+/// Whether a `[...]` reference points at a deleted cell, which is written as `#REF!` in place
+/// of the column, the row or the whole address
+fn is_invalid(reference: &str) -> bool {
+    reference.contains("#REF!")
+}
+
+const OPERATORS: [&str; 13] = ["<>", "<=", ">=", "<", ">", "=", "+", "-", "*", "/", "^", "&", "%"];
+
+const SYNTAX: Syntax = Syntax { operators: &OPERATORS, backslash_escapes: false, bracketed_references: true };
+
+struct FormulaParser {
+    tokens: Tokens,
+}
+
+impl FormulaParser {
+    fn comparison(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        let mut left = self.concatenation()?;
+
+        while let Some(operator) = self.tokens.operator(&["=", "<>", "<", "<=", ">", ">="]) {
+            let right = self.concatenation()?;
+            left = Box::new(match operator {
+                "=" => Operation::Equals(left, right),
+                "<>" => Operation::NotEquals(left, right),
+                "<" => Operation::LessThan(left, right),
+                "<=" => Operation::LessThanOrEqual(left, right),
+                ">" => Operation::GreaterThan(left, right),
+                _ => Operation::GreaterThanOrEqual(left, right),
+            });
+        }
+
+        Ok(left)
+    }
+
+    fn concatenation(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        let left = self.additive()?;
+
+        if self.tokens.operator(&["&"]).is_some() {
+            return Err(String::from("String concatenation (&) is not supported"));
+        }
+
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        let mut left = self.multiplicative()?;
+
+        while let Some(operator) = self.tokens.operator(&["+", "-"]) {
+            let right = self.multiplicative()?;
+            left = Box::new(match operator {
+                "+" => Operation::Add(left, right),
+                _ => Operation::Subtract(left, right),
+            });
+        }
+
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        let mut left = self.power()?;
+
+        while let Some(operator) = self.tokens.operator(&["*", "/"]) {
+            let right = self.power()?;
+            left = Box::new(match operator {
+                "*" => Operation::Multiply(left, right),
+                _ => Operation::Divide(left, right),
+            });
+        }
+
+        Ok(left)
+    }
+
+    fn power(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        let mut left = self.postfix()?;
+
+        while self.tokens.operator(&["^"]).is_some() {
+            let right = self.postfix()?;
+            left = Box::new(Operation::Power(left, right));
+        }
+
+        Ok(left)
+    }
+
+    fn postfix(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        let mut operand = self.prefix()?;
+
+        while self.tokens.operator(&["%"]).is_some() {
+            operand = Box::new(Operation::Divide(operand, Box::new(Primitive::Float(100.0))));
+        }
+
+        Ok(operand)
+    }
+
+    fn prefix(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        match self.tokens.operator(&["+", "-"]) {
+            Some("+") => self.prefix(),
+            Some(_) => {
+                if let Some(Token::Number(text)) = self.tokens.peek() {
+                    let literal = number(&format!("-{text}"))?;
+                    self.tokens.skip();
+                    return Ok(Box::new(literal));
+                }
+                Ok(Box::new(Operation::Subtract(Box::new(Primitive::Integer(0)), self.prefix()?)))
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Box<dyn Evaluatable>, String> {
+        match self.tokens.next()? {
+            Token::Number(text) => Ok(Box::new(number(&text)?)),
+            Token::Text(text) => Ok(Box::new(Primitive::String(text))),
+            Token::Reference(reference) if is_invalid(&reference) => Ok(Box::new(InvalidReference)),
+            Token::Reference(reference) => {
+                let (start, end) = parse_range(&reference)?;
+                if start != end {
+                    return Err(format!("Range [{reference}] can only be used as a function argument"));
+                }
+                Ok(Box::new(CellValue(start)))
+            }
+            Token::Open => {
+                let expression = self.comparison()?;
+                self.tokens.expect(Token::Close)?;
+                Ok(expression)
+            }
+            Token::Word(name) => self.function(&name),
+            token => Err(format!("Unexpected {} in formula", describe(&token))),
+        }
+    }
+
+    fn function(&mut self, name: &str) -> Result<Box<dyn Evaluatable>, String> {
+        let upper = name.to_uppercase();
+        if self.tokens.peek() != Some(&Token::Open) {
+            return match upper.as_str() {
+                "TRUE" => Ok(Box::new(Primitive::Boolean(true))),
+                "FALSE" => Ok(Box::new(Primitive::Boolean(false))),
+                _ => Ok(Box::new(NamedValue::new(name))),
+            };
+        }
+        self.tokens.skip();
+        let name = upper.as_str();
+
+        if let "SUM" | "MAX" | "MIN" | "AVERAGE" = name {
+            let reference = match self.tokens.next()? {
+                Token::Reference(reference) => reference,
+                _ => return Err(format!("{name} is only supported over a single range")),
+            };
+            self.tokens.expect(Token::Close)
+                .map_err(|_| format!("{name} is only supported over a single range"))?;
+            if is_invalid(&reference) {
+                return Ok(Box::new(InvalidReference));
+            }
+
+            let (start, end) = parse_range(&reference)?;
+
+            let range = CellRange { start, end };
+            return Ok(Box::new(match name {
+                "SUM" => Statistics::Sum(range),
+                "MAX" => Statistics::Max(range),
+                "MIN" => Statistics::Min(range),
+                _ => Statistics::Mean(range),
+            }));
+        }
+
+        let mut arguments = Vec::new();
+        if self.tokens.peek() != Some(&Token::Close) {
+            loop {
+                arguments.push(self.comparison()?);
+                if self.tokens.peek() != Some(&Token::Separator) {
+                    break;
+                }
+                self.tokens.skip();
+            }
+        }
+        self.tokens.expect(Token::Close)?;
+
+        let count = arguments.len();
+        let mut arguments = arguments.into_iter();
+        let mut argument = || arguments.next().unwrap();
+        let arity = |expected: usize| {
+            if count == expected {
+                Ok(())
+            } else {
+                Err(format!("{name} expects {expected} argument(s), found {count}"))
+            }
+        };
+
+        match name {
+            "TRUE" | "FALSE" => {
+                arity(0)?;
+                Ok(Box::new(Primitive::Boolean(name == "TRUE")))
+            }
+            "AND" | "OR" => {
+                if count == 0 {
+                    return Err(format!("{name} expects at least 1 argument"));
+                }
+                let mut result = argument();
+                for _ in 1..count {
+                    result = Box::new(if name == "AND" {
+                        Operation::LogicalAnd(result, argument())
+                    } else {
+                        Operation::LogicalOr(result, argument())
+                    });
+                }
+                Ok(result)
+            }
+            "NOT" | "TRUNC" | "ORG.GRIDKID.BITNOT" | "ORG.GRIDKID.FLOAT" => {
+                arity(1)?;
+                Ok(Box::new(match name {
+                    "NOT" => Operation::LogicalNot(argument()),
+                    "TRUNC" => Operation::FloatToInt(argument()),
+                    "ORG.GRIDKID.BITNOT" => Operation::BitwiseNot(argument()),
+                    _ => Operation::IntToFloat(argument()),
+                }))
+            }
+            "MOD" | "POWER" | "BITAND" | "BITOR" | "BITXOR" | "BITLSHIFT" | "BITRSHIFT" => {
+                arity(2)?;
+                let (val1, val2) = (argument(), argument());
+                Ok(Box::new(match name {
+                    "MOD" => Operation::Modulus(val1, val2),
+                    "POWER" => Operation::Power(val1, val2),
+                    "BITAND" => Operation::BitwiseAnd(val1, val2),
+                    "BITOR" => Operation::BitwiseOr(val1, val2),
+                    "BITXOR" => Operation::BitwiseXor(val1, val2),
+                    "BITLSHIFT" => Operation::LeftShift(val1, val2),
+                    _ => Operation::RightShift(val1, val2),
+                }))
+            }
+            _ => Err(format!("Unsupported function {name}")),
+        }
+    }
+}
+
+/// Resolves the contents of a `[...]` reference, or a range address such as `$Sheet1.A1:.B2`,
+/// into its first and last cell
+fn parse_range(reference: &str) -> Result<(CellReference, CellReference), String> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, c) in reference.char_indices() {
+        if c == '\'' {
+            quoted = !quoted;
+        } else if c == ':' && !quoted {
+            parts.push(&reference[start..index]);
+            start = index + 1;
+        }
+    }
+    parts.push(&reference[start..]);
+
+    let cells = parts.iter()
+        .map(|part| parse_reference(part))
+        .collect::<Result<Vec<CellReference>, String>>()?;
+
+    match cells.as_slice() {
+        [cell] => Ok((cell.clone(), cell.clone())),
+        // The end of a range is on the same sheet as its start unless it names another
+        [start, end] if end.sheet.is_none() => Ok((start.clone(), CellReference { sheet: start.sheet.clone(), ..end.clone() })),
+        [start, end] => Ok((start.clone(), end.clone())),
+        _ => Err(format!("Invalid reference [{reference}]")),
+    }
+}
+
+fn parse_reference(part: &str) -> Result<CellReference, String> {
+    let split = part.rfind('.').ok_or(format!("Invalid reference {part}"))?;
+    let (sheet, cell) = (&part[..split], &part[split + 1..]);
+
+    let sheet = sheet.trim_start_matches('$');
+    let sheet = sheet.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')).unwrap_or(sheet).replace("''", "'");
+
+    let reference: CellReference = cell.parse()?;
+    Ok(if sheet.is_empty() { reference } else { reference.on_sheet(&sheet) })
+}
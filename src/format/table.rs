@@ -0,0 +1,174 @@
+//! Rendering a rectangle of evaluated cells as a GitHub-flavoured Markdown table or a standalone
+//! HTML `<table>`. Numbers are right-aligned and cells that fail to evaluate are highlighted.
+
+use std::fmt::Write;
+
+use crate::address::{column_name, CellRange};
+use crate::environment::Environment;
+use crate::format::xml::escape;
+use crate::model::{CellAddress, Primitive};
+
+pub struct TableOptions {
+    /// Adds a header row naming each column
+    pub column_headers: bool,
+    /// Adds a leading column naming each row
+    pub row_headers: bool,
+}
+
+impl Default for TableOptions {
+    fn default() -> TableOptions {
+        TableOptions { column_headers: true, row_headers: true }
+    }
+}
+
+/// Renders the rectangle of the named sheet spanned by two opposite corners as a Markdown table.
+/// Markdown only supports alignment per column, so a column is right-aligned when all of its
+/// values are numbers.
+pub fn to_markdown(environment: &Environment, sheet: &str, corner1: &CellAddress, corner2: &CellAddress, options: &TableOptions) -> String {
+    let region = Region::evaluate(environment, sheet, corner1, corner2);
+    let mut out = String::new();
+
+    let mut header: Vec<String> = Vec::new();
+    let mut alignment: Vec<&str> = Vec::new();
+    if options.row_headers {
+        header.push(String::new());
+        alignment.push("---");
+    }
+    for (index, column) in region.columns.iter().enumerate() {
+        header.push(if options.column_headers { column_header(*column) } else { String::new() });
+        alignment.push(if region.is_numeric_column(index) { "---:" } else { "---" });
+    }
+
+    // GFM requires a header row, so it is left blank when column headers are off
+    let _ = writeln!(out, "| {} |", header.join(" | "));
+    let _ = writeln!(out, "| {} |", alignment.join(" | "));
+
+    for (row, cells) in region.rows.iter().zip(&region.cells) {
+        let mut line: Vec<String> = Vec::new();
+        if options.row_headers {
+            line.push(format!("**{}**", row_header(*row)));
+        }
+        for cell in cells {
+            line.push(match cell {
+                None => String::new(),
+                Some(Ok(val)) => escape_markdown(&display(val)),
+                Some(Err(e)) => format!("**#ERROR** {}", escape_markdown(e)),
+            });
+        }
+        let _ = writeln!(out, "| {} |", line.join(" | "));
+    }
+
+    out
+}
+
+/// Renders the rectangle of the named sheet spanned by two opposite corners as a standalone HTML
+/// table, styled inline so it needs no stylesheet
+pub fn to_html(environment: &Environment, sheet: &str, corner1: &CellAddress, corner2: &CellAddress, options: &TableOptions) -> String {
+    let region = Region::evaluate(environment, sheet, corner1, corner2);
+    let mut out = String::from("<table>\n");
+
+    if options.column_headers {
+        out.push_str("  <thead>\n    <tr>\n");
+        if options.row_headers {
+            out.push_str("      <th></th>\n");
+        }
+        for column in &region.columns {
+            let _ = writeln!(out, "      <th scope=\"col\">{}</th>", column_header(*column));
+        }
+        out.push_str("    </tr>\n  </thead>\n");
+    }
+
+    out.push_str("  <tbody>\n");
+    for (row, cells) in region.rows.iter().zip(&region.cells) {
+        out.push_str("    <tr>\n");
+        if options.row_headers {
+            let _ = writeln!(out, "      <th scope=\"row\">{}</th>", row_header(*row));
+        }
+        for cell in cells {
+            let _ = match cell {
+                None => writeln!(out, "      <td></td>"),
+                Some(Ok(val @ (Primitive::Integer(_) | Primitive::Float(_)))) => writeln!(
+                    out,
+                    "      <td class=\"number\" style=\"text-align: right\">{}</td>",
+                    escape(&display(val))
+                ),
+                Some(Ok(val)) => writeln!(out, "      <td>{}</td>", escape(&display(val))),
+                Some(Err(e)) => writeln!(
+                    out,
+                    "      <td class=\"error\" style=\"color: #a00; background-color: #fdd\">{}</td>",
+                    escape(e)
+                ),
+            };
+        }
+        out.push_str("    </tr>\n");
+    }
+    out.push_str("  </tbody>\n</table>\n");
+
+    out
+}
+
+/// Evaluated cells of a rectangle, listed top to bottom by ascending row
+struct Region {
+    columns: Vec<i32>,
+    rows: Vec<i32>,
+    cells: Vec<Vec<Option<Result<Primitive, String>>>>,
+}
+
+impl Region {
+    fn evaluate(environment: &Environment, sheet: &str, corner1: &CellAddress, corner2: &CellAddress) -> Region {
+        let range = CellRange::new(*corner1, *corner2);
+        let columns: Vec<i32> = range.columns().collect();
+        let rows: Vec<i32> = range.rows().collect();
+
+        // Only populated cells are visited, so a sparse region costs what it holds
+        let mut cells = vec![vec![None; columns.len()]; rows.len()];
+        for (adr, _) in environment.cells_in(sheet, &range) {
+            let (column, row) = ((adr.0 - columns[0]) as usize, (adr.1 - rows[0]) as usize);
+            cells[row][column] = environment.value_on(sheet, adr);
+        }
+
+        Region { columns, rows, cells }
+    }
+
+    fn is_numeric_column(&self, index: usize) -> bool {
+        let mut values = self.cells.iter().filter_map(|row| row[index].as_ref()).peekable();
+        values.peek().is_some()
+            && values.all(|cell| matches!(cell, Ok(Primitive::Integer(_)) | Ok(Primitive::Float(_))))
+    }
+}
+
+/// Column letters, or the raw coordinate for columns that have none
+fn column_header(column: i32) -> String {
+    column_name(column).unwrap_or_else(|_| column.to_string())
+}
+
+/// 1-based row number, or the raw coordinate for rows that have none
+fn row_header(row: i32) -> String {
+    match CellAddress(0, row).validate() {
+        Ok(()) => (row + 1).to_string(),
+        Err(_) => format!("({row})"),
+    }
+}
+
+/// Value as shown to a reader, i.e. strings without their quotes
+fn display(val: &Primitive) -> String {
+    match val {
+        Primitive::String(text) => text.clone(),
+        _ => val.to_string(),
+    }
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '|' | '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '&' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("<br>"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
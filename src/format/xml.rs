@@ -0,0 +1,279 @@
+//! Just enough XML to read and write OpenDocument content: elements, attributes, text
+//! and the predefined/numeric entities. Namespaces are not resolved; names keep their prefix.
+
+use std::fmt::Write;
+
+pub(crate) struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+pub(crate) enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    pub fn new(name: &str) -> Element {
+        Element { name: String::from(name), attributes: Vec::new(), children: Vec::new() }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, val)| val.as_str())
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn with_attribute(mut self, name: &str, value: &str) -> Element {
+        self.attributes.push((String::from(name), String::from(value)));
+        self
+    }
+
+    pub fn with_child(mut self, child: Element) -> Element {
+        self.children.push(Node::Element(child));
+        self
+    }
+
+    pub fn with_text(mut self, text: &str) -> Element {
+        self.children.push(Node::Text(String::from(text)));
+        self
+    }
+
+    pub fn write(&self, out: &mut String) {
+        let _ = write!(out, "<{}", self.name);
+        for (key, val) in &self.attributes {
+            let _ = write!(out, " {}=\"{}\"", key, escape(val));
+        }
+
+        if self.children.is_empty() {
+            out.push_str("/>");
+            return;
+        }
+
+        out.push('>');
+        for child in &self.children {
+            match child {
+                Node::Element(element) => element.write(out),
+                Node::Text(text) => out.push_str(&escape(text)),
+            }
+        }
+        let _ = write!(out, "</{}>", self.name);
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Deepest nesting of elements accepted, so that a hostile document cannot exhaust the stack
+const MAX_DEPTH: usize = 256;
+
+/// Parses a document and returns its root element
+pub(crate) fn parse(text: &str) -> Result<Element, String> {
+    let mut parser = Parser { text, pos: 0, depth: 0 };
+    parser.skip_prolog()?;
+    let root = parser.element()?;
+    parser.skip_misc()?;
+
+    if parser.pos < text.len() {
+        return Err(String::from("Unexpected content after root element"));
+    }
+
+    Ok(root)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Elements open around the one being parsed
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.text.len() - trimmed.len();
+    }
+
+    fn skip_past(&mut self, terminator: &str) -> Result<(), String> {
+        match self.rest().find(terminator) {
+            Some(index) => {
+                self.pos += index + terminator.len();
+                Ok(())
+            }
+            None => Err(format!("Unterminated markup, expected {terminator}")),
+        }
+    }
+
+    /// Skips whitespace, comments and processing instructions
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_prolog(&mut self) -> Result<(), String> {
+        self.text = self.text.strip_prefix('\u{feff}').unwrap_or(self.text);
+        self.skip_misc()?;
+        if self.rest().starts_with("<!DOCTYPE") {
+            self.skip_past(">")?;
+            self.skip_misc()?;
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let end = self.rest()
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/' || c == '=')
+            .unwrap_or(self.rest().len());
+
+        if end == 0 {
+            return Err(String::from("Expected a name"));
+        }
+
+        let name = String::from(&self.rest()[..end]);
+        self.pos += end;
+        Ok(name)
+    }
+
+    fn element(&mut self) -> Result<Element, String> {
+        if !self.rest().starts_with('<') {
+            return Err(String::from("Expected an element"));
+        }
+        self.pos += 1;
+
+        let mut element = Element::new(&self.name()?);
+
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            } else if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+
+            let key = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(format!("Expected value for attribute {key}"));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+
+            let quote = match self.rest().chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Err(format!("Expected quoted value for attribute {key}")),
+            };
+            self.pos += 1;
+            let end = self.rest().find(quote).ok_or(format!("Unterminated value for attribute {key}"))?;
+            let value = unescape(&self.rest()[..end])?;
+            self.pos += end + 1;
+
+            element.attributes.push((key, value));
+        }
+
+        loop {
+            if self.rest().starts_with("</") {
+                self.pos += 2;
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(format!("Mismatched closing tag: expected {}, found {}", element.name, name));
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return Err(format!("Malformed closing tag for {name}"));
+                }
+                self.pos += 1;
+                return Ok(element);
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if let Some(rest) = self.rest().strip_prefix("<![CDATA[") {
+                let end = rest.find("]]>").ok_or("Unterminated CDATA section")?;
+                element.children.push(Node::Text(String::from(&rest[..end])));
+                self.pos += "<![CDATA[".len() + end + "]]>".len();
+            } else if self.rest().starts_with('<') {
+                if self.depth == MAX_DEPTH {
+                    return Err(format!("Elements nested more than {MAX_DEPTH} deep"));
+                }
+                self.depth += 1;
+                let child = self.element();
+                self.depth -= 1;
+                element.children.push(Node::Element(child?));
+            } else if self.rest().is_empty() {
+                return Err(format!("Unterminated element {}", element.name));
+            } else {
+                let end = self.rest().find('<').unwrap_or(self.rest().len());
+                element.children.push(Node::Text(unescape(&self.rest()[..end])?));
+                self.pos += end;
+            }
+        }
+    }
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find(';').ok_or(format!("Unterminated entity in {text}"))?;
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(decimal) = entity.strip_prefix('#') {
+                    decimal.parse::<u32>().ok()
+                } else {
+                    None
+                };
+                code.and_then(char::from_u32).ok_or(format!("Unknown entity &{entity};"))?
+            }
+        };
+
+        unescaped.push(c);
+        rest = &rest[end + 1..];
+    }
+
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
@@ -0,0 +1,338 @@
+//! Minimal zip container support for OpenDocument packages. Reading understands stored and
+//! deflated entries; writing only produces stored entries, which every zip reader accepts.
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+/// Returns every file in the archive as (name, contents)
+pub(crate) fn read_entries(archive: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let end = (0..archive.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| read_u32(archive, pos) == Ok(END_OF_CENTRAL_DIRECTORY))
+        .ok_or("Not a zip archive")?;
+
+    let count = read_u16(archive, end + 10)? as usize;
+    let mut pos = read_u32(archive, end + 16)? as usize;
+    let mut entries = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        if read_u32(archive, pos)? != CENTRAL_HEADER {
+            return Err(String::from("Corrupt zip central directory"));
+        }
+
+        let method = read_u16(archive, pos + 10)?;
+        let crc = read_u32(archive, pos + 16)?;
+        let compressed_size = read_u32(archive, pos + 20)? as usize;
+        let size = read_u32(archive, pos + 24)? as usize;
+        let name_length = read_u16(archive, pos + 28)? as usize;
+        let extra_length = read_u16(archive, pos + 30)? as usize;
+        let comment_length = read_u16(archive, pos + 32)? as usize;
+        let offset = read_u32(archive, pos + 42)? as usize;
+        let name = String::from_utf8_lossy(slice(archive, pos + 46, name_length)?).into_owned();
+        pos += 46 + name_length + extra_length + comment_length;
+
+        if read_u32(archive, offset)? != LOCAL_HEADER {
+            return Err(format!("Corrupt zip entry {name}"));
+        }
+        let data_start = offset + 30 + read_u16(archive, offset + 26)? as usize + read_u16(archive, offset + 28)? as usize;
+        let data = slice(archive, data_start, compressed_size)?;
+
+        let contents = match method {
+            0 => data.to_vec(),
+            8 => inflate(data, size)?,
+            _ => return Err(format!("Unsupported compression method {method} for zip entry {name}")),
+        };
+
+        if contents.len() != size || crc32(&contents) != crc {
+            return Err(format!("Checksum mismatch for zip entry {name}"));
+        }
+
+        entries.push((name, contents));
+    }
+
+    Ok(entries)
+}
+
+/// Builds an archive containing the given files, in order, without compression
+pub(crate) fn write_entries(entries: &[(&str, &[u8])]) -> Result<Vec<u8>, String> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+
+    for (name, contents) in entries {
+        let offset = u32::try_from(archive.len()).map_err(|_| String::from("Zip archive too large"))?;
+        let size = u32::try_from(contents.len()).map_err(|_| format!("Zip entry {name} too large"))?;
+        let name_length = u16::try_from(name.len()).map_err(|_| format!("Zip entry name {name} too long"))?;
+        let crc = crc32(contents);
+
+        archive.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        write_entry_header(&mut archive, crc, size, name_length);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(contents);
+
+        directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        write_entry_header(&mut directory, crc, size, name_length);
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let count = u16::try_from(entries.len()).map_err(|_| String::from("Too many zip entries"))?;
+    let directory_offset = u32::try_from(archive.len()).map_err(|_| String::from("Zip archive too large"))?;
+    let directory_size = directory.len() as u32;
+    archive.extend_from_slice(&directory);
+
+    archive.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&directory_size.to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    archive.extend_from_slice(&[0; 2]);
+
+    Ok(archive)
+}
+
+/// Fields shared by local and central headers, from "version needed" up to the extra field length
+fn write_entry_header(out: &mut Vec<u8>, crc: u32, size: u32, name_length: u16) {
+    out.extend_from_slice(&20u16.to_le_bytes());
+    out.extend_from_slice(&[0; 2]);
+    out.extend_from_slice(&[0; 2]);
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&name_length.to_le_bytes());
+    out.extend_from_slice(&[0; 2]);
+}
+
+fn slice(data: &[u8], start: usize, length: usize) -> Result<&[u8], String> {
+    data.get(start..start + length).ok_or(String::from("Truncated zip archive"))
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, String> {
+    let bytes = slice(data, pos, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, String> {
+    let bytes = slice(data, pos, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, needed: u32) -> Result<u32, String> {
+        while self.count < needed {
+            let byte = *self.data.get(self.pos).ok_or("Truncated deflate stream")?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1u64 << needed) - 1) as u32;
+        self.buffer >>= needed;
+        self.count -= needed;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code stored as per-length counts and symbols sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(String::from("Invalid Huffman code in deflate stream"))
+    }
+}
+
+/// Deflate can expand data at most about 1032 times, which bounds what a size claimed by an
+/// archive is allowed to reserve
+const MAX_EXPANSION: usize = 1032;
+
+/// Decompresses a deflate stream, failing as soon as it grows beyond the size of its entry
+fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader { data, pos: 0, buffer: 0, count: 0 };
+    let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(MAX_EXPANSION)));
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let length = read_u16(data, reader.pos)?;
+                if read_u16(data, reader.pos + 2)? != !length {
+                    return Err(String::from("Invalid length of stored deflate block"));
+                }
+                let length = length as usize;
+                reader.pos += 4;
+                out.extend_from_slice(slice(data, reader.pos, length)?);
+                reader.pos += length;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5u8; 30]);
+                inflate_block(&mut reader, &mut out, size, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, size, &literals, &distances)?;
+            }
+            _ => return Err(String::from("Invalid deflate block type")),
+        }
+
+        if out.len() > size {
+            return Err(String::from("Deflate stream longer than its entry"));
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[*index] = reader.bits(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths);
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = codes.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.get(index.wrapping_sub(1)).ok_or("Invalid deflate code lengths")?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+
+        if index + repeat > lengths.len() {
+            return Err(String::from("Invalid deflate code lengths"));
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, size: usize, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        if out.len() > size {
+            return Err(String::from("Deflate stream longer than its entry"));
+        }
+        let symbol = literals.decode(reader)? as usize;
+
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let index = symbol - 257;
+            if index >= LENGTH_BASE.len() {
+                return Err(String::from("Invalid deflate length code"));
+            }
+            let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+            let index = distances.decode(reader)? as usize;
+            if index >= DISTANCE_BASE.len() {
+                return Err(String::from("Invalid deflate distance code"));
+            }
+            let distance = DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+
+            if distance > out.len() {
+                return Err(String::from("Invalid deflate distance"));
+            }
+            let start = out.len() - distance;
+            for offset in 0..length {
+                out.push(out[start + offset]);
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Sparse storage for the cells of a sheet. Cells are kept in row order, so a range is read by
+//! skipping from one populated cell to the next without visiting the empty ones, and
+//! `Sum(A:A)` over a column of a few values reads just those values.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::model::CellAddress;
+
+/// Address ordered row by row, then column by column
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Key(CellAddress);
+
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        (self.0.1, self.0.0).cmp(&(other.0.1, other.0.0))
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub(crate) struct Grid<T> {
+    cells: BTreeMap<Key, T>,
+    /// Number of populated cells in each column that has any
+    columns: BTreeMap<i32, usize>,
+}
+
+impl<T> Default for Grid<T> {
+    fn default() -> Grid<T> {
+        Grid { cells: BTreeMap::new(), columns: BTreeMap::new() }
+    }
+}
+
+impl<T> Grid<T> {
+    pub fn new() -> Grid<T> {
+        Grid::default()
+    }
+
+    pub fn get(&self, adr: &CellAddress) -> Option<&T> {
+        self.cells.get(&Key(*adr))
+    }
+
+    /// Stores a cell, returning what it held before
+    pub fn insert(&mut self, adr: CellAddress, val: T) -> Option<T> {
+        let previous = self.cells.insert(Key(adr), val);
+        if previous.is_none() {
+            *self.columns.entry(adr.0).or_default() += 1;
+        }
+        previous
+    }
+
+    pub fn remove(&mut self, adr: &CellAddress) -> Option<T> {
+        let previous = self.cells.remove(&Key(*adr));
+        if previous.is_some() {
+            if let Some(count) = self.columns.get_mut(&adr.0) {
+                *count -= 1;
+                if *count == 0 {
+                    self.columns.remove(&adr.0);
+                }
+            }
+        }
+        previous
+    }
+
+    /// Every cell, row by row
+    pub fn iter(&self) -> impl Iterator<Item = (&CellAddress, &T)> {
+        self.cells.iter().map(|(key, val)| (&key.0, val))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &CellAddress> {
+        self.cells.keys().map(|key| &key.0)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.cells.values()
+    }
+
+    pub fn into_cells(self) -> impl Iterator<Item = (CellAddress, T)> {
+        self.cells.into_iter().map(|(key, val)| (key.0, val))
+    }
+
+    /// Cells within the given columns and rows, row by row. Each step finds the next populated
+    /// cell directly, so the cost grows with the cells and rows populated rather than the area.
+    pub fn range(&self, columns: RangeInclusive<i32>, rows: RangeInclusive<i32>) -> Cells<'_, T> {
+        let next = Some(CellAddress(*columns.start(), *rows.start())).filter(|_| !columns.is_empty() && !rows.is_empty());
+        Cells { grid: self, columns, last_row: *rows.end(), next }
+    }
+
+    /// Smallest rectangle holding every populated cell, as its top left and bottom right corners
+    pub fn bounds(&self) -> Option<(CellAddress, CellAddress)> {
+        let (first, _) = self.cells.first_key_value()?;
+        let (last, _) = self.cells.last_key_value()?;
+        let (left, _) = self.columns.first_key_value()?;
+        let (right, _) = self.columns.last_key_value()?;
+        Some((CellAddress(*left, first.0.1), CellAddress(*right, last.0.1)))
+    }
+}
+
+impl<T> FromIterator<(CellAddress, T)> for Grid<T> {
+    fn from_iter<I: IntoIterator<Item = (CellAddress, T)>>(iter: I) -> Grid<T> {
+        let mut grid = Grid::new();
+        for (adr, val) in iter {
+            grid.insert(adr, val);
+        }
+        grid
+    }
+}
+
+/// Populated cells of a rectangle, row by row
+pub(crate) struct Cells<'g, T> {
+    grid: &'g Grid<T>,
+    columns: RangeInclusive<i32>,
+    last_row: i32,
+    /// Where to look for the next cell, or `None` once past the rectangle
+    next: Option<CellAddress>,
+}
+
+impl<'g, T> Iterator for Cells<'g, T> {
+    type Item = (&'g CellAddress, &'g T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let from = self.next?;
+            let Some((key, val)) = self.grid.cells.range(Key(from)..).next() else {
+                self.next = None;
+                return None;
+            };
+            let CellAddress(column, row) = key.0;
+            if row > self.last_row {
+                self.next = None;
+                return None;
+            }
+
+            if column < *self.columns.start() {
+                self.next = Some(CellAddress(*self.columns.start(), row));
+            } else if column > *self.columns.end() {
+                self.next = row.checked_add(1).map(|row| CellAddress(*self.columns.start(), row));
+            } else {
+                self.next = match column.checked_add(1) {
+                    Some(column) if column <= *self.columns.end() => Some(CellAddress(column, row)),
+                    _ => row.checked_add(1).map(|row| CellAddress(*self.columns.start(), row)),
+                };
+                return Some((&key.0, val));
+            }
+        }
+    }
+}
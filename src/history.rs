@@ -0,0 +1,169 @@
+//! Undo and redo bookkeeping. Changes are stored as the inverse of what was done, grouped into
+//! the steps a user undoes at once, and the oldest steps are forgotten once the history grows
+//! beyond its memory budget. The next step to undo and the next to redo are kept whatever their
+//! size, so that even a change larger than the budget can be undone.
+
+use std::collections::VecDeque;
+
+/// Default memory budget of a history, in bytes
+pub const DEFAULT_HISTORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Changes undone or redone together
+pub(crate) struct Group<C> {
+    pub label: String,
+    /// In the order they were recorded; they are applied in reverse
+    pub changes: Vec<C>,
+    pub size: usize,
+}
+
+impl<C> Group<C> {
+    fn new(label: &str) -> Group<C> {
+        Group { label: label.to_string(), changes: Vec::new(), size: 0 }
+    }
+}
+
+pub(crate) struct History<C> {
+    undo: VecDeque<Group<C>>,
+    redo: Vec<Group<C>>,
+    open: Option<Group<C>>,
+    depth: usize,
+    limit: usize,
+    used: usize,
+}
+
+impl<C> History<C> {
+    pub fn new(limit: usize) -> History<C> {
+        History { undo: VecDeque::new(), redo: Vec::new(), open: None, depth: 0, limit, used: 0 }
+    }
+
+    /// Starts a group, or nests within the open one, whose label is kept
+    pub fn begin(&mut self, label: &str) {
+        if self.depth == 0 {
+            self.open = Some(Group::new(label));
+        }
+        self.depth += 1;
+    }
+
+    /// Ends a group, storing it as a single step once the outermost group ends
+    pub fn end(&mut self) {
+        match self.depth {
+            0 => {}
+            1 => self.close(),
+            _ => self.depth -= 1,
+        }
+    }
+
+    /// Records the inverse of a change just made, as a step of its own outside of a group
+    pub fn record(&mut self, label: &str, change: C, size: usize) {
+        let standalone = self.open.is_none();
+        let group = self.open.get_or_insert_with(|| Group::new(label));
+        group.changes.push(change);
+        group.size += size;
+
+        if standalone {
+            self.close();
+        }
+    }
+
+    /// Number of changes recorded in the open group so far
+    pub fn open_len(&self) -> usize {
+        self.open.as_ref().map_or(0, |group| group.changes.len())
+    }
+
+    /// Takes the changes recorded in the open group after the first `start`, which are then
+    /// never stored
+    pub fn take_since(&mut self, start: usize, size: impl Fn(&C) -> usize) -> Vec<C> {
+        match &mut self.open {
+            Some(group) if start < group.changes.len() => {
+                let changes = group.changes.split_off(start);
+                group.size -= changes.iter().map(size).sum::<usize>();
+                changes
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Takes the most recent step to undo, closing any open group first
+    pub fn take_undo(&mut self) -> Option<Group<C>> {
+        self.close();
+        let group = self.undo.pop_back()?;
+        self.used -= group.size;
+        Some(group)
+    }
+
+    pub fn take_redo(&mut self) -> Option<Group<C>> {
+        self.close();
+        let group = self.redo.pop()?;
+        self.used -= group.size;
+        Some(group)
+    }
+
+    /// Stores the inverse of a step just undone
+    pub fn push_redo(&mut self, group: Group<C>) {
+        self.used += group.size;
+        self.redo.push(group);
+        self.evict();
+    }
+
+    /// Stores the inverse of a step just redone, keeping the rest of the redo stack
+    pub fn push_undo(&mut self, group: Group<C>) {
+        self.used += group.size;
+        self.undo.push_back(group);
+        self.evict();
+    }
+
+    pub fn undo_label(&self) -> Option<&str> {
+        self.undo.back().map(|group| group.label.as_str())
+    }
+
+    pub fn redo_label(&self) -> Option<&str> {
+        self.redo.last().map(|group| group.label.as_str())
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.evict();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open = None;
+        self.depth = 0;
+        self.used = 0;
+    }
+
+    fn close(&mut self) {
+        self.depth = 0;
+        if let Some(group) = self.open.take() {
+            if !group.changes.is_empty() {
+                // New changes make the undone steps impossible to redo
+                self.redo_clear();
+                self.used += group.size;
+                self.undo.push_back(group);
+                self.evict();
+            }
+        }
+    }
+
+    fn redo_clear(&mut self) {
+        self.used -= self.redo.iter().map(|group| group.size).sum::<usize>();
+        self.redo.clear();
+    }
+
+    /// Forgets the oldest undo steps, then the furthest redo steps, until within the budget or
+    /// only the next step each way is left
+    fn evict(&mut self) {
+        while self.used > self.limit {
+            let group = if self.undo.len() > 1 {
+                self.undo.pop_front()
+            } else if self.redo.len() > 1 {
+                Some(self.redo.remove(0))
+            } else {
+                None
+            };
+            let Some(group) = group else { break };
+            self.used -= group.size;
+        }
+    }
+}
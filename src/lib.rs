@@ -1,2 +1,13 @@
 pub mod model;
+pub mod address;
 pub mod environment;
+mod dependency;
+mod grid;
+mod columns;
+pub mod events;
+pub mod history;
+pub mod format;
+pub mod types;
+pub mod simplify;
+pub mod bytecode;
+pub mod trace;
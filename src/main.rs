@@ -1,74 +1,401 @@
-use gridkid::{model::{Primitive, Operation, Evaluatable, CellAddress, CellValue}, environment::Environment};
-
-fn main() {
-    let mut environment = Environment::init();
-
-    let five = Primitive::Integer(5);
-    let four = Primitive::Integer(4);
-    let negative_four = Primitive::Integer(-4);
-    let seventeen = Primitive::Integer(17);
-
-    let six_point_four = Primitive::Float(6.4);
-    let five_point_seven = Primitive::Float(5.7);
-    let negative_five_point_seven = Primitive::Float(-5.7);
-
-    let primitive_true = Primitive::Boolean(true);
-    let primitive_false = Primitive::Boolean(false);
-
-    let test_str = Primitive::String("test");
-    let not_test_str = Primitive::String("not test");
-
-    println!("INTEGER ARITHMETIC");
-    println!("5 + -4 = {}", Operation::Add(&five, &negative_four).evaluate(&environment).unwrap().to_string());
-    println!("5 - 17 = {}", Operation::Subtract(&five, &seventeen).evaluate(&environment).unwrap().to_string());
-    println!("-4 * 17 = {}", Operation::Multiply(&negative_four, &seventeen).evaluate(&environment).unwrap().to_string());
-    println!("5 / -4 = {}", Operation::Divide(&five, &negative_four).evaluate(&environment).unwrap().to_string());
-    println!("17 % 5 = {}", Operation::Modulus(&seventeen, &five).evaluate(&environment).unwrap().to_string());
-    println!("-4 ** 5 = {}", Operation::Power(&negative_four, &five).evaluate(&environment).unwrap().to_string());
-    println!();
-
-    println!("FLOAT ARITHMETIC");
-    println!("6.4 + 5.7 = {}", Operation::Add(&six_point_four, &five_point_seven).evaluate(&environment).unwrap().to_string());
-    println!("6.4 - -5.7 = {}", Operation::Subtract(&six_point_four, &negative_five_point_seven).evaluate(&environment).unwrap().to_string());
-    println!("-5.7 * 5.7 = {}", Operation::Multiply(&negative_five_point_seven, &five_point_seven).evaluate(&environment).unwrap().to_string());
-    println!("6.4 / -4 = {}", Operation::Divide(&six_point_four, &negative_four).evaluate(&environment).unwrap().to_string());
-    println!("-5.7 % 5 = {}", Operation::Modulus(&negative_five_point_seven, &five).evaluate(&environment).unwrap().to_string());
-    println!("5.7 ** -5.7 = {}", Operation::Power(&five_point_seven, &negative_five_point_seven).evaluate(&environment).unwrap().to_string());
-    println!();
-
-    println!("LOGICAL");
-    println!("true && true = {}", Operation::LogicalAnd(&primitive_true, &primitive_true).evaluate(&environment).unwrap().to_string());
-    println!("false || false = {}", Operation::LogicalOr(&primitive_false, &primitive_false).evaluate(&environment).unwrap().to_string());
-    println!("!true = {}", Operation::LogicalNot(&primitive_true).evaluate(&environment).unwrap().to_string());
-    println!();
-
-    println!("BITWISE");
-    println!("5 & 4 = {}", Operation::BitwiseAnd(&five, &four).evaluate(&environment).unwrap().to_string());
-    println!("5 | 4 = {}", Operation::BitwiseOr(&five, &four).evaluate(&environment).unwrap().to_string());
-    println!("5 ^ 4 = {}", Operation::BitwiseXor(&five, &four).evaluate(&environment).unwrap().to_string());
-    println!("~5 = {}", Operation::BitwiseNot(&five).evaluate(&environment).unwrap().to_string());
-    println!();
-
-    println!("EQUALITY");
-    println!("5 == 5 = {}", Operation::Equals(&five, &five).evaluate(&environment).unwrap().to_string());
-    println!("\"test\" ==  \"not test\" = {}", Operation::Equals(&test_str, &not_test_str).evaluate(&environment).unwrap().to_string());
-    println!("true == false = {}", Operation::Equals(&primitive_true, &primitive_false).evaluate(&environment).unwrap().to_string());
-    println!("5.7 != -5.7 = {}", Operation::NotEquals(&five_point_seven, &negative_five_point_seven).evaluate(&environment).unwrap().to_string());
-    println!();
-
-    println!("COMBINATION");
-    println!("{} = {}", Operation::Equals(&Operation::Multiply(&Operation::Add(&Operation::Subtract(&five, &five), &five), &Operation::BitwiseOr(&five, &four)), &Primitive::Integer(25)).to_string(), Operation::Equals(&Operation::Multiply(&Operation::Add(&Operation::Subtract(&five, &five), &five), &Operation::BitwiseOr(&five, &four)), &Primitive::Integer(25)).evaluate(&environment).unwrap().to_string());
-
-    println!("Adding to grid");
-    environment.set_cell(&CellAddress(0, 0), Box::new(five));
-    environment.set_cell(&CellAddress(0, 1), Box::new(not_test_str));
-    environment.set_cell(&CellAddress(1, 1), Box::new(CellValue(0, 0)));
-    environment.set_cell(&CellAddress(12, 13), Box::new(Operation::Equals(&Primitive::Integer(6), &Primitive::Integer(6))));
-    environment.set_cell(&CellAddress(13, 12), Box::new(Operation::Equals(&Primitive::Integer(6), &Primitive::String("hello, world"))));
-
-    println!("{}: {}", CellAddress(0, 0).to_string(), environment.get_cell(&CellAddress(0, 0)).unwrap().to_string());
-    println!("{}: {}", CellAddress(0, 1).to_string(), environment.get_cell(&CellAddress(0, 0)).unwrap().to_string());
-    // println!("{}: {}", CellAddress(0, 2).to_string(), environment.get_cell(&CellAddress(0, 2)).unwrap().to_string());
-    println!("(12, 13): {}", environment.get_cell(&CellAddress(12, 13)).unwrap().evaluate(&environment).unwrap().to_string());
-    println!("(13, 12): {}", environment.get_cell(&CellAddress(13, 12)).unwrap().evaluate(&environment).unwrap().to_string());
+//! `gridkid` command line: an interactive session for editing a workbook cell by cell. When
+//! standard input is not a terminal, commands are read from it without prompting so sessions can
+//! be scripted, and the exit status reports whether every command succeeded.
+//!
+//! `gridkid eval` evaluates a workbook without a session, for scripts and CI pipelines:
+//!
+//! ```text
+//! gridkid eval model.grid --set Inputs!B2=42 --get Outputs!C10 --format json
+//! gridkid eval model.grid --check
+//! ```
+//!
+//! It exits with status 1 when a requested cell, or with `--check` any cell, evaluates to an
+//! error, and with status 2 when the arguments or the workbook cannot be read.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::ExitCode;
+
+use gridkid::address::{CellRange, CellReference};
+use gridkid::environment::Environment;
+use gridkid::format::formula::{parse_formula, write_formula_on};
+use gridkid::format::table::{to_markdown, TableOptions};
+use gridkid::format::{json, read_workbook, write_workbook};
+use gridkid::model::{CellAddress, Primitive};
+use gridkid::trace::trace_on;
+
+const HELP: &str = "\
+set CELL = FORMULA   set a cell, e.g. set B1 = A1 * 2
+get CELL             print the value of a cell
+clear CELL           empty a cell
+show RANGE           print the values of a range as a table, e.g. show A1:C5
+deps CELL            list the cells a cell reads and the cells that read it
+audit                list the longest chain of cells, unused inputs and references to empty cells
+trace CELL           show step by step how the value of a cell was worked out
+sheet NAME           switch to a sheet, adding it if there is none of that name
+sheets               list the sheets
+undo, redo           revert or repeat the last change
+load FILE            replace the workbook with a .grid, .csv or .ods file
+save FILE            write the workbook to a .grid, .csv or .ods file
+history              list the commands entered so far
+help                 show this help
+quit                 leave";
+
+const EVAL_USAGE: &str = "\
+usage: gridkid eval FILE [--set CELL=FORMULA]... [--get CELL]... [--check] [--format text|json]
+
+--set CELL=FORMULA   replace a cell before calculating, e.g. --set Inputs!B2=42
+--get CELL           print the value of a cell
+--check              print every cell that evaluates to an error
+--format FORMAT      print text (the default) or json";
+
+struct Session {
+    environment: Environment,
+    history: Vec<String>,
+}
+
+enum Outcome {
+    Continue(Option<String>),
+    Quit,
+}
+
+impl Session {
+    fn run(&mut self, line: &str) -> Result<Outcome, String> {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        let output = match command {
+            "set" => {
+                let (cell, formula) = argument.split_once('=').ok_or("Expected set CELL = FORMULA")?;
+                let reference = self.reference(cell.trim())?;
+                let val = parse_formula(formula.trim())?;
+                let sheet = self.sheet_of(&reference);
+                let warning = self.environment.check_formula(&sheet, val.as_ref()).err().map(|e| format!("warning: {e}"));
+                self.environment.set_cell_on(&sheet, &reference.address, val)?;
+                warning
+            }
+            "get" => {
+                let reference = self.reference(argument)?;
+                match self.environment.value_at(&reference) {
+                    Some(Ok(val)) => Some(display(&val)),
+                    Some(Err(e)) => return Err(e),
+                    None => Some(format!("{} is empty", argument)),
+                }
+            }
+            "clear" => {
+                let reference = self.reference(argument)?;
+                self.on_sheet(&self.sheet_of(&reference), |environment| environment.clear_cell(&reference.address))?;
+                None
+            }
+            "show" => Some(self.show(argument)?),
+            "deps" => Some(self.dependencies(argument)?),
+            "trace" => Some(self.trace(argument)?),
+            "audit" => Some(self.audit()),
+            "sheet" => {
+                if !self.environment.sheet_names().any(|name| name == argument) {
+                    self.environment.add_sheet(argument)?;
+                }
+                self.environment.set_active_sheet(argument)?;
+                None
+            }
+            "sheets" => Some(self.environment.sheet_names().collect::<Vec<_>>().join("\n")),
+            "undo" => (!self.environment.undo()).then(|| String::from("Nothing to undo")),
+            "redo" => (!self.environment.redo()).then(|| String::from("Nothing to redo")),
+            "load" => {
+                self.environment = read_workbook(argument)?;
+                None
+            }
+            "save" => {
+                write_workbook(&self.environment, argument)?;
+                None
+            }
+            "history" => Some(self.history.iter().enumerate().map(|(index, line)| format!("{:>4}  {line}", index + 1)).collect::<Vec<_>>().join("\n")),
+            "help" => Some(HELP.to_string()),
+            "quit" | "exit" => return Ok(Outcome::Quit),
+            _ => return Err(format!("Unknown command {command}; try help")),
+        };
+
+        Ok(Outcome::Continue(output))
+    }
+
+    /// Cell named by a command, e.g. `B1` or `Inputs!B1`
+    fn reference(&self, text: &str) -> Result<CellReference, String> {
+        if text.is_empty() {
+            return Err(String::from("Expected a cell"));
+        }
+        text.parse()
+    }
+
+    fn sheet_of(&self, reference: &CellReference) -> String {
+        reference.sheet.clone().unwrap_or(self.environment.active_sheet().to_string())
+    }
+
+    /// Runs an edit of the active sheet on another sheet
+    fn on_sheet<T>(&mut self, sheet: &str, edit: impl FnOnce(&mut Environment) -> T) -> Result<T, String> {
+        let active = self.environment.active_sheet().to_string();
+        self.environment.set_active_sheet(sheet)?;
+        let result = edit(&mut self.environment);
+        self.environment.set_active_sheet(&active)?;
+        Ok(result)
+    }
+
+    fn show(&self, argument: &str) -> Result<String, String> {
+        let range: CellRange = argument.parse()?;
+        if range.is_3d() {
+            return Err(String::from("Cannot show a range spanning several sheets"));
+        }
+        if range.is_whole_column() || range.is_whole_row() {
+            return Err(String::from("Cannot show whole rows or columns"));
+        }
+
+        let sheet = self.sheet_of(&range.start);
+        let corner1 = CellAddress(*range.columns().start(), *range.rows().start());
+        let corner2 = CellAddress(*range.columns().end(), *range.rows().end());
+        if !self.environment.sheet_names().any(|name| name == sheet) {
+            return Err(format!("Sheet {sheet} not found"));
+        }
+        Ok(to_markdown(&self.environment, &sheet, &corner1, &corner2, &TableOptions::default()).trim_end().to_string())
+    }
+
+    fn dependencies(&self, argument: &str) -> Result<String, String> {
+        let reference = self.reference(argument)?;
+        let sheet = self.sheet_of(&reference);
+        let list = |cells: Vec<CellReference>| cells.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+
+        let mut lines = Vec::new();
+        if let Some(val) = self.environment.get_cell_on(&sheet, &reference.address) {
+            lines.push(format!("formula: {}", write_formula_on(val, &sheet)));
+        }
+        lines.push(format!("precedents: {}", list(self.environment.precedents(&sheet, &reference.address)?)));
+        lines.push(format!("dependents: {}", list(self.environment.dependents(&sheet, &reference.address)?)));
+        lines.push(format!("all precedents: {}", list(self.environment.transitive_precedents(&sheet, &reference.address)?)));
+        lines.push(format!("all dependents: {}", list(self.environment.transitive_dependents(&sheet, &reference.address)?)));
+        Ok(lines.join("\n"))
+    }
+
+    fn audit(&self) -> String {
+        let list = |cells: &[CellReference], separator: &str| cells.iter().map(ToString::to_string).collect::<Vec<_>>().join(separator);
+        let mut lines = vec![
+            format!("longest chain: {}", list(&self.environment.longest_chain(), " -> ")),
+            format!("orphaned inputs: {}", list(&self.environment.orphaned_inputs(), ", ")),
+        ];
+        for (cell, empty) in self.environment.empty_references() {
+            lines.push(format!("{cell} reads empty {}", list(&empty, ", ")));
+        }
+        lines.join("\n")
+    }
+
+    fn trace(&self, argument: &str) -> Result<String, String> {
+        let reference = self.reference(argument)?;
+        let sheet = self.sheet_of(&reference);
+        match self.environment.get_cell_on(&sheet, &reference.address) {
+            Some(val) => Ok(trace_on(val, &sheet, &self.environment).to_string().trim_end().to_string()),
+            None => Ok(format!("{} is empty", argument)),
+        }
+    }
+}
+
+/// Values as users typed them, without quotes around text
+fn display(val: &Primitive) -> String {
+    match val {
+        Primitive::String(text) => text.clone(),
+        _ => val.to_string(),
+    }
+}
+
+/// Arguments of `gridkid eval`
+struct Batch {
+    path: String,
+    overrides: Vec<(String, String)>,
+    requested: Vec<String>,
+    check: bool,
+    json: bool,
+}
+
+/// What `gridkid eval` found: requested cells in the order they were asked for, then the cells
+/// failing the check
+struct Report {
+    cells: Vec<(String, Option<Result<Primitive, String>>)>,
+    errors: Vec<(String, String)>,
+}
+
+impl Batch {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Batch, String> {
+        let mut path = None;
+        let mut batch = Batch { path: String::new(), overrides: Vec::new(), requested: Vec::new(), check: false, json: false };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--set" => {
+                    let text = flag_value(&mut args, "--set")?;
+                    let (cell, formula) = text.split_once('=').ok_or(format!("Expected --set CELL=FORMULA, got {text}"))?;
+                    batch.overrides.push((cell.trim().to_string(), formula.trim().to_string()));
+                }
+                "--get" => batch.requested.push(flag_value(&mut args, "--get")?),
+                "--check" => batch.check = true,
+                "--format" => {
+                    batch.json = match flag_value(&mut args, "--format")?.as_str() {
+                        "json" => true,
+                        "text" => false,
+                        other => return Err(format!("Unknown format {other}; expected text or json")),
+                    }
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {flag}")),
+                _ if path.is_none() => path = Some(arg),
+                _ => return Err(format!("Expected a single workbook, got {arg} as well")),
+            }
+        }
+
+        batch.path = path.ok_or("Expected a workbook to evaluate")?;
+        if batch.requested.is_empty() && !batch.check {
+            return Err(String::from("Nothing to do; expected --get or --check"));
+        }
+        Ok(batch)
+    }
+
+    /// Loads the workbook, applies the overrides in one transaction so it is calculated once,
+    /// and collects the values asked for
+    fn run(&self) -> Result<Report, String> {
+        let mut environment = read_workbook(&self.path)?;
+
+        environment.begin("Overrides");
+        for (cell, formula) in &self.overrides {
+            let reference: CellReference = cell.parse().map_err(|e| format!("--set {cell}: {e}"))?;
+            let sheet = reference.sheet.clone().unwrap_or(environment.active_sheet().to_string());
+            let val = parse_formula(formula).map_err(|e| format!("--set {cell}: {e}"))?;
+            environment.set_cell_on(&sheet, &reference.address, val).map_err(|e| format!("--set {cell}: {e}"))?;
+        }
+        // Circular references show up as errors of the cells involved rather than failing here
+        environment.commit_unchecked()?;
+
+        let mut cells = Vec::new();
+        for cell in &self.requested {
+            let reference: CellReference = cell.parse().map_err(|e| format!("--get {cell}: {e}"))?;
+            cells.push((cell.clone(), environment.value_at(&reference)));
+        }
+
+        let mut errors = Vec::new();
+        if self.check {
+            for sheet in environment.sheet_names() {
+                for (adr, _) in environment.cells_on(sheet) {
+                    if let Some(Err(e)) = environment.value_on(sheet, adr) {
+                        let reference = CellReference { sheet: Some(sheet.to_string()), ..CellReference::relative(*adr) };
+                        errors.push((reference.to_string(), e));
+                    }
+                }
+            }
+        }
+
+        Ok(Report { cells, errors })
+    }
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or(format!("Expected a value after {flag}"))
+}
+
+impl Report {
+    fn failed(&self) -> bool {
+        !self.errors.is_empty() || self.cells.iter().any(|(_, val)| matches!(val, Some(Err(_))))
+    }
+
+    fn text(&self) -> String {
+        let mut lines = Vec::new();
+        for (cell, val) in &self.cells {
+            lines.push(match val {
+                Some(Ok(val)) => format!("{cell} = {}", display(val)),
+                Some(Err(e)) => format!("{cell} = #ERR {e}"),
+                None => format!("{cell} is empty"),
+            });
+        }
+        for (cell, e) in &self.errors {
+            lines.push(format!("{cell} = #ERR {e}"));
+        }
+        lines.join("\n")
+    }
+
+    fn json(&self) -> String {
+        let cells: Vec<String> = self.cells.iter()
+            .map(|(cell, val)| format!("    {}: {}", json::string(cell), json::value(val)))
+            .collect();
+        let errors: Vec<String> = self.errors.iter().map(|(cell, e)| format!("    {}: {}", json::string(cell), json::string(e))).collect();
+
+        let object = |members: Vec<String>| if members.is_empty() { String::from("{}") } else { format!("{{\n{}\n  }}", members.join(",\n")) };
+        format!("{{\n  \"ok\": {},\n  \"cells\": {},\n  \"errors\": {}\n}}", !self.failed(), object(cells), object(errors))
+    }
+}
+
+fn eval(args: impl Iterator<Item = String>) -> ExitCode {
+    let batch = match Batch::parse(args) {
+        Ok(batch) => batch,
+        Err(e) => {
+            eprintln!("error: {e}\n{EVAL_USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match batch.run() {
+        Ok(report) => {
+            let output = if batch.json { report.json() } else { report.text() };
+            if !output.is_empty() {
+                println!("{output}");
+            }
+            if report.failed() { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("eval") {
+        return eval(args.skip(1));
+    }
+
+    let interactive = io::stdin().is_terminal();
+    let mut session = Session { environment: Environment::init(), history: Vec::new() };
+    let mut failed = false;
+
+    if interactive {
+        println!("gridkid {} - type help for a list of commands", env!("CARGO_PKG_VERSION"));
+    }
+
+    let mut lines = io::stdin().lock().lines();
+    for number in 1.. {
+        if interactive {
+            print!("{}> ", session.environment.active_sheet());
+            let _ = io::stdout().flush();
+        }
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+            None => break,
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        session.history.push(line.to_string());
+
+        match session.run(line) {
+            Ok(Outcome::Continue(Some(output))) => println!("{output}"),
+            Ok(Outcome::Continue(None)) => {}
+            Ok(Outcome::Quit) => break,
+            Err(e) if interactive => eprintln!("error: {e}"),
+            Err(e) => {
+                eprintln!("error on line {number}: {e}");
+                failed = true;
+            }
+        }
+    }
+
+    if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS }
 }
@@ -1,10 +1,33 @@
-use crate::environment::Environment;
+use std::cmp::Ordering;
+use std::fmt;
 
-pub trait Evaluatable: ToString {
-    fn evaluate<'a>(&'a self, environment: &'a Environment) -> Result<Primitive, String>;
+use crate::environment::{Definition, Environment};
+
+pub use crate::address::CellAddress;
+use crate::address::{CellRange, CellReference};
+use crate::columns::Tally;
+use crate::format::formula::write_formula;
+
+/// Node of a formula tree. Formulas are shared with the threads recalculating a workbook, hence
+/// `Send + Sync`.
+pub trait Evaluatable: ToString + Send + Sync {
+    fn evaluate(&self, environment: &Environment) -> Result<Primitive, String>;
+
+    /// Structural view of this node, used by code that needs to walk a formula tree
+    fn expression(&self) -> Expression<'_>;
 }
 
-enum OperationType {
+/// Borrowed view of a node in a formula tree
+pub enum Expression<'a> {
+    Primitive(&'a Primitive),
+    Operation(&'a Operation),
+    CellValue(&'a CellValue),
+    Statistics(&'a Statistics),
+    InvalidReference(&'a InvalidReference),
+    NamedValue(&'a NamedValue),
+}
+
+pub(crate) enum OperationType {
     Arithmetic,
     Logical,
     Bitwise,
@@ -14,51 +37,50 @@ enum OperationType {
     IntToFloat
 }
 
-impl ToString for OperationType {
-    fn to_string(&self) -> String {
+impl fmt::Display for OperationType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Arithmetic => String::from("Arithmetic"),
-            Self::Logical => String::from("Logical"),
-            Self::Bitwise => String::from("Bitwise"),
-            Self::Equality => String::from("Equality"),
-            Self::Relational => String::from("Relational"),
-            Self::FloatToInt => String::from("Float to Int"),
-            Self::IntToFloat => String::from("Int to Float"),
+            Self::Arithmetic => write!(f, "Arithmetic"),
+            Self::Logical => write!(f, "Logical"),
+            Self::Bitwise => write!(f, "Bitwise"),
+            Self::Equality => write!(f, "Equality"),
+            Self::Relational => write!(f, "Relational"),
+            Self::FloatToInt => write!(f, "Float to Int"),
+            Self::IntToFloat => write!(f, "Int to Float"),
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum Primitive<'a> {
+#[derive(Clone, Debug, PartialEq)]
+pub enum Primitive {
     Integer(i32),
     Float(f32),
     Boolean(bool),
-    String(&'a str),
+    String(String),
 }
 
-impl ToString for Primitive<'_> {
-    fn to_string(&self) -> String {
+impl fmt::Display for Primitive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Primitive::Integer(val) => format!("{val}"),
-            Primitive::Float(val) => format!("{val}"),
-            Primitive::Boolean(val) => format!("{val}"),
-            Primitive::String(val) => format!("\"{val}\""),
+            Primitive::Integer(val) => write!(f, "{val}"),
+            Primitive::Float(val) => write!(f, "{val}"),
+            Primitive::Boolean(val) => write!(f, "{val}"),
+            Primitive::String(val) => write!(f, "\"{val}\""),
         }
     }
 }
 
-impl Evaluatable for Primitive<'_> {
+impl Evaluatable for Primitive {
     fn evaluate(&self, _environment: &Environment) -> Result<Primitive, String> {
-        match self {
-            Primitive::Integer(val) => Ok(Primitive::Integer(val.clone())),
-            Primitive::Float(val) => Ok(Primitive::Float(val.clone())),
-            Primitive::Boolean(val) => Ok(Primitive::Boolean(val.clone())),
-            Primitive::String(val) => Ok(Primitive::String(val)),
-        }
+        Ok(self.clone())
+    }
+
+    fn expression(&self) -> Expression<'_> {
+        Expression::Primitive(self)
     }
 }
 
-impl Primitive<'_> {
+impl Primitive {
     fn type_string(&self) -> String {
         match self {
             Primitive::Integer(_) => String::from("Integer"),
@@ -69,63 +91,47 @@ impl Primitive<'_> {
     }
 
     fn is_numeric(&self) -> bool {
-        match self {
-            Self::Integer(_) => true,
-            Self::Float(_) => true,
-            _ => false
-        }
+        matches!(self, Self::Integer(_) | Self::Float(_))
     }
 
     fn is_integer(&self) -> bool {
-        match self {
-            Self::Integer(_) => true,
-            _ => false
-        }
+        matches!(self, Self::Integer(_))
     }
 
     fn _is_float(&self) -> bool {
-        match self {
-            Self::Float(_) => true,
-            _ => false
-        }
+        matches!(self, Self::Float(_))
     }
 
     fn is_boolean(&self) -> bool {
-        match self {
-            Self::Boolean(_) => true,
-            _ => false
-        }
+        matches!(self, Self::Boolean(_))
     }
 
     fn _is_string(&self) -> bool {
-        match self {
-            Self::String(_) => true,
-            _ => false
-        }
+        matches!(self, Self::String(_))
     }
 
     fn get_int_value(&self) -> Result<i32, String> {
         match self {
-            Self::Integer(val) => Ok(val.clone()),
+            Self::Integer(val) => Ok(*val),
             _ => Err(format!("Invalid type to get integer value: {}", self.type_string()))
         }
     }
 
     fn get_float_value(&self) -> Result<f32, String> {
         match self {
-            Self::Float(val) => Ok(val.clone()),
+            Self::Float(val) => Ok(*val),
             _ => Err(format!("Invalid type to get float value: {}", self.type_string()))
         }
     }
-    
+
     fn get_boolean_value(&self) -> Result<bool, String> {
         match self {
-            Self::Boolean(val) => Ok(val.clone()),
+            Self::Boolean(val) => Ok(*val),
             _ => Err(format!("Invalid type to get Boolean value: {}", self.type_string()))
         }
     }
 
-    fn get_string_value<'a>(&'a self) -> Result<&'a str, String> {
+    fn get_string_value(&self) -> Result<&str, String> {
         match self {
             Self::String(val) => Ok(val),
             _ => Err(format!("Invalid type to get String value: {}", self.type_string()))
@@ -133,418 +139,626 @@ impl Primitive<'_> {
     }
 }
 
-pub enum Operation<'a> {
-    Add(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    Subtract(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    Multiply(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    Divide(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    Modulus(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    Power(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    LogicalAnd(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    LogicalOr(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    LogicalNot(&'a dyn Evaluatable),
-    BitwiseAnd(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    BitwiseOr(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    BitwiseXor(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    BitwiseNot(&'a dyn Evaluatable),
-    LeftShift(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    RightShift(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    Equals(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    NotEquals(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    LessThan(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    LessThanOrEqual(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    GreaterThan(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    GreaterThanOrEqual(&'a dyn Evaluatable, &'a dyn Evaluatable),
-    FloatToInt(&'a dyn Evaluatable),
-    IntToFloat(&'a dyn Evaluatable),
-}
-
-impl ToString for Operation<'_> {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Add(val1, val2) => format!("({}) + ({})", val1.to_string(), val2.to_string()),
-            Self::Subtract(val1, val2) => format!("({}) - ({})", val1.to_string(), val2.to_string()),
-            Self::Multiply(val1, val2) => format!("({}) * ({})", val1.to_string(), val2.to_string()),
-            Self::Divide(val1, val2) => format!("({}) / ({})", val1.to_string(), val2.to_string()),
-            Self::Modulus(val1, val2) => format!("({}) % ({})", val1.to_string(), val2.to_string()),
-            Self::Power(val1, val2) => format!("({}) ** ({})", val1.to_string(), val2.to_string()),
-
-            Self::LogicalAnd(val1, val2) => format!("({}) && ({})", val1.to_string(), val2.to_string()),
-            Self::LogicalOr(val1, val2) => format!("({}) || ({})", val1.to_string(), val2.to_string()),
-            Self::LogicalNot(val1) => format!("!({})", val1.to_string()),
+/// How far apart two floats may be and still compare equal. Equality, the relational operators
+/// and the picks of `Max` and `Min` all use the tolerance of the workbook, so `a <= b` holds
+/// exactly when `a < b` or `a == b` does. `NaN` equals nothing, itself included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tolerance {
+    /// Only the same value, where `0.0` and `-0.0` are the same
+    Exact,
+    /// Values at most this far apart
+    Absolute(f32),
+    /// Values at most this fraction of the larger magnitude apart
+    Relative(f32),
+    /// Values at most this many representable floats apart
+    Ulps(u32),
+}
 
-            Self::BitwiseAnd(val1, val2) => format!("({}) & ({})", val1.to_string(), val2.to_string()),
-            Self::BitwiseOr(val1, val2) => format!("({}) | ({})", val1.to_string(), val2.to_string()),
-            Self::BitwiseXor(val1, val2) => format!("({}) ^ ({})", val1.to_string(), val2.to_string()),
-            Self::BitwiseNot(val1) => format!("~({})", val1.to_string()),
-            Self::LeftShift(val1, val2) => format!("({}) << ({})", val1.to_string(), val2.to_string()),
-            Self::RightShift(val1, val2) => format!("({}) >> ({})", val1.to_string(), val2.to_string()),
+impl Default for Tolerance {
+    fn default() -> Tolerance {
+        Tolerance::Absolute(f32::EPSILON)
+    }
+}
 
-            Self::Equals(val1, val2) => format!("({}) == ({})", val1.to_string(), val2.to_string()),
-            Self::NotEquals(val1, val2) => format!("({}) != ({})", val1.to_string(), val2.to_string()),
+impl Tolerance {
+    pub fn equal(self, v1: f32, v2: f32) -> bool {
+        if v1 == v2 {
+            return true;
+        }
+        if v1.is_nan() || v2.is_nan() || v1.is_infinite() || v2.is_infinite() {
+            return false;
+        }
+        match self {
+            Tolerance::Exact => false,
+            Tolerance::Absolute(tolerance) => (v1 - v2).abs() <= tolerance,
+            Tolerance::Relative(tolerance) => (v1 - v2).abs() <= tolerance * v1.abs().max(v2.abs()),
+            Tolerance::Ulps(ulps) => ordered(v1).abs_diff(ordered(v2)) <= u64::from(ulps),
+        }
+    }
 
-            Self::LessThan(val1, val2) => format!("({}) < ({})", val1.to_string(), val2.to_string()),
-            Self::LessThanOrEqual(val1, val2) => format!("({}) <= ({})", val1.to_string(), val2.to_string()),
-            Self::GreaterThan(val1, val2) => format!("({}) > ({})", val1.to_string(), val2.to_string()),
-            Self::GreaterThanOrEqual(val1, val2) => format!("({}) >= ({})", val1.to_string(), val2.to_string()),
+    /// Order of two floats, counting those the tolerance finds equal as equal, or `None` if
+    /// either is `NaN`
+    pub fn compare(self, v1: f32, v2: f32) -> Option<Ordering> {
+        if self.equal(v1, v2) {
+            return Some(Ordering::Equal);
+        }
+        v1.partial_cmp(&v2)
+    }
 
-            Self::FloatToInt(val1) => format!("FloatToInt({})", val1.to_string()),
-            Self::IntToFloat(val1) => format!("IntToFloat({})", val1.to_string()),
+    /// `equal` for numbers a range holds, without rounding them to `f32` first: integers beyond
+    /// the precision of `f32` are equal only to numbers within an absolute or relative tolerance
+    fn equal_wide(self, v1: f64, v2: f64) -> bool {
+        if v1 == v2 {
+            return true;
         }
+        if v1 as f32 as f64 == v1 && v2 as f32 as f64 == v2 {
+            return self.equal(v1 as f32, v2 as f32);
+        }
+        match self {
+            Tolerance::Exact | Tolerance::Ulps(_) => false,
+            Tolerance::Absolute(tolerance) => (v1 - v2).abs() <= f64::from(tolerance),
+            Tolerance::Relative(tolerance) => (v1 - v2).abs() <= f64::from(tolerance) * v1.abs().max(v2.abs()),
+        }
+    }
+}
+
+/// Position of a float among all floats in order, so that neighbouring floats are one apart
+fn ordered(val: f32) -> i64 {
+    let bits = val.to_bits();
+    let magnitude = i64::from(bits & 0x7fff_ffff);
+    if bits >> 31 == 1 { -magnitude } else { magnitude }
+}
+
+pub enum Operation {
+    Add(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    Subtract(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    Multiply(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    Divide(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    Modulus(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    Power(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    LogicalAnd(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    LogicalOr(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    LogicalNot(Box<dyn Evaluatable>),
+    BitwiseAnd(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    BitwiseOr(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    BitwiseXor(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    BitwiseNot(Box<dyn Evaluatable>),
+    LeftShift(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    RightShift(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    Equals(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    NotEquals(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    LessThan(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    LessThanOrEqual(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    GreaterThan(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    GreaterThanOrEqual(Box<dyn Evaluatable>, Box<dyn Evaluatable>),
+    FloatToInt(Box<dyn Evaluatable>),
+    IntToFloat(Box<dyn Evaluatable>),
+}
+
+/// Kind of an `Operation`, without its operands
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulus,
+    Power,
+    LogicalAnd,
+    LogicalOr,
+    LogicalNot,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    BitwiseNot,
+    LeftShift,
+    RightShift,
+    Equals,
+    NotEquals,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    FloatToInt,
+    IntToFloat,
+}
+
+/// Formula text with only the parentheses precedence calls for, such as `(A1 - 5) * 2 == 25`
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", write_formula(self))
     }
 }
 
-impl Evaluatable for Operation<'_> {
-    fn evaluate<'a>(&'a self, environment: &'a Environment) -> Result<Primitive<'_>, String> {
-        let (result1, result2) = get_results(self, environment);
+impl Evaluatable for Operation {
+    fn evaluate(&self, environment: &Environment) -> Result<Primitive, String> {
+        let (v1, v2) = self.operands();
+        apply(self.operator(), v1.evaluate(environment), v2.map(|v| v.evaluate(environment)), environment.tolerance())
+    }
 
-        let results = unpack_results(result1, result2);
-        let val1: Primitive<'_>;
-        let val2_option: Option<Primitive<'_>>;
+    fn expression(&self) -> Expression<'_> {
+        Expression::Operation(self)
+    }
+}
 
-        match results {
-            Ok(vals) => (val1, val2_option) = vals,
-            Err(e) => return Err(e)
-        }
+/// Applies an operator to the results of evaluating its operands. Both operands are evaluated
+/// whatever their values, and the error of the first one that fails is the result.
+pub(crate) fn apply(operator: Operator, result1: Result<Primitive, String>, result2: Option<Result<Primitive, String>>, tolerance: Tolerance) -> Result<Primitive, String> {
+    let (val1, val2_option) = unpack_results(result1, result2)?;
+    apply_values(operator, val1, val2_option, tolerance)
+}
 
-        match self.get_type() {
-            OperationType::Arithmetic => {
-                arithmetic(self, &val1, &val2_option.unwrap())
-            }
-            OperationType::Logical => {
-                logic(self, &val1, &val2_option)   
-            }
-            OperationType::Bitwise => {
-                bitwise(self, &val1, &val2_option)
-            }
-            OperationType::Equality => {
-                equality(self, &val1, &val2_option.unwrap())
-            }
-            OperationType::Relational => {
-                relation(self, &val1, &val2_option.unwrap())
-            }
-            OperationType::FloatToInt => {
-                cast_to_integer(&val1)
-            }
-            OperationType::IntToFloat => {
-                cast_to_float(&val1)
-            }
+/// Applies an operator to the values of its operands, comparing floats with the given tolerance
+pub(crate) fn apply_values(operator: Operator, val1: Primitive, val2_option: Option<Primitive>, tolerance: Tolerance) -> Result<Primitive, String> {
+    match operator.get_type() {
+        OperationType::Arithmetic => {
+            arithmetic(operator, &val1, &val2_option.unwrap())
+        }
+        OperationType::Logical => {
+            logic(operator, &val1, &val2_option)
+        }
+        OperationType::Bitwise => {
+            bitwise(operator, &val1, &val2_option)
+        }
+        OperationType::Equality => {
+            equality(operator, &val1, &val2_option.unwrap(), tolerance)
+        }
+        OperationType::Relational => {
+            relation(operator, &val1, &val2_option.unwrap(), tolerance)
+        }
+        OperationType::FloatToInt => {
+            cast_to_integer(&val1)
+        }
+        OperationType::IntToFloat => {
+            cast_to_float(&val1)
         }
     }
 }
 
-
-fn type_mismatch_error<'a>(val1: &Primitive<'a>, val2_option: &Option<Primitive<'a>>, operation: &Operation) -> Result<Primitive<'a>, String> {
+fn type_mismatch_error(val1: &Primitive, val2_option: &Option<Primitive>, operator: Operator) -> Result<Primitive, String> {
     if let Some(val2) = val2_option {
-        Err(format!("Incompatible types: {} and {} for {} operation",val1.type_string(), val2.type_string(), operation.get_type().to_string()))
+        Err(format!("Incompatible types: {} and {} for {} operation",val1.type_string(), val2.type_string(), operator.get_type()))
     } else {
-        Err(format!("Incompatible type: {} for {} operation", val1.type_string(), operation.get_type().to_string()))
-    }   
+        Err(format!("Incompatible type: {} for {} operation", val1.type_string(), operator.get_type()))
+    }
 }
 
-impl Operation<'_> {
-    fn get_type(&self) -> OperationType {
+impl Operator {
+    pub(crate) fn get_type(self) -> OperationType {
         match self {
-            Self::Add(_, _) => OperationType::Arithmetic,
-            Self::Subtract(_, _) => OperationType::Arithmetic,
-            Self::Multiply(_, _) => OperationType::Arithmetic,
-            Self::Divide(_, _) => OperationType::Arithmetic,
-            Self::Modulus(_, _) => OperationType::Arithmetic,
-            Self::Power(_, _) => OperationType::Arithmetic,
+            Self::Add => OperationType::Arithmetic,
+            Self::Subtract => OperationType::Arithmetic,
+            Self::Multiply => OperationType::Arithmetic,
+            Self::Divide => OperationType::Arithmetic,
+            Self::Modulus => OperationType::Arithmetic,
+            Self::Power => OperationType::Arithmetic,
 
-            Self::LogicalAnd(_, _) => OperationType::Logical,
-            Self::LogicalOr(_, _) => OperationType::Logical,
-            Self::LogicalNot(_) => OperationType::Logical,
+            Self::LogicalAnd => OperationType::Logical,
+            Self::LogicalOr => OperationType::Logical,
+            Self::LogicalNot => OperationType::Logical,
 
-            Self::BitwiseAnd(_, _) => OperationType::Bitwise,
-            Self::BitwiseOr(_, _) => OperationType::Bitwise,
-            Self::BitwiseXor(_, _) => OperationType::Bitwise,
-            Self::BitwiseNot(_) => OperationType::Bitwise,
-            Self::LeftShift(_, _) => OperationType::Bitwise,
-            Self::RightShift(_, _) => OperationType::Bitwise,
+            Self::BitwiseAnd => OperationType::Bitwise,
+            Self::BitwiseOr => OperationType::Bitwise,
+            Self::BitwiseXor => OperationType::Bitwise,
+            Self::BitwiseNot => OperationType::Bitwise,
+            Self::LeftShift => OperationType::Bitwise,
+            Self::RightShift => OperationType::Bitwise,
 
-            Self::Equals(_, _) => OperationType::Equality,
-            Self::NotEquals(_, _) => OperationType::Equality,
+            Self::Equals => OperationType::Equality,
+            Self::NotEquals => OperationType::Equality,
 
-            Self::LessThan(_, _) => OperationType::Relational,
-            Self::LessThanOrEqual(_, _) => OperationType::Relational,
-            Self::GreaterThan(_, _) => OperationType::Relational,
-            Self::GreaterThanOrEqual(_, _) => OperationType::Relational,
+            Self::LessThan => OperationType::Relational,
+            Self::LessThanOrEqual => OperationType::Relational,
+            Self::GreaterThan => OperationType::Relational,
+            Self::GreaterThanOrEqual => OperationType::Relational,
 
-            Self::FloatToInt(_) => OperationType::FloatToInt,
-            Self::IntToFloat(_) => OperationType::IntToFloat,
+            Self::FloatToInt => OperationType::FloatToInt,
+            Self::IntToFloat => OperationType::IntToFloat,
         }
     }
-}
 
-/// Address of given cell
-#[derive(Eq, Hash, PartialEq, Clone, Copy)]
-pub struct CellAddress(pub i32, pub i32);
-
-impl ToString for CellAddress {
-    fn to_string(&self) -> String {
-        format!("({}, {})", self.0, self.1)
+    /// Whether the operator takes a single operand
+    pub fn is_unary(self) -> bool {
+        matches!(self, Self::LogicalNot | Self::BitwiseNot | Self::FloatToInt | Self::IntToFloat)
     }
 }
 
-/// Value of given cell
-pub struct CellValue(pub i32, pub i32);
+impl Operation {
+    pub(crate) fn get_type(&self) -> OperationType {
+        self.operator().get_type()
+    }
 
-impl Evaluatable for CellValue {
-    fn evaluate<'a>(&'a self, environment: &'a Environment) -> Result<Primitive, String> {
-        let value = environment.get_cell(&CellAddress(self.0, self.1));
-        match value {
-            Some(val) => val.evaluate(environment),
-            None => Err(format!("Value for cell ({}, {}) not found", self.0, self.1)),
+    pub fn operator(&self) -> Operator {
+        match self {
+            Self::Add(_, _) => Operator::Add,
+            Self::Subtract(_, _) => Operator::Subtract,
+            Self::Multiply(_, _) => Operator::Multiply,
+            Self::Divide(_, _) => Operator::Divide,
+            Self::Modulus(_, _) => Operator::Modulus,
+            Self::Power(_, _) => Operator::Power,
+            Self::LogicalAnd(_, _) => Operator::LogicalAnd,
+            Self::LogicalOr(_, _) => Operator::LogicalOr,
+            Self::LogicalNot(_) => Operator::LogicalNot,
+            Self::BitwiseAnd(_, _) => Operator::BitwiseAnd,
+            Self::BitwiseOr(_, _) => Operator::BitwiseOr,
+            Self::BitwiseXor(_, _) => Operator::BitwiseXor,
+            Self::BitwiseNot(_) => Operator::BitwiseNot,
+            Self::LeftShift(_, _) => Operator::LeftShift,
+            Self::RightShift(_, _) => Operator::RightShift,
+            Self::Equals(_, _) => Operator::Equals,
+            Self::NotEquals(_, _) => Operator::NotEquals,
+            Self::LessThan(_, _) => Operator::LessThan,
+            Self::LessThanOrEqual(_, _) => Operator::LessThanOrEqual,
+            Self::GreaterThan(_, _) => Operator::GreaterThan,
+            Self::GreaterThanOrEqual(_, _) => Operator::GreaterThanOrEqual,
+            Self::FloatToInt(_) => Operator::FloatToInt,
+            Self::IntToFloat(_) => Operator::IntToFloat,
+        }
+    }
+
+    /// Operands of the operation, the second of which is absent for unary operations
+    pub fn operands(&self) -> (&dyn Evaluatable, Option<&dyn Evaluatable>) {
+        match self {
+            Self::Add(v1, v2)
+            | Self::Subtract(v1, v2)
+            | Self::Multiply(v1, v2)
+            | Self::Divide(v1, v2)
+            | Self::Modulus(v1, v2)
+            | Self::Power(v1, v2)
+            | Self::LogicalAnd(v1, v2)
+            | Self::LogicalOr(v1, v2)
+            | Self::BitwiseAnd(v1, v2)
+            | Self::BitwiseOr(v1, v2)
+            | Self::BitwiseXor(v1, v2)
+            | Self::LeftShift(v1, v2)
+            | Self::RightShift(v1, v2)
+            | Self::Equals(v1, v2)
+            | Self::NotEquals(v1, v2)
+            | Self::LessThan(v1, v2)
+            | Self::LessThanOrEqual(v1, v2)
+            | Self::GreaterThan(v1, v2)
+            | Self::GreaterThanOrEqual(v1, v2) => (v1.as_ref(), Some(v2.as_ref())),
+
+            Self::LogicalNot(v1)
+            | Self::BitwiseNot(v1)
+            | Self::FloatToInt(v1)
+            | Self::IntToFloat(v1) => (v1.as_ref(), None),
+        }
+    }
+
+    /// Operation of the same kind applied to different operands. Panics if the number of
+    /// operands does not match the operation.
+    pub fn with_operands(&self, val1: Box<dyn Evaluatable>, val2: Option<Box<dyn Evaluatable>>) -> Operation {
+        let binary = |val2: Option<Box<dyn Evaluatable>>| val2.expect("Binary operation needs two operands");
+        let unary = |val2: Option<Box<dyn Evaluatable>>| assert!(val2.is_none(), "Unary operation takes one operand");
+
+        match self {
+            Self::Add(_, _) => Self::Add(val1, binary(val2)),
+            Self::Subtract(_, _) => Self::Subtract(val1, binary(val2)),
+            Self::Multiply(_, _) => Self::Multiply(val1, binary(val2)),
+            Self::Divide(_, _) => Self::Divide(val1, binary(val2)),
+            Self::Modulus(_, _) => Self::Modulus(val1, binary(val2)),
+            Self::Power(_, _) => Self::Power(val1, binary(val2)),
+
+            Self::LogicalAnd(_, _) => Self::LogicalAnd(val1, binary(val2)),
+            Self::LogicalOr(_, _) => Self::LogicalOr(val1, binary(val2)),
+            Self::LogicalNot(_) => {
+                unary(val2);
+                Self::LogicalNot(val1)
+            }
+
+            Self::BitwiseAnd(_, _) => Self::BitwiseAnd(val1, binary(val2)),
+            Self::BitwiseOr(_, _) => Self::BitwiseOr(val1, binary(val2)),
+            Self::BitwiseXor(_, _) => Self::BitwiseXor(val1, binary(val2)),
+            Self::BitwiseNot(_) => {
+                unary(val2);
+                Self::BitwiseNot(val1)
+            }
+            Self::LeftShift(_, _) => Self::LeftShift(val1, binary(val2)),
+            Self::RightShift(_, _) => Self::RightShift(val1, binary(val2)),
+
+            Self::Equals(_, _) => Self::Equals(val1, binary(val2)),
+            Self::NotEquals(_, _) => Self::NotEquals(val1, binary(val2)),
+
+            Self::LessThan(_, _) => Self::LessThan(val1, binary(val2)),
+            Self::LessThanOrEqual(_, _) => Self::LessThanOrEqual(val1, binary(val2)),
+            Self::GreaterThan(_, _) => Self::GreaterThan(val1, binary(val2)),
+            Self::GreaterThanOrEqual(_, _) => Self::GreaterThanOrEqual(val1, binary(val2)),
+
+            Self::FloatToInt(_) => {
+                unary(val2);
+                Self::FloatToInt(val1)
+            }
+            Self::IntToFloat(_) => {
+                unary(val2);
+                Self::IntToFloat(val1)
+            }
         }
     }
 }
 
-impl ToString for CellValue {
-    fn to_string(&self) -> String {
-        format!("({}, {})", self.0, self.1)
+/// Copy of a formula tree in which every cell reference, including both ends of every range,
+/// has been replaced by `f(reference)`. Stops at the first reference `f` rejects.
+pub fn map_references(
+    expression: &dyn Evaluatable,
+    f: &mut dyn FnMut(&CellReference) -> Result<CellReference, String>,
+) -> Result<Box<dyn Evaluatable>, String> {
+    struct EachReference<'f>(&'f mut dyn FnMut(&CellReference) -> Result<CellReference, String>);
+
+    impl ReferenceRewrite for EachReference<'_> {
+        fn cell(&mut self, reference: &CellReference) -> Result<Option<CellReference>, String> {
+            (self.0)(reference).map(Some)
+        }
+
+        fn range(&mut self, range: &CellRange) -> Result<Option<CellRange>, String> {
+            Ok(Some(CellRange { start: (self.0)(&range.start)?, end: (self.0)(&range.end)? }))
+        }
     }
+
+    rewrite_references(expression, &mut EachReference(f))
 }
 
-pub enum Statistics<'a> {
-    Max(&'a CellAddress, &'a CellAddress),
-    Min(&'a CellAddress, &'a CellAddress),
-    Mean(&'a CellAddress, &'a CellAddress),
-    Sum(&'a CellAddress, &'a CellAddress),
+/// Replacement for the references of a formula tree. A reference or range mapped to `None` no
+/// longer points anywhere and is replaced by an `InvalidReference`.
+pub(crate) trait ReferenceRewrite {
+    fn cell(&mut self, reference: &CellReference) -> Result<Option<CellReference>, String>;
+    fn range(&mut self, range: &CellRange) -> Result<Option<CellRange>, String>;
+
+    /// Names are looked up from the sheet they are used on, which only some rewrites change
+    fn name(&mut self, value: &NamedValue) -> Result<NamedValue, String> {
+        Ok(value.clone())
+    }
 }
 
-impl ToString for Statistics<'_> {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Max(cell1, cell2) => format!("Max({}, {})", cell1.to_string(), cell2.to_string()),
-            Self::Min(cell1, cell2) => format!("Min({}, {})", cell1.to_string(), cell2.to_string()),
-            Self::Mean(cell1, cell2) => format!("Mean({}, {})", cell1.to_string(), cell2.to_string()),
-            Self::Sum(cell1, cell2) => format!("Sum({}, {})", cell1.to_string(), cell2.to_string()),
+pub(crate) fn rewrite_references(
+    expression: &dyn Evaluatable,
+    rewrite: &mut dyn ReferenceRewrite,
+) -> Result<Box<dyn Evaluatable>, String> {
+    Ok(match expression.expression() {
+        Expression::Primitive(primitive) => Box::new(primitive.clone()),
+        Expression::InvalidReference(_) => Box::new(InvalidReference),
+        Expression::NamedValue(value) => Box::new(rewrite.name(value)?),
+        Expression::CellValue(cell) => match rewrite.cell(&cell.0)? {
+            Some(reference) => Box::new(CellValue(reference)),
+            None => Box::new(InvalidReference),
+        },
+        Expression::Statistics(statistics) => match rewrite.range(statistics.range())? {
+            Some(range) => Box::new(statistics.with_range(range)),
+            None => Box::new(InvalidReference),
+        },
+        Expression::Operation(operation) => {
+            let (val1, val2) = operation.operands();
+            let val1 = rewrite_references(val1, rewrite)?;
+            let val2 = match val2 {
+                Some(val2) => Some(rewrite_references(val2, rewrite)?),
+                None => None,
+            };
+            Box::new(operation.with_operands(val1, val2))
         }
-    }
+    })
 }
 
-impl Evaluatable for Statistics<'_> {
-    fn evaluate<'a>(&'a self, environment: &'a Environment) -> Result<Primitive, String> {
-        let top_left_cell: &'a CellAddress;
-        let bot_right_cell: &'a CellAddress;
+/// Reference to a cell that no longer exists, left behind when the row or column holding it
+/// is deleted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidReference;
 
-        match self {
-            Self::Max(val1, val2) => {
-                top_left_cell = val1;
-                bot_right_cell = val2;
-            }
-            Self::Min(val1, val2) => {
-                top_left_cell = val1;
-                bot_right_cell = val2;
-            }
-            Self::Mean(val1, val2) => {
-                top_left_cell = val1;
-                bot_right_cell = val2;
-            }
-            Self::Sum(val1, val2) => {
-                top_left_cell = val1;
-                bot_right_cell = val2;
-            }
-        }
+impl Evaluatable for InvalidReference {
+    fn evaluate(&self, _environment: &Environment) -> Result<Primitive, String> {
+        Err(String::from("#REF!"))
+    }
 
-        if top_left_cell.0 > bot_right_cell.0 || top_left_cell.1 < bot_right_cell.1 {
-            Err(format!("Invalid Address for Statistics Function"))
-        } else {
-
-            let cells: Vec<CellAddress> = get_cells(top_left_cell, bot_right_cell);
-            let mut cell_vals: Vec<Primitive<'_>> = Vec::new();
-            
-            for cell in &cells {
-                let result = environment.get_cell(&cell);
-
-                match result {
-                    Some(val) => {
-                        let evaluated_val = val.evaluate(environment);
-
-                        match evaluated_val {
-                            Ok(primitive_val) => {
-                                if !primitive_val.is_numeric() {
-                                    return Err(format!("Value in cell ({},{}) is not numeric", cell.0, cell.1));
-                                }
-
-                                cell_vals.push(primitive_val);
-                            }
-                            Err(e) => {
-                                return Err(e);
-                            }
-                        }
-                    }
-                    None => return Err(format!("Value for cell ({}, {}) not found", cell.0, cell.1)),
-                }
-            }
+    fn expression(&self) -> Expression<'_> {
+        Expression::InvalidReference(self)
+    }
+}
 
-            match self {
-                Self::Max(_, _) => max(&cell_vals, environment),
-                Self::Min(_, _) => min(&cell_vals, environment),
-                Self::Mean(_, _) => mean(&cell_vals),
-                Self::Sum(_, _) => Ok(Primitive::Float(sum(&cell_vals))),
-            }
-        }
+impl fmt::Display for InvalidReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#REF!")
     }
 }
 
-fn mean<'a>(cell_vals: &Vec<Primitive<'a>>) -> Result<Primitive<'a>, String> {
-    Ok(Primitive::Float(sum(&cell_vals) / cell_vals.len() as f32))
+/// Value of a named cell or constant, such as `TaxRate`. Names defined for the sheet the formula
+/// is on take precedence over names defined for the whole workbook.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamedValue {
+    pub name: String,
+    /// Sheet the formula is on, filled in when the formula is stored in a cell
+    pub sheet: Option<String>,
 }
 
-fn sum(cell_vals: &Vec<Primitive<'_>>) -> f32 {
-    let mut total = 0.0;
-    for val in cell_vals {
-        total += coerce_to_float(&val).unwrap().get_float_value().unwrap();
+impl NamedValue {
+    pub fn new(name: &str) -> NamedValue {
+        NamedValue { name: name.to_string(), sheet: None }
     }
-    total
 }
 
-fn max<'a>(vals: &Vec<Primitive<'a>>, _environment: &'a Environment) -> Result<Primitive<'a>, String> {
-    let mut max_val = Primitive::Integer(i32::MIN);
-    for val in vals {
-        if coerce_to_float(&val).unwrap().get_float_value() > coerce_to_float(&max_val).unwrap().get_float_value() {
-            max_val = *val;
+impl Evaluatable for NamedValue {
+    fn evaluate(&self, environment: &Environment) -> Result<Primitive, String> {
+        match environment.lookup_name(&self.name, self.sheet.as_deref())? {
+            Definition::Cell(reference) => CellValue(reference.clone()).evaluate(environment),
+            Definition::Range(range) => Err(format!("Name {} refers to the range {} rather than a value", self.name, range)),
+            Definition::Constant(val) => val.evaluate(environment),
         }
     }
-    Ok(max_val)
+
+    fn expression(&self) -> Expression<'_> {
+        Expression::NamedValue(self)
+    }
 }
 
-fn min<'a>(vals: &Vec<Primitive<'a>>, _environment: &'a Environment) -> Result<Primitive<'a>, String> {
-    let mut min_val = Primitive::Integer(i32::MAX);
-    for val in vals {
-        if coerce_to_float(&val).unwrap().get_float_value() < coerce_to_float(&min_val).unwrap().get_float_value() {
-            min_val = *val;
-        }
+impl fmt::Display for NamedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
     }
-    Ok(min_val)
 }
 
-fn get_cells(top_left_cell: &CellAddress, bot_right_cell: &CellAddress) -> Vec<CellAddress> {
-    let mut cells: Vec<CellAddress> = Vec::new();
+/// Value of given cell
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellValue(pub CellReference);
 
-    for y in bot_right_cell.1..=top_left_cell.1 {
-        for x in top_left_cell.0..=bot_right_cell.0 {
-            cells.push(CellAddress(x, y));
+impl CellValue {
+    /// Reference that shifts when its formula is copied, like `A1`
+    pub fn relative(column: i32, row: i32) -> CellValue {
+        CellValue(CellReference::relative(CellAddress(column, row)))
+    }
+
+    /// Reference that stays put when its formula is copied, like `$A$1`
+    pub fn absolute(column: i32, row: i32) -> CellValue {
+        CellValue(CellReference::absolute(CellAddress(column, row)))
+    }
+
+    pub fn address(&self) -> CellAddress {
+        self.0.address
+    }
+}
+
+impl Evaluatable for CellValue {
+    fn evaluate(&self, environment: &Environment) -> Result<Primitive, String> {
+        match environment.value_at(&self.0) {
+            Some(result) => result,
+            None => Err(format!("Value for cell {} not found", cell_name(self.0.sheet.as_deref(), &self.address()))),
         }
     }
 
-    cells
+    fn expression(&self) -> Expression<'_> {
+        Expression::CellValue(self)
+    }
 }
 
-fn get_results<'a>(operation: &Operation<'a>, environment: &'a Environment) -> (Result<Primitive<'a>, String>, Option<Result<Primitive<'a>, String>>) {
-    let val1: Result<Primitive<'a>, String>;
-    let val2: Option<Result<Primitive<'a>, String>>;
+/// A1 notation, such as `B2`, `$B$2` or `Inputs!B2`
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
-    match operation {
-        Operation::Add(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::Subtract(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::Multiply(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::Divide(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::Modulus(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::Power(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statistics {
+    Max(CellRange),
+    Min(CellRange),
+    Mean(CellRange),
+    Sum(CellRange),
+}
 
-        Operation::LogicalAnd(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::LogicalOr(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Max(range) => write!(f, "Max({})", range),
+            Self::Min(range) => write!(f, "Min({})", range),
+            Self::Mean(range) => write!(f, "Mean({})", range),
+            Self::Sum(range) => write!(f, "Sum({})", range),
         }
-        Operation::LogicalNot(v1) => {
-            val1 = v1.evaluate(environment);
-            val2 = None;
+    }
+}
+
+impl Statistics {
+    /// The cells the function is applied to
+    pub fn range(&self) -> &CellRange {
+        match self {
+            Self::Max(range)
+            | Self::Min(range)
+            | Self::Mean(range)
+            | Self::Sum(range) => range,
         }
+    }
 
+    /// Top left and bottom right corners of the range the function is applied to
+    pub fn corners(&self) -> (CellAddress, CellAddress) {
+        self.range().statistics_corners()
+    }
 
-        Operation::BitwiseAnd(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::BitwiseOr(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::BitwiseXor(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::BitwiseNot(v1) => {
-            val1 = v1.evaluate(environment);
-            val2 = None;
-        }
-        Operation::RightShift(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::LeftShift(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
+    /// Function of the same kind applied to a different range
+    pub fn with_range(&self, range: CellRange) -> Statistics {
+        match self {
+            Self::Max(_) => Self::Max(range),
+            Self::Min(_) => Self::Min(range),
+            Self::Mean(_) => Self::Mean(range),
+            Self::Sum(_) => Self::Sum(range),
+        }
+    }
+
+    /// First number of the range, sheet by sheet and row by row, that the workbook's tolerance
+    /// finds equal to the extreme `target`, as it was found. Numbers are compared in `f64`, so
+    /// only numbers too close to tell apart tie, and of those `Max` and `Min` pick the first.
+    fn first_near(&self, environment: &Environment, sheets: &[&str], target: f64) -> Option<Primitive> {
+        let tolerance = environment.tolerance();
+        let near = |number: f64| tolerance.equal_wide(number, target);
+        for sheet in sheets {
+            let Some(columns) = environment.columns_on(sheet) else { continue };
+            let other = columns.others_in(self.range())
+                .filter_map(|cell| match environment.value_on(sheet, cell) {
+                    Some(Ok(val)) => Some((*cell, val)),
+                    _ => None,
+                })
+                .find(|(_, val)| match val {
+                    Primitive::Integer(number) => near(f64::from(*number)),
+                    Primitive::Float(number) => near(f64::from(*number)),
+                    _ => false,
+                });
+            let typed = columns.first(self.range(), near);
+
+            let first = match (other, typed) {
+                (Some(other), Some(typed)) => Some(if (other.0.1, other.0.0) < (typed.0.1, typed.0.0) { other } else { typed }),
+                (other, typed) => other.or(typed),
+            };
+            if let Some((_, val)) = first {
+                return Some(val);
+            }
         }
+        None
+    }
+}
 
+impl Evaluatable for Statistics {
+    fn evaluate(&self, environment: &Environment) -> Result<Primitive, String> {
+        let mut tally = Tally::default();
+        let sheets = environment.spanned_sheets(self.range())?;
 
-        Operation::Equals(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::NotEquals(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::GreaterThan(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::GreaterThanOrEqual(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::LessThan(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
-        }
-        Operation::LessThanOrEqual(v1, v2) => {
-            val1 = v1.evaluate(environment);
-            val2 = Some(v2.evaluate(environment));
+        // Cells are only named with their sheet when the range names one
+        let qualified = self.range().start.sheet.is_some();
+        for sheet in &sheets {
+            let Some(columns) = environment.columns_on(sheet) else { continue };
+            // Numbers typed in are added up a column at a time, everything else evaluated row
+            // by row, so that the first cell to fail is the one reported
+            for cell in columns.others_in(self.range()) {
+                let Some(result) = environment.value_on(sheet, cell) else { continue };
+                let primitive_val = result?;
+                if !primitive_val.is_numeric() {
+                    return Err(format!("Value in cell {} is not numeric", cell_name(qualified.then_some(sheet), cell)));
+                }
+                tally.add(&primitive_val);
+            }
+            columns.tally(self.range(), &mut tally);
         }
 
-        Operation::FloatToInt(v1) => {
-            val1 = v1.evaluate(environment);
-            val2 = None;
-        }
-        Operation::IntToFloat(v1) => {
-            val1 = v1.evaluate(environment);
-            val2 = None;
+        let empty = || format!("No values in {}", self.range());
+        match self {
+            Self::Max(_) => tally.max().and_then(|max| self.first_near(environment, &sheets, max)).ok_or_else(empty),
+            Self::Min(_) => tally.min().and_then(|min| self.first_near(environment, &sheets, min)).ok_or_else(empty),
+            Self::Mean(_) => tally.mean().map(Primitive::Float).ok_or_else(empty),
+            Self::Sum(_) => Ok(Primitive::Float(tally.sum())),
         }
     }
 
-    (val1, val2)
+    fn expression(&self) -> Expression<'_> {
+        Expression::Statistics(self)
+    }
+}
+
+/// A1 name of a cell, with its sheet when known
+fn cell_name(sheet: Option<&str>, adr: &CellAddress) -> String {
+    match sheet {
+        Some(sheet) => CellReference::relative(*adr).on_sheet(sheet).to_string(),
+        None => adr.to_string(),
+    }
 }
 
-fn unpack_results<'a> (
-    result1: Result<Primitive<'a>, String>,
-    result2: Option<Result<Primitive<'a>, String>>,
-) -> Result<(Primitive<'a>, Option<Primitive<'a>>), String> {
+fn unpack_results(
+    result1: Result<Primitive, String>,
+    result2: Option<Result<Primitive, String>>,
+) -> Result<(Primitive, Option<Primitive>), String> {
     let result1_unpacked: Option<Primitive>;
     let result2_unpacked: Option<Option<Primitive>>;
     let mut error = String::from("");
@@ -570,67 +784,56 @@ fn unpack_results<'a> (
         None => result2_unpacked = None,
     }
 
-    let result: Result<(Primitive<'a>, Option<Primitive<'a>>), String>;
-
     if error.is_empty() {
-        result =  Ok((result1_unpacked.unwrap(), result2_unpacked.unwrap_or_else(|| {
-            None
-        })));
+        Ok((result1_unpacked.unwrap(), result2_unpacked.unwrap_or_default()))
     } else {
-        result = Err(error)
+        Err(error)
     }
-
-    result
 }
 
-fn arithmetic<'a>(operation: &Operation, val1: &Primitive<'a>, val2: &Primitive<'a>) -> Result<Primitive<'a>, String> {
+fn arithmetic(operator: Operator, val1: &Primitive, val2: &Primitive) -> Result<Primitive, String> {
     if val1.is_integer() && val2.is_integer() {
         let v1 = val1.get_int_value().unwrap();
         let v2 = val2.get_int_value().unwrap();
 
-        match operation {
-            Operation::Add(_, _) => Ok(Primitive::Integer(v1 + v2)),
-            Operation::Subtract(_, _) => Ok(Primitive::Integer(v1 - v2)),
-            Operation::Multiply(_, _) => Ok(Primitive::Integer(v1 * v2)),
-            Operation::Divide(_, _) => {
-                if v2 == 0 {
-                    Err(format!("Division by 0"))
-                } else {
-                    Ok(Primitive::Integer(v1 / v2))
-                }
-            }
-            Operation::Modulus(_, _) => Ok(Primitive::Integer(v1 % v2)),
-            Operation::Power(_, _) => {
+        let result = match operator {
+            Operator::Add => v1.checked_add(v2),
+            Operator::Subtract => v1.checked_sub(v2),
+            Operator::Multiply => v1.checked_mul(v2),
+            Operator::Divide | Operator::Modulus if v2 == 0 => return Err(String::from("Division by 0")),
+            Operator::Divide => v1.checked_div(v2),
+            Operator::Modulus => v1.checked_rem(v2),
+            Operator::Power => {
                 if v2 < 0 {
-                    Err(format!("Integer exponent cannot be less than 0 for integer base"))
+                    return Err(String::from("Integer exponent cannot be less than 0 for integer base"));
                 } else if v1 == 0 && v2 == 0 {
-                    Err(format!("Cannot calculate 0 ^ 0"))
-                } else {
-                    Ok(Primitive::Integer(v1.pow(v2 as u32)))
+                    return Err(String::from("Cannot calculate 0 ^ 0"));
                 }
+                v1.checked_pow(v2 as u32)
             }
             _ => panic!("Unexpected Arithmetic Operation"),
-        }
+        };
+        result.map(Primitive::Integer).ok_or_else(|| String::from("Integer overflow"))
     } else if val1.is_numeric() && val2.is_numeric() { // i.e. Because of the previous check, if
         // both are numeric, one must be a float
         let v1 = coerce_to_float(val1).unwrap().get_float_value().unwrap();
         let v2 = coerce_to_float(val2).unwrap().get_float_value().unwrap();
 
-        match operation {
-            Operation::Add(_, _) => Ok(Primitive::Float(v1 + v2)),
-            Operation::Subtract(_, _) => Ok(Primitive::Float(v1 - v2)),
-            Operation::Multiply(_, _) => Ok(Primitive::Float(v1 * v2)),
-            Operation::Divide(_, _) => {
+        match operator {
+            Operator::Add => Ok(Primitive::Float(v1 + v2)),
+            Operator::Subtract => Ok(Primitive::Float(v1 - v2)),
+            Operator::Multiply => Ok(Primitive::Float(v1 * v2)),
+            Operator::Divide => {
                 if v2 + 1.0 < 1.0 + f32::EPSILON && v2 > -f32::EPSILON {
-                    Err(format!("Division by 0"))
+                    Err(String::from("Division by 0"))
                 } else {
                     Ok(Primitive::Float(v1 / v2))
                 }
             }
-            Operation::Modulus(_, _) => Ok(Primitive::Float(v1 % v2)),
-            Operation::Power(_, _) => {
+            Operator::Modulus => Ok(Primitive::Float(v1 % v2)),
+            Operator::Power => {
                 if v1 + 1.0 < 1.0 + f32::EPSILON  && v2 + 1.0 < 1.0 + f32::EPSILON {
-                    Err(format!("Cannot calculate 0 ^ 0"))
+                    Err(String::from("Cannot calculate 0 ^ 0"))
                 } else {
                     Ok(Primitive::Float(v1.powf(v2)))
                 }
@@ -638,80 +841,87 @@ fn arithmetic<'a>(operation: &Operation, val1: &Primitive<'a>, val2: &Primitive<
             _ => panic!("Unexpected Arithmetic Type"),
         }
     } else {
-        type_mismatch_error(val1, &Some(val2.clone()), operation)
+        type_mismatch_error(val1, &Some(val2.clone()), operator)
     }
 }
 
-fn logic<'a>(operation: &Operation, val1: &Primitive<'a>, val2_option: &Option<Primitive<'a>>) -> Result<Primitive<'a>, String> {
-    let val2 = val2_option.as_ref().unwrap_or_else(|| {
-        &Primitive::Boolean(false)
-    });
+fn logic(operator: Operator, val1: &Primitive, val2_option: &Option<Primitive>) -> Result<Primitive, String> {
+    let val2 = val2_option.as_ref().unwrap_or(&Primitive::Boolean(false));
 
     if !val1.is_boolean() || !val2.is_boolean() {
-        return type_mismatch_error(val1, val2_option, operation);
+        return type_mismatch_error(val1, val2_option, operator);
     }
 
     let v1 = val1.get_boolean_value().unwrap();
     let v2 = val2.get_boolean_value().unwrap();
 
-    Ok(match operation {
-        Operation::LogicalNot(_) => Primitive::Boolean(!v1),
-        Operation::LogicalAnd(_, _) => Primitive::Boolean(v1 && v2),
-        Operation::LogicalOr(_, _) => Primitive::Boolean(v1 || v2),
+    Ok(match operator {
+        Operator::LogicalNot => Primitive::Boolean(!v1),
+        Operator::LogicalAnd => Primitive::Boolean(v1 && v2),
+        Operator::LogicalOr => Primitive::Boolean(v1 || v2),
         _ => panic!("Unexpected Logical type"),
     })
 }
 
-fn bitwise<'a>(operation: &Operation, val1: &Primitive<'a>, val2_option: &Option<Primitive<'a>>) -> Result<Primitive<'a>, String> {
-    let val2 = val2_option.as_ref().unwrap_or_else(|| {
-        &Primitive::Integer(0)
-    });
+fn bitwise(operator: Operator, val1: &Primitive, val2_option: &Option<Primitive>) -> Result<Primitive, String> {
+    let val2 = val2_option.as_ref().unwrap_or(&Primitive::Integer(0));
 
-    if !val1.is_integer() || val2.is_boolean() {
-        return type_mismatch_error(val1, val2_option, operation);
+    if !val1.is_integer() || !val2.is_integer() {
+        return type_mismatch_error(val1, val2_option, operator);
     }
 
     let v1 = val1.get_int_value().unwrap();
     let v2 = val2.get_int_value().unwrap();
 
-    Ok(match operation {
-        Operation::BitwiseNot(_) => Primitive::Integer(!v1),
-        Operation::BitwiseAnd(_, _) => Primitive::Integer(v1 & v2),
-        Operation::BitwiseOr(_, _) => Primitive::Integer(v1 | v2),
-        Operation::BitwiseXor(_, _) => Primitive::Integer(v1 ^ v2),
+    match operator {
+        Operator::BitwiseNot => Ok(Primitive::Integer(!v1)),
+        Operator::BitwiseAnd => Ok(Primitive::Integer(v1 & v2)),
+        Operator::BitwiseOr => Ok(Primitive::Integer(v1 | v2)),
+        Operator::BitwiseXor => Ok(Primitive::Integer(v1 ^ v2)),
+        Operator::LeftShift | Operator::RightShift => {
+            if !(0..i32::BITS as i32).contains(&v2) {
+                return Err(format!("Shift amount {v2} out of range"));
+            }
+
+            if let Operator::LeftShift = operator {
+                Ok(Primitive::Integer(v1 << v2))
+            } else {
+                Ok(Primitive::Integer(v1 >> v2))
+            }
+        }
         _ => panic!("Unexpected Bitwise Type"),
-    })
+    }
 }
 
-fn equality<'a>(operation: &Operation, val1: &Primitive<'a>, val2: &Primitive<'a>) -> Result<Primitive<'a>, String> {
+fn equality(operator: Operator, val1: &Primitive, val2: &Primitive, tolerance: Tolerance) -> Result<Primitive, String> {
     if !val1.type_string().eq(&val2.type_string()) {
-        return type_mismatch_error(val1, &Some(val2.clone()), operation);
+        return type_mismatch_error(val1, &Some(val2.clone()), operator);
     }
 
     let mut result = match val1 {
-        Primitive::Integer(v1) => Primitive::Boolean(v1.clone() == val2.get_int_value().unwrap()),
-        Primitive::Float(v1) => Primitive::Boolean((v1 - val2.get_float_value().unwrap()).abs() < f32::EPSILON),
-        Primitive::String(v1) => Primitive::Boolean(v1.eq(&val2.get_string_value().unwrap())),
+        Primitive::Integer(v1) => Primitive::Boolean(*v1 == val2.get_int_value().unwrap()),
+        Primitive::Float(v1) => Primitive::Boolean(tolerance.equal(*v1, val2.get_float_value().unwrap())),
+        Primitive::String(v1) => Primitive::Boolean(v1.eq(val2.get_string_value().unwrap())),
         Primitive::Boolean(v1) => Primitive::Boolean(v1 == &val2.get_boolean_value().unwrap()),
     };
 
-    if let Operation::NotEquals(_, _) = operation {
+    if let Operator::NotEquals = operator {
         result = Primitive::Boolean(!result.get_boolean_value().unwrap());
     }
 
     Ok(result)
 }
 
-fn relation<'a>(operation: &Operation, val1: &Primitive<'a>, val2: &Primitive<'a>) -> Result<Primitive<'a>, String> {
+fn relation(operator: Operator, val1: &Primitive, val2: &Primitive, tolerance: Tolerance) -> Result<Primitive, String> {
     if val1.is_integer() && val2.is_integer() {
         let v1 = val1.get_int_value().unwrap();
         let v2 = val2.get_int_value().unwrap();
 
-        Ok(match operation {
-            Operation::LessThan(_, _) => Primitive::Boolean(v1 < v2),
-            Operation::LessThanOrEqual(_, _) => Primitive::Boolean(v1 <= v2),
-            Operation::GreaterThan(_, _) => Primitive::Boolean(v1 > v2),
-            Operation::GreaterThanOrEqual(_, _) => Primitive::Boolean(v1 >= v2),
+        Ok(match operator {
+            Operator::LessThan => Primitive::Boolean(v1 < v2),
+            Operator::LessThanOrEqual => Primitive::Boolean(v1 <= v2),
+            Operator::GreaterThan => Primitive::Boolean(v1 > v2),
+            Operator::GreaterThanOrEqual => Primitive::Boolean(v1 >= v2),
             _ => panic!("Unexpected Relational Operation"),
         })
    } else if val1.is_numeric() && val2.is_numeric() { // i.e. Because of the previous check, if
@@ -719,37 +929,39 @@ fn relation<'a>(operation: &Operation, val1: &Primitive<'a>, val2: &Primitive<'a
         let v1 = coerce_to_float(val1).unwrap().get_float_value().unwrap();
         let v2 = coerce_to_float(val2).unwrap().get_float_value().unwrap();
 
-        Ok(match operation {
-            Operation::LessThan(_, _) => Primitive::Boolean(v1 < v2),
-            Operation::LessThanOrEqual(_, _) => Primitive::Boolean((v1 < v2) || (v1 - v2).abs() < f32::EPSILON),
-            Operation::GreaterThan(_, _) => Primitive::Boolean(v1 > v2),
-            Operation::GreaterThanOrEqual(_, _) => Primitive::Boolean((v1 < v2) || (v1 - v2).abs() < f32::EPSILON),
+        // Every comparison with NaN is false
+        let ordering = tolerance.compare(v1, v2);
+        Ok(match operator {
+            Operator::LessThan => Primitive::Boolean(ordering == Some(Ordering::Less)),
+            Operator::LessThanOrEqual => Primitive::Boolean(matches!(ordering, Some(Ordering::Less | Ordering::Equal))),
+            Operator::GreaterThan => Primitive::Boolean(ordering == Some(Ordering::Greater)),
+            Operator::GreaterThanOrEqual => Primitive::Boolean(matches!(ordering, Some(Ordering::Greater | Ordering::Equal))),
             _ => panic!("Unexpected Relational Operation"),
         })
     } else {
-        type_mismatch_error(val1, &Some(val2.clone()), operation)
+        type_mismatch_error(val1, &Some(val2.clone()), operator)
     }
 }
 
-fn cast_to_integer<'a>(float: &Primitive<'a>) -> Result<Primitive<'a>, String> {
+fn cast_to_integer(float: &Primitive) -> Result<Primitive, String> {
     if let Primitive::Float(val) = float {
-        Ok(Primitive::Integer(val.clone() as i32))
+        Ok(Primitive::Integer(*val as i32))
     } else {
         Err(format!("Cannot cast type: {}", float.type_string()))
     }
 }
 
-fn cast_to_float<'a>(integer: &Primitive<'a>) -> Result<Primitive<'a>, String> {
+fn cast_to_float(integer: &Primitive) -> Result<Primitive, String> {
     if let Primitive::Integer(val) = integer {
-        Ok(Primitive::Float(val.clone() as f32))
+        Ok(Primitive::Float(*val as f32))
     } else {
         Err(format!("Cannot cast type: {}", integer.type_string()))
     }
 }
 
-fn coerce_to_float<'a>(num: &Primitive<'a>) -> Result<Primitive<'a>, String> {
+fn coerce_to_float(num: &Primitive) -> Result<Primitive, String> {
     match num {
-        Primitive::Float(val) => Ok(Primitive::Float(val.clone())),
+        Primitive::Float(val) => Ok(Primitive::Float(*val)),
         Primitive::Integer(_) => cast_to_float(num),
         _ => Err(format!("Cannot coerce type {} to float", num.type_string()))
     }
@@ -0,0 +1,105 @@
+//! Simplification of formula trees: operations on constants are replaced by their values, and
+//! operations that leave an operand unchanged, such as `x * 1`, by the operand. Simplified
+//! formulas evaluate to the same values and the same errors as the originals.
+
+use crate::model::{apply_values, map_references, Evaluatable, Expression, Operation, OperationType, Primitive, Tolerance};
+use crate::types::{infer_type, AnyScope, Type, Types};
+
+/// Copy of a formula tree with its constant operations folded and its identities removed. An
+/// operation on constants that fails, such as `1 / 0` or an integer overflow, is kept so that it
+/// still fails when evaluated. Comparisons of floats are kept too, since they depend on the
+/// tolerance of the workbook the formula is evaluated in.
+pub fn simplify(expression: &dyn Evaluatable) -> Box<dyn Evaluatable> {
+    let operation = match expression.expression() {
+        Expression::Operation(operation) => operation,
+        Expression::Primitive(val) => return Box::new(val.clone()),
+        Expression::CellValue(cell) => return Box::new(cell.clone()),
+        Expression::Statistics(statistics) => return Box::new(statistics.clone()),
+        Expression::InvalidReference(invalid) => return Box::new(*invalid),
+        Expression::NamedValue(value) => return Box::new(value.clone()),
+    };
+
+    let (val1, val2) = operation.operands();
+    let operation = operation.with_operands(simplify(val1), val2.map(simplify));
+
+    if !compares_floats(&operation) {
+        if let Some(val) = fold(&operation) {
+            return Box::new(val);
+        }
+    }
+    identity(operation)
+}
+
+/// Value of an operation on constants, unless it fails
+fn fold(operation: &Operation) -> Option<Primitive> {
+    let primitive = |val: &dyn Evaluatable| match val.expression() {
+        Expression::Primitive(val) => Some(val.clone()),
+        _ => None,
+    };
+    let (val1, val2) = operation.operands();
+    let val2 = match val2 {
+        Some(val2) => Some(primitive(val2)?),
+        None => None,
+    };
+    // Only comparisons look at the tolerance, and those of floats are not folded
+    apply_values(operation.operator(), primitive(val1)?, val2, Tolerance::default()).ok()
+}
+
+fn compares_floats(operation: &Operation) -> bool {
+    let is_float = |val: &dyn Evaluatable| matches!(val.expression(), Expression::Primitive(Primitive::Float(_)));
+    let (val1, val2) = operation.operands();
+    matches!(operation.get_type(), OperationType::Equality | OperationType::Relational) && (is_float(val1) || val2.is_some_and(is_float))
+}
+
+fn copy(val: &dyn Evaluatable) -> Box<dyn Evaluatable> {
+    map_references(val, &mut |reference| Ok(reference.clone())).expect("copying references cannot fail")
+}
+
+fn is(val: &dyn Evaluatable, constant: &Primitive) -> bool {
+    matches!(val.expression(), Expression::Primitive(val) if val == constant)
+}
+
+/// Whether an operand can only evaluate to one of the given types, when it evaluates at all
+fn only(val: &dyn Evaluatable, allowed: &[Type]) -> bool {
+    match infer_type(val, &mut AnyScope) {
+        Ok(types) => types != Types::NONE && types.iter().all(|t| allowed.contains(&t)),
+        Err(_) => false,
+    }
+}
+
+const INTEGER: &[Type] = &[Type::Integer];
+const NUMERIC: &[Type] = &[Type::Integer, Type::Float];
+const BOOLEAN: &[Type] = &[Type::Boolean];
+
+/// The operand an operation leaves unchanged, or the operation itself. Only operands whose type
+/// is known qualify: `A1 * 1` is an error when `A1` holds text, where `A1` would not be, and
+/// `x + 0` turns a float `-0.0` into `0.0`. The operand is still evaluated, so its errors remain.
+fn identity(operation: Operation) -> Box<dyn Evaluatable> {
+    let zero = Primitive::Integer(0);
+    let one = Primitive::Integer(1);
+
+    match operation {
+        Operation::Add(x, c) | Operation::Add(c, x) if is(c.as_ref(), &zero) && only(x.as_ref(), INTEGER) => x,
+        Operation::Subtract(x, c) if is(c.as_ref(), &zero) && only(x.as_ref(), NUMERIC) => x,
+        Operation::Multiply(x, c) | Operation::Multiply(c, x) if is(c.as_ref(), &one) && only(x.as_ref(), NUMERIC) => x,
+        Operation::Divide(x, c) | Operation::Power(x, c) if is(c.as_ref(), &one) && only(x.as_ref(), NUMERIC) => x,
+
+        Operation::LogicalAnd(x, c) | Operation::LogicalAnd(c, x) if is(c.as_ref(), &Primitive::Boolean(true)) && only(x.as_ref(), BOOLEAN) => x,
+        Operation::LogicalOr(x, c) | Operation::LogicalOr(c, x) if is(c.as_ref(), &Primitive::Boolean(false)) && only(x.as_ref(), BOOLEAN) => x,
+
+        Operation::BitwiseOr(x, c) | Operation::BitwiseOr(c, x) | Operation::BitwiseXor(x, c) | Operation::BitwiseXor(c, x)
+            if is(c.as_ref(), &zero) && only(x.as_ref(), INTEGER) => x,
+        Operation::BitwiseAnd(x, c) | Operation::BitwiseAnd(c, x) if is(c.as_ref(), &Primitive::Integer(-1)) && only(x.as_ref(), INTEGER) => x,
+        Operation::LeftShift(x, c) | Operation::RightShift(x, c) if is(c.as_ref(), &zero) && only(x.as_ref(), INTEGER) => x,
+
+        Operation::LogicalNot(x) => match x.expression() {
+            Expression::Operation(Operation::LogicalNot(b)) if only(b.as_ref(), BOOLEAN) => copy(b.as_ref()),
+            _ => Box::new(Operation::LogicalNot(x)),
+        },
+        Operation::BitwiseNot(x) => match x.expression() {
+            Expression::Operation(Operation::BitwiseNot(b)) if only(b.as_ref(), INTEGER) => copy(b.as_ref()),
+            _ => Box::new(Operation::BitwiseNot(x)),
+        },
+        operation => Box::new(operation),
+    }
+}
@@ -0,0 +1,183 @@
+//! Step-by-step account of how a formula was evaluated, for explaining a surprising value. Each
+//! node of the formula is listed with the values of its operands, the conversions applied to them
+//! and its own value or error, which are those `Evaluatable::evaluate` gives.
+
+use std::fmt;
+
+use crate::address::CellReference;
+use crate::environment::{Definition, Environment};
+use crate::format::formula::{write_formula, write_formula_on};
+use crate::model::{apply, CellValue, Evaluatable, Expression, OperationType, Primitive, Statistics};
+use crate::types::Type;
+
+/// How one node of a formula was evaluated, along with the nodes it evaluated first
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    /// Formula text of the node, such as `A1 * 2`
+    pub text: String,
+    /// Operands of an operation, cells read by a function, or what a name stands for
+    pub operands: Vec<Trace>,
+    /// Operands whose values were converted before use, such as an integer added to a float
+    pub coercions: Vec<Coercion>,
+    pub result: Result<Primitive, String>,
+}
+
+/// Value of an operand converted to another type, as `1 + 0.5` takes `1` as a float
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coercion {
+    /// Position of the operand among those of the node
+    pub operand: usize,
+    pub from: Type,
+    pub to: Type,
+}
+
+/// Traces a formula, naming the sheet of every reference that has one
+pub fn trace(expression: &dyn Evaluatable, environment: &Environment) -> Trace {
+    Tracer { environment, sheet: None, names: Vec::new() }.trace(expression)
+}
+
+/// Traces a formula of a cell on the given sheet, where references to that sheet need not name it
+pub fn trace_on(expression: &dyn Evaluatable, sheet: &str, environment: &Environment) -> Trace {
+    Tracer { environment, sheet: Some(sheet), names: Vec::new() }.trace(expression)
+}
+
+struct Tracer<'e> {
+    environment: &'e Environment,
+    sheet: Option<&'e str>,
+    /// Names being traced, to stop at a name defined in terms of itself
+    names: Vec<String>,
+}
+
+impl Tracer<'_> {
+    fn trace(&mut self, expression: &dyn Evaluatable) -> Trace {
+        let text = match self.sheet {
+            Some(sheet) => write_formula_on(expression, sheet),
+            None => write_formula(expression),
+        };
+        let leaf = |result| Trace { text: text.clone(), operands: Vec::new(), coercions: Vec::new(), result };
+
+        match expression.expression() {
+            Expression::Primitive(val) => leaf(Ok(val.clone())),
+            Expression::CellValue(cell) => leaf(cell.evaluate(self.environment)),
+            Expression::InvalidReference(invalid) => leaf(invalid.evaluate(self.environment)),
+            Expression::Statistics(statistics) => {
+                let operands = self.range(statistics);
+                let result = statistics.evaluate(self.environment);
+                let coercions = match statistics {
+                    // Max and Min compare numbers as floats but keep the one they pick as it is
+                    Statistics::Max(_) | Statistics::Min(_) => Vec::new(),
+                    Statistics::Sum(_) | Statistics::Mean(_) => floats(&operands),
+                };
+                Trace { text, operands, coercions, result }
+            }
+            Expression::NamedValue(value) => {
+                let scope = value.sheet.clone().unwrap_or_default();
+                let key = format!("{}!{}", scope, value.name);
+                if self.names.contains(&key) {
+                    return leaf(Err(format!("Name {} is defined in terms of itself", value.name)));
+                }
+
+                let operand = match self.environment.lookup_name(&value.name, value.sheet.as_deref()) {
+                    Ok(Definition::Cell(reference)) => Some(self.trace(&CellValue(reference.clone()))),
+                    Ok(Definition::Constant(constant)) => {
+                        self.names.push(key);
+                        let operand = self.trace(constant.as_ref());
+                        self.names.pop();
+                        Some(operand)
+                    }
+                    Ok(Definition::Range(_)) | Err(_) => None,
+                };
+                match operand {
+                    Some(operand) => Trace { text, result: operand.result.clone(), operands: vec![operand], coercions: Vec::new() },
+                    None => leaf(value.evaluate(self.environment)),
+                }
+            }
+            Expression::Operation(operation) => {
+                let (val1, val2) = operation.operands();
+                let mut operands = vec![self.trace(val1)];
+                operands.extend(val2.map(|val2| self.trace(val2)));
+
+                let result = apply(operation.operator(), operands[0].result.clone(), operands.get(1).map(|operand| operand.result.clone()), self.environment.tolerance());
+                // Integers stay integers unless they meet a float
+                let mixed = operands.iter().any(|operand| matches!(operand.result, Ok(Primitive::Float(_))));
+                let coercions = match operation.get_type() {
+                    OperationType::Arithmetic | OperationType::Relational if mixed => floats(&operands),
+                    _ => Vec::new(),
+                };
+                Trace { text, operands, coercions, result }
+            }
+        }
+    }
+
+    /// The populated cells a function reads, up to the first one it fails on
+    fn range(&self, statistics: &Statistics) -> Vec<Trace> {
+        let mut operands = Vec::new();
+        // Cells are only named with their sheet when the range names one
+        let qualified = statistics.range().start.sheet.is_some();
+        for sheet in self.environment.spanned_sheets(statistics.range()).unwrap_or_default() {
+            for (adr, _) in self.environment.cells_in(sheet, statistics.range()) {
+                let reference = CellReference::relative(*adr);
+                let text = match self.sheet {
+                    Some(host) if qualified && host != sheet => reference.on_sheet(sheet).to_string(),
+                    None if qualified => reference.on_sheet(sheet).to_string(),
+                    _ => reference.to_string(),
+                };
+                let Some(result) = self.environment.value_on(sheet, adr) else { continue };
+                let failed = !matches!(result, Ok(Primitive::Integer(_) | Primitive::Float(_)));
+                operands.push(Trace { text, operands: Vec::new(), coercions: Vec::new(), result });
+                if failed {
+                    return operands;
+                }
+            }
+        }
+        operands
+    }
+}
+
+/// Integer operands taken as floats, when every operand is a number
+fn floats(operands: &[Trace]) -> Vec<Coercion> {
+    if !operands.iter().all(|operand| matches!(operand.result, Ok(Primitive::Integer(_) | Primitive::Float(_)))) {
+        return Vec::new();
+    }
+    operands.iter().enumerate()
+        .filter(|(_, operand)| matches!(operand.result, Ok(Primitive::Integer(_))))
+        .map(|(operand, _)| Coercion { operand, from: Type::Integer, to: Type::Float })
+        .collect()
+}
+
+/// Indented outline, one node per line, such as
+///
+/// ```text
+/// A1 + 0.5 = 2.5, taking A1 as Float
+///   A1 = 2
+///   0.5 = 0.5
+/// ```
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Trace {
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}{} = ", "", self.text, indent = depth * 2)?;
+        match &self.result {
+            Ok(val) => write!(f, "{val}")?,
+            Err(e) => write!(f, "#ERR {e}")?,
+        }
+        if !self.coercions.is_empty() {
+            let coerced: Vec<&str> = self.coercions.iter().map(|coercion| self.operands[coercion.operand].text.as_str()).collect();
+            let to = self.coercions[0].to;
+            match coerced.as_slice() {
+                [operand] => write!(f, ", taking {operand} as {to}")?,
+                [rest @ .., last] => write!(f, ", taking {} and {last} as {to}", rest.join(", "))?,
+                [] => {}
+            }
+        }
+        writeln!(f)?;
+        for operand in &self.operands {
+            operand.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
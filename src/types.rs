@@ -0,0 +1,181 @@
+//! Types of formula results, worked out without evaluating so that a formula that can never
+//! succeed, such as `6 == "hello, world"`, is caught when it is entered rather than when it is
+//! calculated. The rules are those `Operation::evaluate` applies to values.
+
+use std::fmt;
+
+use crate::address::CellReference;
+use crate::model::{Evaluatable, Expression, NamedValue, Operation, OperationType, Primitive, Statistics};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Type {
+    Integer,
+    Float,
+    Boolean,
+    String,
+}
+
+impl Type {
+    const ALL: [Type; 4] = [Type::Integer, Type::Float, Type::Boolean, Type::String];
+
+    pub fn of(val: &Primitive) -> Type {
+        match val {
+            Primitive::Integer(_) => Type::Integer,
+            Primitive::Float(_) => Type::Float,
+            Primitive::Boolean(_) => Type::Boolean,
+            Primitive::String(_) => Type::String,
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(self, Type::Integer | Type::Float)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Integer => write!(f, "Integer"),
+            Type::Float => write!(f, "Float"),
+            Type::Boolean => write!(f, "Boolean"),
+            Type::String => write!(f, "String"),
+        }
+    }
+}
+
+/// The types a formula may evaluate to, e.g. `Integer or Float` for the sum of two cells that
+/// hold numbers
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Types(u8);
+
+impl Types {
+    pub const ANY: Types = Types(0b1111);
+    pub const NONE: Types = Types(0);
+
+    pub fn only(t: Type) -> Types {
+        Types(1 << t as u8)
+    }
+
+    pub fn contains(self, t: Type) -> bool {
+        self.0 & Types::only(t).0 != 0
+    }
+
+    pub fn union(self, other: Types) -> Types {
+        Types(self.0 | other.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Type> {
+        Type::ALL.into_iter().filter(move |t| self.contains(*t))
+    }
+}
+
+impl From<Type> for Types {
+    fn from(t: Type) -> Types {
+        Types::only(t)
+    }
+}
+
+impl fmt::Display for Types {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = self.iter().map(|t| t.to_string()).collect();
+        match names.as_slice() {
+            [] => write!(f, "no type"),
+            [name] => write!(f, "{name}"),
+            _ if *self == Types::ANY => write!(f, "any type"),
+            [rest @ .., last] => write!(f, "{} or {last}", rest.join(", ")),
+        }
+    }
+}
+
+/// Where the types of the cells and names a formula refers to come from. An error is what
+/// evaluating the reference would give.
+pub trait TypeScope {
+    fn cell(&mut self, reference: &CellReference) -> Result<Types, String>;
+    fn name(&mut self, value: &NamedValue) -> Result<Types, String>;
+}
+
+/// Scope in which every cell and name may hold a value of any type
+pub struct AnyScope;
+
+impl TypeScope for AnyScope {
+    fn cell(&mut self, _reference: &CellReference) -> Result<Types, String> {
+        Ok(Types::ANY)
+    }
+
+    fn name(&mut self, _value: &NamedValue) -> Result<Types, String> {
+        Ok(Types::ANY)
+    }
+}
+
+/// Types a formula may evaluate to, or the error it gives whatever values it is given
+pub fn infer_type(expression: &dyn Evaluatable, scope: &mut dyn TypeScope) -> Result<Types, String> {
+    match expression.expression() {
+        Expression::Primitive(val) => Ok(Type::of(val).into()),
+        Expression::CellValue(cell) => scope.cell(&cell.0),
+        Expression::NamedValue(value) => scope.name(value),
+        Expression::InvalidReference(_) => Err(String::from("#REF!")),
+        Expression::Statistics(statistics) => Ok(match statistics {
+            Statistics::Sum(_) | Statistics::Mean(_) => Type::Float.into(),
+            // The largest or smallest value keeps its type
+            Statistics::Max(_) | Statistics::Min(_) => Types::only(Type::Integer).union(Type::Float.into()),
+        }),
+        Expression::Operation(operation) => {
+            let (val1, val2) = operation.operands();
+            // Both operands are evaluated, and the first error wins
+            let types1 = infer_type(val1, scope);
+            let types2 = val2.map(|val2| infer_type(val2, scope)).transpose();
+            let (types1, types2) = (types1?, types2?);
+            operation_type(operation, types1, types2)
+        }
+    }
+}
+
+/// Every type the operation can give for some combination of its operands' types
+fn operation_type(operation: &Operation, types1: Types, types2: Option<Types>) -> Result<Types, String> {
+    let mut result = Types::NONE;
+    for type1 in types1.iter() {
+        match types2 {
+            Some(types2) => {
+                for type2 in types2.iter() {
+                    if let Some(t) = result_type(operation, type1, Some(type2)) {
+                        result = result.union(t.into());
+                    }
+                }
+            }
+            None => {
+                if let Some(t) = result_type(operation, type1, None) {
+                    result = result.union(t.into());
+                }
+            }
+        }
+    }
+
+    if !result.is_empty() {
+        return Ok(result);
+    }
+    Err(match (operation.get_type(), types2) {
+        (OperationType::FloatToInt | OperationType::IntToFloat, _) => format!("Cannot cast type: {types1}"),
+        (kind, Some(types2)) => format!("Incompatible types: {types1} and {types2} for {kind} operation"),
+        (kind, None) => format!("Incompatible type: {types1} for {kind} operation"),
+    })
+}
+
+/// Type of the result of an operation on values of the given types, or `None` if it fails
+/// with a type error
+fn result_type(operation: &Operation, type1: Type, type2: Option<Type>) -> Option<Type> {
+    match (operation.get_type(), type1, type2) {
+        (OperationType::Arithmetic, Type::Integer, Some(Type::Integer)) => Some(Type::Integer),
+        (OperationType::Arithmetic, t1, Some(t2)) if t1.is_numeric() && t2.is_numeric() => Some(Type::Float),
+        (OperationType::Logical, Type::Boolean, None | Some(Type::Boolean)) => Some(Type::Boolean),
+        (OperationType::Bitwise, Type::Integer, None | Some(Type::Integer)) => Some(Type::Integer),
+        (OperationType::Equality, t1, Some(t2)) if t1 == t2 => Some(Type::Boolean),
+        (OperationType::Relational, t1, Some(t2)) if t1.is_numeric() && t2.is_numeric() => Some(Type::Boolean),
+        (OperationType::FloatToInt, Type::Float, None) => Some(Type::Integer),
+        (OperationType::IntToFloat, Type::Integer, None) => Some(Type::Float),
+        _ => None,
+    }
+}
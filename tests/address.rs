@@ -0,0 +1,110 @@
+use gridkid::address::{column_index, column_name, CellRange, CellReference, MAX_COLUMN, MAX_ROW};
+use gridkid::model::CellAddress;
+
+#[test]
+fn round_trips_a1_references() {
+    for text in ["A1", "B3", "$B3", "B$3", "$B$3", "AA10", "XFD1048576", "FXSHRXW2147483647", "Inputs!C2", "'Tax rates'!$A$1", "'it''s'!Z99"] {
+        let reference: CellReference = text.parse().unwrap();
+        assert_eq!(reference.to_string(), text);
+    }
+
+    let reference: CellReference = "$B3".parse().unwrap();
+    assert_eq!(reference, CellReference { sheet: None, address: CellAddress(1, 2), absolute_column: true, absolute_row: false });
+    let reference: CellReference = "B$3".parse().unwrap();
+    assert!(!reference.absolute_column && reference.absolute_row);
+}
+
+#[test]
+fn reads_lowercase_a1_references() {
+    assert_eq!("b3".parse::<CellAddress>(), Ok(CellAddress(1, 2)));
+    assert_eq!("aA10".parse::<CellReference>().unwrap().to_string(), "AA10");
+    assert_eq!("$xfd$1".parse::<CellReference>().unwrap().to_string(), "$XFD$1");
+}
+
+#[test]
+fn rejects_invalid_a1_references() {
+    assert_eq!("A0".parse::<CellAddress>(), Err(String::from("Invalid cell reference A0: Row 0 is out of range")));
+    assert_eq!("ZZZZZZZ1".parse::<CellAddress>(), Err(String::from("Invalid cell reference ZZZZZZZ1: Column ZZZZZZZ is out of range")));
+    assert_eq!("A2147483649".parse::<CellAddress>(), Err(String::from("Invalid cell reference A2147483649: Row 2147483649 is out of range")));
+    for text in ["", "A", "1", "$", "1A", "A-1", "A1B", "A 1", "$$A1", "A$$1", "A1$", "É1", "Sheet!", "!A1", "'Unclosed!A1", "Data!Sheet!A1"] {
+        assert!(text.parse::<CellReference>().is_err(), "{text}");
+    }
+    // The sheet name ends at the first unquoted `!`
+    assert_eq!("'a!b'!A1".parse::<CellReference>().unwrap().sheet.as_deref(), Some("a!b"));
+    assert_eq!("Data!A!1".parse::<CellReference>(), Err(String::from("Invalid cell reference A!1: Invalid row number !1")));
+}
+
+#[test]
+fn names_columns() {
+    for (column, name) in [(0, "A"), (25, "Z"), (26, "AA"), (701, "ZZ"), (702, "AAA"), (16383, "XFD"), (MAX_COLUMN, "FXSHRXW")] {
+        assert_eq!(column_name(column).as_deref(), Ok(name));
+        assert_eq!(column_index(name), Ok(column));
+    }
+    assert!(column_name(-1).is_err());
+    assert!(column_name(i32::MAX).is_err());
+    assert!(column_index("FXSHRXX").is_err());
+    assert!(column_index("A1").is_err());
+}
+
+#[test]
+fn round_trips_r1c1_references() {
+    let base = CellAddress(2, 4);
+    for (text, a1) in [("R1C1", "$A$1"), ("RC", "C5"), ("R[-4]C[-2]", "A1"), ("R[1]C3", "$C6"), ("R7C[0]", "C$7"), ("Inputs!R2C[1]", "Inputs!D$2")] {
+        let reference = CellReference::from_r1c1(text, &base).unwrap();
+        assert_eq!(reference.to_string(), a1);
+        let written = reference.to_r1c1(&base).unwrap();
+        assert_eq!(CellReference::from_r1c1(&written, &base), Ok(reference));
+    }
+    assert_eq!(CellReference::from_r1c1("r[1]c", &base).unwrap().to_string(), "C6");
+    assert_eq!(CellAddress::from_r1c1("R3C2"), Ok(CellAddress(1, 2)));
+    assert_eq!(CellAddress(1, 2).to_r1c1().as_deref(), Ok("R3C2"));
+}
+
+#[test]
+fn rejects_invalid_r1c1_references() {
+    let base = CellAddress(0, 0);
+    for text in ["", "R", "C1", "R0C1", "R1C0", "R1C1C1", "R[-1]C", "RC[-1]", "R[1.5]C", "R[x]C", "R[9999999999999999999999]C", "R[3000000000]C", "A1"] {
+        assert!(CellReference::from_r1c1(text, &base).is_err(), "{text}");
+    }
+    assert_eq!(CellAddress::from_r1c1("R[1]C1"), Err(String::from("Relative R1C1 reference R[1]C1 needs a base cell")));
+    assert!(CellAddress(-1, 0).to_r1c1().is_err());
+}
+
+#[test]
+fn reads_whole_rows_and_columns() {
+    let columns: CellRange = "B:D".parse().unwrap();
+    assert_eq!(columns.columns(), CellRange::whole_columns(1, 3).columns());
+    assert!(columns.is_whole_column() && !columns.is_whole_row());
+    assert_eq!(columns.rows(), 0..=MAX_ROW);
+
+    let rows: CellRange = "3:5".parse().unwrap();
+    assert_eq!(rows.rows(), CellRange::whole_rows(2, 4).rows());
+    assert!(rows.is_whole_row() && !rows.is_whole_column());
+    assert_eq!(rows.columns(), 0..=MAX_COLUMN);
+
+    for text in ["B:D", "$B:D", "$B:$B", "3:5", "$3:5", "Inputs!$C:$C", "Q1:Q4!2:2", "A1:C5", "$A$1:B$2", "'Tax rates'!A1:A1"] {
+        assert_eq!(text.parse::<CellRange>().unwrap().to_string(), text);
+    }
+    assert_eq!("C3:A1".parse::<CellRange>().unwrap().to_string(), "C3:A1");
+    assert_eq!("b:b".parse::<CellRange>().unwrap().to_string(), "B:B");
+    assert_eq!("B:D".parse::<CellRange>().unwrap().to_r1c1(&CellAddress(0, 0)).as_deref(), Ok("C[1]:C[3]"));
+    assert_eq!("$3:$5".parse::<CellRange>().unwrap().to_r1c1(&CellAddress(0, 0)).as_deref(), Ok("R3:R5"));
+}
+
+#[test]
+fn rejects_invalid_ranges() {
+    for text in ["", ":", "A1:", ":B2", "A:1", "1:A", "A0:B2", "A1:B2:C3", "B:ZZZZZZZ", "0:3", "Inputs!", "A1:B2!"] {
+        assert!(text.parse::<CellRange>().is_err(), "{text}");
+    }
+}
+
+#[test]
+fn shifts_relative_axes_only() {
+    let reference: CellReference = "$B3".parse().unwrap();
+    assert_eq!(reference.offset(5, 2).unwrap().to_string(), "$B5");
+    let reference: CellReference = "B$3".parse().unwrap();
+    assert_eq!(reference.offset(5, 2).unwrap().to_string(), "G$3");
+    assert_eq!("$B$3".parse::<CellReference>().unwrap().offset(-100, -100).unwrap().to_string(), "$B$3");
+    assert_eq!("B3".parse::<CellReference>().unwrap().offset(-2, 0), Err(String::from("Reference B3 moved out of the grid")));
+    assert!("B3".parse::<CellReference>().unwrap().offset(i64::from(i32::MAX), 0).is_err());
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::set;
+use gridkid::environment::Environment;
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Primitive};
+
+fn value(environment: &Environment, text: &str) -> Result<Primitive, String> {
+    parse_formula(text).unwrap().evaluate(environment)
+}
+
+#[test]
+fn follows_every_change_to_a_cell() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "4");
+    set(&mut environment, "A2", "1.5");
+    assert_eq!(value(&environment, "Sum(A1:A9)"), Ok(Primitive::Float(5.5)));
+
+    set(&mut environment, "A1", "2.5");
+    assert_eq!(value(&environment, "Sum(A1:A9)"), Ok(Primitive::Float(4.0)));
+    set(&mut environment, "A1", "A2 * 2");
+    assert_eq!(value(&environment, "Sum(A1:A9)"), Ok(Primitive::Float(4.5)));
+    set(&mut environment, "A1", "\"text\"");
+    assert_eq!(value(&environment, "Sum(A1:A9)"), Err(String::from("Value in cell A1 is not numeric")));
+    environment.clear_cell(&"A1".parse().unwrap());
+    assert_eq!(value(&environment, "Sum(A1:A9)"), Ok(Primitive::Float(1.5)));
+
+    environment.undo();
+    environment.undo();
+    assert_eq!(value(&environment, "Sum(A1:A9)"), Ok(Primitive::Float(4.5)));
+    environment.redo();
+    environment.redo();
+    assert_eq!(value(&environment, "Sum(A1:A9)"), Ok(Primitive::Float(1.5)));
+}
+
+#[test]
+fn follows_inserted_rows_and_deleted_sheets() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    environment.set_cell_on("Data", &"B2".parse().unwrap(), Box::new(Primitive::Integer(10))).unwrap();
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "A2", "2");
+
+    environment.insert_rows(1, 3).unwrap();
+    assert_eq!(value(&environment, "Sum(A1:A2)"), Ok(Primitive::Float(1.0)));
+    assert_eq!(value(&environment, "Sum(A5:A5)"), Ok(Primitive::Float(2.0)));
+    environment.undo();
+    assert_eq!(value(&environment, "Sum(A1:A2)"), Ok(Primitive::Float(3.0)));
+
+    assert_eq!(value(&environment, "Sum(Data!B:B)"), Ok(Primitive::Float(10.0)));
+    environment.delete_sheet("Data").unwrap();
+    environment.undo();
+    assert_eq!(value(&environment, "Sum(Data!B:B)"), Ok(Primitive::Float(10.0)));
+}
+
+#[test]
+fn picks_the_first_of_equal_extremes() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A2", "2.0");
+    set(&mut environment, "B1", "2");
+    set(&mut environment, "A3", "-1");
+    set(&mut environment, "C3", "0 - 1.0");
+    assert_eq!(value(&environment, "Max(A1:C3)"), Ok(Primitive::Integer(2)));
+    assert_eq!(value(&environment, "Min(A1:C3)"), Ok(Primitive::Integer(-1)));
+
+    set(&mut environment, "C1", "1 + 1.0");
+    set(&mut environment, "B3", "-1.0");
+    assert_eq!(value(&environment, "Max(A1:C3)"), Ok(Primitive::Integer(2)));
+    assert_eq!(value(&environment, "Max(A2:C3)"), Ok(Primitive::Float(2.0)));
+    assert_eq!(value(&environment, "Min(B1:C3)"), Ok(Primitive::Float(-1.0)));
+}
+
+#[test]
+fn spans_sheets() {
+    let mut environment = Environment::init();
+    for (index, sheet) in ["Q1", "Q2", "Q3"].into_iter().enumerate() {
+        environment.add_sheet(sheet).unwrap();
+        for row in 0..4 {
+            let val = parse_formula(&format!("{}", index as i32 * 10 + row)).unwrap();
+            environment.set_cell_on(sheet, &CellAddress(0, row), val).unwrap();
+        }
+    }
+    environment.set_cell_on("Q2", &"A9".parse().unwrap(), parse_formula("Q1!A1 + 0.5").unwrap()).unwrap();
+
+    assert_eq!(value(&environment, "Sum(Q1:Q3!A1:A9)"), Ok(Primitive::Float(138.5)));
+    assert_eq!(value(&environment, "Mean(Q1:Q3!A2:A3)"), Ok(Primitive::Float(11.5)));
+    assert_eq!(value(&environment, "Max(Q1:Q2!A:A)"), Ok(Primitive::Integer(13)));
+    assert_eq!(value(&environment, "Min(Q2:Q3!A:A)"), Ok(Primitive::Float(0.5)));
+}
+
+#[test]
+fn agrees_with_adding_up_cell_by_cell() {
+    let mut environment = Environment::init();
+    let mut seed: u32 = 7;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) % 1000
+    };
+    for _ in 0..600 {
+        let adr = CellAddress((next() % 6) as i32, (next() % 400) as i32);
+        let text = match next() % 4 {
+            0 => format!("{}", next() as i32 - 500),
+            1 => format!("{}.25", next() as i32 - 500),
+            2 => format!("{} * 2", next()),
+            _ => String::from("-"),
+        };
+        match text.as_str() {
+            "-" => environment.clear_cell(&adr),
+            text => environment.set_cell(&adr, parse_formula(text).unwrap()).unwrap(),
+        }
+    }
+
+    let range = "B20:E300".parse().unwrap();
+    let numbers: Vec<f64> = environment.cells_in("Sheet1", &range)
+        .map(|(adr, _)| match environment.value(adr) {
+            Some(Ok(Primitive::Integer(number))) => f64::from(number),
+            Some(Ok(Primitive::Float(number))) => f64::from(number),
+            other => panic!("{adr} holds {other:?}"),
+        })
+        .collect();
+    let total: f64 = numbers.iter().sum();
+    let max = numbers.iter().cloned().fold(f64::MIN, f64::max);
+    let min = numbers.iter().cloned().fold(f64::MAX, f64::min);
+
+    let number = |text: &str| match value(&environment, text) {
+        Ok(Primitive::Integer(number)) => f64::from(number),
+        Ok(Primitive::Float(number)) => f64::from(number),
+        other => panic!("{text} gave {other:?}"),
+    };
+    assert_eq!(number("Sum(B20:E300)"), f64::from(total as f32));
+    assert_eq!(number("Mean(B20:E300)"), f64::from((total / numbers.len() as f64) as f32));
+    assert_eq!(number("Max(B20:E300)"), max);
+    assert_eq!(number("Min(B20:E300)"), min);
+}
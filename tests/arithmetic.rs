@@ -0,0 +1,85 @@
+use gridkid::environment::Environment;
+use gridkid::model::{CellAddress, CellValue, Evaluatable, Operation, Primitive};
+
+fn int(val: i32) -> Box<dyn Evaluatable> {
+    Box::new(Primitive::Integer(val))
+}
+
+fn float(val: f32) -> Box<dyn Evaluatable> {
+    Box::new(Primitive::Float(val))
+}
+
+fn value(operation: Operation) -> Result<Primitive, String> {
+    operation.evaluate(&Environment::init())
+}
+
+#[test]
+fn fails_on_integer_division_by_zero() {
+    assert_eq!(value(Operation::Divide(int(5), int(0))), Err(String::from("Division by 0")));
+    assert_eq!(value(Operation::Modulus(int(5), int(0))), Err(String::from("Division by 0")));
+    assert_eq!(value(Operation::Modulus(int(5), int(3))), Ok(Primitive::Integer(2)));
+}
+
+#[test]
+fn fails_on_integer_overflow() {
+    let overflow = Err(String::from("Integer overflow"));
+    assert_eq!(value(Operation::Add(int(i32::MAX), int(1))), overflow);
+    assert_eq!(value(Operation::Subtract(int(i32::MIN), int(1))), overflow);
+    assert_eq!(value(Operation::Multiply(int(65536), int(65536))), overflow);
+    assert_eq!(value(Operation::Divide(int(i32::MIN), int(-1))), overflow);
+    assert_eq!(value(Operation::Modulus(int(i32::MIN), int(-1))), overflow);
+    assert_eq!(value(Operation::Power(int(2), int(31))), overflow);
+    assert_eq!(value(Operation::Power(int(2), int(30))), Ok(Primitive::Integer(1 << 30)));
+}
+
+#[test]
+fn keeps_working_after_a_formula_fails() {
+    let mut environment = Environment::init();
+    environment.set_cell(&CellAddress(0, 0), Box::new(Operation::Modulus(int(5), int(0)))).unwrap();
+    environment.set_cell(&CellAddress(1, 0), int(i32::MAX)).unwrap();
+    environment.set_cell(&CellAddress(1, 1), Box::new(Operation::Add(Box::new(CellValue::relative(1, 0)), int(1)))).unwrap();
+    environment.set_cell(&CellAddress(2, 0), int(7)).unwrap();
+
+    assert_eq!(environment.value(&CellAddress(0, 0)), Some(Err(String::from("Division by 0"))));
+    assert_eq!(environment.value(&CellAddress(1, 1)), Some(Err(String::from("Integer overflow"))));
+    assert_eq!(environment.value(&CellAddress(2, 0)), Some(Ok(Primitive::Integer(7))));
+}
+
+#[test]
+fn combines_integers_bit_by_bit() {
+    assert_eq!(value(Operation::BitwiseAnd(int(5), int(4))), Ok(Primitive::Integer(4)));
+    assert_eq!(value(Operation::BitwiseOr(int(5), int(2))), Ok(Primitive::Integer(7)));
+    assert_eq!(value(Operation::BitwiseXor(int(5), int(4))), Ok(Primitive::Integer(1)));
+    assert_eq!(value(Operation::BitwiseNot(int(5))), Ok(Primitive::Integer(-6)));
+}
+
+#[test]
+fn rejects_bitwise_operands_that_are_not_integers() {
+    let error = Err(String::from("Incompatible types: Integer and Float for Bitwise operation"));
+    assert_eq!(value(Operation::BitwiseAnd(int(5), float(4.0))), error);
+    assert_eq!(value(Operation::LeftShift(int(5), float(1.0))), error);
+    assert_eq!(value(Operation::BitwiseNot(float(5.0))), Err(String::from("Incompatible type: Float for Bitwise operation")));
+}
+
+#[test]
+fn shifts_integers() {
+    assert_eq!(value(Operation::LeftShift(int(5), int(2))), Ok(Primitive::Integer(20)));
+    assert_eq!(value(Operation::RightShift(int(20), int(2))), Ok(Primitive::Integer(5)));
+    assert_eq!(value(Operation::RightShift(int(-8), int(1))), Ok(Primitive::Integer(-4)));
+    assert_eq!(value(Operation::LeftShift(int(1), int(31))), Ok(Primitive::Integer(i32::MIN)));
+    assert_eq!(value(Operation::LeftShift(int(1), int(32))), Err(String::from("Shift amount 32 out of range")));
+    assert_eq!(value(Operation::RightShift(int(1), int(-1))), Err(String::from("Shift amount -1 out of range")));
+}
+
+#[test]
+fn stores_formulas_built_at_runtime() {
+    let mut environment = Environment::init();
+    let formula = Operation::Multiply(int(2), Box::new(CellValue::relative(0, 0)));
+    assert_eq!(formula.to_string(), "2 * A1");
+
+    environment.set_cell(&CellAddress(0, 0), int(21)).unwrap();
+    environment.set_cell(&CellAddress(1, 0), Box::new(formula)).unwrap();
+    let stored = environment.get_cell(&CellAddress(1, 0)).unwrap();
+    assert_eq!(stored.evaluate(&environment), Ok(Primitive::Integer(42)));
+    assert_eq!(Primitive::String(String::from("total")).to_string(), "\"total\"");
+}
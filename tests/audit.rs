@@ -0,0 +1,79 @@
+use gridkid::address::CellReference;
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::format::formula::parse_formula;
+
+fn workbook(cells: &[(&str, &str)]) -> Environment {
+    let mut environment = Environment::init();
+    environment.add_sheet("Outputs").unwrap();
+    for (cell, text) in cells {
+        let reference: CellReference = cell.parse().unwrap();
+        let sheet = reference.sheet.as_deref().unwrap_or("Sheet1");
+        environment.set_cell_on(sheet, &reference.address, parse_formula(text).unwrap()).unwrap();
+    }
+    environment
+}
+
+fn names(cells: &[CellReference]) -> Vec<String> {
+    cells.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn follows_precedents_and_dependents_through_ranges_and_sheets() {
+    let environment = workbook(&[
+        ("A1", "1"),
+        ("A2", "2"),
+        ("B1", "Sum(A1:A2)"),
+        ("C1", "B1 * 2"),
+        ("Outputs!A1", "Sheet1!C1 + Sheet1!A2"),
+        ("Outputs!A2", "A1 - 1"),
+    ]);
+    let adr = |text: &str| text.parse().unwrap();
+
+    assert_eq!(names(&environment.precedents("Outputs", &adr("A1")).unwrap()), ["Sheet1!C1", "Sheet1!A2"]);
+    assert_eq!(names(&environment.transitive_precedents("Outputs", &adr("A1")).unwrap()), ["Sheet1!C1", "Sheet1!A2", "Sheet1!B1", "Sheet1!A1"]);
+    assert_eq!(names(&environment.transitive_dependents("Sheet1", &adr("A1")).unwrap()), ["Sheet1!B1", "Sheet1!C1", "Outputs!A1", "Outputs!A2"]);
+    assert!(environment.transitive_dependents("Outputs", &adr("A2")).unwrap().is_empty());
+    assert!(environment.transitive_precedents("Missing", &adr("A1")).is_err());
+}
+
+#[test]
+fn stops_at_circular_references() {
+    let environment = workbook(&[("A1", "B1 + 1"), ("B1", "C1 + 1"), ("C1", "A1 + 1")]);
+    let adr = "A1".parse().unwrap();
+    assert_eq!(names(&environment.transitive_precedents("Sheet1", &adr).unwrap()), ["Sheet1!B1", "Sheet1!C1"]);
+    assert_eq!(names(&environment.transitive_dependents("Sheet1", &adr).unwrap()), ["Sheet1!C1", "Sheet1!B1"]);
+}
+
+#[test]
+fn finds_the_longest_chain() {
+    let environment = workbook(&[
+        ("A1", "1"),
+        ("A2", "A1 + 1"),
+        ("B1", "5"),
+        ("B2", "A2 * B1"),
+        ("B3", "Max(B1:B2) + A1"),
+        ("C1", "A1"),
+    ]);
+    assert_eq!(names(&environment.longest_chain()), ["Sheet1!A1", "Sheet1!A2", "Sheet1!B2", "Sheet1!B3"]);
+    assert!(Environment::init().longest_chain().is_empty());
+}
+
+#[test]
+fn finds_orphaned_inputs() {
+    let mut environment = workbook(&[("A1", "1"), ("A2", "2"), ("A3", "3"), ("B1", "A1 * Rate"), ("Outputs!B2", "7")]);
+    environment.define_name("Rate", &NameScope::Workbook, Definition::Cell("A3".parse().unwrap())).unwrap();
+    assert_eq!(names(&environment.orphaned_inputs()), ["Sheet1!A2", "Outputs!B2"]);
+}
+
+#[test]
+fn finds_references_to_empty_cells() {
+    let mut environment = workbook(&[("A1", "1"), ("B1", "A1 + A2"), ("B2", "Sum(A1:A9) + Outputs!C3 + Missing!A1"), ("B3", "Tax * 2")]);
+    environment.define_name("Tax", &NameScope::Workbook, Definition::Constant(parse_formula("D4 / 100").unwrap())).unwrap();
+
+    let found: Vec<(String, Vec<String>)> = environment.empty_references().iter().map(|(cell, empty)| (cell.to_string(), names(empty))).collect();
+    assert_eq!(found, [
+        (String::from("Sheet1!B1"), vec![String::from("Sheet1!A2")]),
+        (String::from("Sheet1!B2"), vec![String::from("Outputs!C3")]),
+        (String::from("Sheet1!B3"), vec![String::from("Sheet1!D4")]),
+    ]);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{set, tree, Random};
+use gridkid::bytecode::Program;
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, CellValue, Evaluatable, InvalidReference, NamedValue, Primitive, Statistics};
+
+fn workbook() -> Environment {
+    let mut environment = Environment::init();
+    for (cell, text) in [("A1", "3"), ("A2", "1.5"), ("A3", "true"), ("B1", "\"text\""), ("B2", "A1 * 2"), ("B3", "1 / 0")] {
+        set(&mut environment, cell, text);
+    }
+    environment.define_name("Rate", &NameScope::Workbook, Definition::Constant(Box::new(Primitive::Float(0.25)))).unwrap();
+    environment.define_name("Inputs", &NameScope::Workbook, Definition::Range("A1:A2".parse().unwrap())).unwrap();
+    environment
+}
+
+fn assert_same_result(expression: &dyn Evaluatable, environment: &Environment) {
+    let program = Program::compile(expression);
+    assert_eq!(program.evaluate(environment), expression.evaluate(environment), "{}", expression.to_string());
+}
+
+#[test]
+fn lists_instructions_in_postfix_order() {
+    let program = Program::compile(parse_formula("(A1 + 2) * -B1").unwrap().as_ref());
+    assert_eq!(program.to_string(), "cell A1\napply Add 2\npush 0\ncell B1\napply Subtract\napply Multiply\n");
+    assert_eq!(program.len(), 6);
+}
+
+#[test]
+fn folds_constants() {
+    let program = Program::compile(parse_formula("A1 * (2 + 3) - Sum(A1:A2)").unwrap().as_ref());
+    assert_eq!(program.to_string(), "cell A1\napply Multiply 5\ncall Sum(A1:A2)\napply Subtract\n");
+}
+
+#[test]
+fn gives_the_same_errors() {
+    let environment = workbook();
+    for text in [
+        "B1 + 1", "B3 * 2", "C9 + 1", "#REF! + A1", "Inputs * 2", "Missing + 1", "!A1", "FloatToInt(A1)", "Sum(A1:B3)", "1 / 0 + B1",
+        "A1 + 2147483647", "2147483647 * A1", "(0 - 2147483647) - A1", "A1 % 0", "A1 ** 40",
+    ] {
+        assert_same_result(parse_formula(text).unwrap().as_ref(), &environment);
+    }
+}
+
+#[test]
+fn recalculates_the_same_compiled() {
+    let mut environment = workbook();
+    environment.set_compiled(true);
+    environment.set_cell(&CellAddress(2, 0), parse_formula("B2 + Rate").unwrap()).unwrap();
+    assert_eq!(environment.value(&CellAddress(2, 0)), Some(Ok(Primitive::Float(6.25))));
+
+    // A changed formula is compiled afresh
+    environment.set_cell(&CellAddress(1, 1), parse_formula("A1 - 1").unwrap()).unwrap();
+    assert_eq!(environment.value(&CellAddress(2, 0)), Some(Ok(Primitive::Float(2.25))));
+    environment.undo();
+    assert_eq!(environment.value(&CellAddress(2, 0)), Some(Ok(Primitive::Float(6.25))));
+}
+
+/// Leaves over integers small and large enough to overflow
+fn leaf(random: &mut Random) -> Box<dyn Evaluatable> {
+    match random.below(9) {
+        0 => Box::new(Primitive::Integer(random.below(10) as i32)),
+        1 => Box::new(Primitive::Integer([i32::MAX, i32::MIN, -1, 65536][random.below(4)])),
+        2 => Box::new(Primitive::Float([0.5, -2.25, 0.0][random.below(3)])),
+        3 => Box::new(Primitive::Boolean(random.below(2) == 0)),
+        4 => Box::new(Primitive::String(String::from("text"))),
+        5 | 6 => Box::new(CellValue(["A1", "A2", "A3", "B1", "B2", "B3", "C9"][random.below(7)].parse().unwrap())),
+        7 => Box::new(NamedValue::new(["Rate", "Inputs", "Missing"][random.below(3)])),
+        _ => match random.below(3) {
+            0 => Box::new(InvalidReference),
+            _ => Box::new([Statistics::Sum, Statistics::Max][random.below(2)]("A1:A2".parse().unwrap())),
+        },
+    }
+}
+
+#[test]
+fn agrees_with_the_tree_on_generated_formulas() {
+    let environment = workbook();
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    for _ in 0..5000 {
+        assert_same_result(tree(&mut random, 3, leaf).as_ref(), &environment);
+    }
+}
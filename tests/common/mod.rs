@@ -0,0 +1,52 @@
+//! Helpers shared by the integration tests. Each test binary uses only some of them.
+#![allow(dead_code)]
+
+use gridkid::environment::Environment;
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{Evaluatable, Operation, Primitive};
+
+/// Stores a formula in a cell of the active sheet
+pub fn set(environment: &mut Environment, cell: &str, text: &str) {
+    environment.set_cell(&cell.parse().unwrap(), parse_formula(text).unwrap()).unwrap();
+}
+
+/// Value of a cell of the active sheet
+pub fn value(environment: &Environment, cell: &str) -> Option<Result<Primitive, String>> {
+    environment.value(&cell.parse().unwrap())
+}
+
+/// Deterministic source of pseudo-random numbers, so failures can be reproduced
+pub struct Random(pub u64);
+
+impl Random {
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+type Binary = fn(Box<dyn Evaluatable>, Box<dyn Evaluatable>) -> Operation;
+
+/// Formula tree of any operators up to the given depth, with leaves made by `leaf`
+pub fn tree(random: &mut Random, depth: usize, leaf: fn(&mut Random) -> Box<dyn Evaluatable>) -> Box<dyn Evaluatable> {
+    if depth == 0 || random.below(4) == 0 {
+        return leaf(random);
+    }
+
+    let binary: [Binary; 19] = [
+        Operation::Add, Operation::Subtract, Operation::Multiply, Operation::Divide, Operation::Modulus, Operation::Power,
+        Operation::LogicalAnd, Operation::LogicalOr, Operation::BitwiseAnd, Operation::BitwiseOr, Operation::BitwiseXor,
+        Operation::LeftShift, Operation::RightShift, Operation::Equals, Operation::NotEquals, Operation::LessThan,
+        Operation::LessThanOrEqual, Operation::GreaterThan, Operation::GreaterThanOrEqual,
+    ];
+    let unary: [fn(Box<dyn Evaluatable>) -> Operation; 4] = [Operation::LogicalNot, Operation::BitwiseNot, Operation::FloatToInt, Operation::IntToFloat];
+
+    match random.below(binary.len() + unary.len() + 2) {
+        // Negations, which are subtractions from 0
+        n if n >= binary.len() + unary.len() => Box::new(Operation::Subtract(Box::new(Primitive::Integer(0)), tree(random, depth - 1, leaf))),
+        n if n >= binary.len() => Box::new(unary[n - binary.len()](tree(random, depth - 1, leaf))),
+        n => Box::new(binary[n](tree(random, depth - 1, leaf), tree(random, depth - 1, leaf))),
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::set;
+use gridkid::environment::Environment;
+use gridkid::format::formula::write_formula_on;
+use gridkid::model::Primitive;
+
+fn formula(environment: &Environment, cell: &str) -> Option<String> {
+    environment.get_cell(&cell.parse().unwrap()).map(|val| write_formula_on(val, "Sheet1"))
+}
+
+#[test]
+fn copies_relative_and_anchored_references() {
+    let mut environment = Environment::init();
+    set(&mut environment, "B2", "A1 + $A1 + A$1 + $A$1");
+    environment.copy_cell(&"B2".parse().unwrap(), &"D5".parse().unwrap()).unwrap();
+    assert_eq!(formula(&environment, "D5").as_deref(), Some("C4 + $A4 + C$1 + $A$1"));
+    environment.copy_cell(&"D5".parse().unwrap(), &"C3".parse().unwrap()).unwrap();
+    assert_eq!(formula(&environment, "C3").as_deref(), Some("B2 + $A2 + B$1 + $A$1"));
+
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "A2", "10");
+    set(&mut environment, "B1", "100");
+    assert_eq!(environment.value(&"C3".parse().unwrap()), Some(Ok(Primitive::Integer(4 + 10 + 100 + 1))));
+}
+
+#[test]
+fn copies_references_to_ranges_and_other_sheets() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    set(&mut environment, "A1", "Sum(B1:B$3) + Data!$C1 + Mean(Data!A1:$A$2)");
+    environment.copy_cell(&"A1".parse().unwrap(), &"B3".parse().unwrap()).unwrap();
+    assert_eq!(formula(&environment, "B3").as_deref(), Some("Sum(C3:C$3) + Data!$C3 + Mean(Data!B3:$A$2)"));
+}
+
+#[test]
+fn clears_the_destination_when_copying_an_empty_cell() {
+    let mut environment = Environment::init();
+    set(&mut environment, "B1", "5");
+    environment.copy_cell(&"A1".parse().unwrap(), &"B1".parse().unwrap()).unwrap();
+    assert_eq!(formula(&environment, "B1"), None);
+}
+
+#[test]
+fn refuses_copies_shifted_off_the_grid() {
+    let mut environment = Environment::init();
+    set(&mut environment, "B2", "A1 * 2");
+    set(&mut environment, "A5", "7");
+    assert_eq!(
+        environment.copy_cell(&"B2".parse().unwrap(), &"A5".parse().unwrap()),
+        Err(String::from("Reference Sheet1!A1 moved out of the grid")),
+    );
+    assert_eq!(formula(&environment, "A5").as_deref(), Some("7"));
+
+    // Anchored axes stay where they are
+    set(&mut environment, "B2", "$A1 * A$1");
+    assert!(environment.copy_cell(&"B2".parse().unwrap(), &"B1".parse().unwrap()).is_err());
+    set(&mut environment, "B2", "$A$1 * 2");
+    environment.copy_cell(&"B2".parse().unwrap(), &"A1".parse().unwrap()).unwrap();
+    assert_eq!(formula(&environment, "A1").as_deref(), Some("$A$1 * 2"));
+}
+
+#[test]
+fn fills_ranges_with_shifted_copies() {
+    let mut environment = Environment::init();
+    for (cell, text) in [("A1", "1"), ("A2", "2"), ("A3", "3"), ("B1", "10")] {
+        set(&mut environment, cell, text);
+    }
+    set(&mut environment, "C1", "A1 * $B$1 + A$1 + $A1");
+    environment.fill_range(&"C1".parse().unwrap(), &"C1:D3".parse().unwrap()).unwrap();
+
+    assert_eq!(formula(&environment, "C3").as_deref(), Some("A3 * $B$1 + A$1 + $A3"));
+    assert_eq!(formula(&environment, "D2").as_deref(), Some("B2 * $B$1 + B$1 + $A2"));
+    assert_eq!(environment.value(&"C3".parse().unwrap()), Some(Ok(Primitive::Integer(30 + 1 + 3))));
+    assert_eq!(environment.undo_label(), Some("Fill C1:D3"));
+    environment.undo();
+    assert_eq!(formula(&environment, "C3"), None);
+}
+
+#[test]
+fn fills_nothing_when_any_copy_fails() {
+    let mut environment = Environment::init();
+    set(&mut environment, "B2", "A1 + 1");
+    assert_eq!(
+        environment.fill_range(&"B2".parse().unwrap(), &"A1:B3".parse().unwrap()),
+        Err(String::from("Reference Sheet1!A1 moved out of the grid")),
+    );
+    assert_eq!(environment.cells_on("Sheet1").count(), 1);
+
+    assert_eq!(
+        environment.fill_range(&"B2".parse().unwrap(), &"B:B".parse().unwrap()),
+        Err(String::from("Cannot fill whole rows or columns: B:B")),
+    );
+}
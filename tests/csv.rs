@@ -0,0 +1,59 @@
+use gridkid::environment::Environment;
+use gridkid::format::csv::{from_text, to_text};
+use gridkid::format::formula::{parse_formula, write_formula_on};
+use gridkid::model::{CellAddress, Expression, Primitive};
+
+fn text(val: &str) -> Primitive {
+    Primitive::String(val.to_string())
+}
+
+/// Constant in a cell of the active sheet, or `None` for an empty cell or a formula
+fn constant(environment: &Environment, column: i32) -> Option<Primitive> {
+    match environment.get_cell(&CellAddress(column, 0))?.expression() {
+        Expression::Primitive(val) => Some(val.clone()),
+        _ => None,
+    }
+}
+
+#[test]
+fn round_trips_text_that_reads_as_other_types() {
+    let primitives = [
+        text("42"), text("true"), text("=A1"), text(""), text("1.5"), text("a, \"b\""), text("nan"),
+        Primitive::Integer(42), Primitive::Boolean(true), Primitive::Float(1.5), Primitive::Float(f32::NEG_INFINITY),
+    ];
+    let mut environment = Environment::init();
+    for (column, val) in primitives.iter().enumerate() {
+        environment.set_cell(&CellAddress(column as i32, 0), Box::new(val.clone())).unwrap();
+    }
+    environment.set_cell(&CellAddress(11, 0), Box::new(Primitive::Float(f32::NAN))).unwrap();
+    environment.set_cell(&CellAddress(12, 0), parse_formula("A2 * 2").unwrap()).unwrap();
+
+    let text = to_text(&environment);
+    assert_eq!(text, "\"42\",\"true\",\"=A1\",\"\",\"1.5\",\"a, \"\"b\"\"\",nan,42,true,1.5,=-Inf,=NaN,=A2 * 2\n");
+    let read = from_text(&text).unwrap();
+    for (column, val) in primitives.iter().enumerate() {
+        assert_eq!(constant(&read, column as i32).as_ref(), Some(val), "column {column}");
+    }
+    assert!(matches!(constant(&read, 11), Some(Primitive::Float(val)) if val.is_nan()));
+    assert_eq!(write_formula_on(read.get_cell(&CellAddress(12, 0)).unwrap(), "Sheet1"), "A2 * 2");
+}
+
+#[test]
+fn reads_only_numbers_written_in_digits() {
+    let environment = from_text("nan,inf,-Infinity,1e3,.5,-2.,e5,1.2.3\n").unwrap();
+    let expected = [text("nan"), text("inf"), text("-Infinity"), Primitive::Float(1000.0), Primitive::Float(0.5), Primitive::Float(-2.0), text("e5"), text("1.2.3")];
+    for (column, val) in expected.iter().enumerate() {
+        assert_eq!(constant(&environment, column as i32).as_ref(), Some(val), "column {column}");
+    }
+}
+
+#[test]
+fn reads_quoted_fields_as_text() {
+    let environment = from_text("\"42\",42,\"\",,\"=1 + 2\"\r\n").unwrap();
+    assert_eq!(constant(&environment, 0), Some(text("42")));
+    assert_eq!(constant(&environment, 1), Some(Primitive::Integer(42)));
+    assert_eq!(constant(&environment, 2), Some(text("")));
+    assert!(environment.get_cell(&CellAddress(3, 0)).is_none());
+    assert_eq!(constant(&environment, 4), Some(text("=1 + 2")));
+    assert_eq!(from_text("\"open\n").err(), Some(String::from("Unterminated quoted field")));
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use gridkid::environment::Environment;
+use gridkid::format::formula::parse_formula;
+use gridkid::format::write_workbook;
+
+/// Workbook file with inputs and outputs on separate sheets, removed when dropped
+struct Model(PathBuf);
+
+impl Model {
+    fn write(name: &str) -> Model {
+        let mut environment = Environment::init();
+        environment.add_sheet("Inputs").unwrap();
+        environment.add_sheet("Outputs").unwrap();
+        for (sheet, cell, text) in [("Inputs", "B2", "10"), ("Outputs", "C10", "Inputs!B2 * 2"), ("Outputs", "C11", "Inputs!B2 / Inputs!B3"), ("Outputs", "C12", "\"hi\"")] {
+            environment.set_cell_on(sheet, &cell.parse().unwrap(), parse_formula(text).unwrap()).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("gridkid-eval-{name}-{}.grid", std::process::id()));
+        write_workbook(&environment, &path).unwrap();
+        Model(path)
+    }
+
+    /// Exit status, standard output and standard error of evaluating the model
+    fn eval(&self, args: &[&str]) -> (i32, String, String) {
+        let output = Command::new(env!("CARGO_BIN_EXE_gridkid")).arg("eval").arg(&self.0).args(args).output().unwrap();
+        (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+    }
+}
+
+impl Drop for Model {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn prints_requested_cells() {
+    let model = Model::write("get");
+    assert_eq!(model.eval(&["--get", "Outputs!C10", "--get", "Outputs!C12", "--get", "Outputs!A1"]),
+               (0, String::from("Outputs!C10 = 20\nOutputs!C12 = hi\nOutputs!A1 is empty\n"), String::new()));
+}
+
+#[test]
+fn applies_overrides_before_calculating() {
+    let model = Model::write("set");
+    let (status, out, _) = model.eval(&["--set", "Inputs!B2=42", "--set", "Inputs!B3 = 4", "--get", "Outputs!C10", "--get", "Outputs!C11"]);
+    assert_eq!((status, out.as_str()), (0, "Outputs!C10 = 84\nOutputs!C11 = 10\n"));
+    // The file itself is left as it was
+    assert_eq!(model.eval(&["--get", "Outputs!C10"]).1, "Outputs!C10 = 20\n");
+}
+
+#[test]
+fn prints_json() {
+    let model = Model::write("json");
+    let (status, out, _) = model.eval(&["--set", "Inputs!B2=42", "--get", "Outputs!C10", "--get", "Outputs!C11", "--format", "json"]);
+    assert_eq!(status, 1);
+    assert_eq!(out, concat!(
+        "{\n",
+        "  \"ok\": false,\n",
+        "  \"cells\": {\n",
+        "    \"Outputs!C10\": 84,\n",
+        "    \"Outputs!C11\": {\"error\": \"Value for cell Inputs!B3 not found\"}\n",
+        "  },\n",
+        "  \"errors\": {}\n",
+        "}\n",
+    ));
+}
+
+#[test]
+fn fails_when_a_requested_cell_is_an_error() {
+    let model = Model::write("error");
+    assert_eq!(model.eval(&["--get", "Outputs!C11"]).0, 1);
+    let (status, out, _) = model.eval(&["--set", "Outputs!C10=Outputs!C10 + 1", "--get", "Outputs!C10"]);
+    assert_eq!((status, out.as_str()), (1, "Outputs!C10 = #ERR Circular reference: Outputs!C10 -> Outputs!C10\n"));
+}
+
+#[test]
+fn checks_every_cell() {
+    let model = Model::write("check");
+    assert_eq!(model.eval(&["--check"]), (1, String::from("Outputs!C11 = #ERR Value for cell Inputs!B3 not found\n"), String::new()));
+    assert_eq!(model.eval(&["--set", "Inputs!B3=4", "--check"]), (0, String::new(), String::new()));
+    let (status, out, _) = model.eval(&["--set", "Inputs!B3=0", "--check", "--format", "json"]);
+    assert_eq!(status, 1);
+    assert_eq!(out, "{\n  \"ok\": false,\n  \"cells\": {},\n  \"errors\": {\n    \"Outputs!C11\": \"Division by 0\"\n  }\n}\n");
+}
+
+#[test]
+fn refuses_bad_arguments() {
+    let model = Model::write("usage");
+    for (args, message) in [
+        (&[][..], "error: Nothing to do; expected --get or --check\n"),
+        (&["--set", "X", "--check"][..], "error: Expected --set CELL=FORMULA, got X\n"),
+        (&["--get"][..], "error: Expected a value after --get\n"),
+        (&["--format", "xml", "--check"][..], "error: Unknown format xml; expected text or json\n"),
+        (&["--verbose", "--check"][..], "error: Unknown option --verbose\n"),
+        (&["other.grid", "--check"][..], "error: Expected a single workbook, got other.grid as well\n"),
+    ] {
+        let (status, out, err) = model.eval(args);
+        assert_eq!((status, out.as_str()), (2, ""), "{args:?}");
+        assert!(err.starts_with(message), "{err}");
+        assert!(err.contains("usage: gridkid eval FILE"));
+    }
+}
+
+#[test]
+fn refuses_workbooks_and_cells_it_cannot_read() {
+    let model = Model::write("read");
+    assert_eq!(model.eval(&["--get", "A0"]), (2, String::new(), String::from("error: --get A0: Invalid cell reference A0: Row 0 is out of range\n")));
+    assert_eq!(model.eval(&["--set", "Inputs!B2=(", "--check"]), (2, String::new(), String::from("error: --set Inputs!B2: Unexpected end of formula\n")));
+    assert_eq!(model.eval(&["--set", "Missing!A1=1", "--check"]).0, 2);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gridkid")).args(["eval", "missing.grid", "--check"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("error: Cannot read missing.grid"));
+}
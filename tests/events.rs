@@ -0,0 +1,85 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::set;
+use gridkid::environment::Environment;
+use gridkid::events::Event;
+use gridkid::model::{CellAddress, Primitive};
+
+fn changed(cell: &str, old: Option<i32>, new: Option<i32>) -> Event {
+    Event::ValueChanged {
+        sheet: String::from("Sheet1"),
+        adr: cell.parse().unwrap(),
+        old: old.map(|val| Ok(Primitive::Integer(val))),
+        new: new.map(|val| Ok(Primitive::Integer(val))),
+    }
+}
+
+#[test]
+fn tells_callbacks_of_edits_and_the_values_they_change() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "B1", "A1 * 2");
+    set(&mut environment, "C1", "7");
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    environment.subscribe(move |event| seen.lock().unwrap().push(event.clone()));
+    set(&mut environment, "A1", "3");
+    environment.clear_cell(&"C1".parse().unwrap());
+
+    // Values changed by one edit come in no particular order
+    let events = events.lock().unwrap();
+    let sheet = String::from("Sheet1");
+    assert_eq!(events[0], Event::CellSet { sheet: sheet.clone(), adr: CellAddress(0, 0) });
+    assert!(events[1..3].contains(&changed("A1", Some(1), Some(3))));
+    assert!(events[1..3].contains(&changed("B1", Some(2), Some(6))));
+    assert_eq!(events[3..], [Event::CellCleared { sheet, adr: CellAddress(2, 0) }, changed("C1", Some(7), None)]);
+}
+
+#[test]
+fn holds_events_back_until_a_transaction_commits() {
+    let mut environment = Environment::init();
+    let events = environment.subscribe_channel();
+
+    environment.begin("Edit");
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "A2", "A1 + 1");
+    assert_eq!(events.try_iter().count(), 0);
+    environment.commit().unwrap();
+
+    let received: Vec<_> = events.try_iter().collect();
+    assert_eq!(received.len(), 4);
+    assert!(received.contains(&changed("A2", None, Some(2))));
+}
+
+#[test]
+fn stops_telling_cancelled_subscriptions() {
+    let mut environment = Environment::init();
+    let count = Arc::new(Mutex::new(0));
+    let counter = Arc::clone(&count);
+    let id = environment.subscribe(move |_| *counter.lock().unwrap() += 1);
+    let events = environment.subscribe_channel();
+
+    set(&mut environment, "A1", "1");
+    assert_eq!(*count.lock().unwrap(), 2);
+    assert!(environment.unsubscribe(id));
+    assert!(!environment.unsubscribe(id));
+    drop(events);
+
+    set(&mut environment, "A1", "2");
+    assert_eq!(*count.lock().unwrap(), 2);
+}
+
+#[test]
+fn reports_structural_changes_once() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    let events = environment.subscribe_channel();
+
+    environment.insert_rows(0, 1).unwrap();
+    environment.add_sheet("Data").unwrap();
+    assert_eq!(events.try_iter().collect::<Vec<_>>(), [Event::StructureChanged, Event::StructureChanged]);
+    assert_eq!(environment.value(&"A2".parse().unwrap()), Some(Ok(Primitive::Integer(1))));
+}
@@ -0,0 +1,182 @@
+mod common;
+
+use common::{tree, Random};
+use gridkid::address::{CellRange, CellReference};
+use gridkid::format::formula::{parse_formula, write_formula};
+use gridkid::model::{CellAddress, CellValue, Evaluatable, Expression, InvalidReference, NamedValue, Operation, Primitive, Statistics};
+
+fn int(val: i32) -> Box<dyn Evaluatable> {
+    Box::new(Primitive::Integer(val))
+}
+
+fn cell(text: &str) -> Box<dyn Evaluatable> {
+    Box::new(CellValue(text.parse().unwrap()))
+}
+
+/// Whether two trees have the same shape and leaves, comparing floats by their bits
+fn same(a: &dyn Evaluatable, b: &dyn Evaluatable) -> bool {
+    match (a.expression(), b.expression()) {
+        (Expression::Primitive(Primitive::Float(x)), Expression::Primitive(Primitive::Float(y))) => x.to_bits() == y.to_bits(),
+        (Expression::Primitive(x), Expression::Primitive(y)) => x == y,
+        (Expression::CellValue(x), Expression::CellValue(y)) => x == y,
+        (Expression::Statistics(x), Expression::Statistics(y)) => x == y,
+        (Expression::InvalidReference(_), Expression::InvalidReference(_)) => true,
+        (Expression::NamedValue(x), Expression::NamedValue(y)) => x == y,
+        (Expression::Operation(x), Expression::Operation(y)) => {
+            if std::mem::discriminant(x) != std::mem::discriminant(y) {
+                return false;
+            }
+            match (x.operands(), y.operands()) {
+                ((x1, Some(x2)), (y1, Some(y2))) => same(x1, y1) && same(x2, y2),
+                ((x1, None), (y1, None)) => same(x1, y1),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn assert_round_trip(expression: &dyn Evaluatable) {
+    let text = write_formula(expression);
+    let parsed = parse_formula(&text).unwrap_or_else(|e| panic!("{text} does not parse: {e}"));
+    assert!(same(expression, parsed.as_ref()), "{text} reads back as {}", write_formula(parsed.as_ref()));
+}
+
+#[test]
+fn follows_operator_precedence() {
+    use gridkid::environment::Environment;
+    let environment = Environment::init();
+    let value = |text: &str| parse_formula(text).unwrap().evaluate(&environment);
+    assert_eq!(value("1 + 2 * 3"), Ok(Primitive::Integer(7)));
+    assert_eq!(value("(1 + 2) * 3"), Ok(Primitive::Integer(9)));
+    assert_eq!(value("2 ** 3 ** 2"), Ok(Primitive::Integer(512)));
+    assert_eq!(value("10 - 4 - 3"), Ok(Primitive::Integer(3)));
+    assert_eq!(value("-2 ** 2"), Ok(Primitive::Integer(4)));
+    assert_eq!(value("1 << 2 + 3"), Ok(Primitive::Integer(32)));
+    assert_eq!(value("1 < 2 == true"), Ok(Primitive::Boolean(true)));
+    assert_eq!(value("!true || true && false"), Ok(Primitive::Boolean(false)));
+}
+
+#[test]
+fn prints_minimal_parentheses() {
+    let expression = Operation::Equals(
+        Box::new(Operation::Multiply(
+            Box::new(Operation::Add(Box::new(Operation::Subtract(int(5), int(5))), int(5))),
+            Box::new(Operation::BitwiseOr(int(5), int(4))),
+        )),
+        int(25),
+    );
+    assert_eq!(expression.to_string(), "(5 - 5 + 5) * (5 | 4) == 25");
+}
+
+#[test]
+fn keeps_parentheses_against_associativity() {
+    let left = Operation::Subtract(Box::new(Operation::Subtract(cell("A1"), cell("B1"))), cell("C1"));
+    let right = Operation::Subtract(cell("A1"), Box::new(Operation::Subtract(cell("B1"), cell("C1"))));
+    assert_eq!(left.to_string(), "A1 - B1 - C1");
+    assert_eq!(right.to_string(), "A1 - (B1 - C1)");
+
+    let right = Operation::Power(cell("A1"), Box::new(Operation::Power(cell("B1"), cell("C1"))));
+    let left = Operation::Power(Box::new(Operation::Power(cell("A1"), cell("B1"))), cell("C1"));
+    assert_eq!(right.to_string(), "A1 ** B1 ** C1");
+    assert_eq!(left.to_string(), "(A1 ** B1) ** C1");
+}
+
+#[test]
+fn prints_unary_operators() {
+    assert_eq!(Operation::Subtract(int(0), cell("A1")).to_string(), "-A1");
+    assert_eq!(Operation::Subtract(int(0), int(5)).to_string(), "0 - 5");
+    assert_eq!(Operation::Subtract(int(0), int(-5)).to_string(), "--5");
+    assert_eq!(Operation::LogicalNot(Box::new(Operation::LogicalAnd(cell("A1"), cell("B1")))).to_string(), "!(A1 && B1)");
+    assert_eq!(Operation::Power(Box::new(Operation::Subtract(int(0), cell("A1"))), int(2)).to_string(), "-A1 ** 2");
+    assert_eq!(Operation::Subtract(int(0), Box::new(Operation::Power(cell("A1"), int(2)))).to_string(), "-(A1 ** 2)");
+}
+
+#[test]
+fn prints_references_and_functions() {
+    assert_eq!(CellValue::relative(0, 0).to_string(), "A1");
+    assert_eq!(CellValue(CellReference::absolute(CellAddress(1, 2)).on_sheet("Tax rates")).to_string(), "'Tax rates'!$B$3");
+    assert_eq!(Statistics::Sum("A1:B3".parse().unwrap()).to_string(), "Sum(A1:B3)");
+    assert_eq!(Operation::FloatToInt(Box::new(Operation::Add(cell("A1"), int(1)))).to_string(), "FloatToInt(A1 + 1)");
+}
+
+#[test]
+fn reprints_parsed_formulas_unchanged() {
+    for text in [
+        "A1 * 2",
+        "(A1 + B1) * C1",
+        "Inputs!B2 ** 2 >= 10",
+        "Sum(A1:A5) / Mean('Tax rates'!B1:B9)",
+        "Sum('Tax rates':Q4!B2:B9) + Max('2024':'2025'!A1:B2)",
+        "Min(Q1:'it''s'!C:C)",
+        "A1 < B1 == (C1 < D1)",
+        "!A1 || B1 && C1",
+        "1 << 2 + 3",
+        "(1 << 2) + 3",
+        "A1 & B1 ^ C1 | D1",
+        "A1 & (B1 ^ C1)",
+        "-(A1 + 1) % 3",
+        "\"say \\\"hi\\\"\" == Rate",
+        "IntToFloat(A1) * -1.5",
+        "#REF! + 1",
+        "A1 == NaN || A1 < -Inf",
+        "-Inf ** 2 - Inf",
+        "0 - Inf",
+        "0 - NaN",
+    ] {
+        assert_eq!(write_formula(parse_formula(text).unwrap().as_ref()), text);
+    }
+}
+
+#[test]
+fn writes_floats_that_are_not_numbers() {
+    use gridkid::environment::Environment;
+
+    let environment = Environment::init();
+    for val in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+        let text = write_formula(&Primitive::Float(val));
+        match parse_formula(&text).unwrap().evaluate(&environment) {
+            Ok(Primitive::Float(parsed)) => assert_eq!(parsed.to_bits(), val.to_bits(), "{text}"),
+            other => panic!("{text} gave {other:?}"),
+        }
+    }
+    assert_eq!(write_formula(&Operation::Subtract(int(0), Box::new(Primitive::Float(f32::INFINITY)))), "0 - Inf");
+    assert_eq!(write_formula(parse_formula("inf + nan").unwrap().as_ref()), "Inf + NaN");
+}
+
+#[test]
+fn rejects_malformed_formulas() {
+    for text in ["", "1 +", "(1 + 2", "1 2", "\"open", "A1 $ 2", "'Tax rates' + 1", "'Tax rates':B2"] {
+        assert!(parse_formula(text).is_err(), "{text}");
+    }
+}
+
+fn leaf(random: &mut Random) -> Box<dyn Evaluatable> {
+    match random.below(12) {
+        0 => int(random.below(100) as i32),
+        1 => int(-(random.below(100) as i32)),
+        2 => int(i32::MIN),
+        3 => Box::new(Primitive::Float([0.5, -2.25, 1e-7, -3e20, 0.0, -0.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY][random.below(9)])),
+        4 => Box::new(Primitive::Boolean(random.below(2) == 0)),
+        5 => Box::new(Primitive::String(["", "text", "a \"quoted\" \\ word"][random.below(3)].to_string())),
+        6 => cell(["A1", "$B$7", "C$2", "AA10"][random.below(4)]),
+        7 => cell(["Inputs!B2", "'Tax rates'!$A1", "'it''s'!Z99"][random.below(3)]),
+        8 => Box::new(NamedValue::new(["Rate", "total_2"][random.below(2)])),
+        9 => Box::new(InvalidReference),
+        10 => {
+            let range: CellRange = [
+                "A1:B3", "Inputs!A1:C2", "'Tax rates'!B2:B9", "Q1:Q4!C2", "'Tax rates':Q4!B2:B9", "'2024':'2025'!A1:B2", "Q1:'it''s'!$A:$A",
+            ][random.below(7)].parse().unwrap();
+            Box::new([Statistics::Sum, Statistics::Max, Statistics::Min, Statistics::Mean][random.below(4)](range))
+        }
+        _ => Box::new(Primitive::Integer(0)),
+    }
+}
+
+#[test]
+fn round_trips_generated_formulas() {
+    let mut random = Random(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..5000 {
+        assert_round_trip(tree(&mut random, 5, leaf).as_ref());
+    }
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use common::{set, value};
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::model::Primitive;
+
+fn sheets(environment: &Environment) -> Vec<&str> {
+    environment.sheet_names().collect()
+}
+
+#[test]
+fn undoes_and_redoes_cells() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "A1", "2");
+    environment.clear_cell(&"A1".parse().unwrap());
+    assert_eq!(environment.undo_label(), Some("Clear A1"));
+
+    assert!(environment.undo());
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(2))));
+    assert_eq!(environment.redo_label(), Some("Clear A1"));
+    assert!(environment.undo());
+    assert!(environment.undo());
+    assert_eq!(value(&environment, "A1"), None);
+    assert!(!environment.undo());
+
+    assert!(environment.redo());
+    assert!(environment.redo());
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(2))));
+    assert!(environment.redo());
+    assert_eq!(value(&environment, "A1"), None);
+    assert!(!environment.redo());
+}
+
+#[test]
+fn undoes_and_redoes_names() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "Rate * 2");
+    environment.define_name("Rate", &NameScope::Workbook, Definition::Constant(Box::new(Primitive::Integer(3)))).unwrap();
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(6))));
+    environment.remove_name("Rate", &NameScope::Workbook).unwrap();
+    assert!(value(&environment, "A1").unwrap().is_err());
+
+    environment.undo();
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(6))));
+    environment.undo();
+    assert!(environment.lookup_name("Rate", None).is_err());
+    environment.redo();
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(6))));
+}
+
+#[test]
+fn undoes_and_redoes_sheets() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    environment.set_cell_on("Data", &"A1".parse().unwrap(), Box::new(Primitive::Integer(5))).unwrap();
+    set(&mut environment, "B1", "Data!A1 + 1");
+    environment.move_sheet("Data", 0).unwrap();
+    environment.rename_sheet("Data", "Inputs").unwrap();
+    environment.delete_sheet("Inputs").unwrap();
+    assert_eq!(environment.get_cell(&"B1".parse().unwrap()).unwrap().to_string(), "#REF! + 1");
+
+    environment.undo();
+    assert_eq!(sheets(&environment), ["Inputs", "Sheet1"]);
+    assert_eq!(environment.get_cell(&"B1".parse().unwrap()).unwrap().to_string(), "Inputs!A1 + 1");
+    environment.undo();
+    assert_eq!(sheets(&environment), ["Data", "Sheet1"]);
+    environment.undo();
+    assert_eq!(sheets(&environment), ["Sheet1", "Data"]);
+    assert_eq!(value(&environment, "B1"), Some(Ok(Primitive::Integer(6))));
+    environment.undo();
+    environment.undo();
+    environment.undo();
+    assert_eq!(sheets(&environment), ["Sheet1"]);
+
+    while environment.redo() {}
+    assert_eq!(sheets(&environment), ["Sheet1"]);
+    assert_eq!(environment.get_cell(&"B1".parse().unwrap()).unwrap().to_string(), "#REF! + 1");
+}
+
+#[test]
+fn undoes_and_redoes_restructuring() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A2", "4");
+    set(&mut environment, "B1", "A2 * 2");
+    environment.insert_rows(0, 2).unwrap();
+    assert_eq!(value(&environment, "B3"), Some(Ok(Primitive::Integer(8))));
+    assert_eq!(environment.undo_label(), Some("Insert rows"));
+
+    environment.undo();
+    assert_eq!(value(&environment, "B1"), Some(Ok(Primitive::Integer(8))));
+    assert_eq!(value(&environment, "B3"), None);
+    environment.redo();
+    assert_eq!(environment.get_cell(&"B3".parse().unwrap()).unwrap().to_string(), "Sheet1!A4 * 2");
+}
+
+#[test]
+fn undoes_a_group_as_one_step() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    environment.begin_group("Paste");
+    set(&mut environment, "A1", "2");
+    environment.begin_group("Inner");
+    set(&mut environment, "A2", "3");
+    environment.end_group();
+    environment.add_sheet("Data").unwrap();
+    environment.end_group();
+    // An empty group is no step at all
+    environment.begin_group("Nothing");
+    environment.end_group();
+
+    assert_eq!(environment.undo_label(), Some("Paste"));
+    environment.undo();
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(1))));
+    assert_eq!(value(&environment, "A2"), None);
+    assert_eq!(sheets(&environment), ["Sheet1"]);
+    environment.redo();
+    assert_eq!(value(&environment, "A2"), Some(Ok(Primitive::Integer(3))));
+    assert_eq!(sheets(&environment), ["Sheet1", "Data"]);
+}
+
+#[test]
+fn forgets_undone_steps_on_a_new_change() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "A2", "2");
+    environment.undo();
+    assert_eq!(environment.redo_label(), Some("Set A2"));
+
+    set(&mut environment, "A3", "3");
+    assert_eq!(environment.redo_label(), None);
+    assert!(!environment.redo());
+    environment.undo();
+    environment.undo();
+    assert!(!environment.undo());
+    assert_eq!(value(&environment, "A2"), None);
+}
+
+#[test]
+fn forgets_the_oldest_steps_beyond_the_budget() {
+    let mut environment = Environment::init();
+    for row in 1..=20 {
+        set(&mut environment, &format!("A{row}"), &format!("{row}"));
+    }
+    environment.undo();
+    environment.undo();
+
+    environment.set_history_limit(1);
+    assert!(environment.undo());
+    assert!(!environment.undo());
+    assert_eq!(value(&environment, "A18"), None);
+    assert_eq!(value(&environment, "A17"), Some(Ok(Primitive::Integer(17))));
+    // Only the step undone last is left to redo
+    assert!(environment.redo());
+    assert!(!environment.redo());
+    assert_eq!(value(&environment, "A18"), Some(Ok(Primitive::Integer(18))));
+    assert_eq!(value(&environment, "A19"), None);
+
+    environment.clear_history();
+    assert!(!environment.undo());
+    assert!(!environment.redo());
+}
+
+#[test]
+fn keeps_a_change_larger_than_the_budget() {
+    let mut environment = Environment::init();
+    environment.begin_group("Fill");
+    for row in 1..=200 {
+        set(&mut environment, &format!("A{row}"), &format!("B{row} + {row}"));
+    }
+    environment.end_group();
+    environment.set_history_limit(4096);
+    assert_eq!(environment.undo_label(), Some("Fill"));
+
+    set(&mut environment, "C1", "1");
+    environment.insert_rows(0, 1).unwrap();
+    assert_eq!(environment.undo_label(), Some("Insert rows"));
+    assert!(environment.undo());
+    assert_eq!(environment.get_cell(&"A1".parse().unwrap()).unwrap().to_string(), "Sheet1!B1 + 1");
+    assert_eq!(value(&environment, "C1"), Some(Ok(Primitive::Integer(1))));
+    assert!(environment.redo());
+    assert_eq!(environment.get_cell(&"A2".parse().unwrap()).unwrap().to_string(), "Sheet1!B2 + 1");
+}
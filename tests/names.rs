@@ -0,0 +1,114 @@
+mod common;
+
+use common::{set, value};
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::format::formula::{parse_formula, write_formula_on};
+use gridkid::model::Primitive;
+
+fn constant(text: &str) -> Definition {
+    Definition::Constant(parse_formula(text).unwrap())
+}
+
+fn sheet(name: &str) -> NameScope {
+    NameScope::Sheet(name.to_string())
+}
+
+#[test]
+fn prefers_names_of_the_sheet_to_names_of_the_workbook() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    environment.define_name("Rate", &NameScope::Workbook, constant("10")).unwrap();
+    environment.define_name("Rate", &sheet("Data"), constant("20")).unwrap();
+    set(&mut environment, "A1", "Rate");
+    environment.set_cell_on("Data", &"A1".parse().unwrap(), parse_formula("Rate").unwrap()).unwrap();
+
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(10))));
+    assert_eq!(environment.value_on("Data", &"A1".parse().unwrap()), Some(Ok(Primitive::Integer(20))));
+    // The sheet a formula is on decides, not the active sheet
+    environment.set_active_sheet("Data").unwrap();
+    assert_eq!(environment.value_on("Sheet1", &"A1".parse().unwrap()), Some(Ok(Primitive::Integer(10))));
+    assert_eq!(environment.name_range("Rate"), Err(String::from("Name Rate does not refer to cells")));
+
+    // Removing the sheet's name uncovers the workbook's
+    environment.remove_name("Rate", &sheet("Data")).unwrap();
+    assert_eq!(environment.value_on("Data", &"A1".parse().unwrap()), Some(Ok(Primitive::Integer(10))));
+    environment.undo();
+    assert_eq!(environment.value_on("Data", &"A1".parse().unwrap()), Some(Ok(Primitive::Integer(20))));
+}
+
+#[test]
+fn binds_references_of_names_to_their_scope() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    environment.set_cell_on("Data", &"B2".parse().unwrap(), Box::new(Primitive::Integer(4))).unwrap();
+    set(&mut environment, "B2", "1");
+    environment.define_name("Here", &sheet("Data"), Definition::Cell("B2".parse().unwrap())).unwrap();
+    environment.define_name("There", &NameScope::Workbook, Definition::Cell("B2".parse().unwrap())).unwrap();
+
+    assert_eq!(environment.lookup_name("Here", Some("Data")).unwrap().to_string(), "Data!B2");
+    assert_eq!(environment.lookup_name("There", Some("Data")).unwrap().to_string(), "Sheet1!B2");
+    environment.set_cell_on("Data", &"C1".parse().unwrap(), parse_formula("Here * 10 + There").unwrap()).unwrap();
+    assert_eq!(environment.value_on("Data", &"C1".parse().unwrap()), Some(Ok(Primitive::Integer(41))));
+    // A name of another sheet is not visible
+    set(&mut environment, "C1", "Here");
+    assert_eq!(value(&environment, "C1"), Some(Err(String::from("Name Here is not defined"))));
+}
+
+#[test]
+fn refuses_names_defined_in_terms_of_themselves() {
+    let mut environment = Environment::init();
+    environment.define_name("Loop", &NameScope::Workbook, constant("Loop + 1")).unwrap();
+    environment.define_name("Ping", &NameScope::Workbook, constant("Pong * 2")).unwrap();
+    environment.define_name("Pong", &NameScope::Workbook, constant("Ping - 1")).unwrap();
+    set(&mut environment, "A1", "Loop");
+    set(&mut environment, "A2", "Ping");
+    set(&mut environment, "A3", "7");
+
+    assert!(matches!(value(&environment, "A1"), Some(Err(e)) if e.contains("Loop")));
+    assert!(matches!(value(&environment, "A2"), Some(Err(_))));
+    assert_eq!(value(&environment, "A3"), Some(Ok(Primitive::Integer(7))));
+
+    // Through a cell, it is a circular reference like any other
+    environment.define_name("Total", &NameScope::Workbook, Definition::Cell("B1".parse().unwrap())).unwrap();
+    set(&mut environment, "B1", "Total + 1");
+    assert!(matches!(value(&environment, "B1"), Some(Err(e)) if e.starts_with("Circular reference")));
+    environment.define_name("Twice", &NameScope::Workbook, constant("B2 * 2")).unwrap();
+    set(&mut environment, "B2", "Twice");
+    assert!(matches!(value(&environment, "B2"), Some(Err(e)) if e.starts_with("Circular reference")));
+}
+
+#[test]
+fn moves_names_with_their_cells() {
+    let mut environment = Environment::init();
+    for (cell, text) in [("A1", "2"), ("A2", "3"), ("A3", "5")] {
+        set(&mut environment, cell, text);
+    }
+    environment.define_name("Last", &NameScope::Workbook, Definition::Cell("A3".parse().unwrap())).unwrap();
+    environment.define_name("Column", &sheet("Sheet1"), Definition::Range("A1:A3".parse().unwrap())).unwrap();
+    environment.define_name("Double", &NameScope::Workbook, constant("A2 * 2")).unwrap();
+    set(&mut environment, "B1", "Last + Double");
+    assert_eq!(value(&environment, "B1"), Some(Ok(Primitive::Integer(11))));
+
+    environment.insert_rows(1, 2).unwrap();
+    assert_eq!(environment.lookup_name("Last", None).unwrap().to_string(), "Sheet1!A5");
+    assert_eq!(environment.name_range("Column").unwrap().to_string(), "Sheet1!A1:A5");
+    let Ok(Definition::Constant(double)) = environment.lookup_name("Double", None) else { panic!("Double is not a constant") };
+    assert_eq!(write_formula_on(double.as_ref(), "Sheet1"), "A4 * 2");
+    assert_eq!(value(&environment, "B1"), Some(Ok(Primitive::Integer(11))));
+
+    environment.delete_rows(4, 1).unwrap();
+    assert_eq!(environment.lookup_name("Last", None).unwrap().to_string(), "#REF!");
+    assert!(matches!(value(&environment, "B1"), Some(Err(_))));
+}
+
+#[test]
+fn refuses_names_that_look_like_cells() {
+    let mut environment = Environment::init();
+    for name in ["A1", "TAX2023", "R1C1", "TRUE", "nan", ""] {
+        assert!(environment.define_name(name, &NameScope::Workbook, constant("1")).is_err(), "{name}");
+    }
+    environment.define_name("Rate", &NameScope::Workbook, constant("1")).unwrap();
+    assert_eq!(environment.define_name("Rate", &NameScope::Workbook, constant("2")), Err(String::from("Name Rate is already defined")));
+    assert_eq!(environment.remove_name("Rate", &sheet("Sheet1")), Err(String::from("Name Rate is not defined")));
+    assert!(environment.define_name("Rate", &sheet("Missing"), constant("2")).is_err());
+}
@@ -0,0 +1,261 @@
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::format::ods::{from_bytes, parse_formula, to_bytes, write_formula};
+use gridkid::model::{CellAddress, Primitive};
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Contents as a deflate stream of stored blocks, which need no compressor to write
+fn deflate(contents: &[u8]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    let mut blocks = contents.chunks(100).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(u8::from(blocks.peek().is_none()));
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream
+}
+
+/// Zip archive of the given files, deflated or stored
+fn archive(entries: &[(&str, &[u8])], deflated: bool) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for (name, contents) in entries {
+        let data = if deflated { deflate(contents) } else { contents.to_vec() };
+        let mut header = Vec::new();
+        header.extend_from_slice(&20u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&(if deflated { 8u16 } else { 0 }).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&crc32(contents).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&[0; 2]);
+
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&header);
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&(archive.len() as u32).to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&data);
+    }
+
+    let offset = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&offset.to_le_bytes());
+    archive.extend_from_slice(&[0; 2]);
+    archive
+}
+
+/// content.xml of a spreadsheet with the given tables
+fn content(tables: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<office:document-content><office:body><office:spreadsheet>{tables}</office:spreadsheet></office:body></office:document-content>"
+    )
+}
+
+const TABLE: &str = r#"<table:table table:name="Data"><table:table-row><table:table-cell office:value-type="float" office:value="21"/><table:table-cell table:formula="of:=[.A1]*2"/></table:table-row></table:table>"#;
+
+/// Sheet and cell, formula and value, ordered by sheet and cell
+type Cell = (String, (i32, i32), String, Result<Primitive, String>);
+
+fn cells(environment: &Environment) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    for sheet in environment.sheet_names() {
+        let mut sheet_cells: Vec<Cell> = environment.cells_on(sheet)
+            .map(|(adr, val)| (sheet.to_string(), (adr.1, adr.0), write_formula(val).unwrap(), val.evaluate(environment)))
+            .collect();
+        sheet_cells.sort_by_key(|cell| cell.1);
+        cells.extend(sheet_cells);
+    }
+    cells
+}
+
+fn value(environment: &Environment, adr: CellAddress) -> Option<Result<Primitive, String>> {
+    environment.get_cell(&adr).map(|val| val.evaluate(environment))
+}
+
+#[test]
+fn round_trips_formulas_and_types() {
+    let mut environment = Environment::init();
+    for (adr, formula) in [
+        (CellAddress(0, 0), "of:=42"),
+        (CellAddress(1, 0), "of:=-0.125"),
+        (CellAddress(2, 0), "of:=3E+20"),
+        (CellAddress(3, 0), "of:=TRUE()"),
+        (CellAddress(4, 0), "of:=\"<a & b>  \"\"quoted\"\"\""),
+        (CellAddress(0, 4), "of:=MOD([.A1];5)+TRUNC([.B1]*8)"),
+        (CellAddress(2, 2), "of:=BITOR(ORG.GRIDKID.BITNOT([.A1]);BITLSHIFT(1;2))"),
+        (CellAddress(0, 6), "of:=AND([.A1]*0.2>=8;NOT([.D1]))"),
+        (CellAddress(1, 6), "of:=SUM([.A1:.C1])+AVERAGE([.A1:.B1])"),
+        (CellAddress(2, 6), "of:=ORG.GRIDKID.FLOAT([.A1])^0.5"),
+        (CellAddress(3, 6), "of:=[.A1]/0"),
+    ] {
+        environment.set_cell(&adr, parse_formula(formula).unwrap()).unwrap();
+    }
+
+    let read = from_bytes(&to_bytes(&environment).unwrap()).unwrap();
+    assert_eq!(cells(&read), cells(&environment));
+    assert_eq!(value(&read, CellAddress(0, 4)), Some(Ok(Primitive::Integer(1))));
+    assert_eq!(value(&read, CellAddress(4, 0)), Some(Ok(Primitive::String(String::from("<a & b>  \"quoted\"")))));
+}
+
+#[test]
+fn round_trips_sheets_and_references_between_them() {
+    let mut environment = Environment::init();
+    environment.rename_sheet("Sheet1", "Inputs").unwrap();
+    environment.add_sheet("Tax rates").unwrap();
+    environment.add_sheet("Out").unwrap();
+    let set = |environment: &mut Environment, sheet: &str, cell: &str, formula: &str| {
+        environment.set_cell_on(sheet, &cell.parse().unwrap(), parse_formula(formula).unwrap()).unwrap();
+    };
+    set(&mut environment, "Inputs", "A1", "of:=42");
+    set(&mut environment, "Inputs", "B2", "of:=0.5");
+    set(&mut environment, "Tax rates", "B2", "of:=0.2");
+    set(&mut environment, "Tax rates", "C3", "of:=BITOR(ORG.GRIDKID.BITNOT([$Inputs.$A$1]);1)");
+    set(&mut environment, "Out", "A1", "of:=[$Inputs.A1]*[$'Tax rates'.B2]>=8");
+    set(&mut environment, "Out", "A2", "of:=AVERAGE([$Inputs.B2:$'Tax rates'.B2])");
+    set(&mut environment, "Out", "A3", "of:=Rate+Local");
+    environment.define_name("Rate", &NameScope::Workbook, Definition::Cell("'Tax rates'!B2".parse().unwrap())).unwrap();
+    environment.define_name("Local", &NameScope::Sheet(String::from("Out")), Definition::Constant(parse_formula("of:=1+2").unwrap())).unwrap();
+    environment.define_name("Row", &NameScope::Workbook, Definition::Range("Inputs!A1:E1".parse().unwrap())).unwrap();
+
+    let mut read = from_bytes(&to_bytes(&environment).unwrap()).unwrap();
+    assert_eq!(read.sheet_names().collect::<Vec<_>>(), ["Inputs", "Tax rates", "Out"]);
+    assert_eq!(cells(&read), cells(&environment));
+    let average = read.get_cell_on("Out", &"A2".parse().unwrap()).unwrap();
+    assert_eq!(average.evaluate(&read), Ok(Primitive::Float(0.35)));
+    assert_eq!(read.name_range("Row").unwrap().to_string(), "Inputs!A1:E1");
+    let named = read.get_cell_on("Out", &"A3".parse().unwrap()).unwrap();
+    assert_eq!(named.evaluate(&read), Ok(Primitive::Float(3.2)));
+    assert!(!read.undo());
+}
+
+#[test]
+fn refuses_floats_it_cannot_write() {
+    let mut environment = Environment::init();
+    environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Float(f32::INFINITY))).unwrap();
+    assert!(to_bytes(&environment).is_err());
+}
+
+#[test]
+fn reads_deflated_entries() {
+    let document = content(TABLE);
+    let environment = from_bytes(&archive(&[("mimetype", b"application/vnd.oasis.opendocument.spreadsheet"), ("content.xml", document.as_bytes())], true)).unwrap();
+    assert_eq!(environment.sheet_names().collect::<Vec<_>>(), ["Data"]);
+    assert_eq!(value(&environment, CellAddress(1, 0)), Some(Ok(Primitive::Integer(42))));
+}
+
+#[test]
+fn rejects_truncated_archives() {
+    let mut environment = Environment::init();
+    environment.set_cell(&CellAddress(0, 0), parse_formula("of:=\"text\"=\"text\"").unwrap()).unwrap();
+    for bytes in [to_bytes(&environment).unwrap(), archive(&[("content.xml", content(TABLE).as_bytes())], true)] {
+        assert!(from_bytes(&bytes).is_ok());
+        for length in 0..bytes.len() {
+            assert!(from_bytes(&bytes[..length]).is_err(), "read {length} of {} bytes", bytes.len());
+        }
+    }
+}
+
+#[test]
+fn survives_corrupted_archives() {
+    let bytes = archive(&[("content.xml", content(TABLE).as_bytes())], true);
+    for pos in 0..bytes.len() {
+        for flip in [0x01, 0x80, 0xff] {
+            let mut corrupted = bytes.clone();
+            corrupted[pos] ^= flip;
+            // Fields such as the version are not checked, so this need not fail, but must not panic
+            let _ = from_bytes(&corrupted);
+        }
+    }
+
+    assert_eq!(from_bytes(b"").err(), Some(String::from("Not a zip archive")));
+    assert_eq!(from_bytes(&[0x50; 100]).err(), Some(String::from("Not a zip archive")));
+    // The central directory, which is where the end record says, gives the compression method
+    let mut unsupported = archive(&[("content.xml", b"")], false);
+    let length = unsupported.len();
+    let directory = u32::from_le_bytes(unsupported[length - 6..length - 2].try_into().unwrap()) as usize;
+    unsupported[directory + 10] = 14;
+    assert_eq!(from_bytes(&unsupported).err(), Some(String::from("Unsupported compression method 14 for zip entry content.xml")));
+}
+
+#[test]
+fn rejects_malformed_documents() {
+    let read = |content: &str| from_bytes(&archive(&[("content.xml", content.as_bytes())], false));
+
+    assert_eq!(
+        from_bytes(&archive(&[("mimetype", b"application/vnd.oasis.opendocument.spreadsheet")], false)).err(),
+        Some(String::from("Not an OpenDocument file: content.xml is missing")),
+    );
+    assert_eq!(from_bytes(&archive(&[("content.xml", &[0xc3, 0x28])], false)).err(), Some(String::from("content.xml is not valid UTF-8")));
+    assert_eq!(read("<office:document-content/>").err(), Some(String::from("Document does not contain a spreadsheet")));
+    assert_eq!(read(&content("")).err(), Some(String::from("Document does not contain a spreadsheet table")));
+    assert!(read(&content(r#"<table:table><table:table-row table:number-rows-repeated="many"/></table:table>"#)).is_err());
+    assert!(read(&content(r#"<table:table><table:table-row><table:table-cell table:formula="of:=[.A1"/></table:table-row></table:table>"#)).is_err());
+    assert!(read(&content(r#"<table:table><table:table-row><table:table-cell office:value-type="float"/></table:table-row></table:table>"#)).is_err());
+    assert!(read(&content(r#"<table:table><table:table-row><table:table-cell office:value-type="float" office:value="x"/></table:table-row></table:table>"#)).is_err());
+    assert!(read(&content(r#"<table:table><table:table-row><table:table-cell office:value-type="void" office:value="1"/></table:table-row></table:table>"#)).is_err());
+
+    let document = content(TABLE);
+    assert!(read(&document).is_ok());
+    for (end, _) in document.char_indices().filter(|(end, _)| *end > 0) {
+        assert!(read(&document[..end]).is_err(), "read {}", &document[..end]);
+    }
+    for broken in ["<a>&bogus;</a>", "<a>&#xZZ;</a>", "<a>&#1114112;</a>", "<a b=\"1\" b></a>", "<a></b>", "<a/><b/>", "<a b='1>", "<a><!-- </a>"] {
+        assert!(read(broken).is_err(), "{broken}");
+    }
+}
+
+#[test]
+fn refuses_documents_that_expand_without_bound() {
+    let bytes = archive(&[("content.xml", content(TABLE).as_bytes())], true);
+    let length = bytes.len();
+    let directory = u32::from_le_bytes(bytes[length - 6..length - 2].try_into().unwrap()) as usize;
+    let with_size = |size: u32| {
+        let mut bytes = bytes.clone();
+        bytes[directory + 24..directory + 28].copy_from_slice(&size.to_le_bytes());
+        from_bytes(&bytes)
+    };
+    assert_eq!(with_size(10).err(), Some(String::from("Deflate stream longer than its entry")));
+    // A size far beyond what the data could hold reserves no more than the data could give
+    assert!(with_size(u32::MAX).is_err());
+
+    // The length of a stored block must match its complement
+    let mut mismatched = bytes.clone();
+    let local_data = 30 + "content.xml".len();
+    mismatched[local_data + 3] ^= 1;
+    assert_eq!(from_bytes(&mismatched).err(), Some(String::from("Invalid length of stored deflate block")));
+
+    let read = |content: &str| from_bytes(&archive(&[("content.xml", content.as_bytes())], false));
+    let nested = format!("{}{}", "<a>".repeat(100_000), "</a>".repeat(100_000));
+    assert_eq!(read(&nested).err(), Some(String::from("Elements nested more than 256 deep")));
+    let repeated = r#"<table:table table:name="Big"><table:table-row table:number-rows-repeated="1000000"><table:table-cell table:number-columns-repeated="5000000" office:value-type="float" office:value="1"/></table:table-row></table:table>"#;
+    assert_eq!(read(&content(repeated)).err(), Some(String::from("Table Big fills more than 4194304 cells")));
+    let repeated = r#"<table:table table:name="Big"><table:table-row table:number-rows-repeated="1000000"><table:table-cell table:number-columns-repeated="1000" office:value-type="float" office:value="1"/></table:table-row></table:table>"#;
+    assert_eq!(read(&content(repeated)).err(), Some(String::from("Table Big fills more than 4194304 cells")));
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::Random;
+use gridkid::environment::Environment;
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Primitive};
+
+/// Wide sheet of independent rows over a shared input, with a total, a circular reference and
+/// formulas that fail, and a second sheet reading it
+fn workbook(threads: usize, compiled: bool) -> Environment {
+    let mut environment = Environment::init();
+    environment.set_threads(threads);
+    environment.set_compiled(compiled);
+    environment.add_sheet("Summary").unwrap();
+
+    environment.begin("Generate");
+    environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(7))).unwrap();
+    for row in 1..2000 {
+        let n = row + 1;
+        for (column, formula) in [
+            format!("$A$1 * {row} % 11"),
+            format!("IntToFloat(B{n}) / 4.0 + {row}"),
+            format!("C{n} - B{n} * 2"),
+            format!("1 / (B{n} - 3)"),
+        ].iter().enumerate() {
+            environment.set_cell(&CellAddress(column as i32 + 1, row), parse_formula(formula).unwrap()).unwrap();
+        }
+    }
+    environment.set_cell(&"F1".parse().unwrap(), parse_formula("Sum(D2:D2000) + G1").unwrap()).unwrap();
+    environment.set_cell(&"G1".parse().unwrap(), parse_formula("F1 + 1").unwrap()).unwrap();
+    environment.set_cell_on("Summary", &"A1".parse().unwrap(), parse_formula("Mean(Sheet1!C2:C2000)").unwrap()).unwrap();
+    environment.set_cell_on("Summary", &"A2".parse().unwrap(), parse_formula("Max(Sheet1!E2:E2000)").unwrap()).unwrap();
+    environment.commit_unchecked().unwrap();
+    environment
+}
+
+type Value = (String, CellAddress, Option<Result<Primitive, String>>);
+
+fn values(environment: &Environment) -> Vec<Value> {
+    let mut values = Vec::new();
+    for sheet in environment.sheet_names() {
+        let mut cells: Vec<CellAddress> = environment.cells_on(sheet).map(|(adr, _)| *adr).collect();
+        cells.sort_by_key(|adr| (adr.1, adr.0));
+        values.extend(cells.into_iter().map(|adr| (sheet.to_string(), adr, environment.value_on(sheet, &adr))));
+    }
+    values
+}
+
+#[test]
+fn gives_the_same_values_on_any_number_of_threads() {
+    for (threads, compiled) in [(2, false), (8, false), (3, true)] {
+        let mut expected = workbook(1, false);
+        let mut environment = workbook(threads, compiled);
+        assert_eq!(values(&environment), values(&expected), "{threads} threads");
+
+        environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(-2))).unwrap();
+        expected.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(-2))).unwrap();
+        assert_eq!(values(&environment), values(&expected), "{threads} threads after a change");
+        assert!(matches!(environment.value(&"G1".parse().unwrap()), Some(Err(e)) if e.starts_with("Circular reference")));
+    }
+}
+
+#[test]
+fn recalculates_after_edits_as_from_scratch() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    let cells = ["A1", "A2", "B1", "B2", "C3", "Data!A1"];
+    let formulas = ["1", "2.5", "A1 + 1", "B2 * A2", "Sum(A1:B2)", "Max(A:A) - C3", "Data!A1 + B1", "Sheet1!C3 / 2", "1 / (A1 - 1)"];
+
+    let mut random = Random(0x0123_4567_89ab_cdef);
+    for _ in 0..500 {
+        let picked = cells[random.below(cells.len())];
+        let (sheet, cell) = picked.split_once('!').unwrap_or(("Sheet1", picked));
+        let cell = cell.parse().unwrap();
+        match random.below(5) {
+            0 => environment.clear_cell_on(sheet, &cell).unwrap(),
+            1 => {
+                environment.undo();
+            }
+            _ => environment.set_cell_on(sheet, &cell, parse_formula(formulas[random.below(formulas.len())]).unwrap()).unwrap(),
+        }
+
+        // Changing the tolerance recalculates every cell
+        let edited = values(&environment);
+        environment.set_tolerance(environment.tolerance());
+        assert_eq!(edited, values(&environment));
+    }
+}
+
+#[test]
+fn shares_workbooks_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Environment>();
+}
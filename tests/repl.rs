@@ -0,0 +1,100 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Exit status, standard output and standard error of a session reading the given script
+fn session(script: &str) -> (i32, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gridkid"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn runs_a_script_without_prompting() {
+    let (status, out, err) = session("set A1 = 5\nset B1 = A1 * 2\n\n# Comments and blank lines are skipped\nget B1\nshow A1:B2\n");
+    assert_eq!(status, 0);
+    assert_eq!(out, "10\n|  | A | B |\n| --- | ---: | ---: |\n| **1** | 5 | 10 |\n| **2** |  |  |\n");
+    assert_eq!(err, "");
+}
+
+#[test]
+fn reports_errors_by_line_and_fails() {
+    let script = "set A1 = 1 / 0\nget A1\nset A2 = 2147483647 + 1\nget A2\nset A3 = -2147483648 / -1\nget A3\nbogus\nget\nset A4 5\nget A9\n";
+    let (status, out, err) = session(script);
+    assert_eq!(status, 1);
+    assert_eq!(out, "A9 is empty\n");
+    assert_eq!(err, concat!(
+        "error on line 2: Division by 0\n",
+        "error on line 4: Integer overflow\n",
+        "error on line 6: Integer overflow\n",
+        "error on line 7: Unknown command bogus; try help\n",
+        "error on line 8: Expected a cell\n",
+        "error on line 9: Expected set CELL = FORMULA\n",
+    ));
+}
+
+#[test]
+fn keeps_going_after_an_error() {
+    let (status, out, err) = session("set A1 = (\nset A1 = 3\nget A1\n");
+    assert_eq!(status, 1);
+    assert_eq!(out, "3\n");
+    assert!(err.starts_with("error on line 1: "));
+}
+
+#[test]
+fn warns_of_formulas_that_cannot_evaluate() {
+    let (status, out, _) = session("set A1 = \"x\" + 1\n");
+    assert_eq!(status, 0);
+    assert_eq!(out, "warning: Incompatible types: String and Integer for Arithmetic operation\n");
+}
+
+#[test]
+fn edits_cells_on_several_sheets() {
+    let script = "set A1 = 5\nsheet Data\nset A1 = Sheet1!A1 + 1\nset Sheet1!B1 = Data!A1 * 2\nget Sheet1!B1\nsheets\nclear Sheet1!A1\nget Sheet1!A1\nundo\nget A1\nundo\nundo\nundo\nundo\nundo\n";
+    let (status, out, err) = session(script);
+    assert_eq!((status, err.as_str()), (0, ""));
+    assert_eq!(out, "12\nSheet1\nData\nSheet1!A1 is empty\n6\nNothing to undo\n");
+}
+
+#[test]
+fn lists_dependencies_and_history() {
+    let (_, out, _) = session("set A1 = 5\nset B1 = A1 * 2\nset C1 = B1 + 1\ndeps B1\nhistory\n");
+    assert_eq!(out, concat!(
+        "formula: A1 * 2\n",
+        "precedents: Sheet1!A1\n",
+        "dependents: Sheet1!C1\n",
+        "all precedents: Sheet1!A1\n",
+        "all dependents: Sheet1!C1\n",
+        "   1  set A1 = 5\n",
+        "   2  set B1 = A1 * 2\n",
+        "   3  set C1 = B1 + 1\n",
+        "   4  deps B1\n",
+        "   5  history\n",
+    ));
+}
+
+#[test]
+fn saves_and_loads_workbooks() {
+    let path = std::env::temp_dir().join(format!("gridkid-repl-{}.grid", std::process::id()));
+    let path = path.to_str().unwrap();
+    let (status, _, err) = session(&format!("set A1 = 5\nset B1 = A1 * 2\nsave {path}\n"));
+    assert_eq!((status, err.as_str()), (0, ""));
+    let (status, out, _) = session(&format!("load {path}\nget B1\n"));
+    std::fs::remove_file(path).unwrap();
+    assert_eq!((status, out.as_str()), (0, "10\n"));
+
+    let (status, _, err) = session(&format!("load {path}\n"));
+    assert_eq!(status, 1);
+    assert!(err.starts_with("error on line 1: "));
+}
+
+#[test]
+fn stops_at_quit() {
+    let (status, out, _) = session("set A1 = 1\nquit\nbogus\nget A1\n");
+    assert_eq!((status, out.as_str()), (0, ""));
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::set;
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::format::formula::write_formula_on;
+use gridkid::model::Primitive;
+
+fn formula(environment: &Environment, sheet: &str, cell: &str) -> Option<String> {
+    environment.get_cell_on(sheet, &cell.parse().unwrap()).map(|val| write_formula_on(val, sheet))
+}
+
+#[test]
+fn moves_cells_and_references_on_insertion() {
+    let mut environment = Environment::init();
+    for (cell, text) in [("A1", "1"), ("A2", "2"), ("A3", "3"), ("B1", "A1 + $A$2 + A$3"), ("B5", "Sum(A1:A3) + Sum(A3:A1)")] {
+        set(&mut environment, cell, text);
+    }
+    environment.insert_rows(1, 2).unwrap();
+
+    assert_eq!(formula(&environment, "Sheet1", "A4").as_deref(), Some("2"));
+    assert_eq!(formula(&environment, "Sheet1", "B1").as_deref(), Some("A1 + $A$4 + A$5"));
+    assert_eq!(formula(&environment, "Sheet1", "B7").as_deref(), Some("Sum(A1:A5) + Sum(A5:A1)"));
+    assert_eq!(environment.value(&"B7".parse().unwrap()), Some(Ok(Primitive::Float(12.0))));
+    assert_eq!(formula(&environment, "Sheet1", "A2"), None);
+
+    environment.insert_columns(0, 1).unwrap();
+    assert_eq!(formula(&environment, "Sheet1", "C1").as_deref(), Some("B1 + $B$4 + B$5"));
+}
+
+#[test]
+fn grows_ranges_spanning_the_insertion_point() {
+    let mut environment = Environment::init();
+    set(&mut environment, "D1", "Sum(A2:A4) + Sum(A1:A2) + Sum(A3:A4) + Sum(A:A) + Sum(1:2)");
+    environment.insert_rows(2, 5).unwrap();
+    // Inserting at the first row of a range moves it rather than growing it
+    assert_eq!(formula(&environment, "Sheet1", "D1").as_deref(), Some("Sum(A2:A9) + Sum(A1:A2) + Sum(A8:A9) + Sum(A:A) + Sum(1:2)"));
+}
+
+#[test]
+fn turns_references_to_deleted_cells_into_errors() {
+    let mut environment = Environment::init();
+    for (cell, text) in [("A1", "1"), ("A2", "2"), ("A3", "3"), ("A4", "4"), ("B1", "A2 + A4"), ("B2", "Sum(A1:A4) + Sum(A2:A3)")] {
+        set(&mut environment, cell, text);
+    }
+    environment.delete_rows(1, 2).unwrap();
+
+    assert_eq!(formula(&environment, "Sheet1", "B1").as_deref(), Some("#REF! + A2"));
+    assert!(matches!(environment.value(&"B1".parse().unwrap()), Some(Err(_))));
+    // B2 was in one of the deleted rows
+    assert_eq!(formula(&environment, "Sheet1", "B2"), None);
+
+    // A range shrinks to what is left of it
+    set(&mut environment, "C10", "Sum(A1:A2)");
+    environment.delete_rows(1, 1).unwrap();
+    assert_eq!(formula(&environment, "Sheet1", "C9").as_deref(), Some("Sum(A1:A1)"));
+    assert_eq!(environment.value(&"C9".parse().unwrap()), Some(Ok(Primitive::Float(1.0))));
+    environment.delete_rows(0, 1).unwrap();
+    assert_eq!(formula(&environment, "Sheet1", "C8").as_deref(), Some("#REF!"));
+    assert!(matches!(environment.value(&"C8".parse().unwrap()), Some(Err(_))));
+}
+
+#[test]
+fn rewrites_references_from_other_sheets() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    environment.set_cell_on("Data", &"A3".parse().unwrap(), Box::new(Primitive::Integer(7))).unwrap();
+    set(&mut environment, "A1", "Data!A3 + Sum(Data!A1:A5) + A3");
+    set(&mut environment, "B1", "Data!A3 * 2");
+    environment.define_name("Total", &NameScope::Workbook, Definition::Cell("Data!A5".parse().unwrap())).unwrap();
+
+    environment.set_active_sheet("Data").unwrap();
+    environment.insert_rows(0, 1).unwrap();
+    assert_eq!(formula(&environment, "Sheet1", "A1").as_deref(), Some("Data!A4 + Sum(Data!A2:A6) + A3"));
+    assert_eq!(environment.value_on("Sheet1", &"B1".parse().unwrap()), Some(Ok(Primitive::Integer(14))));
+    assert_eq!(environment.name_range("Total").unwrap().to_string(), "Data!A6:A6");
+
+    environment.delete_rows(3, 1).unwrap();
+    assert_eq!(formula(&environment, "Sheet1", "A1").as_deref(), Some("#REF! + Sum(Data!A2:A5) + A3"));
+}
+
+#[test]
+fn leaves_3d_ranges_alone() {
+    let mut environment = Environment::init();
+    environment.add_sheet("Data").unwrap();
+    set(&mut environment, "A5", "Sum(Sheet1:Data!A1:A3)");
+    environment.insert_rows(0, 1).unwrap();
+    assert_eq!(formula(&environment, "Sheet1", "A6").as_deref(), Some("Sum(Sheet1:Data!A1:A3)"));
+}
+
+#[test]
+fn refuses_edits_out_of_range() {
+    let mut environment = Environment::init();
+    assert_eq!(environment.insert_rows(0, -1), Err(String::from("Cannot insert or delete a negative number of rows")));
+    assert_eq!(environment.delete_columns(-1, 1), Err(String::from("Column -1 is out of range")));
+
+    set(&mut environment, "A2147483647", "1");
+    assert_eq!(environment.insert_rows(0, 1), Err(String::from("Inserting would push cell A2147483647 off the grid")));
+    assert_eq!(environment.undo_label(), Some("Set A2147483647"));
+}
@@ -0,0 +1,122 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// Server on a free port, stopped when dropped
+struct Server {
+    child: Child,
+    address: String,
+}
+
+impl Server {
+    fn start() -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_gridkid-server"))
+            .args(["--port", "0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let address = line.trim().strip_prefix("listening on http://").unwrap().to_string();
+        Server { child, address }
+    }
+
+    fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(&self.address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream
+    }
+
+    /// Status and body of the response to a request
+    fn request(&self, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = self.connect();
+        write!(stream, "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head[9..12].parse().unwrap(), body.to_string())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn routes_requests_to_the_workbook() {
+    let server = Server::start();
+    assert_eq!(server.request("GET", "/sheets", ""), (200, String::from("{\"sheets\": [\"Sheet1\"]}")));
+    assert_eq!(server.request("PUT", "/cells/A1", "2 + 3"), (200, String::from("{\"cell\": \"Sheet1!A1\", \"formula\": \"2 + 3\", \"value\": 5}")));
+    assert_eq!(server.request("PUT", "/cells/B1", "A1 * 2\n"), (200, String::from("{\"cell\": \"Sheet1!B1\", \"formula\": \"A1 * 2\", \"value\": 10}")));
+    assert_eq!(server.request("GET", "/cells/B1", ""), (200, String::from("{\"cell\": \"Sheet1!B1\", \"formula\": \"A1 * 2\", \"value\": 10}")));
+    assert_eq!(server.request("POST", "/eval", "B1 - A1"), (200, String::from("{\"value\": 5}")));
+    assert_eq!(server.request("GET", "/precedents/B1", ""), (200, String::from("{\"cell\": \"Sheet1!B1\", \"precedents\": [\"Sheet1!A1\"]}")));
+    assert_eq!(server.request("GET", "/dependents/A1", ""), (200, String::from("{\"cell\": \"Sheet1!A1\", \"dependents\": [\"Sheet1!B1\"]}")));
+    assert_eq!(server.request("DELETE", "/cells/A1", ""), (200, String::from("{\"cell\": \"Sheet1!A1\", \"formula\": null, \"value\": null}")));
+    assert_eq!(server.request("GET", "/cells/B1?fresh=1", "").1, "{\"cell\": \"Sheet1!B1\", \"formula\": \"A1 * 2\", \"value\": {\"error\": \"Value for cell Sheet1!A1 not found\"}}");
+}
+
+#[test]
+fn decodes_percent_encoded_cells() {
+    let server = Server::start();
+    server.request("PUT", "/cells/C3", "7");
+    assert_eq!(server.request("GET", "/cells/Sheet1%21%24C%243", ""), (200, String::from("{\"cell\": \"Sheet1!C3\", \"formula\": \"7\", \"value\": 7}")));
+    assert_eq!(server.request("GET", "/cells/C%zz", ""), (400, String::from("{\"error\": \"Invalid escape in /cells/C%zz\"}")));
+    assert_eq!(server.request("GET", "/cells/%27Tax%20rates%27%21A1", "").0, 400);
+}
+
+#[test]
+fn answers_bad_requests_with_errors() {
+    let server = Server::start();
+    assert_eq!(server.request("GET", "/nothing", ""), (404, String::from("{\"error\": \"Nothing at /nothing\"}")));
+    assert_eq!(server.request("GET", "/cells/A1/B1", "").0, 400);
+    assert_eq!(server.request("POST", "/sheets", ""), (405, String::from("{\"error\": \"POST is not supported on /sheets\"}")));
+    assert_eq!(server.request("PATCH", "/cells/A1", "").0, 405);
+    assert_eq!(server.request("PUT", "/cells/A1", "1 +"), (400, String::from("{\"error\": \"Unexpected end of formula\"}")));
+    assert_eq!(server.request("PUT", "/cells/Other!A1", "1"), (400, String::from("{\"error\": \"Sheet Other not found\"}")));
+    assert_eq!(server.request("POST", "/eval", "1 / 0"), (200, String::from("{\"value\": {\"error\": \"Division by 0\"}}")));
+
+    let mut stream = server.connect();
+    write!(stream, "PUT /cells/A1 HTTP/1.1\r\nContent-Length: many\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+
+    // The workbook still serves requests afterwards
+    assert_eq!(server.request("POST", "/eval", "1 + 1"), (200, String::from("{\"value\": 2}")));
+}
+
+#[test]
+fn streams_changes_as_events() {
+    let server = Server::start();
+    let mut stream = server.connect();
+    write!(stream, "GET /events HTTP/1.1\r\n\r\n").unwrap();
+    let mut events = BufReader::new(stream);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    while events.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+        lines.push(std::mem::take(&mut line));
+    }
+    assert_eq!(lines[0], "HTTP/1.1 200 OK\r\n");
+    assert!(lines.contains(&String::from("Content-Type: text/event-stream\r\n")));
+
+    server.request("PUT", "/cells/A1", "4");
+    let mut received = Vec::new();
+    for _ in 0..6 {
+        line.clear();
+        events.read_line(&mut line).unwrap();
+        received.push(line.clone());
+    }
+    assert_eq!(received, [
+        "event: cell-set\n",
+        "data: {\"cell\": \"Sheet1!A1\"}\n",
+        "\n",
+        "event: value-changed\n",
+        "data: {\"cell\": \"Sheet1!A1\", \"old\": null, \"new\": 4}\n",
+        "\n",
+    ]);
+}
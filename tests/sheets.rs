@@ -0,0 +1,131 @@
+mod common;
+
+use common::{set, value};
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::format::formula::{parse_formula, write_formula_on};
+use gridkid::model::Primitive;
+
+fn formula(environment: &Environment, cell: &str) -> Option<String> {
+    environment.get_cell(&cell.parse().unwrap()).map(|val| write_formula_on(val, environment.active_sheet()))
+}
+
+/// Summary sheet followed by a sheet per month, each with its number in A1
+fn months() -> Environment {
+    let mut environment = Environment::init();
+    environment.rename_sheet("Sheet1", "Summary").unwrap();
+    for (month, name) in ["Jan", "Feb", "Mar", "Apr"].into_iter().enumerate() {
+        environment.add_sheet(name).unwrap();
+        environment.set_cell_on(name, &"A1".parse().unwrap(), Box::new(Primitive::Integer(month as i32 + 1))).unwrap();
+    }
+    environment
+}
+
+#[test]
+fn renames_references_to_a_sheet() {
+    let mut environment = months();
+    set(&mut environment, "A1", "Feb!A1 + Sum(Jan:Mar!A1:A1) + Sum(Feb!A1:B2)");
+    environment.define_name("Second", &NameScope::Workbook, Definition::Cell("Feb!A1".parse().unwrap())).unwrap();
+    environment.define_name("Local", &NameScope::Sheet(String::from("Feb")), Definition::Constant(Box::new(Primitive::Integer(9)))).unwrap();
+    set(&mut environment, "A2", "Second * 10");
+    environment.set_cell_on("Feb", &"B1".parse().unwrap(), parse_formula("Local + A1").unwrap()).unwrap();
+
+    environment.rename_sheet("Feb", "February 2024").unwrap();
+    assert_eq!(formula(&environment, "A1").as_deref(), Some("'February 2024'!A1 + Sum(Jan:Mar!A1:A1) + Sum('February 2024'!A1:B2)"));
+    assert_eq!(environment.name_range("Second").unwrap().to_string(), "'February 2024'!A1:A1");
+    assert_eq!(value(&environment, "A2"), Some(Ok(Primitive::Integer(20))));
+    assert_eq!(environment.value_on("February 2024", &"B1".parse().unwrap()), Some(Ok(Primitive::Integer(11))));
+
+    environment.rename_sheet("Mar", "March").unwrap();
+    assert_eq!(formula(&environment, "A1").as_deref(), Some("'February 2024'!A1 + Sum(Jan:March!A1:A1) + Sum('February 2024'!A1:B2)"));
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Float(21.0))));
+}
+
+#[test]
+fn refuses_bad_sheet_names() {
+    let mut environment = months();
+    assert_eq!(environment.rename_sheet("Jan", "Feb"), Err(String::from("Sheet Feb already exists")));
+    assert_eq!(environment.add_sheet(""), Err(String::from("Sheet name cannot be empty")));
+    assert_eq!(environment.add_sheet("Q1/Q2"), Err(String::from("Sheet name Q1/Q2 cannot contain '/'")));
+    assert_eq!(environment.add_sheet("'Quoted'"), Err(String::from("Sheet name 'Quoted' cannot start or end with an apostrophe")));
+    assert!(environment.rename_sheet("May", "June").is_err());
+    assert_eq!(environment.rename_sheet("Jan", "Jan"), Ok(()));
+    assert_eq!(environment.undo_label(), Some("Set A1"));
+}
+
+#[test]
+fn breaks_references_to_a_removed_sheet() {
+    let mut environment = months();
+    set(&mut environment, "A1", "Feb!A1 + 1");
+    set(&mut environment, "A2", "Sum(Feb!A1:A3)");
+    set(&mut environment, "A3", "Jan!A1 * 2");
+    environment.define_name("Second", &NameScope::Workbook, Definition::Cell("Feb!A1".parse().unwrap())).unwrap();
+
+    environment.delete_sheet("Feb").unwrap();
+    assert_eq!(formula(&environment, "A1").as_deref(), Some("#REF! + 1"));
+    assert!(matches!(value(&environment, "A1"), Some(Err(_))));
+    assert!(matches!(value(&environment, "A2"), Some(Err(_))));
+    assert_eq!(value(&environment, "A3"), Some(Ok(Primitive::Integer(2))));
+    assert!(environment.name_range("Second").is_err());
+
+    environment.undo();
+    assert_eq!(formula(&environment, "A1").as_deref(), Some("Feb!A1 + 1"));
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(3))));
+}
+
+#[test]
+fn shrinks_3d_ranges_whose_end_is_removed() {
+    let mut environment = months();
+    set(&mut environment, "A1", "Sum(Jan:Mar!A1:A1)");
+    set(&mut environment, "A2", "Sum(Mar:Jan!A1:A1)");
+    set(&mut environment, "A3", "Sum(Feb:Feb!A1:A1)");
+
+    environment.delete_sheet("Jan").unwrap();
+    assert_eq!(formula(&environment, "A1").as_deref(), Some("Sum(Feb:Mar!A1:A1)"));
+    assert_eq!(formula(&environment, "A2").as_deref(), Some("Sum(Mar:Feb!A1:A1)"));
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Float(5.0))));
+    environment.delete_sheet("Mar").unwrap();
+    assert_eq!(formula(&environment, "A1").as_deref(), Some("Sum(Feb!A1:A1)"));
+    environment.delete_sheet("Feb").unwrap();
+    assert!(matches!(value(&environment, "A1"), Some(Err(_))));
+    assert!(matches!(value(&environment, "A3"), Some(Err(_))));
+}
+
+#[test]
+fn spans_the_sheets_between_the_ends_of_a_3d_range() {
+    let mut environment = months();
+    set(&mut environment, "A1", "Sum(Jan:Mar!A1:A1)");
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Float(6.0))));
+
+    // Moving a sheet out of the span drops it, moving one in adds it
+    environment.move_sheet("Feb", 4).unwrap();
+    assert_eq!(environment.sheet_names().collect::<Vec<_>>(), ["Summary", "Jan", "Mar", "Apr", "Feb"]);
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Float(4.0))));
+    environment.move_sheet("Apr", 2).unwrap();
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Float(8.0))));
+
+    // The ends may swap places
+    environment.move_sheet("Jan", 4).unwrap();
+    assert_eq!(environment.sheet_names().collect::<Vec<_>>(), ["Summary", "Apr", "Mar", "Feb", "Jan"]);
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Float(1.0 + 2.0 + 3.0))));
+
+    assert_eq!(environment.move_sheet("Jan", 5), Err(String::from("Sheet position 5 is out of range")));
+    environment.undo();
+    environment.undo();
+    environment.undo();
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Float(6.0))));
+}
+
+#[test]
+fn keeps_the_active_sheet_when_another_is_removed() {
+    let mut environment = months();
+    environment.set_active_sheet("Mar").unwrap();
+    environment.delete_sheet("Jan").unwrap();
+    assert_eq!(environment.active_sheet(), "Mar");
+    environment.delete_sheet("Mar").unwrap();
+    assert_eq!(environment.active_sheet(), "Apr");
+    environment.delete_sheet("Apr").unwrap();
+    assert_eq!(environment.active_sheet(), "Feb");
+
+    environment.delete_sheet("Feb").unwrap();
+    assert_eq!(environment.delete_sheet("Summary"), Err(String::from("Cannot delete the only sheet")));
+}
@@ -0,0 +1,64 @@
+use gridkid::environment::Environment;
+use gridkid::format::formula::{parse_formula, write_formula};
+use gridkid::model::{CellAddress, Primitive};
+use gridkid::simplify::simplify;
+
+fn simplified(text: &str) -> String {
+    write_formula(simplify(parse_formula(text).unwrap().as_ref()).as_ref())
+}
+
+#[test]
+fn folds_constant_operations() {
+    assert_eq!(simplified("5 - 5"), "0");
+    assert_eq!(simplified("(5 - 5 + 5) * (5 | 4) == 25"), "true");
+    assert_eq!(simplified("A1 + 2 * 3"), "A1 + 6");
+    assert_eq!(simplified("2 ** 3 > 7 - 1"), "true");
+}
+
+#[test]
+fn removes_identities_of_known_types() {
+    assert_eq!(simplified("(A1 + B1) * 1"), "A1 + B1");
+    assert_eq!(simplified("FloatToInt(A1) + 0"), "FloatToInt(A1)");
+    assert_eq!(simplified("!!(A1 < B1)"), "A1 < B1");
+    assert_eq!(simplified("(A1 == 2) && true"), "A1 == 2");
+    assert_eq!(simplified("~~(A1 & 3)"), "A1 & 3");
+    assert_eq!(simplified("Sum(A1:A3) / (3 - 2)"), "Sum(A1:A3)");
+}
+
+#[test]
+fn keeps_what_could_change_a_result() {
+    // A1 may hold text, for which multiplying is an error
+    assert_eq!(simplified("A1 * 1"), "A1 * 1");
+    assert_eq!(simplified("!!A1"), "!!A1");
+    // Adding 0 turns -0.0 into 0.0
+    assert_eq!(simplified("Sum(A1:A3) + 0"), "Sum(A1:A3) + 0");
+    // Absorbing operands would hide errors of the other operand
+    assert_eq!(simplified("A1 * 0"), "A1 * 0");
+    assert_eq!(simplified("(A1 < 1) && false"), "A1 < 1 && false");
+    // Comparing floats depends on the tolerance of the workbook
+    assert_eq!(simplified("IntToFloat(2) ** 0.5 > 1"), "1.4142135 > 1");
+    assert_eq!(simplified("0.1 + 0.2 == 0.3"), "0.3 == 0.3");
+}
+
+#[test]
+fn keeps_errors() {
+    assert_eq!(simplified("A1 / 0"), "A1 / 0");
+    assert_eq!(simplified("1 / 0 + 2"), "1 / 0 + 2");
+    assert_eq!(simplified("1 + \"x\""), "1 + \"x\"");
+    assert_eq!(simplified("2147483647 + 1"), "2147483647 + 1");
+    assert_eq!(simplified("(0 - 2147483647 - 1) / (0 - 1)"), "-2147483648 / -1");
+    assert_eq!(simplified("2 ** 31 - 1"), "2 ** 31 - 1");
+    assert_eq!(simplified("5 % 0"), "5 % 0");
+}
+
+#[test]
+fn evaluates_as_before() {
+    let mut environment = Environment::init();
+    environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Integer(7))).unwrap();
+    environment.set_cell(&CellAddress(1, 0), Box::new(Primitive::Float(-0.0))).unwrap();
+
+    for text in ["(A1 + 0) * 1 - 0", "!!(A1 > 3) || false", "~~A1 | 0", "IntToFloat(A1) ** 1 / 1", "B1 - 0", "(B1 * 1) / 0", "A1 ** (3 - 3)"] {
+        let val = parse_formula(text).unwrap();
+        assert_eq!(simplify(val.as_ref()).evaluate(&environment), val.evaluate(&environment), "{text}");
+    }
+}
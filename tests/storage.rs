@@ -0,0 +1,70 @@
+mod common;
+
+use common::set;
+use gridkid::address::CellRange;
+use gridkid::environment::Environment;
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Evaluatable, Primitive};
+
+fn workbook(cells: &[(&str, &str)]) -> Environment {
+    let mut environment = Environment::init();
+    for (cell, text) in cells {
+        set(&mut environment, cell, text);
+    }
+    environment
+}
+
+fn addresses<'e>(cells: impl Iterator<Item = (&'e CellAddress, &'e dyn Evaluatable)>) -> Vec<String> {
+    cells.map(|(adr, _)| adr.to_string()).collect()
+}
+
+#[test]
+fn aggregates_whole_columns_of_a_few_cells() {
+    let mut environment = workbook(&[("A1", "2"), ("A500000", "3"), ("A2000000", "5.5"), ("B1", "Sum(A:A)"), ("B2", "Max(A:A)")]);
+    assert_eq!(environment.value(&"B1".parse().unwrap()), Some(Ok(Primitive::Float(10.5))));
+    assert_eq!(environment.value(&"B2".parse().unwrap()), Some(Ok(Primitive::Float(5.5))));
+
+    environment.set_cell(&"B3".parse().unwrap(), parse_formula("Mean(A1:A1000000)").unwrap()).unwrap();
+    assert_eq!(environment.value(&"B3".parse().unwrap()), Some(Ok(Primitive::Float(2.5))));
+}
+
+#[test]
+fn skips_empty_cells_of_a_range() {
+    let environment = workbook(&[("A1", "1"), ("A3", "4"), ("C2", "\"x\"")]);
+    let value = |text: &str| parse_formula(text).unwrap().evaluate(&environment);
+
+    assert_eq!(value("Sum(A1:A3)"), Ok(Primitive::Float(5.0)));
+    assert_eq!(value("Mean(A1:A3)"), Ok(Primitive::Float(2.5)));
+    assert_eq!(value("Min(A1:B3)"), Ok(Primitive::Integer(1)));
+    assert_eq!(value("Sum(D1:D9)"), Ok(Primitive::Float(0.0)));
+    assert_eq!(value("Max(D1:D9)"), Err(String::from("No values in D1:D9")));
+    assert_eq!(value("Sum(A1:C3)"), Err(String::from("Value in cell C2 is not numeric")));
+}
+
+#[test]
+fn iterates_ranges_rows_and_columns() {
+    let environment = workbook(&[("C3", "1"), ("A1", "1"), ("B2", "1"), ("D2", "1"), ("B5", "1"), ("A2", "1")]);
+
+    assert_eq!(addresses(environment.cells_on("Sheet1")), ["A1", "A2", "B2", "D2", "C3", "B5"]);
+    assert_eq!(addresses(environment.cells_in("Sheet1", &"B2:C5".parse().unwrap())), ["B2", "C3", "B5"]);
+    assert_eq!(addresses(environment.cells_in("Sheet1", &CellRange::whole_columns(1, 1))), ["B2", "B5"]);
+    assert_eq!(addresses(environment.cells_in("Sheet1", &CellRange::whole_rows(1, 1))), ["A2", "B2", "D2"]);
+    assert_eq!(addresses(environment.cells_in_row("Sheet1", 1)), ["A2", "B2", "D2"]);
+    assert_eq!(addresses(environment.cells_in_column("Sheet1", 0)), ["A1", "A2"]);
+    assert!(environment.cells_in_row("Sheet1", 3).next().is_none());
+    assert!(environment.cells_in("Missing", &"A1:D5".parse().unwrap()).next().is_none());
+}
+
+#[test]
+fn finds_the_used_range() {
+    let mut environment = workbook(&[("C3", "1"), ("B7", "1"), ("E4", "1")]);
+    assert_eq!(environment.used_range("Sheet1").unwrap().to_string(), "B3:E7");
+
+    environment.clear_cell(&"E4".parse().unwrap());
+    assert_eq!(environment.used_range("Sheet1").unwrap().to_string(), "B3:C7");
+
+    environment.clear_cell(&"C3".parse().unwrap());
+    environment.clear_cell(&"B7".parse().unwrap());
+    assert_eq!(environment.used_range("Sheet1"), None);
+    assert_eq!(environment.used_range("Missing"), None);
+}
@@ -0,0 +1,101 @@
+use gridkid::environment::Environment;
+use gridkid::format::ods::parse_formula;
+use gridkid::format::table::{to_html, to_markdown, TableOptions};
+use gridkid::model::CellAddress;
+
+fn workbook() -> Environment {
+    let mut environment = Environment::init();
+    for (adr, formula) in [(CellAddress(0, 0), "of:=\"a | b <c> &lt; *d*\""), (CellAddress(1, 0), "of:=1.5"), (CellAddress(0, 1), "of:=1/0"), (CellAddress(1, 1), "of:=2"), (CellAddress(2, 1), "of:=\"x\\\ny\"")] {
+        environment.set_cell(&adr, parse_formula(formula).unwrap()).unwrap();
+    }
+    environment
+}
+
+const BARE: TableOptions = TableOptions { column_headers: false, row_headers: false };
+
+#[test]
+fn escapes_markdown() {
+    let markdown = to_markdown(&workbook(), "Sheet1", &CellAddress(2, 2), &CellAddress(0, 0), &TableOptions::default());
+    assert_eq!(markdown, concat!(
+        "|  | A | B | C |\n",
+        "| --- | --- | ---: | --- |\n",
+        "| **1** | a \\| b \\<c\\> \\&lt; \\*d\\* | 1.5 |  |\n",
+        "| **2** | **#ERROR** Division by 0 | 2 | x\\\\<br>y |\n",
+        "| **3** |  |  |  |\n",
+    ));
+}
+
+#[test]
+fn escapes_html() {
+    let html = to_html(&workbook(), "Sheet1", &CellAddress(0, 0), &CellAddress(2, 1), &TableOptions::default());
+    assert_eq!(html, concat!(
+        "<table>\n",
+        "  <thead>\n",
+        "    <tr>\n",
+        "      <th></th>\n",
+        "      <th scope=\"col\">A</th>\n",
+        "      <th scope=\"col\">B</th>\n",
+        "      <th scope=\"col\">C</th>\n",
+        "    </tr>\n",
+        "  </thead>\n",
+        "  <tbody>\n",
+        "    <tr>\n",
+        "      <th scope=\"row\">1</th>\n",
+        "      <td>a | b &lt;c&gt; &amp;lt; *d*</td>\n",
+        "      <td class=\"number\" style=\"text-align: right\">1.5</td>\n",
+        "      <td></td>\n",
+        "    </tr>\n",
+        "    <tr>\n",
+        "      <th scope=\"row\">2</th>\n",
+        "      <td class=\"error\" style=\"color: #a00; background-color: #fdd\">Division by 0</td>\n",
+        "      <td class=\"number\" style=\"text-align: right\">2</td>\n",
+        "      <td>x\\\ny</td>\n",
+        "    </tr>\n",
+        "  </tbody>\n",
+        "</table>\n",
+    ));
+}
+
+#[test]
+fn leaves_out_headers() {
+    let environment = workbook();
+    assert_eq!(to_markdown(&environment, "Sheet1", &CellAddress(1, 0), &CellAddress(1, 1), &BARE), "|  |\n| ---: |\n| 1.5 |\n| 2 |\n");
+    assert_eq!(
+        to_html(&environment, "Sheet1", &CellAddress(1, 1), &CellAddress(1, 1), &TableOptions { column_headers: false, row_headers: true }),
+        "<table>\n  <tbody>\n    <tr>\n      <th scope=\"row\">2</th>\n      <td class=\"number\" style=\"text-align: right\">2</td>\n    </tr>\n  </tbody>\n</table>\n",
+    );
+}
+
+#[test]
+fn aligns_only_columns_of_numbers() {
+    let environment = workbook();
+    // An error or an empty column is not numeric
+    assert_eq!(to_markdown(&environment, "Sheet1", &CellAddress(0, 1), &CellAddress(1, 1), &BARE).lines().nth(1), Some("| --- | ---: |"));
+    assert_eq!(to_markdown(&environment, "Sheet1", &CellAddress(3, 0), &CellAddress(3, 0), &BARE), "|  |\n| --- |\n|  |\n");
+}
+
+#[test]
+fn spans_any_two_corners() {
+    let environment = workbook();
+    let options = TableOptions::default();
+    let table = to_markdown(&environment, "Sheet1", &CellAddress(1, 1), &CellAddress(0, 0), &options);
+    assert_eq!(table, to_markdown(&environment, "Sheet1", &CellAddress(0, 1), &CellAddress(1, 0), &options));
+    assert_eq!(table.lines().count(), 4);
+
+    // Coordinates off the grid are shown as they are
+    let table = to_markdown(&environment, "Sheet1", &CellAddress(-1, -1), &CellAddress(0, 0), &options);
+    assert_eq!(table.lines().next(), Some("|  | -1 | A |"));
+    assert_eq!(table.lines().nth(3), Some("| **1** |  | a \\| b \\<c\\> \\&lt; \\*d\\* |"));
+    assert!(table.contains("| **(-1)** |  |  |"));
+}
+
+#[test]
+fn renders_the_named_sheet() {
+    let mut environment = workbook();
+    environment.add_sheet("Data").unwrap();
+    environment.set_cell_on("Data", &CellAddress(1, 1), parse_formula("of:=[$Sheet1.B2]*21").unwrap()).unwrap();
+
+    assert_eq!(to_markdown(&environment, "Data", &CellAddress(0, 0), &CellAddress(1, 1), &BARE), "|  |  |\n| --- | ---: |\n|  |  |\n|  | 42 |\n");
+    assert_eq!(to_markdown(&environment, "Missing", &CellAddress(0, 0), &CellAddress(0, 0), &BARE), "|  |\n| --- |\n|  |\n");
+    assert_eq!(environment.active_sheet(), "Sheet1");
+}
@@ -0,0 +1,154 @@
+use std::cmp::Ordering;
+
+use gridkid::environment::Environment;
+use gridkid::events::Event;
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Primitive, Tolerance};
+
+const TOLERANCES: [Tolerance; 4] = [Tolerance::Exact, Tolerance::Absolute(0.01), Tolerance::Relative(1e-6), Tolerance::Ulps(4)];
+
+/// Pairs of floats, and whether each of `TOLERANCES` finds them equal
+const PAIRS: [(f32, f32, [bool; 4]); 12] = [
+    (1.0, 1.0, [true, true, true, true]),
+    (0.0, -0.0, [true, true, true, true]),
+    (1.0, 1.0 + f32::EPSILON, [false, true, true, true]),
+    (1.0, 1.0 + 8.0 * f32::EPSILON, [false, true, true, false]),
+    (1.0, 1.005, [false, true, false, false]),
+    (1.0, 1.02, [false, false, false, false]),
+    (16777216.0, 16777218.0, [false, false, true, true]),
+    (1e30, 1.0000001e30, [false, false, true, true]),
+    (-2.5, 2.5, [false, false, false, false]),
+    (1e-40, -1e-40, [false, true, false, false]),
+    (f32::INFINITY, f32::MAX, [false, false, false, false]),
+    (f32::NAN, f32::NAN, [false, false, false, false]),
+];
+
+#[test]
+fn decides_which_floats_are_equal() {
+    for (v1, v2, expected) in PAIRS {
+        for (tolerance, equal) in TOLERANCES.iter().zip(expected) {
+            assert_eq!(tolerance.equal(v1, v2), equal, "{v1} and {v2} with {tolerance:?}");
+            assert_eq!(tolerance.equal(v2, v1), equal, "{v2} and {v1} with {tolerance:?}");
+        }
+    }
+    assert_eq!(Tolerance::default().compare(f32::NAN, 1.0), None);
+    assert_eq!(Tolerance::Ulps(1).compare(2.0, 1.0), Some(Ordering::Greater));
+}
+
+/// Workbook comparing A1 with B1 in every way, on row 2
+fn comparisons(tolerance: Tolerance, compiled: bool) -> Environment {
+    let mut environment = Environment::init();
+    environment.set_tolerance(tolerance);
+    environment.set_compiled(compiled);
+    for (column, operator) in ["==", "!=", "<", "<=", ">", ">="].iter().enumerate() {
+        environment.set_cell(&CellAddress(column as i32, 1), parse_formula(&format!("A1 {operator} B1")).unwrap()).unwrap();
+    }
+    environment
+}
+
+fn compare(environment: &mut Environment, v1: f32, v2: f32) -> Vec<bool> {
+    environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::Float(v1))).unwrap();
+    environment.set_cell(&CellAddress(1, 0), Box::new(Primitive::Float(v2))).unwrap();
+    (0..6)
+        .map(|column| match environment.value(&CellAddress(column, 1)) {
+            Some(Ok(Primitive::Boolean(val))) => val,
+            other => panic!("{v1} and {v2} gave {other:?}"),
+        })
+        .collect()
+}
+
+#[test]
+fn orders_floats_consistently_with_equality() {
+    for compiled in [false, true] {
+        for (index, tolerance) in TOLERANCES.into_iter().enumerate() {
+            let mut environment = comparisons(tolerance, compiled);
+            for (v1, v2, expected) in PAIRS {
+                for (v1, v2) in [(v1, v2), (v2, v1)] {
+                    let equal = expected[index];
+                    let [eq, ne, lt, le, gt, ge] = compare(&mut environment, v1, v2)[..] else { unreachable!() };
+                    let context = format!("{v1} and {v2} with {tolerance:?}");
+
+                    assert_eq!(eq, equal, "{context}");
+                    assert_eq!(ne, !equal, "{context}");
+                    assert_eq!(le, lt || eq, "{context}");
+                    assert_eq!(ge, gt || eq, "{context}");
+                    if v1.is_nan() || v2.is_nan() {
+                        assert!(!lt && !le && !gt && !ge, "{context}");
+                    } else {
+                        assert_eq!([lt, eq, gt].iter().filter(|val| **val).count(), 1, "{context}");
+                        assert_eq!(lt, !equal && v1 < v2, "{context}");
+                        assert_eq!(gt, !equal && v1 > v2, "{context}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn compares_greater_floats_as_greater() {
+    let environment = Environment::init();
+    let value = |text: &str| parse_formula(text).unwrap().evaluate(&environment);
+    assert_eq!(value("2.5 >= 1.0"), Ok(Primitive::Boolean(true)));
+    assert_eq!(value("1.0 >= 2.5"), Ok(Primitive::Boolean(false)));
+    assert_eq!(value("2.5 <= 1.0"), Ok(Primitive::Boolean(false)));
+    assert_eq!(value("1 >= 2.5"), Ok(Primitive::Boolean(false)));
+    assert_eq!(value("3 > 2.5"), Ok(Primitive::Boolean(true)));
+}
+
+#[test]
+fn picks_extremes_within_the_tolerance() {
+    let mut environment = Environment::init();
+    for (row, val) in [Primitive::Float(1.0), Primitive::Integer(-3), Primitive::Float(1.0 + f32::EPSILON), Primitive::Float(-3.001)].into_iter().enumerate() {
+        environment.set_cell(&CellAddress(0, row as i32), Box::new(val)).unwrap();
+    }
+    environment.set_cell(&"B1".parse().unwrap(), parse_formula("Max(A1:A4)").unwrap()).unwrap();
+    environment.set_cell(&"B2".parse().unwrap(), parse_formula("Min(A1:A4)").unwrap()).unwrap();
+    environment.set_cell(&"B3".parse().unwrap(), parse_formula("A3 == Max(A1:A4)").unwrap()).unwrap();
+    let extremes = |environment: &Environment| ["B1", "B2", "B3"].map(|cell| environment.value(&cell.parse().unwrap()).unwrap().unwrap());
+
+    environment.set_tolerance(Tolerance::Exact);
+    assert_eq!(extremes(&environment), [Primitive::Float(1.0 + f32::EPSILON), Primitive::Float(-3.001), Primitive::Boolean(true)]);
+    // Numbers too close to tell apart are equal, and the first of them is picked
+    environment.set_tolerance(Tolerance::Absolute(0.01));
+    assert_eq!(extremes(&environment), [Primitive::Float(1.0), Primitive::Integer(-3), Primitive::Boolean(true)]);
+    environment.set_tolerance(Tolerance::Ulps(1));
+    assert_eq!(extremes(&environment), [Primitive::Float(1.0), Primitive::Float(-3.001), Primitive::Boolean(true)]);
+}
+
+#[test]
+fn picks_extremes_beyond_the_precision_of_floats() {
+    let mut environment = Environment::init();
+    environment.set_cell(&"A1".parse().unwrap(), Box::new(Primitive::Integer(16_777_216))).unwrap();
+    environment.set_cell(&"A2".parse().unwrap(), Box::new(Primitive::Integer(16_777_217))).unwrap();
+    environment.set_cell(&"B1".parse().unwrap(), parse_formula("Max(A1:A2)").unwrap()).unwrap();
+    environment.set_cell(&"B2".parse().unwrap(), parse_formula("Min(A2:A1)").unwrap()).unwrap();
+    let extremes = |environment: &Environment| ["B1", "B2"].map(|cell| environment.value(&cell.parse().unwrap()).unwrap().unwrap());
+
+    for tolerance in [Tolerance::Exact, Tolerance::default(), Tolerance::Ulps(1)] {
+        environment.set_tolerance(tolerance);
+        assert_eq!(extremes(&environment), [Primitive::Integer(16_777_217), Primitive::Integer(16_777_216)], "{tolerance:?}");
+    }
+    environment.set_tolerance(Tolerance::Absolute(1.0));
+    assert_eq!(extremes(&environment), [Primitive::Integer(16_777_216), Primitive::Integer(16_777_216)]);
+}
+
+#[test]
+fn recalculates_when_the_tolerance_changes() {
+    let mut environment = Environment::init();
+    environment.set_cell(&"A2".parse().unwrap(), Box::new(Primitive::Float(1.0))).unwrap();
+    environment.set_cell(&"A3".parse().unwrap(), Box::new(Primitive::Float(1.0 + f32::EPSILON))).unwrap();
+    environment.set_cell(&"A1".parse().unwrap(), parse_formula("A2 == A3").unwrap()).unwrap();
+    assert_eq!(environment.value(&"A1".parse().unwrap()), Some(Ok(Primitive::Boolean(true))));
+
+    let changes = environment.subscribe_channel();
+    environment.set_tolerance(Tolerance::Exact);
+    assert_eq!(environment.tolerance(), Tolerance::Exact);
+    assert_eq!(environment.value(&"A1".parse().unwrap()), Some(Ok(Primitive::Boolean(false))));
+    assert_eq!(changes.try_iter().collect::<Vec<_>>(), [Event::ValueChanged {
+        sheet: String::from("Sheet1"),
+        adr: "A1".parse().unwrap(),
+        old: Some(Ok(Primitive::Boolean(true))),
+        new: Some(Ok(Primitive::Boolean(false))),
+    }]);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::set;
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Primitive};
+use gridkid::trace::{trace, trace_on, Coercion};
+use gridkid::types::Type;
+
+fn workbook() -> Environment {
+    let mut environment = Environment::init();
+    for (cell, text) in [("A1", "2"), ("A2", "1.5"), ("A3", "\"x\""), ("B1", "A1 * 10")] {
+        set(&mut environment, cell, text);
+    }
+    environment.define_name("Rate", &NameScope::Workbook, Definition::Constant(parse_formula("A2 / 2").unwrap())).unwrap();
+    environment
+}
+
+#[test]
+fn renders_an_indented_outline() {
+    let environment = workbook();
+    let traced = trace(parse_formula("(A1 + A2) * 2 > Sum(A1:A2)").unwrap().as_ref(), &environment);
+    assert_eq!(traced.to_string(), "\
+(A1 + A2) * 2 > Sum(A1:A2) = true
+  (A1 + A2) * 2 = 7, taking 2 as Float
+    A1 + A2 = 3.5, taking A1 as Float
+      A1 = 2
+      A2 = 1.5
+    2 = 2
+  Sum(A1:A2) = 3.5, taking A1 as Float
+    A1 = 2
+    A2 = 1.5
+");
+}
+
+#[test]
+fn records_coercions() {
+    let environment = workbook();
+    let traced = trace(parse_formula("A1 + A2").unwrap().as_ref(), &environment);
+    assert_eq!(traced.coercions, vec![Coercion { operand: 0, from: Type::Integer, to: Type::Float }]);
+    assert_eq!(traced.result, Ok(Primitive::Float(3.5)));
+
+    // Integers meeting integers, and values picked by Max, are left alone
+    assert!(trace(parse_formula("A1 + B1").unwrap().as_ref(), &environment).coercions.is_empty());
+    assert!(trace(parse_formula("Max(A1:A2)").unwrap().as_ref(), &environment).coercions.is_empty());
+}
+
+#[test]
+fn shows_where_errors_come_from() {
+    let environment = workbook();
+    let traced = trace(parse_formula("1 + Sum(A1:A3)").unwrap().as_ref(), &environment);
+    assert_eq!(traced.result, Err(String::from("Value in cell A3 is not numeric")));
+    assert_eq!(traced.operands[1].operands.last().unwrap().result, Ok(Primitive::String(String::from("x"))));
+
+    let traced = trace(parse_formula("A3 * 2 + C9").unwrap().as_ref(), &environment);
+    assert_eq!(traced.result, Err(String::from("Incompatible types: String and Integer for Arithmetic operation")));
+    assert_eq!(traced.operands[1].result, Err(String::from("Value for cell C9 not found")));
+}
+
+#[test]
+fn follows_names() {
+    let environment = workbook();
+    let traced = trace(parse_formula("B1 * Rate").unwrap().as_ref(), &environment);
+    assert_eq!(traced.to_string(), "\
+B1 * Rate = 15, taking B1 as Float
+  B1 = 20
+  Rate = 0.75
+    Sheet1!A2 / 2 = 0.75, taking 2 as Float
+      Sheet1!A2 = 1.5
+      2 = 2
+");
+}
+
+#[test]
+fn agrees_with_evaluation() {
+    let environment = workbook();
+    for text in ["A1 ** 2 - B1 % 3", "!(A1 < A2) || A3 == \"x\"", "FloatToInt(Mean(A1:A2) * 4)", "A2 / 0", "#REF! + Missing", "Min(A1:B1) + Rate"] {
+        let val = parse_formula(text).unwrap();
+        assert_eq!(trace(val.as_ref(), &environment).result, val.evaluate(&environment), "{text}");
+    }
+}
+
+#[test]
+fn names_sheets_relative_to_the_cell() {
+    let mut environment = workbook();
+    environment.add_sheet("Totals").unwrap();
+    let adr = CellAddress(0, 0);
+    environment.set_cell_on("Totals", &adr, parse_formula("Sum(Sheet1!A1:A2) + B2").unwrap()).unwrap();
+    environment.set_cell_on("Totals", &"B2".parse().unwrap(), Box::new(Primitive::Integer(1))).unwrap();
+
+    let val = environment.get_cell_on("Totals", &adr).unwrap();
+    assert_eq!(trace_on(val, "Totals", &environment).to_string(), "\
+Sum(Sheet1!A1:A2) + B2 = 4.5, taking B2 as Float
+  Sum(Sheet1!A1:A2) = 3.5, taking Sheet1!A1 as Float
+    Sheet1!A1 = 2
+    Sheet1!A2 = 1.5
+  B2 = 1
+");
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::{set, value};
+use gridkid::environment::{Definition, Environment, NameScope};
+use gridkid::model::Primitive;
+
+#[test]
+fn recalculates_once_the_outermost_transaction_commits() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "B1", "A1 * 10");
+
+    environment.begin("Outer");
+    set(&mut environment, "A1", "2");
+    environment.begin("Inner");
+    set(&mut environment, "A2", "3");
+    assert_eq!(environment.commit(), Ok(()));
+    assert!(environment.in_transaction());
+    set(&mut environment, "B2", "A2 + B1");
+    assert_eq!(environment.commit(), Ok(()));
+    assert!(!environment.in_transaction());
+    assert_eq!(environment.commit(), Err(String::from("No transaction to commit")));
+
+    assert_eq!(value(&environment, "B2"), Some(Ok(Primitive::Integer(23))));
+    assert_eq!(environment.undo_label(), Some("Outer"));
+    environment.undo();
+    assert_eq!(value(&environment, "B1"), Some(Ok(Primitive::Integer(10))));
+    assert_eq!(value(&environment, "A2"), None);
+}
+
+#[test]
+fn rolls_back_cells_and_names() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    environment.define_name("Rate", &NameScope::Workbook, Definition::Constant(Box::new(Primitive::Integer(5)))).unwrap();
+
+    environment.begin("Edit");
+    set(&mut environment, "A1", "2");
+    set(&mut environment, "A2", "A1 + 1");
+    environment.remove_name("Rate", &NameScope::Workbook).unwrap();
+    environment.define_name("Tax", &NameScope::Sheet(String::from("Sheet1")), Definition::Cell("A1".parse().unwrap())).unwrap();
+    environment.begin("Nested");
+    environment.clear_cell(&"A1".parse().unwrap());
+    environment.rollback();
+
+    assert!(!environment.in_transaction());
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(1))));
+    assert_eq!(value(&environment, "A2"), None);
+    assert!(environment.lookup_name("Rate", None).is_ok());
+    assert!(environment.lookup_name("Tax", Some("Sheet1")).is_err());
+    assert_eq!(environment.undo_label(), Some("Define Rate"));
+}
+
+#[test]
+fn reverts_a_transaction_that_makes_a_circular_reference() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+
+    environment.begin("Loop");
+    set(&mut environment, "A1", "B1 + 1");
+    set(&mut environment, "B1", "A1 + 1");
+    // Values read before the commit are worked out on the spot, loop and all
+    assert_eq!(environment.value_on("Sheet1", &"B1".parse().unwrap()), Some(Err(String::from("Circular reference: Sheet1!B1 -> Sheet1!A1 -> Sheet1!B1"))));
+    assert_eq!(environment.commit(), Err(String::from("Circular reference: Sheet1!A1 -> Sheet1!B1 -> Sheet1!A1")));
+
+    assert!(!environment.in_transaction());
+    assert_eq!(value(&environment, "A1"), Some(Ok(Primitive::Integer(1))));
+    assert_eq!(value(&environment, "B1"), None);
+    assert_eq!(environment.undo_label(), Some("Set A1"));
+}
+
+#[test]
+fn keeps_an_unchecked_transaction_with_a_circular_reference() {
+    let mut environment = Environment::init();
+    environment.begin("Loop");
+    set(&mut environment, "A1", "B1 + 1");
+    set(&mut environment, "B1", "A1 + 1");
+    set(&mut environment, "C1", "7");
+    assert_eq!(environment.commit_unchecked(), Ok(()));
+
+    assert!(matches!(value(&environment, "A1"), Some(Err(e)) if e.starts_with("Circular reference")));
+    assert_eq!(value(&environment, "C1"), Some(Ok(Primitive::Integer(7))));
+    assert_eq!(environment.commit_unchecked(), Err(String::from("No transaction to commit")));
+}
+
+#[test]
+fn commits_despite_circular_references_already_there() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "B1 + 1");
+    set(&mut environment, "B1", "A1 + 1");
+    assert!(matches!(value(&environment, "A1"), Some(Err(e)) if e.starts_with("Circular reference")));
+
+    environment.begin("Elsewhere");
+    set(&mut environment, "D1", "5");
+    set(&mut environment, "D2", "D1 * 2");
+    assert_eq!(environment.commit(), Ok(()));
+    assert_eq!(value(&environment, "D2"), Some(Ok(Primitive::Integer(10))));
+
+    set(&mut environment, "C1", "A1 + 1");
+    assert_eq!(environment.fill_range(&"C1".parse().unwrap(), &"C1:C3".parse().unwrap()), Ok(()));
+    assert!(matches!(value(&environment, "C3"), Some(Err(_))));
+
+    // A second circular reference is still refused
+    environment.begin("Another loop");
+    set(&mut environment, "E1", "E2");
+    set(&mut environment, "E2", "E1");
+    assert!(environment.commit().is_err());
+    assert_eq!(value(&environment, "E1"), None);
+}
+
+#[test]
+fn tells_subscribers_nothing_of_a_reverted_transaction() {
+    let mut environment = Environment::init();
+    set(&mut environment, "A1", "1");
+    set(&mut environment, "B1", "A1 * 2");
+    let events = environment.subscribe_channel();
+
+    environment.begin("Loop");
+    set(&mut environment, "A1", "B1 + 1");
+    set(&mut environment, "C1", "B1 + 5");
+    assert!(environment.commit().is_err());
+    environment.begin("Abandoned");
+    set(&mut environment, "A1", "4");
+    environment.rollback();
+
+    assert_eq!(events.try_iter().collect::<Vec<_>>(), []);
+    assert_eq!(value(&environment, "B1"), Some(Ok(Primitive::Integer(2))));
+}
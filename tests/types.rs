@@ -0,0 +1,76 @@
+use gridkid::environment::{Environment, TypeCheck};
+use gridkid::format::formula::parse_formula;
+use gridkid::model::{CellAddress, Evaluatable, Operation, Primitive};
+use gridkid::types::{infer_type, AnyScope, Type, Types};
+
+fn samples() -> Vec<Primitive> {
+    vec![Primitive::Integer(3), Primitive::Float(1.5), Primitive::Boolean(true), Primitive::String(String::from("a"))]
+}
+
+fn boxed(val: &Primitive) -> Box<dyn Evaluatable> {
+    Box::new(val.clone())
+}
+
+type Binary = fn(Box<dyn Evaluatable>, Box<dyn Evaluatable>) -> Operation;
+
+/// Inference gives the type of the value evaluation gives, or the same error
+fn assert_agrees(operation: &Operation, environment: &Environment) {
+    match (operation.evaluate(environment), infer_type(operation, &mut AnyScope)) {
+        (Ok(val), Ok(types)) => assert_eq!(types, Types::only(Type::of(&val)), "{operation}"),
+        (Err(e), Err(inferred)) => assert_eq!(e, inferred, "{operation}"),
+        (evaluated, inferred) => panic!("{operation} evaluates to {evaluated:?} but is inferred as {inferred:?}"),
+    }
+}
+
+#[test]
+fn agrees_with_evaluation() {
+    let environment = Environment::init();
+    let binary: [Binary; 19] = [
+        Operation::Add, Operation::Subtract, Operation::Multiply, Operation::Divide, Operation::Modulus, Operation::Power,
+        Operation::LogicalAnd, Operation::LogicalOr, Operation::BitwiseAnd, Operation::BitwiseOr, Operation::BitwiseXor,
+        Operation::LeftShift, Operation::RightShift, Operation::Equals, Operation::NotEquals, Operation::LessThan,
+        Operation::LessThanOrEqual, Operation::GreaterThan, Operation::GreaterThanOrEqual,
+    ];
+    let unary: [fn(Box<dyn Evaluatable>) -> Operation; 4] = [Operation::LogicalNot, Operation::BitwiseNot, Operation::FloatToInt, Operation::IntToFloat];
+
+    for val1 in samples() {
+        for operation in unary {
+            assert_agrees(&operation(boxed(&val1)), &environment);
+        }
+        for val2 in samples() {
+            for operation in binary {
+                assert_agrees(&operation(boxed(&val1), boxed(&val2)), &environment);
+            }
+        }
+    }
+}
+
+#[test]
+fn combines_possible_types() {
+    let types = |text: &str| infer_type(parse_formula(text).unwrap().as_ref(), &mut AnyScope);
+
+    assert_eq!(types("A1 + 1"), Ok(Types::only(Type::Integer).union(Type::Float.into())));
+    assert_eq!(types("A1 == B1 && true"), Ok(Types::only(Type::Boolean)));
+    assert_eq!(types("Sum(A1:A3) + 1"), Ok(Types::only(Type::Float)));
+    assert_eq!(types("A1 + \"x\""), Err(String::from("Incompatible types: any type and String for Arithmetic operation")));
+    assert_eq!(types("(A1 < B1) + 1"), Err(String::from("Incompatible types: Boolean and Integer for Arithmetic operation")));
+    assert_eq!(types("FloatToInt(Sum(A1:A3) > 1)"), Err(String::from("Cannot cast type: Boolean")));
+    assert_eq!(types("#REF! + 1"), Err(String::from("#REF!")));
+}
+
+#[test]
+fn checks_against_referenced_cells() {
+    let mut environment = Environment::init();
+    environment.set_cell(&CellAddress(0, 0), Box::new(Primitive::String(String::from("hello, world")))).unwrap();
+    environment.set_cell(&CellAddress(1, 0), parse_formula("IntToFloat(C1)").unwrap()).unwrap();
+
+    let check = |environment: &Environment, text: &str| environment.check_formula("Sheet1", parse_formula(text).unwrap().as_ref());
+    assert_eq!(check(&environment, "6 == A1"), Err(String::from("Incompatible types: Integer and String for Equality operation")));
+    assert_eq!(check(&environment, "B1 * 2"), Ok(Types::only(Type::Float)));
+    assert_eq!(check(&environment, "D1 * 2"), Ok(Types::only(Type::Integer).union(Type::Float.into())));
+
+    environment.set_type_check(TypeCheck::Reject);
+    assert!(environment.set_cell(&CellAddress(2, 1), parse_formula("6 == A1").unwrap()).is_err());
+    assert!(environment.get_cell(&CellAddress(2, 1)).is_none());
+    assert!(environment.set_cell(&CellAddress(2, 1), parse_formula("\"hi\" == A1").unwrap()).is_ok());
+}